@@ -0,0 +1,36 @@
+/*
+ * C interface of the truesocks crate, built with
+ *   cargo rustc --lib --release --features ffi --crate-type cdylib
+ *
+ * Commands return JSON strings owned by the caller, release them with
+ * truesocks_string_free: {"ok": <result>} on success, or
+ * {"error": {"code": <code>, "message": <text>}}. Proxies are passed as
+ * JSON objects taken from the ListOnline result.
+ */
+#ifndef TRUESOCKS_H
+#define TRUESOCKS_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct TrueSocksHandle TrueSocksHandle;
+
+/* base_url may be NULL for the public API. Returns NULL on invalid input. */
+TrueSocksHandle *truesocks_client_new(const char *api_key, const char *base_url);
+void truesocks_client_free(TrueSocksHandle *handle);
+void truesocks_string_free(char *value);
+
+char *truesocks_list_online(const TrueSocksHandle *handle);
+char *truesocks_purchase(const TrueSocksHandle *handle, const char *proxy, bool private_rent);
+char *truesocks_check(const TrueSocksHandle *handle, const char *proxy);
+char *truesocks_refund(const TrueSocksHandle *handle, const char *proxy);
+char *truesocks_account_status(const TrueSocksHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,232 @@
+use crate::client::{renewal_history_id, TrueSocksClient};
+use crate::credits::Credits;
+use crate::filter::ProxyFilter;
+use crate::history::HistoryQuery;
+use crate::models::{
+    ApiError, EnableProxyRenewalResult, ListInfo, ListOnlineResult, ProxyInfo, PurchaseKind,
+    PurchaseResult,
+};
+use crate::status_codes::NOT_FOUND;
+
+#[derive(Debug, Clone)]
+pub enum RenewalAdvice {
+    // Renewing is the cheapest way to keep an equivalent proxy
+    Renew {
+        history_id: u64,
+        cost: Credits,
+    },
+    // An equivalent-or-better proxy in the current inventory is cheaper
+    Rebuy {
+        history_id: u64,
+        renewal_cost: Credits,
+        replacement: Box<ProxyInfo>,
+        kind: PurchaseKind,
+        cost: Credits,
+    },
+}
+
+impl RenewalAdvice {
+    pub fn history_id(&self) -> u64 {
+        match self {
+            RenewalAdvice::Renew { history_id, .. } | RenewalAdvice::Rebuy { history_id, .. } => {
+                *history_id
+            }
+        }
+    }
+
+    // Credits saved by following the advice instead of renewing
+    pub fn savings(&self) -> Credits {
+        match self {
+            RenewalAdvice::Renew { .. } => Credits::ZERO,
+            RenewalAdvice::Rebuy {
+                renewal_cost, cost, ..
+            } => *renewal_cost - *cost,
+        }
+    }
+}
+
+/// When `apply_renewal_advice` may replace a rental instead of renewing it.
+#[derive(Debug, Clone)]
+pub struct AdvicePolicy {
+    pub allow_rebuy: bool,
+    // Smallest saving worth switching proxies for
+    pub min_savings: Credits,
+    // Turn auto-renewal off on the replaced rental
+    pub disable_old_renewal: bool,
+}
+
+impl Default for AdvicePolicy {
+    fn default() -> Self {
+        AdvicePolicy {
+            allow_rebuy: true,
+            min_savings: Credits(1),
+            disable_old_renewal: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AdviceOutcome {
+    Renewed(EnableProxyRenewalResult),
+    Rebought {
+        purchase: Box<PurchaseResult>,
+        // Set when disabling renewal on the replaced rental failed
+        renewal_error: Option<ApiError>,
+    },
+}
+
+// Same country and connection type, no worse on ping, speed and uptime, and clean
+fn equivalent_filter(current: &ProxyInfo) -> ProxyFilter {
+    ProxyFilter::new()
+        .country(current.country_code.as_str())
+        .connection_type(current.connection_type.clone())
+        .max_ping(current.ping)
+        .min_speed(current.speed)
+        .min_uptime_quality(current.uptime_quality)
+        .exclude_blacklisted()
+}
+
+/// Compare renewing `entry` against buying the cheapest equivalent-or-better
+/// proxy from `inventory`. The renewal cost is the current price of the same
+/// proxy when it is listed, otherwise the price recorded in the history.
+pub fn advise(entry: &ListInfo, inventory: &ListOnlineResult) -> RenewalAdvice {
+    let kind = if entry.is_rented {
+        PurchaseKind::PrivateRent
+    } else {
+        PurchaseKind::SharedBuy
+    };
+    let current = inventory
+        .proxy_list
+        .iter()
+        .find(|proxy| proxy.proxy_id == entry.proxy_info.proxy_id)
+        .unwrap_or(&entry.proxy_info);
+    let renewal_cost = current.cost(kind).unwrap_or(current.rent_cost);
+
+    let filter = equivalent_filter(&entry.proxy_info);
+    let replacement = inventory
+        .proxy_list
+        .iter()
+        .filter(|proxy| proxy.proxy_id != entry.proxy_info.proxy_id)
+        .filter(|proxy| filter.matches(proxy))
+        .filter_map(|proxy| proxy.cost(kind).map(|cost| (proxy, cost)))
+        .filter(|(_, cost)| *cost < renewal_cost)
+        .min_by_key(|(proxy, cost)| (*cost, proxy.proxy_id));
+
+    match replacement {
+        Some((proxy, cost)) => RenewalAdvice::Rebuy {
+            history_id: entry.history_id,
+            renewal_cost,
+            replacement: Box::new(proxy.clone()),
+            kind,
+            cost,
+        },
+        None => RenewalAdvice::Renew {
+            history_id: entry.history_id,
+            cost: renewal_cost,
+        },
+    }
+}
+
+impl TrueSocksClient {
+    /// Advise whether to renew the active rental `history_id` or replace it.
+    pub async fn renewal_advisor(&self, history_id: u64) -> Result<RenewalAdvice, ApiError> {
+        let entry = self
+            .find_history_entry(&HistoryQuery::active(), history_id)
+            .await?
+            .ok_or(ApiError::from(NOT_FOUND))?;
+        let inventory = self.list_online_proxies().await?;
+        Ok(advise(&entry, &inventory))
+    }
+
+    /// Carry out `advice`: buy the replacement when `policy` allows it and the
+    /// saving is large enough, otherwise enable renewal.
+    pub async fn apply_renewal_advice(
+        &self,
+        advice: &RenewalAdvice,
+        policy: &AdvicePolicy,
+    ) -> Result<AdviceOutcome, ApiError> {
+        let history_id = advice.history_id();
+        match advice {
+            RenewalAdvice::Rebuy {
+                replacement, kind, ..
+            } if policy.allow_rebuy && advice.savings() >= policy.min_savings => {
+                let purchase = self.purchase(replacement, *kind).await?;
+                let renewal_error = if policy.disable_old_renewal {
+                    let result = match renewal_history_id(history_id) {
+                        Ok(id) => self.bought_proxy_renew_disable(id).await.map(|_| ()),
+                        Err(err) => Err(err),
+                    };
+                    result.err()
+                } else {
+                    None
+                };
+                Ok(AdviceOutcome::Rebought {
+                    purchase: Box::new(purchase),
+                    renewal_error,
+                })
+            }
+            _ => {
+                let result = self
+                    .bought_proxy_renew_enable(renewal_history_id(history_id)?)
+                    .await?;
+                Ok(AdviceOutcome::Renewed(result))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{list_info, proxy_info_json};
+
+    fn inventory(proxies: Vec<ProxyInfo>) -> ListOnlineResult {
+        ListOnlineResult {
+            last_update: 0,
+            proxy_count: proxies.len() as u32,
+            proxy_list: proxies,
+            duplicates_removed: 0,
+            extra: Default::default(),
+        }
+    }
+
+    fn proxy(proxy_id: u32, cost: u32) -> ProxyInfo {
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(proxy_id)).unwrap();
+        proxy.rent_cost = Credits(cost);
+        proxy
+    }
+
+    #[test]
+    fn test_advise_rebuy_when_cheaper() {
+        let entry = list_info(1);
+        let mut slower = proxy(3, 2);
+        slower.speed = 0;
+        let advice = advise(
+            &entry,
+            &inventory(vec![proxy(1, 10), proxy(2, 6), slower, proxy(4, 8)]),
+        );
+        match &advice {
+            RenewalAdvice::Rebuy {
+                replacement, cost, ..
+            } => {
+                assert_eq!(replacement.proxy_id, 2);
+                assert_eq!(*cost, Credits(6));
+            }
+            other => panic!("unexpected advice {:?}", other),
+        }
+        assert_eq!(advice.savings(), Credits(4));
+    }
+
+    #[test]
+    fn test_advise_renew_without_cheaper_equivalent() {
+        let entry = list_info(1);
+        let advice = advise(&entry, &inventory(vec![proxy(1, 5), proxy(2, 5)]));
+        assert!(matches!(
+            advice,
+            RenewalAdvice::Renew {
+                history_id: 1,
+                cost: Credits(5)
+            }
+        ));
+    }
+}
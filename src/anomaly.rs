@@ -0,0 +1,126 @@
+use crate::credits::Credits;
+use crate::ledger::{Ledger, LedgerKind};
+use crate::unix_now;
+
+const HOUR: u64 = 3600;
+
+/// Spending in one hour well above the recent hourly average.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendAnomaly {
+    // Unix timestamp of the start of the hour
+    pub hour_start: u64,
+    pub spent: Credits,
+    // Mean and standard deviation of hourly spend over the baseline window
+    pub mean: f64,
+    pub deviation: f64,
+}
+
+/// Flags hours whose spend exceeds the rolling mean by more than `multiple`
+/// standard deviations, to catch runaway automation early.
+#[derive(Debug, Clone)]
+pub struct SpendAnomalyDetector {
+    // Number of previous hours forming the baseline, hours without spend count as zero
+    pub window_hours: u64,
+    pub multiple: f64,
+    // Hours spending less than this are never flagged, avoids noise on quiet accounts
+    pub min_spend: Credits,
+    last_reported: Option<u64>,
+}
+
+impl Default for SpendAnomalyDetector {
+    fn default() -> Self {
+        SpendAnomalyDetector {
+            window_hours: 24,
+            multiple: 3.0,
+            min_spend: Credits(10),
+            last_reported: None,
+        }
+    }
+}
+
+impl SpendAnomalyDetector {
+    pub fn new(window_hours: u64, multiple: f64, min_spend: Credits) -> Self {
+        SpendAnomalyDetector {
+            window_hours: window_hours.max(1),
+            multiple,
+            min_spend,
+            last_reported: None,
+        }
+    }
+
+    pub fn check(&mut self, ledger: &Ledger) -> Option<SpendAnomaly> {
+        self.check_at(ledger, unix_now())
+    }
+
+    /// Check the hour containing `timestamp`, each hour is reported at most once.
+    pub fn check_at(&mut self, ledger: &Ledger, timestamp: u64) -> Option<SpendAnomaly> {
+        let hour = timestamp / HOUR;
+        if self.last_reported == Some(hour) {
+            return None;
+        }
+
+        let first = hour.saturating_sub(self.window_hours);
+        let mut hourly = vec![0_u64; (hour - first + 1) as usize];
+        for entry in ledger.entries() {
+            let entry_hour = entry.timestamp / HOUR;
+            if entry.kind == LedgerKind::Spend && (first..=hour).contains(&entry_hour) {
+                hourly[(entry_hour - first) as usize] += entry.amount.amount() as u64;
+            }
+        }
+        let spent = hourly.pop().unwrap_or(0);
+        if spent < self.min_spend.amount() as u64 {
+            return None;
+        }
+
+        let count = hourly.len().max(1) as f64;
+        let mean = hourly.iter().sum::<u64>() as f64 / count;
+        let variance = hourly
+            .iter()
+            .map(|spent| (*spent as f64 - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        let deviation = variance.sqrt();
+        if (spent as f64) <= mean + self.multiple * deviation {
+            return None;
+        }
+
+        self.last_reported = Some(hour);
+        Some(SpendAnomaly {
+            hour_start: hour * HOUR,
+            spent: Credits(spent.min(u32::MAX as u64) as u32),
+            mean,
+            deviation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::LedgerEntry;
+
+    fn spend(ledger: &mut Ledger, timestamp: u64, amount: u32) {
+        ledger.record(LedgerEntry {
+            timestamp,
+            kind: LedgerKind::Spend,
+            amount: Credits(amount),
+            balance: Credits(1000),
+        });
+    }
+
+    #[test]
+    fn test_spike_is_reported_once() {
+        let mut ledger = Ledger::new();
+        for hour in 0..24 {
+            spend(&mut ledger, hour * HOUR, 10 + (hour % 3) as u32);
+        }
+        let mut detector = SpendAnomalyDetector::default();
+        assert_eq!(detector.check_at(&ledger, 23 * HOUR + 10), None);
+
+        spend(&mut ledger, 24 * HOUR + 5, 200);
+        let anomaly = detector.check_at(&ledger, 24 * HOUR + 10).unwrap();
+        assert_eq!(anomaly.hour_start, 24 * HOUR);
+        assert_eq!(anomaly.spent, Credits(200));
+        assert_eq!(detector.check_at(&ledger, 24 * HOUR + 20), None);
+    }
+}
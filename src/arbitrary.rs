@@ -0,0 +1,214 @@
+//! [`proptest`] strategies producing valid API models, for property tests in
+//! downstream crates. Enabled by the `arbitrary` feature.
+use crate::credits::Credits;
+use crate::models::{
+    BlacklistInfo, BlacklistType, ConnectInfo, ConnectionType, ListInfo, ProxyInfo,
+};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+impl Arbitrary for Credits {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (0u32..100_000).prop_map(Credits).boxed()
+    }
+}
+
+impl Arbitrary for BlacklistType {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(BlacklistType::OpenProxy),
+            Just(BlacklistType::WebAbuse),
+            Just(BlacklistType::EmailSpam),
+            "[A-Z][a-z]{3,10}".prop_map(BlacklistType::Other),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for BlacklistInfo {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            "[A-Z0-9]{1,8}",
+            "[A-Za-z ]{1,20}",
+            any::<BlacklistType>(),
+            "[A-Za-z .]{0,40}",
+            option::of("https://[a-z]{1,10}\\.example/[a-z]{0,10}"),
+        )
+            .prop_map(|(id, name, blacklist_type, desc, link)| BlacklistInfo {
+                id,
+                name,
+                blacklist_type,
+                desc,
+                link,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for ConnectionType {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(ConnectionType::Mobile),
+            Just(ConnectionType::DSL),
+            Just(ConnectionType::Hosting),
+            Just(ConnectionType::Unknown),
+            Just(ConnectionType::NotAvailable),
+            "[A-Z][a-z]{3,10}"
+                .prop_map(ConnectionType::from)
+                .prop_filter(
+                    "known names decode to their own variant",
+                    |connection_type| { matches!(connection_type, ConnectionType::Other(_)) }
+                ),
+        ]
+        .boxed()
+    }
+}
+
+fn ipv4() -> impl Strategy<Value = String> {
+    any::<[u8; 4]>().prop_map(|[a, b, c, d]| format!("{}.{}.{}.{}", a, b, c, d))
+}
+
+// Decimal values with one fractional digit survive a JSON round trip exactly
+fn decimal(max: u32) -> impl Strategy<Value = f64> {
+    (0..max * 10).prop_map(|tenths| tenths as f64 / 10.0)
+}
+
+impl Arbitrary for ProxyInfo {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let pricing = (
+            1u32..10_000_000,
+            any::<Credits>(),
+            any::<Credits>(),
+            any::<bool>(),
+        );
+        let location = (
+            option::of(ipv4()),
+            "[a-z0-9.-]{1,30}",
+            "[A-Za-z ]{1,20}",
+            "[A-Z]{2}",
+            "[A-Za-z ]{1,20}",
+            "[A-Za-z ]{1,20}",
+            "[A-Za-z ]{1,20}",
+            option::of("[0-9]{5}"),
+            "[A-Za-z]{1,10}/[A-Za-z_]{1,12}",
+        );
+        let quality = (
+            any::<ConnectionType>(),
+            decimal(2_000),
+            any::<u32>(),
+            0u32..=100,
+            option::of(vec(any::<BlacklistInfo>(), 0..3)),
+            option::of(decimal(5_000)),
+        );
+        (pricing, location, quality)
+            .prop_map(
+                |(
+                    (proxy_id, rent_cost, private_rent_cost, is_fresh),
+                    (ip, hostname, isp, country_code, country, region, city, zip_code, timezone),
+                    (connection_type, ping, speed, uptime_quality, blacklist, distance),
+                )| ProxyInfo {
+                    proxy_id,
+                    rent_cost,
+                    private_rent_cost,
+                    is_fresh,
+                    ip,
+                    hostname,
+                    isp,
+                    country_code,
+                    country,
+                    region,
+                    city,
+                    zip_code,
+                    timezone,
+                    connection_type,
+                    ping,
+                    speed,
+                    uptime_quality,
+                    blacklist,
+                    distance,
+                    extra: Default::default(),
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for ConnectInfo {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (ipv4(), 1024u16.., "[a-f0-9]{8,32}")
+            .prop_map(
+                |(connect_ip, connect_port, connect_session_id)| ConnectInfo {
+                    connect_ip,
+                    connect_port,
+                    connect_session_id,
+                },
+            )
+            .boxed()
+    }
+}
+
+impl Arbitrary for ListInfo {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        let ids = (
+            1u64..100_000_000,
+            option::of(any::<ConnectInfo>()),
+            any::<ProxyInfo>(),
+            any::<u32>(),
+            0u64..2_592_000,
+        );
+        let flags = (
+            any::<[bool; 6]>(),
+            0u64..100,
+            option::of("[a-z0-9:, ]{1,40}"),
+        );
+        (ids, flags)
+            .prop_map(
+                |(
+                    (history_id, connect_info, proxy_info, last_bought, remaining_time),
+                    (flags, renew_count_remaining, note),
+                )| {
+                    let [is_online, is_fresh, is_rented, refund_available, renew_enabled, ip_has_changed] =
+                        flags;
+                    ListInfo {
+                        history_id,
+                        connect_info,
+                        proxy_info,
+                        last_bought: last_bought as u64,
+                        remaining_time,
+                        is_online,
+                        is_fresh,
+                        is_rented,
+                        refund_available,
+                        renew_enabled,
+                        renew_count_remaining,
+                        ip_has_changed,
+                        note,
+                        extra: Default::default(),
+                    }
+                },
+            )
+            .boxed()
+    }
+}
@@ -0,0 +1,143 @@
+use crate::isp::{normalize_isp, IspResolver};
+use crate::models::ProxyInfo;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsnRecord {
+    pub asn: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsnTableError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsnTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsnTableError {}
+
+// Addresses as numbers, IPv4 mapped into the IPv6 space so both sort together
+fn address_key(address: IpAddr) -> u128 {
+    match address {
+        IpAddr::V4(v4) => u128::from(v4.to_ipv6_mapped()),
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+/// IP range to autonomous system table supplied by the caller, e.g. an
+/// ip2asn dump embedded with `include_str!`. Used as an [`IspResolver`] it
+/// groups proxies by AS number, falling back to the normalized ISP name for
+/// addresses it does not cover.
+#[derive(Debug, Clone, Default)]
+pub struct AsnDatabase {
+    // (first, last, record) sorted by first address, not overlapping
+    ranges: Vec<(u128, u128, AsnRecord)>,
+}
+
+impl AsnDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the inclusive range `first..=last`. Overlapping ranges are
+    /// resolved to the one starting last.
+    pub fn insert(&mut self, first: IpAddr, last: IpAddr, record: AsnRecord) {
+        let (first, last) = (address_key(first), address_key(last));
+        let position = self.ranges.partition_point(|range| range.0 < first);
+        self.ranges.insert(position, (first, last, record));
+    }
+
+    /// Load `first_ip,last_ip,asn,name` rows. Blank lines and lines starting
+    /// with `#` are skipped, the name may contain commas.
+    pub fn from_csv(data: &str) -> Result<Self, AsnTableError> {
+        let mut database = AsnDatabase::new();
+        for (index, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| AsnTableError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.splitn(4, ',').map(str::trim).collect();
+            let [first, last, asn, name] = fields[..] else {
+                return Err(error("expected 4 fields"));
+            };
+            let first: IpAddr = first.parse().map_err(|_| error("invalid first address"))?;
+            let last: IpAddr = last.parse().map_err(|_| error("invalid last address"))?;
+            let asn = asn.trim_start_matches("AS");
+            let asn: u32 = asn.parse().map_err(|_| error("invalid AS number"))?;
+            database.insert(
+                first,
+                last,
+                AsnRecord {
+                    asn,
+                    name: name.to_string(),
+                },
+            );
+        }
+        Ok(database)
+    }
+
+    pub fn lookup(&self, address: IpAddr) -> Option<&AsnRecord> {
+        let key = address_key(address);
+        let position = self.ranges.partition_point(|range| range.0 <= key);
+        let (_, last, record) = self.ranges[..position].last()?;
+        (key <= *last).then_some(record)
+    }
+
+    /// AS of the proxy's listed IP, None without a known IP.
+    pub fn lookup_proxy(&self, proxy: &ProxyInfo) -> Option<&AsnRecord> {
+        let address = proxy.ip.as_deref()?.trim().parse().ok()?;
+        self.lookup(address)
+    }
+}
+
+impl IspResolver for AsnDatabase {
+    fn isp_key(&self, proxy: &ProxyInfo) -> String {
+        match self.lookup_proxy(proxy) {
+            Some(record) => format!("AS{}", record.asn),
+            None => normalize_isp(&proxy.isp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+
+    #[test]
+    fn test_asn_lookup() {
+        let database = AsnDatabase::from_csv(
+            "# first,last,asn,name\n\
+             203.0.113.0,203.0.113.255,AS64500,Example Net, Inc.\n\
+             2001:db8::,2001:db8::ffff,64501,Example Six\n",
+        )
+        .unwrap();
+        let record = database.lookup("203.0.113.7".parse().unwrap()).unwrap();
+        assert_eq!(record.asn, 64500);
+        assert_eq!(record.name, "Example Net, Inc.");
+        assert_eq!(
+            database.lookup("2001:db8::1".parse().unwrap()).unwrap().asn,
+            64501
+        );
+        assert!(database.lookup("203.0.114.1".parse().unwrap()).is_none());
+
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        assert_eq!(database.isp_key(&proxy), "AS64500");
+        proxy.ip = None;
+        assert_eq!(database.isp_key(&proxy), "example");
+
+        let error = AsnDatabase::from_csv("1.1.1.1,1.1.1.2,x,name").unwrap_err();
+        assert_eq!(error.line, 1);
+    }
+}
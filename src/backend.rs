@@ -0,0 +1,386 @@
+//! The HTTP layer of [`TrueSocksClient`](crate::client::TrueSocksClient),
+//! replaceable through [`HttpBackend`] where reqwest does not fit. With the
+//! `ureq` feature, [`UreqBackend`] sends requests with blocking I/O on their
+//! own threads and needs no async runtime.
+
+use crate::hooks::CommandContext;
+use crate::models::ApiError;
+use crate::status_codes::{TIMEOUT, TRANSPORT};
+use futures::stream::{self, StreamExt};
+use reqwest::header::HeaderValue;
+use reqwest_middleware::ClientWithMiddleware;
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+        }
+    }
+}
+
+/// A request built by the client. It carries the API key in the URL, the
+/// form or a header depending on the key transport, so it is not `Debug`.
+#[derive(Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    // Form parameters of POST requests
+    pub form: Option<Vec<(String, String)>>,
+    // Limit for the whole exchange, body included
+    pub timeout: Duration,
+    pub(crate) context: Option<CommandContext>,
+}
+
+/// Body chunks in the order they arrived.
+#[cfg(not(target_arch = "wasm32"))]
+pub type HttpBody = stream::BoxStream<'static, Result<Vec<u8>, HttpError>>;
+#[cfg(target_arch = "wasm32")]
+pub type HttpBody = stream::LocalBoxStream<'static, Result<Vec<u8>, HttpError>>;
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: HttpBody,
+}
+
+impl HttpResponse {
+    /// A response whose body was read whole.
+    pub fn new(status: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> Self {
+        HttpResponse::streamed(status, headers, Box::pin(stream::once(async { Ok(body) })))
+    }
+
+    pub fn streamed(status: u16, headers: Vec<(String, String)>, body: HttpBody) -> Self {
+        HttpResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Value of the first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>, HttpError> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.body.next().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok(body)
+    }
+
+    // The API answers in UTF-8, anything else is replaced rather than rejected
+    pub async fn text(self) -> Result<String, HttpError> {
+        let body = self.bytes().await?;
+        Ok(String::from_utf8(body)
+            .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HttpError {
+    // The request or the body took longer than `HttpRequest::timeout`
+    Timeout,
+    Transport(String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::Timeout => write!(f, "request timed out"),
+            HttpError::Transport(reason) => write!(f, "request failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<HttpError> for ApiError {
+    fn from(err: HttpError) -> Self {
+        ApiError::from(match err {
+            HttpError::Timeout => TIMEOUT,
+            HttpError::Transport(_) => TRANSPORT,
+        })
+    }
+}
+
+/// Sends the requests of a client. Status retries, rate limits and decoding
+/// stay in the client, a backend only exchanges bytes and may retry transport
+/// failures on its own.
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+pub trait HttpBackend: Send + Sync + 'static {
+    /// Any HTTP status is a response, `Err` is for requests that got none.
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, HttpError>;
+}
+
+/// The default backend: a reqwest client, with the compression, connect
+/// timeout and transport retries set up by
+/// [`TrueSocksClientBuilder`](crate::client::TrueSocksClientBuilder).
+#[derive(Clone)]
+pub struct ReqwestBackend {
+    http: ClientWithMiddleware,
+}
+
+impl ReqwestBackend {
+    pub fn new(http: impl Into<ClientWithMiddleware>) -> Self {
+        ReqwestBackend { http: http.into() }
+    }
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl HttpBackend for ReqwestBackend {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let mut builder = match (&request.method, &request.form) {
+            (Method::Post, Some(form)) => self.http.post(&request.url).form(form),
+            (Method::Post, None) => self.http.post(&request.url),
+            (Method::Get, _) => self.http.get(&request.url),
+        };
+        for (name, value) in &request.headers {
+            // The only header the client adds is the API key
+            let mut value = HeaderValue::from_str(value)
+                .map_err(|err| HttpError::Transport(err.to_string()))?;
+            value.set_sensitive(true);
+            builder = builder.header(name.as_str(), value);
+        }
+        if let Some(context) = request.context {
+            builder = builder.with_extension(context);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let sent = builder.timeout(request.timeout).send().await;
+        // Browsers have no per-request timeout, the wait for the response is raced instead
+        #[cfg(target_arch = "wasm32")]
+        let sent = crate::runtime::timeout(request.timeout, builder.send())
+            .await
+            .ok_or(HttpError::Timeout)?;
+        let response = sent.map_err(|err| match err {
+            reqwest_middleware::Error::Reqwest(err) => reqwest_error(err),
+            err => HttpError::Transport(err.to_string()),
+        })?;
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        Ok(HttpResponse::streamed(
+            response.status().as_u16(),
+            headers,
+            reqwest_body(response),
+        ))
+    }
+}
+
+fn reqwest_error(err: reqwest::Error) -> HttpError {
+    if err.is_timeout() {
+        HttpError::Timeout
+    } else {
+        HttpError::Transport(err.to_string())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn reqwest_body(response: reqwest::Response) -> HttpBody {
+    Box::pin(stream::try_unfold(response, |mut response| async move {
+        let chunk = response.chunk().await.map_err(reqwest_error)?;
+        Ok(chunk.map(|chunk| (chunk.to_vec(), response)))
+    }))
+}
+
+// Browsers only hand over the body whole
+#[cfg(target_arch = "wasm32")]
+fn reqwest_body(response: reqwest::Response) -> HttpBody {
+    Box::pin(stream::once(async move {
+        response
+            .bytes()
+            .await
+            .map(|body| body.to_vec())
+            .map_err(reqwest_error)
+    }))
+}
+
+/// Blocking [`ureq`] requests, each on a thread of its own, so the client
+/// works under any executor, or none.
+#[cfg(feature = "ureq")]
+#[derive(Clone)]
+pub struct UreqBackend {
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "ureq")]
+impl UreqBackend {
+    pub fn new() -> Self {
+        UreqBackend::with_agent(ureq::Agent::new())
+    }
+
+    pub fn with_agent(agent: ureq::Agent) -> Self {
+        UreqBackend { agent }
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl Default for UreqBackend {
+    fn default() -> Self {
+        UreqBackend::new()
+    }
+}
+
+#[cfg(feature = "ureq")]
+#[async_trait::async_trait]
+impl HttpBackend for UreqBackend {
+    async fn send(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+        let agent = self.agent.clone();
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        std::thread::spawn(move || {
+            let _ = sender.send(send_blocking(&agent, request));
+        });
+        receiver
+            .await
+            .unwrap_or_else(|_| Err(HttpError::Transport("request thread panicked".to_string())))
+    }
+}
+
+#[cfg(feature = "ureq")]
+fn send_blocking(agent: &ureq::Agent, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+    use std::io::Read;
+
+    let mut call = agent
+        .request(request.method.as_str(), &request.url)
+        .timeout(request.timeout);
+    for (name, value) in &request.headers {
+        call = call.set(name, value);
+    }
+    let sent = match &request.form {
+        Some(form) => {
+            let form: Vec<(&str, &str)> = form
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect();
+            call.send_form(&form)
+        }
+        None => call.call(),
+    };
+    let response = match sent {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(ureq::Error::Transport(err)) => return Err(ureq_error(&err)),
+    };
+    let status = response.status();
+    let headers = response
+        .headers_names()
+        .into_iter()
+        .filter_map(|name| {
+            let value = response.header(&name)?.to_string();
+            Some((name, value))
+        })
+        .collect();
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut body)
+        .map_err(|err| io_error(&err))?;
+    Ok(HttpResponse::new(status, headers, body))
+}
+
+#[cfg(feature = "ureq")]
+fn ureq_error(err: &ureq::Transport) -> HttpError {
+    match std::error::Error::source(err).and_then(|source| source.downcast_ref()) {
+        Some(err) => io_error(err),
+        None => HttpError::Transport(err.to_string()),
+    }
+}
+
+#[cfg(feature = "ureq")]
+fn io_error(err: &std::io::Error) -> HttpError {
+    match err.kind() {
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => HttpError::Timeout,
+        _ => HttpError::Transport(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::ok_response;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    // Answers every request with the next canned response and records the URLs
+    struct Canned {
+        responses: Mutex<Vec<(u16, serde_json::Value)>>,
+        urls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpBackend for Canned {
+        async fn send(&self, request: HttpRequest) -> Result<HttpResponse, HttpError> {
+            self.urls.lock().unwrap().push(request.url);
+            let (status, body) = self.responses.lock().unwrap().remove(0);
+            let headers = vec![("Retry-After".to_string(), "0".to_string())];
+            Ok(HttpResponse::new(
+                status,
+                headers,
+                body.to_string().into_bytes(),
+            ))
+        }
+    }
+
+    #[derive(Default)]
+    struct Retries(std::sync::atomic::AtomicU32);
+
+    impl crate::hooks::ApiHooks for Arc<Retries> {
+        fn on_retry(&self, _command: &str, attempt: u32) {
+            self.0.store(attempt, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_backend() {
+        let urls = Arc::new(Mutex::new(Vec::new()));
+        let backend = Canned {
+            responses: Mutex::new(vec![(503, json!({})), (200, ok_response(json!(true)))]),
+            urls: urls.clone(),
+        };
+        let retries = Arc::new(Retries::default());
+        let client = TrueSocksClient::builder("test")
+            .http_backend(backend)
+            .hook(retries.clone())
+            .build();
+        assert!(client.ping().await.unwrap());
+        // The 503 went through the client's status retries and hooks
+        assert_eq!(retries.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let urls = urls.lock().unwrap();
+        assert_eq!(urls.len(), 2);
+        assert!(urls[0].contains("cmd=Ping"));
+    }
+
+    #[cfg(feature = "ureq")]
+    #[tokio::test]
+    async fn test_ureq_backend() {
+        let (url, request) = crate::fixtures::serve_once(ok_response(json!(true)));
+        let client = TrueSocksClient::builder("test")
+            .base_url(url)
+            .http_backend(UreqBackend::new())
+            .build();
+        assert!(client.ping().await.unwrap());
+        assert!(request.join().unwrap().contains("cmd=Ping"));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ApiError, ListInfo};
+use futures::stream::{self, StreamExt};
+use log::Level;
+use reqwest::Url;
+use std::time::{Duration, Instant};
+
+// Per-request timeout; a request that takes longer counts as a failure
+pub const BENCHMARK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HEAD latencies of one proxy across all benchmark targets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyBenchmark {
+    pub history_id: u64,
+    pub proxy_id: u32,
+    // Latencies of the requests that got a response, sorted ascending
+    pub samples: Vec<Duration>,
+    // Requests that failed to connect or timed out
+    pub failures: usize,
+}
+
+impl ProxyBenchmark {
+    pub fn attempts(&self) -> usize {
+        self.samples.len() + self.failures
+    }
+
+    /// Nearest-rank percentile of the successful samples, `p` in 0..=100.
+    pub fn percentile(&self, p: u8) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let rank = (usize::from(p.min(100)) * self.samples.len()).div_ceil(100);
+        Some(self.samples[rank.saturating_sub(1)])
+    }
+
+    pub fn p50(&self) -> Option<Duration> {
+        self.percentile(50)
+    }
+
+    pub fn p95(&self) -> Option<Duration> {
+        self.percentile(95)
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        match self.attempts() {
+            0 => 0.0,
+            attempts => self.failures as f64 / attempts as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BenchmarkReport {
+    // One entry per benchmarked proxy, in the order they were passed in
+    pub proxies: Vec<ProxyBenchmark>,
+}
+
+impl BenchmarkReport {
+    /// Proxies with at least one response, by failure rate then p50 latency.
+    pub fn ranked(&self) -> Vec<&ProxyBenchmark> {
+        let mut ranked: Vec<&ProxyBenchmark> = self
+            .proxies
+            .iter()
+            .filter(|proxy| !proxy.samples.is_empty())
+            .collect();
+        ranked.sort_by(|a, b| {
+            a.failure_rate()
+                .total_cmp(&b.failure_rate())
+                .then_with(|| a.p50().cmp(&b.p50()))
+        });
+        ranked
+    }
+
+    pub fn fastest(&self) -> Option<&ProxyBenchmark> {
+        self.ranked().into_iter().next()
+    }
+}
+
+/// Send a HEAD request to every target through every entry, at most
+/// `concurrency` requests at a time. Any HTTP response counts as a success;
+/// entries without connect info are left out.
+pub async fn benchmark_entries(
+    entries: &[ListInfo],
+    targets: &[Url],
+    concurrency: usize,
+) -> BenchmarkReport {
+    let proxies: Vec<(&ListInfo, reqwest::Client)> = entries
+        .iter()
+        .filter_map(|entry| {
+            let client = entry
+                .connect_info
+                .as_ref()?
+                .http_client(BENCHMARK_TIMEOUT)
+                .ok()?;
+            Some((entry, client))
+        })
+        .collect();
+    let requests = proxies
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (entry, client))| {
+            targets
+                .iter()
+                .map(move |target| (index, entry.history_id, client, target))
+        });
+    let results: Vec<(usize, Option<Duration>)> = stream::iter(requests)
+        .map(|(index, history_id, client, target)| async move {
+            let started = Instant::now();
+            match client.head(target.clone()).send().await {
+                Ok(_) => (index, Some(started.elapsed())),
+                Err(err) => {
+                    sublog!(
+                        Subsystem::Health,
+                        Level::Debug,
+                        "benchmark of history entry {} against {} failed: {}",
+                        history_id,
+                        target,
+                        err
+                    );
+                    (index, None)
+                }
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await;
+
+    let mut report = BenchmarkReport {
+        proxies: proxies
+            .iter()
+            .map(|(entry, _)| ProxyBenchmark {
+                history_id: entry.history_id,
+                proxy_id: entry.proxy_info.proxy_id,
+                samples: Vec::new(),
+                failures: 0,
+            })
+            .collect(),
+    };
+    for (index, latency) in results {
+        let proxy = &mut report.proxies[index];
+        match latency {
+            Some(latency) => proxy.samples.push(latency),
+            None => proxy.failures += 1,
+        }
+    }
+    for proxy in &mut report.proxies {
+        proxy.samples.sort();
+    }
+    report
+}
+
+impl TrueSocksClient {
+    /// [`benchmark_entries`] over every active purchase.
+    pub async fn benchmark(
+        &self,
+        targets: &[Url],
+        concurrency: usize,
+    ) -> Result<BenchmarkReport, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::active()).await?;
+        Ok(benchmark_entries(&entries, targets, concurrency).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+    use crate::models::ConnectInfo;
+
+    fn bench(millis: &[u64], failures: usize) -> ProxyBenchmark {
+        ProxyBenchmark {
+            history_id: 1,
+            proxy_id: 1,
+            samples: millis.iter().copied().map(Duration::from_millis).collect(),
+            failures,
+        }
+    }
+
+    #[test]
+    fn test_percentiles() {
+        let proxy = bench(&(1..=20).collect::<Vec<u64>>(), 5);
+        assert_eq!(proxy.p50(), Some(Duration::from_millis(10)));
+        assert_eq!(proxy.p95(), Some(Duration::from_millis(19)));
+        assert_eq!(proxy.percentile(0), Some(Duration::from_millis(1)));
+        assert_eq!(proxy.failure_rate(), 0.2);
+        assert_eq!(bench(&[], 0).p50(), None);
+    }
+
+    #[test]
+    fn test_ranked() {
+        let mut slow = bench(&[300, 400], 0);
+        slow.history_id = 2;
+        let mut flaky = bench(&[50], 1);
+        flaky.history_id = 3;
+        let report = BenchmarkReport {
+            proxies: vec![bench(&[], 2), slow, flaky, bench(&[100], 0)],
+        };
+        let ranked: Vec<u64> = report.ranked().iter().map(|p| p.history_id).collect();
+        assert_eq!(ranked, vec![1, 2, 3]);
+        assert_eq!(
+            report.fastest().unwrap().p50(),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_proxy_fails() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut entry = list_info(7);
+        entry.connect_info = Some(ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: port,
+            connect_session_id: "session".to_string(),
+        });
+        let mut offline = list_info(8);
+        offline.connect_info = None;
+        let targets = [
+            Url::parse("http://a.test/").unwrap(),
+            Url::parse("http://b.test/").unwrap(),
+        ];
+        let report = benchmark_entries(&[entry, offline], &targets, 4).await;
+        assert_eq!(report.proxies.len(), 1);
+        assert_eq!(report.proxies[0].history_id, 7);
+        assert_eq!(report.proxies[0].failures, 2);
+        assert_eq!(report.proxies[0].failure_rate(), 1.0);
+        assert!(report.fastest().is_none());
+    }
+}
@@ -0,0 +1,404 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::io;
+use std::process::ExitCode;
+use truesocks::export::{ExportError, ExportFormat};
+use truesocks::filter::ProxyFilter;
+use truesocks::history::HistoryQuery;
+use truesocks::models::{ApiError, ListInfo, ProxyInfo, PurchaseKind};
+use truesocks::TrueSocksClient;
+
+#[derive(Parser)]
+#[command(
+    name = "truesocks",
+    version,
+    about = "Command line client for the TrueSocks API"
+)]
+struct Cli {
+    /// API key, the client is configured from TRUESOCKS_* variables when not given
+    #[arg(long, global = true)]
+    api_key: Option<String>,
+    /// Print JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Online proxies
+    List {
+        #[arg(long)]
+        country: Option<String>,
+        #[arg(long)]
+        city: Option<String>,
+        #[arg(long)]
+        max_ping: Option<f64>,
+        #[arg(long)]
+        exclude_blacklisted: bool,
+    },
+    /// Online proxies near a zip code
+    Search {
+        country: String,
+        zip: String,
+        /// `mi` or `km`
+        #[arg(long)]
+        units: Option<String>,
+        #[arg(long)]
+        range: Option<u32>,
+    },
+    /// Buy or rent an online proxy by ProxyID
+    Buy {
+        proxy_id: u32,
+        /// Private rental instead of a shared purchase
+        #[arg(long)]
+        private: bool,
+    },
+    /// Test a purchased proxy by HistoryID
+    Check { history_id: u64 },
+    /// Test a purchased proxy by HistoryID and refund it when the tests fail
+    Refund { history_id: u64 },
+    /// Purchase history
+    History {
+        /// Only entries that are still active
+        #[arg(long)]
+        active: bool,
+        /// Only entries whose note contains this text
+        #[arg(long)]
+        note: Option<String>,
+        #[arg(long)]
+        country: Option<String>,
+        /// Only entries tagged with this tag, may be repeated
+        #[arg(long)]
+        tag: Vec<String>,
+    },
+    /// Account status
+    Account,
+    /// Active purchases in a proxy list format
+    Export {
+        #[arg(long, value_enum, default_value_t = Format::Uri)]
+        format: Format,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    HostPort,
+    Uri,
+    Csv,
+    Json,
+}
+
+impl From<Format> for ExportFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::HostPort => ExportFormat::HostPort,
+            Format::Uri => ExportFormat::Socks5Uri,
+            Format::Csv => ExportFormat::Csv,
+            Format::Json => ExportFormat::Json,
+        }
+    }
+}
+
+enum CliError {
+    Usage(String),
+    Api(ApiError),
+    Io(io::Error),
+}
+
+impl From<ApiError> for CliError {
+    fn from(err: ApiError) -> Self {
+        CliError::Api(err)
+    }
+}
+
+impl From<ExportError> for CliError {
+    fn from(err: ExportError) -> Self {
+        match err {
+            ExportError::Api(err) => CliError::Api(err),
+            ExportError::Io(err) => CliError::Io(err),
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(err: io::Error) -> Self {
+        CliError::Io(err)
+    }
+}
+
+// Left aligned columns separated by two spaces
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let print_row = |cells: Vec<String>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<1$}", cell, width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(headers.iter().map(|header| header.to_string()).collect());
+    for row in rows {
+        print_row(row);
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), CliError> {
+    serde_json::to_writer_pretty(io::stdout().lock(), value).map_err(io::Error::from)?;
+    println!();
+    Ok(())
+}
+
+fn print_proxies(proxies: &[ProxyInfo], json: bool) -> Result<(), CliError> {
+    if json {
+        return print_json(&proxies);
+    }
+    let rows = proxies
+        .iter()
+        .map(|proxy| {
+            vec![
+                proxy.proxy_id.to_string(),
+                proxy.country_code.clone(),
+                proxy.city.clone(),
+                proxy.isp.clone(),
+                format!("{:.0}", proxy.ping),
+                proxy.speed.to_string(),
+                proxy.uptime_quality.to_string(),
+                proxy.rent_cost.to_string(),
+                if proxy.is_fresh { "yes" } else { "" }.to_string(),
+                if proxy.is_blacklisted() { "yes" } else { "" }.to_string(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "PROXY",
+            "CC",
+            "CITY",
+            "ISP",
+            "PING",
+            "SPEED",
+            "UPTIME",
+            "COST",
+            "FRESH",
+            "BLACKLIST",
+        ],
+        rows,
+    );
+    Ok(())
+}
+
+fn print_entries(entries: &[ListInfo], json: bool) -> Result<(), CliError> {
+    if json {
+        return print_json(&entries);
+    }
+    let rows = entries
+        .iter()
+        .map(|entry| {
+            let address = entry
+                .connect_info
+                .as_ref()
+                .map(|info| format!("{}:{}", info.connect_ip, info.connect_port))
+                .unwrap_or_default();
+            vec![
+                entry.history_id.to_string(),
+                entry.proxy_info.proxy_id.to_string(),
+                entry.proxy_info.country_code.clone(),
+                entry.proxy_info.city.clone(),
+                address,
+                entry.remaining_time.to_string(),
+                if entry.is_online { "yes" } else { "no" }.to_string(),
+                entry.note.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    print_table(
+        &[
+            "HISTORY",
+            "PROXY",
+            "CC",
+            "CITY",
+            "ADDRESS",
+            "REMAINING",
+            "ONLINE",
+            "NOTE",
+        ],
+        rows,
+    );
+    Ok(())
+}
+
+async fn history_entry(client: &TrueSocksClient, history_id: u64) -> Result<ListInfo, CliError> {
+    client
+        .get_history_entry(history_id)
+        .await?
+        .ok_or_else(|| CliError::Usage(format!("no history entry {}", history_id)))
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    let client = match cli.api_key {
+        Some(api_key) => TrueSocksClient::new(api_key),
+        None => TrueSocksClient::from_env().map_err(|err| {
+            CliError::Usage(format!("{}, pass --api-key or set TRUESOCKS_API_KEY", err))
+        })?,
+    };
+    let json = cli.json;
+
+    match cli.command {
+        Command::List {
+            country,
+            city,
+            max_ping,
+            exclude_blacklisted,
+        } => {
+            let mut filter = ProxyFilter::new();
+            if let Some(country) = country {
+                filter = filter.country(country);
+            }
+            if let Some(city) = city {
+                filter = filter.city(city);
+            }
+            if let Some(max_ping) = max_ping {
+                filter = filter.max_ping(max_ping);
+            }
+            if exclude_blacklisted {
+                filter = filter.exclude_blacklisted();
+            }
+            let online = client.list_online_proxies().await?;
+            let proxies: Vec<ProxyInfo> = online
+                .proxy_list
+                .into_iter()
+                .filter(|proxy| filter.matches(proxy))
+                .collect();
+            print_proxies(&proxies, json)
+        }
+        Command::Search {
+            country,
+            zip,
+            units,
+            range,
+        } => {
+            let result = client
+                .list_zip_search(&country, &zip, units.as_deref(), range)
+                .await?;
+            print_proxies(&result.proxy_list, json)
+        }
+        Command::Buy { proxy_id, private } => {
+            let online = client.list_online_proxies().await?;
+            let proxy = online
+                .proxy_list
+                .into_iter()
+                .find(|proxy| proxy.proxy_id == proxy_id)
+                .ok_or_else(|| CliError::Usage(format!("proxy {} is not online", proxy_id)))?;
+            let kind = if private {
+                PurchaseKind::PrivateRent
+            } else {
+                PurchaseKind::SharedBuy
+            };
+            let result = client.purchase(&proxy, kind).await?;
+            if json {
+                return print_json(&result);
+            }
+            let entries: Vec<ListInfo> = result.history_entry.into_iter().collect();
+            print_entries(&entries, false)?;
+            if let Some(credits) = result.credits_left {
+                println!("credits left: {}", credits);
+            }
+            Ok(())
+        }
+        Command::Check { history_id } => {
+            let entry = history_entry(&client, history_id).await?;
+            let result = client.check_purchased_proxy(&entry.proxy_info).await?;
+            if json {
+                return print_json(&result);
+            }
+            println!(
+                "{}/{} tests passed: {}",
+                result.tests_passed, result.tests_total, result.test_result_long
+            );
+            Ok(())
+        }
+        Command::Refund { history_id } => {
+            let entry = history_entry(&client, history_id).await?;
+            let result = client.refund_purchased_proxy(&entry.proxy_info).await?;
+            if json {
+                return print_json(&result);
+            }
+            println!(
+                "{}/{} tests passed: {}",
+                result.tests_passed, result.tests_total, result.test_result_long
+            );
+            println!("{}", result.refund_result_long);
+            Ok(())
+        }
+        Command::History {
+            active,
+            note,
+            country,
+            tag,
+        } => {
+            let mut query = HistoryQuery::new().active_only(active);
+            if let Some(note) = note {
+                query = query.note_contains(note);
+            }
+            if let Some(country) = country {
+                query = query.country(country);
+            }
+            for tag in tag {
+                query = query.tag(tag);
+            }
+            let entries = client.list_all_history(&query).await?;
+            print_entries(&entries, json)
+        }
+        Command::Account => {
+            let account = client.get_account_status().await?;
+            if json {
+                return print_json(&account);
+            }
+            print_table(
+                &["EMAIL", "PLAN", "ACTIVE", "EXPIRES", "CREDITS"],
+                vec![vec![
+                    account.email,
+                    account.plan,
+                    account.active.to_string(),
+                    account.expires.to_string(),
+                    account.credits.to_string(),
+                ]],
+            );
+            Ok(())
+        }
+        Command::Export { format } => {
+            let format = if json { Format::Json } else { format };
+            client
+                .export_history(io::stdout().lock(), &HistoryQuery::active(), format.into())
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CliError::Usage(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::from(2)
+        }
+        Err(CliError::Api(err)) => {
+            eprintln!("error: API error {}: {:?}", err.code(), err);
+            ExitCode::FAILURE
+        }
+        Err(CliError::Io(err)) => {
+            eprintln!("error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::models::{ApiError, BlacklistInfo, BlacklistType, ListInfo, ProxyInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+// Used by the background task only, which is not built for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+use {std::time::Duration, tokio::sync::broadcast, tokio::task::JoinHandle};
+
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_CAPACITY: usize = 16;
+
+/// How many blacklist listings of each category a proxy may have. Categories
+/// without their own limit fall back to `default_max`, no limit when unset.
+///
+/// `BlacklistPolicy::new().reject(BlacklistType::EmailSpam).tolerate(BlacklistType::OpenProxy, 1)`
+/// turns down any spam listing but accepts a single open proxy listing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlacklistPolicy {
+    // Category with the most listings of it tolerated
+    #[serde(default)]
+    pub max_per_type: Vec<(BlacklistType, usize)>,
+    #[serde(default)]
+    pub default_max: Option<usize>,
+    // Most listings tolerated over all categories
+    #[serde(default)]
+    pub max_total: Option<usize>,
+}
+
+impl BlacklistPolicy {
+    /// Policy tolerating any listing until limits are added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Policy rejecting every listing, like `ProxyFilter::exclude_blacklisted`.
+    pub fn strict() -> Self {
+        Self::new().default_max(0)
+    }
+
+    pub fn tolerate(mut self, blacklist_type: BlacklistType, max: usize) -> Self {
+        self.max_per_type
+            .retain(|(existing, _)| *existing != blacklist_type);
+        self.max_per_type.push((blacklist_type, max));
+        self
+    }
+
+    pub fn reject(self, blacklist_type: BlacklistType) -> Self {
+        self.tolerate(blacklist_type, 0)
+    }
+
+    pub fn default_max(mut self, max: usize) -> Self {
+        self.default_max = Some(max);
+        self
+    }
+
+    pub fn max_total(mut self, max: usize) -> Self {
+        self.max_total = Some(max);
+        self
+    }
+
+    fn limit(&self, blacklist_type: &BlacklistType) -> Option<usize> {
+        self.max_per_type
+            .iter()
+            .find(|(limited, _)| limited == blacklist_type)
+            .map(|(_, max)| *max)
+            .or(self.default_max)
+    }
+
+    /// Categories whose listings exceed their limit, in order of first
+    /// listing. Empty when the proxy is acceptable on a per-category basis.
+    pub fn violations(&self, listings: &[BlacklistInfo]) -> Vec<BlacklistType> {
+        let mut violations: Vec<BlacklistType> = Vec::new();
+        for listing in listings {
+            let blacklist_type = &listing.blacklist_type;
+            if violations.contains(blacklist_type) {
+                continue;
+            }
+            let count = listings
+                .iter()
+                .filter(|other| other.blacklist_type == *blacklist_type)
+                .count();
+            if self.limit(blacklist_type).is_some_and(|max| count > max) {
+                violations.push(blacklist_type.clone());
+            }
+        }
+        violations
+    }
+
+    pub fn allows_listings(&self, listings: &[BlacklistInfo]) -> bool {
+        self.max_total.is_none_or(|max| listings.len() <= max)
+            && self.violations(listings).is_empty()
+    }
+
+    pub fn allows(&self, proxy: &ProxyInfo) -> bool {
+        self.allows_listings(proxy.blacklist.as_deref().unwrap_or_default())
+    }
+
+    pub(crate) fn describe(&self) -> String {
+        let mut limits: Vec<String> = self
+            .max_per_type
+            .iter()
+            .map(|(blacklist_type, max)| format!("{}:{}", blacklist_type, max))
+            .collect();
+        if let Some(max) = self.default_max {
+            limits.push(format!("*:{}", max));
+        }
+        if let Some(max) = self.max_total {
+            limits.push(format!("total:{}", max));
+        }
+        limits.join("|")
+    }
+}
+
+/// Where the blacklist of an owned proxy was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlacklistSource {
+    // The proxy is still listed by `ListOnline`, whose data is the freshest
+    Online,
+    // Only the history entry carries it
+    History,
+}
+
+#[derive(Debug, Clone)]
+pub struct BlacklistStatus {
+    pub entry: ListInfo,
+    pub listings: Vec<BlacklistInfo>,
+    pub source: BlacklistSource,
+}
+
+impl BlacklistStatus {
+    pub fn is_blacklisted(&self) -> bool {
+        !self.listings.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum BlacklistEvent {
+    // A clean proxy got listed, or a new entry was found listed
+    Blacklisted {
+        entry: Box<ListInfo>,
+        listings: Vec<BlacklistInfo>,
+    },
+    // A listed proxy is clean again
+    Cleared {
+        entry: Box<ListInfo>,
+    },
+    PollFailed(ApiError),
+}
+
+/// Turns successive [`BlacklistStatus`] snapshots into events. The first
+/// snapshot is the baseline and yields none.
+#[derive(Debug, Default)]
+pub struct BlacklistTracker {
+    // Whether each known entry was listed, by HistoryID
+    listed: HashMap<u64, bool>,
+    initialized: bool,
+}
+
+impl BlacklistTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, statuses: &[BlacklistStatus]) -> Vec<BlacklistEvent> {
+        let mut events = Vec::new();
+        let mut listed = HashMap::new();
+        for status in statuses {
+            let history_id = status.entry.history_id;
+            let was_listed = self.listed.get(&history_id).copied();
+            let is_listed = status.is_blacklisted();
+            if self.initialized && is_listed && was_listed != Some(true) {
+                events.push(BlacklistEvent::Blacklisted {
+                    entry: Box::new(status.entry.clone()),
+                    listings: status.listings.clone(),
+                });
+            }
+            if !is_listed && was_listed == Some(true) {
+                events.push(BlacklistEvent::Cleared {
+                    entry: Box::new(status.entry.clone()),
+                });
+            }
+            listed.insert(history_id, is_listed);
+        }
+        self.listed = listed;
+        self.initialized = true;
+        events
+    }
+}
+
+impl TrueSocksClient {
+    /// Current blacklist of every active entry, read from a fresh `ListOnline`
+    /// snapshot for proxies still listed there and from the history otherwise.
+    pub async fn recheck_blacklists(&self) -> Result<Vec<BlacklistStatus>, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::active()).await?;
+        let online = self.list_online_proxies().await?;
+        let listed: HashMap<u32, &[BlacklistInfo]> = online
+            .proxy_list
+            .iter()
+            .map(|proxy| {
+                (
+                    proxy.proxy_id,
+                    proxy.blacklist.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect();
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let (listings, source) = match listed.get(&entry.proxy_info.proxy_id) {
+                    Some(listings) => (listings.to_vec(), BlacklistSource::Online),
+                    None => (
+                        entry.proxy_info.blacklist.clone().unwrap_or_default(),
+                        BlacklistSource::History,
+                    ),
+                };
+                BlacklistStatus {
+                    entry,
+                    listings,
+                    source,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Background task running [`TrueSocksClient::recheck_blacklists`] every
+/// `interval` and sending an event when an owned proxy gets blacklisted or
+/// cleared. The task stops when the monitor is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct BlacklistMonitor {
+    events: broadcast::Sender<BlacklistEvent>,
+    handle: JoinHandle<()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BlacklistMonitor {
+    pub fn spawn(client: TrueSocksClient, interval: Duration) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let sender = events.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut tracker = BlacklistTracker::new();
+            loop {
+                ticker.tick().await;
+                let events = match client.recheck_blacklists().await {
+                    Ok(statuses) => tracker.update(&statuses),
+                    Err(err) => vec![BlacklistEvent::PollFailed(err)],
+                };
+                for event in events {
+                    let _ = sender.send(event);
+                }
+            }
+        });
+        BlacklistMonitor { events, handle }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<BlacklistEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for BlacklistMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_page, list_info_json, ok_response, proxy_info_json, serve};
+    use serde_json::json;
+
+    fn listing() -> serde_json::Value {
+        json!([{"ID": "sbl", "Name": "SBL", "Type": "Email Spam", "Desc": "", "Link": ""}])
+    }
+
+    fn listed(types: &[&str]) -> Vec<BlacklistInfo> {
+        types
+            .iter()
+            .enumerate()
+            .map(|(index, blacklist_type)| {
+                serde_json::from_value(json!({
+                    "ID": index.to_string(),
+                    "Name": "list",
+                    "Type": blacklist_type,
+                    "Desc": "",
+                    "Link": ""
+                }))
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_blacklist_policy() {
+        let policy = BlacklistPolicy::new()
+            .reject(BlacklistType::EmailSpam)
+            .tolerate(BlacklistType::OpenProxy, 1);
+        assert!(policy.allows_listings(&[]));
+        assert!(policy.allows_listings(&listed(&["Open Proxy", "Web Abuse", "Web Abuse"])));
+        assert!(!policy.allows_listings(&listed(&["Open Proxy", "Open Proxy"])));
+        assert_eq!(
+            policy.violations(&listed(&["Email Spam", "Open Proxy"])),
+            vec![BlacklistType::EmailSpam]
+        );
+        assert!(!policy.clone().max_total(2).allows_listings(&listed(&[
+            "Web Abuse",
+            "Web Abuse",
+            "Open Proxy"
+        ])));
+        assert!(!BlacklistPolicy::strict().allows_listings(&listed(&["Botnet"])));
+        assert_eq!(policy.describe(), "Email Spam:0|Open Proxy:1");
+    }
+
+    #[tokio::test]
+    async fn test_recheck_blacklists() {
+        let mut listed = proxy_info_json(2);
+        listed["Blacklist"] = listing();
+        let mut owned_only = list_info_json(12, 3);
+        owned_only["ProxyInfo"]["Blacklist"] = listing();
+        let (url, _requests) = serve(vec![
+            ok_response(history_page(
+                vec![list_info_json(10, 1), list_info_json(11, 2), owned_only],
+                1,
+                1,
+            )),
+            ok_response(json!({
+                "LastUpdate": 1,
+                "ProxyCount": 2,
+                "ProxyList": [proxy_info_json(1), listed]
+            })),
+        ]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let statuses = client.recheck_blacklists().await.unwrap();
+        let summary: Vec<_> = statuses
+            .iter()
+            .map(|status| {
+                (
+                    status.entry.history_id,
+                    status.is_blacklisted(),
+                    status.source,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (10, false, BlacklistSource::Online),
+                (11, true, BlacklistSource::Online),
+                (12, true, BlacklistSource::History),
+            ]
+        );
+
+        let mut tracker = BlacklistTracker::new();
+        let mut clean = statuses.clone();
+        for status in &mut clean {
+            status.listings.clear();
+        }
+        assert!(tracker.update(&clean).is_empty());
+        let events = tracker.update(&statuses);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[0],
+            BlacklistEvent::Blacklisted { entry, listings } if entry.history_id == 11 && listings[0].id == "sbl"
+        ));
+        assert!(tracker.update(&statuses).is_empty());
+        assert!(matches!(
+            &tracker.update(&clean)[..],
+            [
+                BlacklistEvent::Cleared { .. },
+                BlacklistEvent::Cleared { .. }
+            ]
+        ));
+    }
+}
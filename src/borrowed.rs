@@ -0,0 +1,215 @@
+use crate::client::{decode_body, TrueSocksClient};
+use crate::credits::Credits;
+use crate::models::{lenient, lenient_bool, ApiError, ConnectionType};
+use serde::de::{self, Deserializer, IgnoredAny, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fmt;
+
+const COMMAND: &str = "ListOnline";
+
+/// A `ProxyList` record borrowing its strings from the response body, for
+/// code that only inspects proxies and drops them. Strings containing JSON
+/// escapes are the only ones allocated. Blacklist details and unknown fields
+/// are skipped, use [`ProxyInfo`](crate::models::ProxyInfo) to keep them.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProxyInfoRef<'a> {
+    #[serde(rename = "ProxyID", deserialize_with = "lenient")]
+    pub proxy_id: u32,
+    #[serde(rename = "CostBuy", deserialize_with = "lenient")]
+    pub rent_cost: Credits,
+    #[serde(rename = "CostRent", deserialize_with = "lenient")]
+    pub private_rent_cost: Credits,
+    #[serde(rename = "IsFresh", deserialize_with = "lenient_bool")]
+    pub is_fresh: bool,
+    #[serde(rename = "IP", borrow, deserialize_with = "borrowed_ip")]
+    pub ip: Option<Cow<'a, str>>,
+    #[serde(rename = "Hostname", borrow)]
+    pub hostname: Cow<'a, str>,
+    #[serde(rename = "ISP", borrow)]
+    pub isp: Cow<'a, str>,
+    #[serde(rename = "CountryCode", borrow)]
+    pub country_code: Cow<'a, str>,
+    #[serde(rename = "Country", borrow)]
+    pub country: Cow<'a, str>,
+    #[serde(rename = "Region", borrow)]
+    pub region: Cow<'a, str>,
+    #[serde(rename = "City", borrow)]
+    pub city: Cow<'a, str>,
+    // None when the API sends "-"
+    #[serde(rename = "ZipCode", borrow, deserialize_with = "borrowed_zip_code")]
+    pub zip_code: Option<Cow<'a, str>>,
+    #[serde(rename = "Timezone", borrow)]
+    pub timezone: Cow<'a, str>,
+    // Connection type as sent, see `connection_type()`
+    #[serde(rename = "Connect", borrow)]
+    pub connect: Cow<'a, str>,
+    #[serde(rename = "Ping", deserialize_with = "lenient")]
+    pub ping: f64,
+    #[serde(rename = "Speed", deserialize_with = "lenient")]
+    pub speed: u32,
+    #[serde(rename = "UpTimeQuality", deserialize_with = "lenient")]
+    pub uptime_quality: u32,
+    // Whether the proxy is on at least one blacklist
+    #[serde(rename = "Blacklist", deserialize_with = "listed")]
+    pub blacklisted: bool,
+    #[serde(rename = "Distance", default, deserialize_with = "lenient")]
+    pub distance: Option<f64>,
+}
+
+impl ProxyInfoRef<'_> {
+    pub fn connection_type(&self) -> ConnectionType {
+        ConnectionType::from(self.connect.to_string())
+    }
+}
+
+/// `ListOnline` result borrowing from a [`ListOnlineBody`]. Records are in
+/// the order sent by the API, duplicates included.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ListOnlineRef<'a> {
+    #[serde(rename = "LastUpdate", deserialize_with = "lenient")]
+    pub last_update: u64,
+    #[serde(rename = "ProxyCount", deserialize_with = "lenient")]
+    pub proxy_count: u32,
+    #[serde(rename = "ProxyList", borrow)]
+    pub proxy_list: Vec<ProxyInfoRef<'a>>,
+}
+
+/// The raw body of an accepted `ListOnline` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListOnlineBody {
+    body: String,
+}
+
+impl ListOnlineBody {
+    /// Decode the result, borrowing strings from this body.
+    pub fn parse(&self) -> Result<ListOnlineRef<'_>, ApiError> {
+        #[derive(Deserialize)]
+        struct Envelope<'a> {
+            #[serde(borrow)]
+            result: ListOnlineRef<'a>,
+        }
+        decode_body::<Envelope>(COMMAND, &self.body).map(|envelope| envelope.result)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.body
+    }
+}
+
+impl TrueSocksClient {
+    /// `ListOnline` kept as text, to be decoded into borrowed records with
+    /// [`ListOnlineBody::parse`].
+    pub async fn list_online_borrowed(&self) -> Result<ListOnlineBody, ApiError> {
+        let body = self.execute_text(COMMAND, None).await?;
+        Ok(ListOnlineBody { body })
+    }
+}
+
+fn borrowed_ip<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IpField<'a> {
+        Missing(bool),
+        Ip(#[serde(borrow)] Cow<'a, str>),
+    }
+
+    match IpField::deserialize(deserializer)? {
+        IpField::Missing(false) => Ok(None),
+        IpField::Missing(true) => Err(de::Error::invalid_value(
+            de::Unexpected::Bool(true),
+            &"an IP or false",
+        )),
+        IpField::Ip(ip) => Ok(Some(ip)),
+    }
+}
+
+fn borrowed_zip_code<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let zip_code: Cow<'a, str> = Deserialize::deserialize(deserializer)?;
+    Ok(Some(zip_code).filter(|zip_code| zip_code != "-"))
+}
+
+// `false` or a list of blacklist records, only checked for being non-empty
+fn listed<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Listed;
+
+    impl<'de> Visitor<'de> for Listed {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("false or a list of blacklist records")
+        }
+
+        fn visit_bool<E: de::Error>(self, _: bool) -> Result<bool, E> {
+            Ok(false)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<bool, A::Error> {
+            let mut listed = false;
+            while seq.next_element::<IgnoredAny>()?.is_some() {
+                listed = true;
+            }
+            Ok(listed)
+        }
+    }
+
+    deserializer.deserialize_any(Listed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ok_response, proxy_info_json, serve};
+    use crate::models::ListOnlineResult;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_list_online_borrowed() {
+        let mut escaped = proxy_info_json(2);
+        escaped["City"] = json!("S\u{e3}o \"Paulo\"");
+        escaped["IP"] = json!(false);
+        escaped["ZipCode"] = json!("-");
+        escaped["Blacklist"] =
+            json!([{"ID": "1", "Name": "list", "Type": "WebAbuse", "Desc": "", "Link": ""}]);
+        escaped["Speed"] = json!("512");
+        let body = ok_response(json!({
+            "LastUpdate": 3,
+            "ProxyCount": 2,
+            "ProxyList": [proxy_info_json(1), escaped],
+        }));
+        let (url, _) = serve(vec![body.clone()]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+
+        let body = client.list_online_borrowed().await.unwrap();
+        let list = body.parse().unwrap();
+        assert_eq!(list.last_update, 3);
+        let plain = &list.proxy_list[0];
+        assert!(matches!(plain.isp, Cow::Borrowed("Example ISP")));
+        assert_eq!(plain.ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(plain.connection_type(), ConnectionType::DSL);
+        assert!(!plain.blacklisted);
+
+        let escaped = &list.proxy_list[1];
+        assert_eq!(escaped.city, "S\u{e3}o \"Paulo\"");
+        assert!(matches!(escaped.city, Cow::Owned(_)));
+        assert_eq!(escaped.ip, None);
+        assert_eq!(escaped.zip_code, None);
+        assert!(escaped.blacklisted);
+        assert_eq!(escaped.speed, 512);
+
+        // Same records as the owned model
+        let owned: ListOnlineResult = serde_json::from_str::<serde_json::Value>(body.as_str())
+            .map(|value| serde_json::from_value(value["result"].clone()).unwrap())
+            .unwrap();
+        assert_eq!(owned.proxy_list[1].city, escaped.city);
+    }
+}
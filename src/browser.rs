@@ -0,0 +1,72 @@
+use crate::models::ConnectInfo;
+use serde::{Deserialize, Serialize};
+
+/// The `proxy` option of Playwright's `launch` / `newContext`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaywrightProxy {
+    pub server: String,
+    // Comma separated hosts that skip the proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bypass: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+impl ConnectInfo {
+    fn socks5_server(&self) -> String {
+        format!("socks5://{}:{}", self.connect_ip, self.connect_port)
+    }
+
+    /// Chromium flags routing all traffic, DNS included, through this proxy.
+    ///
+    /// Chromium cannot authenticate to SOCKS proxies, so the proxy needs to
+    /// accept the client without credentials (e.g. IP authorization).
+    pub fn to_chromium_args(&self) -> Vec<String> {
+        vec![
+            format!("--proxy-server={}", self.socks5_server()),
+            // Keep the browser from resolving hosts locally
+            format!(
+                "--host-resolver-rules=MAP * ~NOTFOUND , EXCLUDE {}",
+                self.connect_ip
+            ),
+        ]
+    }
+
+    pub fn to_playwright_proxy(&self) -> PlaywrightProxy {
+        let credentials = self.credentials();
+        PlaywrightProxy {
+            server: self.socks5_server(),
+            bypass: None,
+            username: credentials.map(|(username, _)| username.to_string()),
+            password: credentials.map(|(_, password)| password.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::list_info;
+    use serde_json::json;
+
+    #[test]
+    fn test_browser_helpers() {
+        let connect_info = list_info(3).connect_info.unwrap();
+        assert_eq!(
+            connect_info.to_chromium_args(),
+            vec![
+                "--proxy-server=socks5://198.51.100.1:20003",
+                "--host-resolver-rules=MAP * ~NOTFOUND , EXCLUDE 198.51.100.1",
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(connect_info.to_playwright_proxy()).unwrap(),
+            json!({
+                "server": "socks5://198.51.100.1:20003",
+                "username": "session3",
+                "password": "session3"
+            })
+        );
+    }
+}
@@ -0,0 +1,317 @@
+use crate::client::TrueSocksClient;
+use crate::credits::Credits;
+use crate::models::{ApiError, ProxyInfo, PurchaseKind, PurchaseResult};
+use futures::stream::{self, StreamExt};
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+// Number of purchase commands in flight at once during a bulk purchase
+const PURCHASE_CONCURRENCY: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    // The purchase would exceed `max_credits`
+    BudgetExceeded,
+    // The purchase would exceed the account balance
+    InsufficientBalance,
+    // The proxy does not offer the requested purchase kind
+    NotOffered,
+    // Cancelled before the purchase was sent
+    Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub enum BulkPurchaseStatus {
+    Purchased(Box<PurchaseResult>),
+    Failed(ApiError),
+    Skipped(SkipReason),
+}
+
+#[derive(Debug, Clone)]
+pub struct BulkPurchaseItem {
+    pub proxy_id: u32,
+    pub status: BulkPurchaseStatus,
+}
+
+#[derive(Debug, Clone)]
+pub struct BulkPurchaseReport {
+    // One entry per requested proxy, in the order they were passed in
+    pub items: Vec<BulkPurchaseItem>,
+    pub spent: Credits,
+}
+
+impl BulkPurchaseReport {
+    pub fn purchased(&self) -> impl Iterator<Item = &BulkPurchaseItem> {
+        self.items
+            .iter()
+            .filter(|item| matches!(item.status, BulkPurchaseStatus::Purchased(_)))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.purchased().count() == self.items.len()
+    }
+}
+
+struct BudgetState {
+    budget: Credits,
+    balance: Credits,
+}
+
+impl TrueSocksClient {
+    /// Purchase a batch of proxies concurrently without spending more than
+    /// `max_credits` or the current account balance.
+    ///
+    /// Credits are reserved in input order and a proxy that does not fit in what
+    /// is left is skipped. Purchases rejected by the API release their
+    /// reservation, so proxies reserved after them can still use those credits.
+    /// Purchases failing with a timeout, transport or HTTP error keep it, as they
+    /// may have been charged.
+    pub async fn purchase_many(
+        &self,
+        proxies: &[&ProxyInfo],
+        kind: PurchaseKind,
+        max_credits: Credits,
+    ) -> Result<BulkPurchaseReport, ApiError> {
+        self.purchase_batch(proxies, kind, max_credits, None).await
+    }
+
+    /// Like [`purchase_many`](Self::purchase_many), but no purchase is sent once
+    /// `cancel` is triggered; the remaining proxies are skipped as cancelled.
+    /// Purchases already sent are awaited so none of them goes unreported.
+    pub async fn purchase_many_cancellable(
+        &self,
+        proxies: &[&ProxyInfo],
+        kind: PurchaseKind,
+        max_credits: Credits,
+        cancel: &CancellationToken,
+    ) -> Result<BulkPurchaseReport, ApiError> {
+        self.purchase_batch(proxies, kind, max_credits, Some(cancel))
+            .await
+    }
+
+    async fn purchase_batch(
+        &self,
+        proxies: &[&ProxyInfo],
+        kind: PurchaseKind,
+        max_credits: Credits,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<BulkPurchaseReport, ApiError> {
+        let account = self.get_account_status().await?;
+        let state = Mutex::new(BudgetState {
+            budget: max_credits,
+            balance: account.credits,
+        });
+
+        let mut results: Vec<(usize, BulkPurchaseItem, Credits)> =
+            stream::iter(proxies.iter().enumerate())
+                .map(|(index, proxy)| {
+                    let state = &state;
+                    async move {
+                        let (status, spent) = self
+                            .purchase_within_budget(proxy, kind, state, cancel)
+                            .await;
+                        let item = BulkPurchaseItem {
+                            proxy_id: proxy.proxy_id,
+                            status,
+                        };
+                        (index, item, spent)
+                    }
+                })
+                .buffer_unordered(PURCHASE_CONCURRENCY)
+                .collect()
+                .await;
+        results.sort_by_key(|(index, _, _)| *index);
+
+        Ok(BulkPurchaseReport {
+            spent: results.iter().map(|(_, _, spent)| *spent).sum(),
+            items: results.into_iter().map(|(_, item, _)| item).collect(),
+        })
+    }
+
+    async fn purchase_within_budget(
+        &self,
+        proxy: &ProxyInfo,
+        kind: PurchaseKind,
+        state: &Mutex<BudgetState>,
+        cancel: Option<&CancellationToken>,
+    ) -> (BulkPurchaseStatus, Credits) {
+        if cancel.is_some_and(CancellationToken::is_cancelled) {
+            return (
+                BulkPurchaseStatus::Skipped(SkipReason::Cancelled),
+                Credits::ZERO,
+            );
+        }
+        let cost = match proxy.cost(kind) {
+            Some(cost) => cost,
+            None => {
+                return (
+                    BulkPurchaseStatus::Skipped(SkipReason::NotOffered),
+                    Credits::ZERO,
+                )
+            }
+        };
+
+        {
+            let mut state = state.lock().unwrap();
+            let reason = if cost > state.budget {
+                Some(SkipReason::BudgetExceeded)
+            } else if cost > state.balance {
+                Some(SkipReason::InsufficientBalance)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                return (BulkPurchaseStatus::Skipped(reason), Credits::ZERO);
+            }
+            state.budget -= cost;
+            state.balance -= cost;
+        }
+
+        match self.purchase(proxy, kind).await {
+            Ok(result) => (BulkPurchaseStatus::Purchased(Box::new(result)), cost),
+            Err(err) => {
+                if err.is_definite_rejection() {
+                    let mut state = state.lock().unwrap();
+                    state.budget += cost;
+                    state.balance += cost;
+                }
+                (BulkPurchaseStatus::Failed(err), Credits::ZERO)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        account_status, ok_response, proxy_info_json, serve, serve_once, serve_with_status,
+    };
+    use serde_json::{json, Value};
+
+    fn account(credits: u32) -> Value {
+        ok_response(serde_json::to_value(account_status(credits)).unwrap())
+    }
+
+    fn skipped(report: &BulkPurchaseReport) -> Vec<SkipReason> {
+        report
+            .items
+            .iter()
+            .filter_map(|item| match item.status {
+                BulkPurchaseStatus::Skipped(reason) => Some(reason),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_budget_and_balance_limits() {
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        let bought = ok_response(json!({ "CreditsLeft": 0 }));
+
+        let (url, _) = serve(vec![account(100), bought.clone()]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let report = client
+            .purchase_many(
+                &[&proxy, &proxy],
+                PurchaseKind::SharedBuy,
+                Credits::from(15),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.spent, Credits::from(10));
+        assert_eq!(report.purchased().count(), 1);
+        assert_eq!(skipped(&report), vec![SkipReason::BudgetExceeded]);
+
+        let (url, _) = serve(vec![account(15), bought]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let report = client
+            .purchase_many(
+                &[&proxy, &proxy],
+                PurchaseKind::SharedBuy,
+                Credits::from(100),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.spent, Credits::from(10));
+        assert_eq!(skipped(&report), vec![SkipReason::InsufficientBalance]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_purchases_release_credits() {
+        // The first four purchases take the whole balance and fail, the fifth
+        // is only reserved once one of them has released its credits
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        let failed = json!({"status": {"code": 3, "message": "Proxy offline"}, "result": null});
+        let mut responses = vec![account(40)];
+        responses.extend(vec![failed; PURCHASE_CONCURRENCY]);
+        responses.push(ok_response(json!({ "CreditsLeft": 30 })));
+        let (url, server) = serve(responses);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+
+        let proxies = vec![&proxy; PURCHASE_CONCURRENCY + 1];
+        let report = client
+            .purchase_many(&proxies, PurchaseKind::SharedBuy, Credits::from(100))
+            .await
+            .unwrap();
+        assert_eq!(report.spent, Credits::from(10));
+        assert!(skipped(&report).is_empty());
+        assert!(matches!(
+            report.items[PURCHASE_CONCURRENCY].status,
+            BulkPurchaseStatus::Purchased(_)
+        ));
+        assert_eq!(server.join().unwrap().len(), PURCHASE_CONCURRENCY + 2);
+    }
+
+    #[tokio::test]
+    async fn test_ambiguous_failures_keep_credits_reserved() {
+        // A 502 may come after the API charged the purchase, so the fifth proxy
+        // must not be bought with credits the failed ones may have spent
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        let mut responses = vec![("200 OK", account(100))];
+        responses.extend(vec![("502 Bad Gateway", json!({})); PURCHASE_CONCURRENCY]);
+        let (url, server) = serve_with_status(responses);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+
+        let proxies = vec![&proxy; PURCHASE_CONCURRENCY + 1];
+        let report = client
+            .purchase_many(&proxies, PurchaseKind::SharedBuy, Credits::from(40))
+            .await
+            .unwrap();
+        assert_eq!(report.spent, Credits::ZERO);
+        assert_eq!(skipped(&report), vec![SkipReason::BudgetExceeded]);
+        assert!(report.items[..PURCHASE_CONCURRENCY]
+            .iter()
+            .all(|item| matches!(
+                item.status,
+                BulkPurchaseStatus::Failed(ApiError::StatusError(502))
+            )));
+        assert_eq!(server.join().unwrap().len(), PURCHASE_CONCURRENCY + 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_purchases_are_skipped() {
+        let (url, _) = serve_once(ok_response(
+            serde_json::to_value(account_status(100)).unwrap(),
+        ));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let report = client
+            .purchase_many_cancellable(
+                &[&proxy, &proxy],
+                PurchaseKind::SharedBuy,
+                Credits::from(100),
+                &cancel,
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.spent, Credits::ZERO);
+        assert!(report.items.iter().all(|item| matches!(
+            item.status,
+            BulkPurchaseStatus::Skipped(SkipReason::Cancelled)
+        )));
+    }
+}
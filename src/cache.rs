@@ -0,0 +1,385 @@
+use crate::client::TrueSocksClient;
+use crate::filter::ProxyFilter;
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ApiError, ListOnlineResult, ProxyInfo, PurchaseKind, PurchaseResult};
+use crate::runtime::{spawn, Instant};
+use crate::score::ProxyScorer;
+use crate::state::{read_state, write_state, SavedOnlineList};
+use crate::status_codes::NOT_FOUND;
+use crate::unix_now;
+use log::Level;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+type Entry = Arc<Mutex<Option<(Instant, Arc<ListOnlineResult>)>>>;
+
+/// Proxies of a cached list matching a filter, cheapest first. Equal costs
+/// are ordered by [`ProxyScorer`] score with the filter's country preferences,
+/// then by ProxyID.
+#[derive(Debug, Clone)]
+pub struct Candidates {
+    list: Arc<ListOnlineResult>,
+    // Positions in `list.proxy_list`
+    indices: Vec<usize>,
+}
+
+impl Candidates {
+    fn rank(list: Arc<ListOnlineResult>, filter: &ProxyFilter, kind: PurchaseKind) -> Self {
+        let scorer = ProxyScorer::new().country_preferences(filter);
+        let mut ranked: Vec<(usize, _, f64)> = list
+            .proxy_list
+            .iter()
+            .enumerate()
+            .filter(|(_, proxy)| filter.matches(proxy))
+            .filter_map(|(index, proxy)| Some((index, proxy.cost(kind)?, scorer.score(proxy))))
+            .collect();
+        ranked.sort_by(|a, b| {
+            a.1.cmp(&b.1).then(b.2.total_cmp(&a.2)).then(
+                list.proxy_list[a.0]
+                    .proxy_id
+                    .cmp(&list.proxy_list[b.0].proxy_id),
+            )
+        });
+        Candidates {
+            indices: ranked.into_iter().map(|(index, _, _)| index).collect(),
+            list,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProxyInfo> {
+        self.indices
+            .iter()
+            .map(|index| &self.list.proxy_list[*index])
+    }
+
+    pub fn first(&self) -> Option<&ProxyInfo> {
+        self.iter().next()
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+// Rankings of the list they were computed from, keyed by filter hash
+#[derive(Default)]
+struct Rankings {
+    list: Option<Arc<ListOnlineResult>>,
+    by_filter: HashMap<(u64, PurchaseKind), Arc<Candidates>>,
+    // ProxyIDs `buy_cheapest` already tried on this list, bought or failed
+    attempted: HashSet<u32>,
+}
+
+/// `ListOnline` result shared between callers and refetched once it is older
+/// than the TTL. Concurrent callers wait for a single fetch.
+pub struct OnlineCache {
+    client: TrueSocksClient,
+    ttl: Duration,
+    // How long past the TTL an expired list is still served while it is refreshed
+    stale_while_revalidate: Option<Duration>,
+    entry: Entry,
+    refreshing: Arc<AtomicBool>,
+    rankings: std::sync::Mutex<Rankings>,
+}
+
+impl OnlineCache {
+    pub fn new(client: TrueSocksClient) -> Self {
+        Self::with_ttl(client, DEFAULT_TTL)
+    }
+
+    pub fn with_ttl(client: TrueSocksClient, ttl: Duration) -> Self {
+        OnlineCache {
+            client,
+            ttl,
+            stale_while_revalidate: None,
+            entry: Arc::new(Mutex::new(None)),
+            refreshing: Arc::new(AtomicBool::new(false)),
+            rankings: std::sync::Mutex::new(Rankings::default()),
+        }
+    }
+
+    /// Serve an expired list for up to `window` past the TTL and refresh it in
+    /// the background instead of making the caller wait. Needs a tokio runtime outside the browser.
+    pub fn stale_while_revalidate(mut self, window: Duration) -> Self {
+        self.stale_while_revalidate = Some(window);
+        self
+    }
+
+    pub fn client(&self) -> &TrueSocksClient {
+        &self.client
+    }
+
+    /// The cached list, fetching it first if it is missing or expired.
+    pub async fn get(&self) -> Result<Arc<ListOnlineResult>, ApiError> {
+        let mut entry = self.entry.lock().await;
+        if let Some((fetched, list)) = entry.as_ref() {
+            let age = fetched.elapsed();
+            if age < self.ttl {
+                return Ok(list.clone());
+            }
+            if self
+                .stale_while_revalidate
+                .is_some_and(|window| age < self.ttl + window)
+            {
+                self.spawn_refresh();
+                return Ok(list.clone());
+            }
+        }
+        let list = Arc::new(self.client.list_online_proxies().await?);
+        *entry = Some((Instant::now(), list.clone()));
+        Ok(list)
+    }
+
+    // At most one refresh runs at a time, a failed one leaves the stale list in place
+    fn spawn_refresh(&self) {
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let client = self.client.clone();
+        let entry = self.entry.clone();
+        let refreshing = self.refreshing.clone();
+        spawn(async move {
+            match client.list_online_proxies().await {
+                Ok(list) => *entry.lock().await = Some((Instant::now(), Arc::new(list))),
+                Err(err) => sublog!(
+                    Subsystem::Cache,
+                    Level::Warn,
+                    "background ListOnline refresh failed: {:?}",
+                    err
+                ),
+            }
+            refreshing.store(false, Ordering::Release);
+        });
+    }
+
+    /// Proxies matching `filter` that offer `kind`, cheapest first. The ranking
+    /// is kept until the list is refetched, so repeated calls with an equal
+    /// filter between refreshes skip filtering and scoring.
+    pub async fn candidates(
+        &self,
+        filter: &ProxyFilter,
+        kind: PurchaseKind,
+    ) -> Result<Arc<Candidates>, ApiError> {
+        let list = self.get().await?;
+        let mut rankings = self.rankings.lock().unwrap();
+        if !rankings
+            .list
+            .as_ref()
+            .is_some_and(|ranked| Arc::ptr_eq(ranked, &list))
+        {
+            *rankings = Rankings {
+                list: Some(list.clone()),
+                ..Default::default()
+            };
+        }
+        let candidates = rankings
+            .by_filter
+            .entry((filter.cache_key(), kind))
+            .or_insert_with(|| Arc::new(Candidates::rank(list, filter, kind)));
+        Ok(candidates.clone())
+    }
+
+    /// The cached list narrowed to the proxies matching `filter`.
+    pub async fn list_filtered(&self, filter: &ProxyFilter) -> Result<ListOnlineResult, ApiError> {
+        Ok(self.get().await?.filtered(filter))
+    }
+
+    /// Buy the cheapest proxy of [`candidates`](Self::candidates) not tried by
+    /// an earlier call on the same list, so repeated calls between refreshes
+    /// target different proxies. 404 when no proxy is left.
+    pub async fn buy_cheapest(
+        &self,
+        filter: &ProxyFilter,
+        kind: PurchaseKind,
+    ) -> Result<PurchaseResult, ApiError> {
+        let candidates = self.candidates(filter, kind).await?;
+        let proxy = {
+            let mut rankings = self.rankings.lock().unwrap();
+            candidates
+                .iter()
+                .find(|proxy| rankings.attempted.insert(proxy.proxy_id))
+        };
+        let proxy = proxy.ok_or(ApiError::from(NOT_FOUND))?;
+        self.client.purchase(proxy, kind).await
+    }
+
+    /// The cached list whatever its age, without fetching.
+    pub async fn cached(&self) -> Option<Arc<ListOnlineResult>> {
+        self.entry
+            .lock()
+            .await
+            .as_ref()
+            .map(|(_, list)| list.clone())
+    }
+
+    /// Store a list fetched elsewhere.
+    pub async fn insert(&self, list: ListOnlineResult) {
+        *self.entry.lock().await = Some((Instant::now(), Arc::new(list)));
+    }
+
+    pub async fn invalidate(&self) {
+        *self.entry.lock().await = None;
+    }
+
+    /// Save the cached list with its fetch time to `path`, nothing is written
+    /// when no list is cached.
+    pub async fn save_state(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let entry = self.entry.lock().await;
+        let Some((fetched, list)) = entry.as_ref() else {
+            return Ok(());
+        };
+        let saved = SavedOnlineList {
+            fetched_at: unix_now().saturating_sub(fetched.elapsed().as_secs()),
+            list: ListOnlineResult::clone(list),
+        };
+        write_state(path.as_ref(), &saved)
+    }
+
+    /// Restore a list saved by [`save_state`](Self::save_state). It keeps its
+    /// age, so a list older than the TTL is refetched on the next `get`.
+    pub async fn load_state(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let saved: SavedOnlineList = read_state(path.as_ref())?;
+        let age = Duration::from_secs(unix_now().saturating_sub(saved.fetched_at));
+        let now = Instant::now();
+        let fetched = now.checked_sub(age).unwrap_or(now);
+        *self.entry.lock().await = Some((fetched, Arc::new(saved.list)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credits::Credits;
+    use crate::fixtures::{ok_response, proxy_info_json, serve};
+    use crate::retry::StatusRetryPolicy;
+    use serde_json::json;
+
+    fn unreachable_client() -> TrueSocksClient {
+        TrueSocksClient::builder("test")
+            .base_url("http://127.0.0.1:1/")
+            .max_retries(0)
+            .status_retry(StatusRetryPolicy::disabled())
+            .build()
+    }
+
+    fn online_list() -> ListOnlineResult {
+        serde_json::from_value(json!({
+            "LastUpdate": 1,
+            "ProxyCount": 1,
+            "ProxyList": [proxy_info_json(1)]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_stale_while_revalidate() {
+        let cache = OnlineCache::with_ttl(unreachable_client(), Duration::ZERO)
+            .stale_while_revalidate(Duration::from_secs(60));
+        cache.insert(online_list()).await;
+
+        // Expired but inside the window, served while the refresh fails in the background
+        assert_eq!(cache.get().await.unwrap().proxy_list.len(), 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(cache.cached().await.is_some());
+
+        let cache = OnlineCache::with_ttl(unreachable_client(), Duration::ZERO);
+        cache.insert(online_list()).await;
+        assert!(cache.get().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_state() {
+        let path =
+            std::env::temp_dir().join(format!("truesocks-online-{}.json", std::process::id()));
+        let cache = OnlineCache::new(unreachable_client());
+        cache.insert(online_list()).await;
+        cache.save_state(&path).await.unwrap();
+
+        let restored = OnlineCache::new(unreachable_client());
+        restored.load_state(&path).await.unwrap();
+        assert_eq!(restored.get().await.unwrap().proxy_list[0].proxy_id, 1);
+
+        // Still saved, but past the TTL once loaded
+        let expired = OnlineCache::with_ttl(unreachable_client(), Duration::ZERO);
+        expired.load_state(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(expired.get().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_candidates_cached_per_filter() {
+        let mut list = online_list();
+        for (proxy_id, cost) in [(2, 5), (3, 2), (4, 2)] {
+            let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(proxy_id)).unwrap();
+            proxy.rent_cost = Credits::from(cost);
+            list.proxy_list.push(proxy);
+        }
+        list.proxy_list[0].rent_cost = Credits::from(9);
+        let cache = OnlineCache::new(unreachable_client());
+        cache.insert(list.clone()).await;
+
+        let filter = ProxyFilter::new();
+        let candidates = cache
+            .candidates(&filter, PurchaseKind::SharedBuy)
+            .await
+            .unwrap();
+        let ids: Vec<u32> = candidates.iter().map(|proxy| proxy.proxy_id).collect();
+        assert_eq!(ids, vec![3, 4, 2, 1]);
+        let again = cache
+            .candidates(&ProxyFilter::new(), PurchaseKind::SharedBuy)
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&candidates, &again));
+
+        // A new list drops the rankings of the previous one
+        cache.insert(list).await;
+        let fresh = cache
+            .candidates(&filter, PurchaseKind::SharedBuy)
+            .await
+            .unwrap();
+        assert!(!Arc::ptr_eq(&candidates, &fresh));
+    }
+
+    #[tokio::test]
+    async fn test_buy_cheapest_skips_attempted_proxies() {
+        let mut list = online_list();
+        list.proxy_list.clear();
+        for (proxy_id, cost) in [(12, 5), (11, 2)] {
+            let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(proxy_id)).unwrap();
+            proxy.rent_cost = Credits::from(cost);
+            list.proxy_list.push(proxy);
+        }
+        let bought = ok_response(json!({ "CreditsLeft": 0 }));
+        let (url, server) = serve(vec![bought.clone(), bought]);
+        let cache = OnlineCache::new(TrueSocksClient::builder("test").base_url(url).build());
+        cache.insert(list).await;
+
+        let filter = ProxyFilter::new();
+        for _ in 0..2 {
+            cache
+                .buy_cheapest(&filter, PurchaseKind::SharedBuy)
+                .await
+                .unwrap();
+        }
+        let err = cache
+            .buy_cheapest(&filter, PurchaseKind::SharedBuy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), NOT_FOUND as u64);
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("proxyid=11"));
+        assert!(requests[1].contains("proxyid=12"));
+    }
+}
@@ -0,0 +1,379 @@
+//! Other spellings of the model field names for storage layers that do not
+//! want the API's PascalCase.
+//!
+//! Only the field names the API uses are renamed, so unknown fields kept in
+//! `extra` keep their names and every conversion reads back to the same
+//! model.
+
+use serde::de::DeserializeOwned;
+use serde::ser::Error;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+
+// Every field name of the models, as sent by the API
+const API_FIELDS: &[&str] = &[
+    "Active",
+    "Blacklist",
+    "City",
+    "Connect",
+    "ConnectIP",
+    "ConnectInfo",
+    "ConnectPort",
+    "ConnectSessionID",
+    "Cost",
+    "CostBuy",
+    "CostRent",
+    "Country",
+    "CountryCode",
+    "Created",
+    "Credits",
+    "CreditsLeft",
+    "Desc",
+    "Distance",
+    "Email",
+    "Enabled",
+    "Expires",
+    "HistoryCount",
+    "HistoryCurrentPage",
+    "HistoryEntriesPerPage",
+    "HistoryEntry",
+    "HistoryID",
+    "HistoryList",
+    "HistoryMaxPages",
+    "Hostname",
+    "ID",
+    "IP",
+    "IPHasChanged",
+    "ISP",
+    "IsFresh",
+    "IsOnline",
+    "IsRented",
+    "LastBought",
+    "LastUpdate",
+    "Link",
+    "Name",
+    "Note",
+    "Ping",
+    "Plan",
+    "ProxyCount",
+    "ProxyID",
+    "ProxyInfo",
+    "ProxyList",
+    "RefundAvailable",
+    "Region",
+    "RemainingTime",
+    "RenewCountRemaining",
+    "RenewEnabled",
+    "SearchCountryCode",
+    "SearchRange",
+    "SearchUnits",
+    "SearchZipCode",
+    "ServerTime",
+    "Speed",
+    "Timezone",
+    "Type",
+    "UpTimeQuality",
+    "UserID",
+    "ZipCode",
+    "code",
+    "message",
+    "refund_result",
+    "refund_result_str",
+    "result",
+    "status",
+    "tests_passed",
+    "tests_result",
+    "tests_result_str",
+    "tests_total",
+];
+
+/// Spelling of the field names when serializing models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FieldCase {
+    /// As sent by the API, `ProxyID` or `tests_passed`.
+    #[default]
+    Api,
+    /// `proxyId`, `ipHasChanged`.
+    CamelCase,
+    /// `proxy_id`, `ip_has_changed`.
+    SnakeCase,
+}
+
+impl FieldCase {
+    /// `name` spelled in this case.
+    pub fn convert(self, name: &str) -> String {
+        let words = words(name);
+        match self {
+            FieldCase::Api => name.to_string(),
+            FieldCase::SnakeCase => words.join("_"),
+            FieldCase::CamelCase => {
+                let mut converted = String::with_capacity(name.len());
+                for (index, word) in words.iter().enumerate() {
+                    let mut chars = word.chars();
+                    match chars.next() {
+                        Some(first) if index > 0 => {
+                            converted.push(first.to_ascii_uppercase());
+                            converted.push_str(chars.as_str());
+                        }
+                        _ => converted.push_str(word),
+                    }
+                }
+                converted
+            }
+        }
+    }
+
+    // API name of a field spelled in this case, None for unknown fields
+    fn api_name(self, name: &str) -> Option<&'static str> {
+        API_FIELDS
+            .iter()
+            .copied()
+            .find(|field| self.convert(field) == name)
+    }
+}
+
+// Lowercase words of a PascalCase or snake_case name, keeping acronyms such
+// as `IP` in `IPHasChanged` together
+fn words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for (index, &c) in chars.iter().enumerate() {
+        if c == '_' {
+            words.push(std::mem::take(&mut word));
+            continue;
+        }
+        let starts_word = c.is_ascii_uppercase()
+            && index > 0
+            && (chars[index - 1].is_ascii_lowercase()
+                || chars
+                    .get(index + 1)
+                    .is_some_and(|next| next.is_ascii_lowercase()));
+        if starts_word && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c.to_ascii_lowercase());
+    }
+    words.push(word);
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+fn rename(value: Value, rename_key: &impl Fn(String) -> String) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (rename_key(key), rename(value, rename_key)))
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| rename(value, rename_key))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// `value` as JSON with its field names spelled in `case`.
+pub fn to_value<T: Serialize + ?Sized>(value: &T, case: FieldCase) -> serde_json::Result<Value> {
+    let value = serde_json::to_value(value)?;
+    if case == FieldCase::Api {
+        return Ok(value);
+    }
+    Ok(rename(value, &|key: String| {
+        if API_FIELDS.contains(&key.as_str()) {
+            case.convert(&key)
+        } else {
+            key
+        }
+    }))
+}
+
+/// Read a model from JSON written by [`to_value`] or [`Cased`] in `case`.
+pub fn from_value<T: DeserializeOwned>(value: Value, case: FieldCase) -> serde_json::Result<T> {
+    if case == FieldCase::Api {
+        return serde_json::from_value(value);
+    }
+    serde_json::from_value(rename(value, &|key: String| match case.api_name(&key) {
+        Some(name) => name.to_string(),
+        None => key,
+    }))
+}
+
+/// Serializes the wrapped model with its field names spelled in a
+/// [`FieldCase`], e.g. `serde_json::to_string(&Cased::snake_case(&proxy))`.
+#[derive(Debug, Clone, Copy)]
+pub struct Cased<'a, T: ?Sized> {
+    value: &'a T,
+    case: FieldCase,
+}
+
+impl<'a, T: ?Sized> Cased<'a, T> {
+    pub fn new(value: &'a T, case: FieldCase) -> Self {
+        Cased { value, case }
+    }
+
+    pub fn camel_case(value: &'a T) -> Self {
+        Cased::new(value, FieldCase::CamelCase)
+    }
+
+    pub fn snake_case(value: &'a T) -> Self {
+        Cased::new(value, FieldCase::SnakeCase)
+    }
+}
+
+impl<T: Serialize + ?Sized> Serialize for Cased<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        to_value(self.value, self.case)
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{account_status, history_page, list_info_json, ok_response};
+    use crate::models::{
+        ApiResponse, DisableProxyRenewalResult, EnableProxyRenewalResult, ListHistoryResult,
+        ListInfo, ProxyCheckResult, PurchaseResult, TestAndRefundResult,
+    };
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::fmt::Debug;
+
+    const CASES: [FieldCase; 3] = [FieldCase::Api, FieldCase::CamelCase, FieldCase::SnakeCase];
+
+    fn keys(value: &Value, found: &mut HashSet<String>) {
+        match value {
+            Value::Object(fields) => {
+                for (key, value) in fields {
+                    found.insert(key.clone());
+                    keys(value, found);
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| keys(value, found)),
+            _ => {}
+        }
+    }
+
+    // Every field is renamed in each case and reads back to an equal model
+    fn assert_round_trips<T>(model: &T)
+    where
+        T: Serialize + DeserializeOwned + Debug,
+    {
+        let api = serde_json::to_value(model).unwrap();
+        let mut api_keys = HashSet::new();
+        keys(&api, &mut api_keys);
+        for key in &api_keys {
+            assert!(API_FIELDS.contains(&key.as_str()), "unlisted field {}", key);
+        }
+        for case in CASES {
+            let value = serde_json::to_value(Cased::new(model, case)).unwrap();
+            let decoded: T = from_value(value.clone(), case).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", model));
+            assert_eq!(serde_json::to_value(&decoded).unwrap(), api);
+            if case != FieldCase::Api {
+                let mut cased_keys = HashSet::new();
+                keys(&value, &mut cased_keys);
+                let converted: HashSet<String> =
+                    api_keys.iter().map(|key| case.convert(key)).collect();
+                assert_eq!(cased_keys, converted);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert() {
+        let converted: Vec<(String, String)> =
+            ["ProxyID", "IPHasChanged", "ISP", "tests_result_str"]
+                .iter()
+                .map(|name| {
+                    (
+                        FieldCase::CamelCase.convert(name),
+                        FieldCase::SnakeCase.convert(name),
+                    )
+                })
+                .collect();
+        let expected = [
+            ("proxyId", "proxy_id"),
+            ("ipHasChanged", "ip_has_changed"),
+            ("isp", "isp"),
+            ("testsResultStr", "tests_result_str"),
+        ];
+        for ((camel, snake), (expected_camel, expected_snake)) in converted.iter().zip(expected) {
+            assert_eq!(camel, expected_camel);
+            assert_eq!(snake, expected_snake);
+        }
+        // Distinct names stay distinct
+        for case in CASES {
+            let names: HashSet<String> = API_FIELDS.iter().map(|name| case.convert(name)).collect();
+            assert_eq!(names.len(), API_FIELDS.len());
+        }
+    }
+
+    #[test]
+    fn test_models_round_trip() {
+        let mut entry = list_info_json(4, 9);
+        entry["ProxyInfo"]["Blacklist"] =
+            json!([{"ID": "sbl", "Name": "SBL", "Type": "Web Abuse", "Desc": "", "Link": ""}]);
+        entry["Note"] = json!("office");
+        let history: ApiResponse<ListHistoryResult> =
+            serde_json::from_value(ok_response(history_page(vec![entry.clone()], 1, 1))).unwrap();
+        assert_round_trips(&history);
+        assert_round_trips(&account_status(30));
+
+        let purchase: PurchaseResult = serde_json::from_value(json!({
+            "ServerTime": 5,
+            "CreditsLeft": 12,
+            "HistoryEntry": entry,
+        }))
+        .unwrap();
+        assert_round_trips(&purchase);
+        let check: ProxyCheckResult = serde_json::from_value(json!({
+            "tests_passed": 3,
+            "tests_total": 4,
+            "tests_result": "ok",
+            "tests_result_str": "3 of 4 tests passed",
+        }))
+        .unwrap();
+        assert_round_trips(&check);
+        let refund: TestAndRefundResult = serde_json::from_value(json!({
+            "tests_passed": 0,
+            "tests_total": 4,
+            "tests_result": "failed",
+            "tests_result_str": "no test passed",
+            "refund_result": "ok",
+            "refund_result_str": "refunded",
+        }))
+        .unwrap();
+        assert_round_trips(&refund);
+        let enabled: EnableProxyRenewalResult = serde_json::from_value(json!({
+            "HistoryID": 4,
+            "Enabled": true,
+            "CreditsLeft": 10,
+            "Cost": 2,
+        }))
+        .unwrap();
+        assert_round_trips(&enabled);
+        let disabled: DisableProxyRenewalResult =
+            serde_json::from_value(json!({"HistoryID": 4, "Enabled": false})).unwrap();
+        assert_round_trips(&disabled);
+    }
+
+    #[test]
+    fn test_unknown_fields_kept() {
+        let mut entry = list_info_json(4, 9);
+        entry["Carrier"] = json!({"Name": "Example Mobile", "mcc": 310});
+        let entry: ListInfo = serde_json::from_value(entry).unwrap();
+        let value = to_value(&entry, FieldCase::SnakeCase).unwrap();
+        assert_eq!(value["history_id"], 4);
+        assert_eq!(value["proxy_info"]["proxy_id"], 9);
+        assert_eq!(value["Carrier"]["name"], "Example Mobile");
+        let decoded: ListInfo = from_value(value, FieldCase::SnakeCase).unwrap();
+        assert_eq!(decoded, entry);
+    }
+}
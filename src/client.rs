@@ -0,0 +1,1780 @@
+use crate::backend::{HttpBackend, HttpRequest, HttpResponse, Method, ReqwestBackend};
+use crate::coalesce::SingleFlight;
+use crate::credits::Credits;
+use crate::disk_cache::DiskCache;
+use crate::history::HistoryQuery;
+use crate::hooks::{count_attempt, run_hooks, ApiHooks, CommandContext, RetryObserver};
+use crate::logging::{sublog, Subsystem};
+use crate::models::{
+    AccountStatusResult, ApiError, ApiResponse, ConnectInfo, DecodeError,
+    DisableProxyRenewalResult, EnableProxyRenewalResult, KeyTransport, ListHistoryResult, ListInfo,
+    ListOnlineResult, ListZipSearchResult, ProxyCheckResult, ProxyInfo, PurchaseKind,
+    PurchaseResult, ResultWithWarnings, Status, StatusHandling, TestAndRefundResult, Warning,
+};
+use crate::purchase::PurchaseValidationError;
+use crate::ratelimit::{RateLimit, RateLimitMode, RateLimiter, RateLimits};
+use crate::redact::{redact_url, redact_value};
+use crate::retry::{parse_retry_after, RetryClass, StatusRetryPolicy};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::retry::{IdempotentOnly, TransportOnly};
+use crate::runtime::{blocking, sleep, Instant};
+use crate::scoped::BudgetGuard;
+use crate::status_codes::{ACCEPTED_WITH_WARNING, BAD_REQUEST, NOT_FOUND, OK};
+use crate::support::{ClientSummary, RecentCommands};
+use crate::tap::{TapEvent, TapOutcome, TapSink};
+use log::Level;
+use reqwest::header::{HeaderName, HeaderValue};
+use reqwest_middleware::ClientBuilder;
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest_retry::policies::ExponentialBackoff;
+#[cfg(not(target_arch = "wasm32"))]
+use reqwest_retry::RetryTransientMiddleware;
+use secrecy::{ExposeSecret, SecretString};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+const API_URL: &str = "https://api.truesocks.net/";
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(3000);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// Commands running tests on the proxy server side before answering
+const SLOW_COMMANDS: [&str; 2] = ["BoughtProxyCheck", "BoughtProxyRefund"];
+const SLOW_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_RETRIES: u32 = 3;
+// Read-only commands whose concurrent identical calls share one request by default
+const COALESCED_COMMANDS: [&str; 5] = [
+    "Ping",
+    "ListOnline",
+    "ListZipSearch",
+    "ListHistory",
+    "AccountStatus",
+];
+
+// Outcome of sending a command: the status, the warning it was downgraded to
+// if any, and the body text, decoded once by the caller
+type SendResult = Result<(Status, Option<Warning>, Arc<str>), ApiError>;
+
+// The status of a response, the rest of the body is skipped without being built
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    status: Value,
+}
+
+fn merge_values(mut params1: Value, params2: Value) -> Value {
+    let params2_object = params2.as_object().expect("params2 must be an object");
+
+    let params1_object = params1.as_object_mut().expect("params1 must be an object");
+
+    for (key, value) in params2_object {
+        params1_object.insert(key.clone(), value.clone());
+    }
+
+    params1
+}
+
+tokio::task_local! {
+    // Warnings of the commands sent inside a `with_warnings` scope
+    static WARNINGS: RefCell<Vec<Warning>>;
+}
+
+/// Run `future` and return its output with the warnings of every command it
+/// sent, such as the 209 statuses accepted by default. Unlike
+/// [`TrueSocksClient::last_warning`] this is not affected by other tasks using
+/// the client, but commands sent from tasks spawned by `future` are missed.
+/// Nested calls also report their warnings to the enclosing call.
+pub async fn with_warnings<T, F>(future: F) -> Result<ResultWithWarnings<T>, ApiError>
+where
+    F: Future<Output = Result<T, ApiError>>,
+{
+    let (result, warnings) = WARNINGS
+        .scope(RefCell::new(Vec::new()), async {
+            let result = future.await;
+            (result, WARNINGS.with(|warnings| warnings.take()))
+        })
+        .await;
+    let _ = WARNINGS.try_with(|outer| outer.borrow_mut().extend(warnings.iter().cloned()));
+    Ok(ResultWithWarnings {
+        result: result?,
+        warnings,
+    })
+}
+
+// An accepted response, decoded by the caller of `dispatch`
+struct Reply<R> {
+    status: Status,
+    warning: Option<Warning>,
+    body: R,
+}
+
+/// Client for the TrueSocks API. Cloning is cheap, clones share the same
+/// HTTP connection pool and configuration.
+#[derive(Clone)]
+pub struct TrueSocksClient {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    api_key: SecretString,
+    api_url: String,
+    key_transport: KeyTransport,
+    timeout: Duration,
+    command_timeouts: HashMap<String, Duration>,
+    backend: Arc<dyn HttpBackend>,
+    status_retry: StatusRetryPolicy,
+    coalesced: HashSet<String>,
+    in_flight: SingleFlight<SendResult>,
+    disk_cache: Option<Arc<DiskCache>>,
+    tap: Option<Arc<dyn TapSink>>,
+    hooks: Vec<Arc<dyn ApiHooks>>,
+    status_handling: HashMap<u64, StatusHandling>,
+    debug_logging: bool,
+    validate_purchases: bool,
+    rate_limiter: RateLimiter,
+    // Caps spending of scoped clients, see `ScopedClient`
+    budget: Option<BudgetGuard>,
+    recent: RecentCommands,
+    last_warning: Mutex<Option<Warning>>,
+}
+
+// The key is shown as `[REDACTED]` by `SecretString`
+impl fmt::Debug for TrueSocksClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrueSocksClient")
+            .field("api_key", &self.inner.api_key)
+            .field("api_url", &self.inner.api_url)
+            .field("key_transport", &self.inner.key_transport)
+            .field("timeout", &self.inner.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct TrueSocksClientBuilder {
+    api_key: SecretString,
+    api_url: String,
+    key_transport: KeyTransport,
+    connect_timeout: Duration,
+    timeout: Duration,
+    command_timeouts: HashMap<String, Duration>,
+    max_retries: u32,
+    status_retry: StatusRetryPolicy,
+    coalesced: HashSet<String>,
+    disk_cache: Option<Arc<DiskCache>>,
+    backend: Option<Arc<dyn HttpBackend>>,
+    tap: Option<Arc<dyn TapSink>>,
+    hooks: Vec<Arc<dyn ApiHooks>>,
+    status_handling: HashMap<u64, StatusHandling>,
+    debug_logging: bool,
+    validate_purchases: bool,
+    rate_limits: RateLimits,
+}
+
+impl TrueSocksClientBuilder {
+    /// Send commands to another endpoint, e.g. a mock server or a proxy in front of the API.
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.api_url = url.into();
+        self
+    }
+
+    /// Send the API key as a query parameter (the default), a header or in a POST body.
+    pub fn key_transport(mut self, transport: KeyTransport) -> Self {
+        self.key_transport = transport;
+        self
+    }
+
+    /// Time allowed to establish the HTTP connection, 3 seconds by default.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Time allowed for each HTTP request from connecting to reading the
+    /// body, 30 seconds by default. Timed out commands fail with status 408.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Time allowed for requests sending `command`, instead of the client wide
+    /// timeout. `BoughtProxyCheck` and `BoughtProxyRefund` get 2 minutes by default.
+    pub fn command_timeout(mut self, command: impl Into<String>, timeout: Duration) -> Self {
+        self.command_timeouts.insert(command.into(), timeout);
+        self
+    }
+
+    /// Retries of transient transport failures, 3 by default. Purchases and
+    /// refunds are never retried after a transport failure. Not used on
+    /// wasm32, where the status retry policy retries transport failures.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Retries of commands failing with an HTTP or API status. Timeouts,
+    /// transport failures, 429 and 5xx are retried 3 times by default,
+    /// honoring Retry-After.
+    pub fn status_retry(mut self, policy: StatusRetryPolicy) -> Self {
+        self.status_retry = policy;
+        self
+    }
+
+    /// Whether concurrent calls of `command` with the same parameters share
+    /// one in-flight request. On by default for `Ping`, `ListOnline`,
+    /// `ListZipSearch`, `ListHistory` and `AccountStatus`. Hooks and the tap
+    /// still see every call.
+    pub fn coalesce(mut self, command: impl Into<String>, enabled: bool) -> Self {
+        let command = command.into();
+        if enabled {
+            self.coalesced.insert(command);
+        } else {
+            self.coalesced.remove(&command);
+        }
+        self
+    }
+
+    /// Answer cached commands from `cache` while its copy is fresh, and store
+    /// their accepted responses in it. Hooks and the tap still see every call.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn disk_cache(mut self, cache: DiskCache) -> Self {
+        self.disk_cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Send requests through `backend` instead of the default reqwest client.
+    /// `connect_timeout` and `max_retries` only configure the default client.
+    pub fn http_backend<B: HttpBackend>(mut self, backend: B) -> Self {
+        self.backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// Mirror every command (with the API key removed) to `sink`.
+    pub fn tap<S: TapSink>(mut self, sink: S) -> Self {
+        self.tap = Some(Arc::new(sink));
+        self
+    }
+
+    /// Register lifecycle callbacks, hooks run in the order they were added.
+    pub fn hook<H: ApiHooks>(mut self, hooks: H) -> Self {
+        self.hooks.push(Arc::new(hooks));
+        self
+    }
+
+    /// Decide whether a non-zero status `code` fails the call or is returned
+    /// as a [`Warning`]. Unlisted codes are errors; 209 is a warning by default.
+    pub fn status_handling(mut self, code: u64, handling: StatusHandling) -> Self {
+        self.status_handling.insert(code, handling);
+        self
+    }
+
+    /// Log full request URLs and response bodies at debug level under the
+    /// `truesocks::transport` target. The API key and session IDs are redacted.
+    pub fn debug_logging(mut self, enabled: bool) -> Self {
+        self.debug_logging = enabled;
+        self
+    }
+
+    /// Check the account balance and the active history before every
+    /// purchase, see [`TrueSocksClient::validate_purchase`]. Off by default.
+    pub fn validate_purchases(mut self, enabled: bool) -> Self {
+        self.validate_purchases = enabled;
+        self
+    }
+
+    /// Limit how fast commands are sent, across all commands.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limits.global = Some(limit);
+        self
+    }
+
+    /// Limit how fast `command` is sent, on top of the client wide limit.
+    pub fn command_rate_limit(mut self, command: impl Into<String>, limit: RateLimit) -> Self {
+        self.rate_limits.commands.insert(command.into(), limit);
+        self
+    }
+
+    /// Wait for a free slot (the default) or fail with status 429 when a limit is hit.
+    pub fn rate_limit_mode(mut self, mode: RateLimitMode) -> Self {
+        self.rate_limits.mode = mode;
+        self
+    }
+
+    // Coalesced futures are not Send on wasm32, where the client never leaves its thread
+    #[cfg_attr(target_arch = "wasm32", allow(clippy::arc_with_non_send_sync))]
+    pub fn build(self) -> TrueSocksClient {
+        let backend = self.backend.unwrap_or_else(|| {
+            let http = ClientBuilder::new(http_client(self.connect_timeout));
+            let http = with_transport_retries(http, self.max_retries)
+                .with(RetryObserver {
+                    hooks: self.hooks.clone(),
+                })
+                .build();
+            Arc::new(ReqwestBackend::new(http))
+        });
+
+        TrueSocksClient {
+            inner: Arc::new(ClientInner {
+                api_key: self.api_key,
+                api_url: self.api_url,
+                key_transport: self.key_transport,
+                timeout: self.timeout,
+                command_timeouts: self.command_timeouts,
+                backend,
+                status_retry: self.status_retry,
+                coalesced: self.coalesced,
+                in_flight: SingleFlight::default(),
+                disk_cache: self.disk_cache,
+                tap: self.tap,
+                hooks: self.hooks,
+                status_handling: self.status_handling,
+                debug_logging: self.debug_logging,
+                validate_purchases: self.validate_purchases,
+                rate_limiter: RateLimiter::new(self.rate_limits),
+                budget: None,
+                recent: RecentCommands::default(),
+                last_warning: Mutex::new(None),
+            }),
+        }
+    }
+}
+
+impl TrueSocksClient {
+    pub fn new(api_key: impl Into<SecretString>) -> Self {
+        Self::builder(api_key).build()
+    }
+
+    pub fn builder(api_key: impl Into<SecretString>) -> TrueSocksClientBuilder {
+        TrueSocksClientBuilder {
+            api_key: api_key.into(),
+            api_url: API_URL.to_string(),
+            key_transport: KeyTransport::default(),
+            connect_timeout: CONNECT_TIMEOUT,
+            timeout: REQUEST_TIMEOUT,
+            command_timeouts: SLOW_COMMANDS
+                .iter()
+                .map(|command| (command.to_string(), SLOW_COMMAND_TIMEOUT))
+                .collect(),
+            max_retries: MAX_RETRIES,
+            status_retry: StatusRetryPolicy::default(),
+            coalesced: COALESCED_COMMANDS
+                .iter()
+                .map(|command| command.to_string())
+                .collect(),
+            disk_cache: None,
+            backend: None,
+            tap: None,
+            hooks: Vec::new(),
+            status_handling: HashMap::from([(
+                ACCEPTED_WITH_WARNING as u64,
+                StatusHandling::Warning,
+            )]),
+            debug_logging: false,
+            validate_purchases: false,
+            rate_limits: RateLimits::default(),
+        }
+    }
+
+    /// Warning attached to the most recent successful command, if any. Use
+    /// [`with_warnings`] to get the warnings of specific calls.
+    pub fn last_warning(&self) -> Option<Warning> {
+        self.inner.last_warning.lock().unwrap().clone()
+    }
+
+    fn handling_for(&self, code: u64) -> StatusHandling {
+        self.inner
+            .status_handling
+            .get(&code)
+            .copied()
+            .unwrap_or(StatusHandling::Error)
+    }
+
+    // Send requests to the API, 418 is when deserialization fails for unknown reason / Unable to send request
+    pub(crate) async fn execute_command<T: DeserializeOwned>(
+        &self,
+        command: &str,
+        additional_params: Option<Value>,
+    ) -> Result<ApiResponse<T>, ApiError> {
+        let reply = self
+            .dispatch(command, additional_params, |body| {
+                decode_body::<ApiResponse<T>>(command, body)
+            })
+            .await?;
+        let mut api_response = reply.body;
+        api_response.warning = reply.warning;
+        Ok(api_response)
+    }
+
+    /// Run `command` and return the response body as sent by the API, without
+    /// decoding it into a model. Status handling still applies.
+    pub async fn execute_raw(
+        &self,
+        command: &str,
+        params: Option<Value>,
+    ) -> Result<Value, ApiError> {
+        self.dispatch(command, params, |body| decode_body(command, body))
+            .await
+            .map(|reply| reply.body)
+    }
+
+    // Run `command` and return the accepted body as text
+    pub(crate) async fn execute_text(
+        &self,
+        command: &str,
+        params: Option<Value>,
+    ) -> Result<String, ApiError> {
+        self.dispatch(command, params, |body| Ok(body.to_string()))
+            .await
+            .map(|reply| reply.body)
+    }
+
+    // Send a command, decode the accepted body with `decode` and report the outcome
+    // to the hooks, tap and tracing span
+    async fn dispatch<R>(
+        &self,
+        command: &str,
+        additional_params: Option<Value>,
+        decode: impl FnOnce(&str) -> Result<R, ApiError>,
+    ) -> Result<Reply<R>, ApiError> {
+        let started = Instant::now();
+        let additional_params = additional_params.unwrap_or(json!({}));
+        let redacted_params = params_to_pairs(additional_params.clone());
+        run_hooks(&self.inner.hooks, command, |hook| {
+            hook.on_request(command, &redacted_params)
+        });
+
+        let attempts = Arc::new(AtomicU32::new(0));
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "truesocks.command",
+            command,
+            proxy_id = tracing::field::Empty,
+            history_id = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+            retries = tracing::field::Empty,
+            status_code = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        for (name, value) in &redacted_params {
+            if name == "proxyid" {
+                span.record("proxy_id", value.as_str());
+            } else if name == "historyid" {
+                span.record("history_id", value.as_str());
+            }
+        }
+
+        let send = self.send_cached(command, additional_params, attempts.clone());
+        #[cfg(feature = "tracing")]
+        let send = tracing::Instrument::instrument(send, span.clone());
+        let result = send.await.and_then(|(status, warning, body)| {
+            if let Some(warning) = &warning {
+                let _ = WARNINGS.try_with(|warnings| warnings.borrow_mut().push(warning.clone()));
+            }
+            Ok(Reply {
+                body: decode(&body)?,
+                status,
+                warning,
+            })
+        });
+
+        #[cfg(feature = "tracing")]
+        {
+            let status_code = match &result {
+                Ok(reply) => reply.status.code,
+                Err(err) => err.code(),
+            };
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+            span.record(
+                "retries",
+                attempts
+                    .load(std::sync::atomic::Ordering::Relaxed)
+                    .saturating_sub(1),
+            );
+            span.record("status_code", status_code);
+        }
+
+        let outcome = match &result {
+            Ok(reply) => Ok((&reply.status, reply.warning.as_ref())),
+            Err(err) => Err(err),
+        };
+        self.report(command, redacted_params, started, outcome)
+            .await;
+
+        result
+    }
+
+    pub(crate) fn hooks(&self) -> &[Arc<dyn ApiHooks>] {
+        &self.inner.hooks
+    }
+
+    // Report the outcome of a command to the metrics, hooks, recent commands and tap
+    pub(crate) async fn report(
+        &self,
+        command: &str,
+        redacted_params: Vec<(String, String)>,
+        started: Instant,
+        result: Result<(&Status, Option<&Warning>), &ApiError>,
+    ) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_command(command, result.map(|_| ()), started.elapsed());
+
+        run_hooks(&self.inner.hooks, command, |hook| match result {
+            Ok((status, _)) => hook.on_response(command, status, started.elapsed()),
+            Err(err) => hook.on_api_error(command, err),
+        });
+
+        let outcome = match result {
+            Ok((_, Some(warning))) => TapOutcome::Warning(warning.clone()),
+            Ok((status, None)) => TapOutcome::Success(status.clone()),
+            Err(err) => TapOutcome::Failure(err.clone()),
+        };
+        let event = TapEvent {
+            command: command.to_string(),
+            params: redacted_params,
+            duration: started.elapsed(),
+            outcome,
+        };
+        self.inner.recent.record(&event);
+        if let Some(tap) = &self.inner.tap {
+            tap.send(event).await;
+        }
+    }
+
+    // `send_coalesced`, answered from the disk cache while it holds a fresh response
+    async fn send_cached(
+        &self,
+        command: &str,
+        additional_params: Value,
+        attempts: Arc<AtomicU32>,
+    ) -> SendResult {
+        let cache = match &self.inner.disk_cache {
+            Some(cache) if cache.caches(command) => cache.clone(),
+            _ => {
+                return self
+                    .send_coalesced(command, additional_params, attempts)
+                    .await
+            }
+        };
+        // The key only goes into the file name hash, so keys never share entries
+        let scope = self.inner.api_key.expose_secret().to_string();
+        let params = additional_params.to_string();
+        let lookup = {
+            let (cache, scope, command, params) = (
+                cache.clone(),
+                scope.clone(),
+                command.to_string(),
+                params.clone(),
+            );
+            blocking(move || cache.get(&scope, &command, &params))
+        };
+        if let Some(Some(body)) = lookup.await {
+            sublog!(
+                Subsystem::Transport,
+                Level::Debug,
+                "{} answered from the disk cache",
+                command
+            );
+            return self.accept_body(command, body);
+        }
+
+        let result = self
+            .send_coalesced(command, additional_params, attempts)
+            .await;
+        if let Ok((_, None, body)) = &result {
+            let (body, command) = (body.clone(), command.to_string());
+            let _ = blocking(move || cache.put(&scope, &command, &params, &body)).await;
+        }
+        result
+    }
+
+    // `send_with_retries`, shared with identical calls in flight when the command
+    // is coalesced. Attempts are counted on the counter of the caller starting
+    // the run, callers joining it sent nothing themselves and keep theirs at zero.
+    async fn send_coalesced(
+        &self,
+        command: &str,
+        additional_params: Value,
+        attempts: Arc<AtomicU32>,
+    ) -> SendResult {
+        if !self.inner.coalesced.contains(command) {
+            return self
+                .send_with_retries(command, additional_params, attempts)
+                .await;
+        }
+        let key = format!("{} {}", command, additional_params);
+        let client = self.clone();
+        let command = command.to_string();
+        self.inner
+            .in_flight
+            .run(key, move || async move {
+                client
+                    .send_with_retries(&command, additional_params, attempts)
+                    .await
+            })
+            .await
+    }
+
+    // `send_command`, sent again while the failure is retryable under the status retry policy
+    async fn send_with_retries(
+        &self,
+        command: &str,
+        additional_params: Value,
+        attempts: Arc<AtomicU32>,
+    ) -> SendResult {
+        let policy = &self.inner.status_retry;
+        let mut retry = 0;
+        loop {
+            let sent_before = attempts.load(Ordering::Relaxed);
+            let mut retry_after = None;
+            let result = self
+                .send_command(
+                    command,
+                    additional_params.clone(),
+                    attempts.clone(),
+                    &mut retry_after,
+                )
+                .await;
+            let err = match result {
+                Err(err) if retry < policy.max_retries => err,
+                result => return result,
+            };
+            // Failures of requests that never went out, such as client side
+            // rate limits, are not retried
+            let sent = attempts.load(Ordering::Relaxed) > sent_before;
+            if !sent || policy.classify(command, &err) == RetryClass::Terminal {
+                return Err(err);
+            }
+            let delay = policy.delay(retry, retry_after);
+            sublog!(
+                Subsystem::Transport,
+                Level::Debug,
+                "{} failed with status {}, retrying in {:?}",
+                command,
+                err.code(),
+                delay
+            );
+            sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    // Returns the status, the warning it was downgraded to if any, and the raw body
+    async fn send_command(
+        &self,
+        command: &str,
+        additional_params: Value,
+        attempts: Arc<AtomicU32>,
+        retry_after_header: &mut Option<Duration>,
+    ) -> SendResult {
+        let res = self
+            .send_request(command, additional_params, attempts, retry_after_header)
+            .await?;
+        let body = res.text().await?;
+        self.accept_body(command, body)
+    }
+
+    // The status of a response body checked by `accept_status`, with the body kept for decoding
+    fn accept_body(&self, command: &str, body: String) -> SendResult {
+        let envelope: Envelope = serde_json::from_str(&body)
+            .map_err(|err| DecodeError::new(command, String::new(), err.to_string(), &body))?;
+        if self.inner.debug_logging {
+            if let Ok(value) = serde_json::from_str::<Value>(&body) {
+                sublog!(
+                    Subsystem::Transport,
+                    Level::Debug,
+                    "{} response: {}",
+                    command,
+                    redact_value(&value)
+                );
+            }
+        }
+        let (status, warning) = self.accept_status(command, envelope.status)?;
+        Ok((status, warning, Arc::from(body)))
+    }
+
+    // Send a command and return the response once its HTTP status is a success,
+    // with the body still unread
+    pub(crate) async fn send_request(
+        &self,
+        command: &str,
+        additional_params: Value,
+        attempts: Arc<AtomicU32>,
+        retry_after_header: &mut Option<Duration>,
+    ) -> Result<HttpResponse, ApiError> {
+        self.inner.rate_limiter.acquire(command).await?;
+        let mut request_params = json!({ "cmd": command });
+        if !matches!(self.inner.key_transport, KeyTransport::Header(_)) {
+            request_params["key"] = json!(self.inner.api_key.expose_secret());
+        }
+        let merged_params = merge_values(request_params, additional_params);
+        let params = params_to_pairs(merged_params);
+
+        let (method, url, form) = match &self.inner.key_transport {
+            KeyTransport::PostForm => {
+                let url = reqwest::Url::parse(&self.inner.api_url).map_err(|_| BAD_REQUEST)?;
+                if self.inner.debug_logging {
+                    sublog!(
+                        Subsystem::Transport,
+                        Level::Debug,
+                        "POST {} {}",
+                        url,
+                        command
+                    );
+                }
+                (Method::Post, url, Some(params))
+            }
+            _ => {
+                let url = reqwest::Url::parse_with_params(&self.inner.api_url, &params)
+                    .map_err(|_| BAD_REQUEST)?;
+                if self.inner.debug_logging {
+                    sublog!(
+                        Subsystem::Transport,
+                        Level::Debug,
+                        "GET {}",
+                        redact_url(&url)
+                    );
+                }
+                (Method::Get, url, None)
+            }
+        };
+        let mut headers = Vec::new();
+        if let KeyTransport::Header(name) = &self.inner.key_transport {
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| BAD_REQUEST)?;
+            let key = self.inner.api_key.expose_secret();
+            HeaderValue::from_str(key).map_err(|_| BAD_REQUEST)?;
+            headers.push((name.clone(), key.to_string()));
+        }
+        let timeout = self
+            .inner
+            .command_timeouts
+            .get(command)
+            .copied()
+            .unwrap_or(self.inner.timeout);
+        count_attempt(&self.inner.hooks, command, &attempts);
+        let request = HttpRequest {
+            method,
+            url: url.to_string(),
+            headers,
+            form,
+            timeout,
+            context: Some(CommandContext {
+                command: Arc::from(command),
+                attempts,
+                sent: false,
+            }),
+        };
+        let res = self.inner.backend.send(request).await?;
+        if !res.is_success() {
+            *retry_after_header = res.header("Retry-After").and_then(parse_retry_after);
+            return Err(ApiError::from(res.status));
+        }
+        Ok(res)
+    }
+
+    // The status of a response body, failing unless it is OK or configured as a warning
+    pub(crate) fn accept_status(
+        &self,
+        command: &str,
+        status: Value,
+    ) -> Result<(Status, Option<Warning>), ApiError> {
+        let status = decode_response::<Status>(command, status)?;
+        let mut warning = None;
+        if status.code != OK as u64 {
+            match self.handling_for(status.code) {
+                StatusHandling::Warning => warning = Some(Warning::from(status.clone())),
+                StatusHandling::Error => return Err(ApiError::from(status)),
+            }
+        }
+        *self.inner.last_warning.lock().unwrap() = warning.clone();
+        Ok((status, warning))
+    }
+
+    pub async fn ping(&self) -> Result<bool, ApiError> {
+        self.ping_with_status().await.map(|_| true)
+    }
+
+    /// The `_with_status` methods return the whole response, including the
+    /// status message the other methods drop.
+    pub async fn ping_with_status(&self) -> Result<ApiResponse<bool>, ApiError> {
+        self.execute_command::<bool>("Ping", None).await
+    }
+
+    pub async fn list_online_proxies(&self) -> Result<ListOnlineResult, ApiError> {
+        self.list_online_proxies_with_status()
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn list_online_proxies_with_status(
+        &self,
+    ) -> Result<ApiResponse<ListOnlineResult>, ApiError> {
+        self.execute_command::<ListOnlineResult>("ListOnline", None)
+            .await
+    }
+
+    pub async fn list_zip_search(
+        &self,
+        country_code: &str,
+        zip_code: &str,
+        units: Option<&str>,
+        range: Option<u32>,
+    ) -> Result<ListZipSearchResult, ApiError> {
+        self.list_zip_search_with_status(country_code, zip_code, units, range)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn list_zip_search_with_status(
+        &self,
+        country_code: &str,
+        zip_code: &str,
+        units: Option<&str>,
+        range: Option<u32>,
+    ) -> Result<ApiResponse<ListZipSearchResult>, ApiError> {
+        self.execute_command::<ListZipSearchResult>(
+            "ListZipSearch",
+            Some(zip_search_params(country_code, zip_code, units, range)),
+        )
+        .await
+    }
+
+    /// One page of the history. Entries not matching the client side criteria
+    /// of `query` are left out, the counts are the ones reported by the API.
+    pub async fn list_history(&self, query: &HistoryQuery) -> Result<ListHistoryResult, ApiError> {
+        self.list_history_page(query, query.page).await
+    }
+
+    pub async fn list_history_with_status(
+        &self,
+        query: &HistoryQuery,
+    ) -> Result<ApiResponse<ListHistoryResult>, ApiError> {
+        self.history_page_response(query, query.page).await
+    }
+
+    pub(crate) async fn list_history_page(
+        &self,
+        query: &HistoryQuery,
+        page: Option<u32>,
+    ) -> Result<ListHistoryResult, ApiError> {
+        self.history_page_response(query, page)
+            .await
+            .map(|res| res.result)
+    }
+
+    async fn history_page_response(
+        &self,
+        query: &HistoryQuery,
+        page: Option<u32>,
+    ) -> Result<ApiResponse<ListHistoryResult>, ApiError> {
+        let mut res = self
+            .execute_command::<ListHistoryResult>("ListHistory", Some(query.params(page)))
+            .await?;
+        res.result.history_list.retain(|entry| query.matches(entry));
+        Ok(res)
+    }
+
+    // Fetch every page of the history, sorted by HistoryID
+    pub async fn list_all_history(&self, query: &HistoryQuery) -> Result<Vec<ListInfo>, ApiError> {
+        let entries = self.collect_history(query, None).await?;
+        #[cfg(feature = "metrics")]
+        if *query == HistoryQuery::active() {
+            crate::metrics::record_active_proxies(entries.len());
+        }
+        Ok(entries)
+    }
+
+    /// Like [`list_all_history`](Self::list_all_history), but stops once `cancel`
+    /// is triggered and returns the pages fetched so far. The caller tells a
+    /// partial list apart with `cancel.is_cancelled()`.
+    pub async fn list_all_history_cancellable(
+        &self,
+        query: &HistoryQuery,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<ListInfo>, ApiError> {
+        self.collect_history(query, Some(cancel)).await
+    }
+
+    /// Current connect details of an active purchase, re-read from the
+    /// history since the API has no command for it. Status 404 when the entry
+    /// expired or has no connect info.
+    pub async fn refresh_connect_info(&self, history_id: u64) -> Result<ConnectInfo, ApiError> {
+        self.find_history_entry(&HistoryQuery::active(), history_id)
+            .await?
+            .and_then(|entry| entry.connect_info)
+            .ok_or(ApiError::from(NOT_FOUND))
+    }
+
+    /// The history entry `history_id`, active or not. The API has no command
+    /// for a single entry, so pages are fetched until the entry turns up.
+    pub async fn get_history_entry(&self, history_id: u64) -> Result<Option<ListInfo>, ApiError> {
+        self.find_history_entry(&HistoryQuery::new(), history_id)
+            .await
+    }
+
+    pub(crate) async fn find_history_entry(
+        &self,
+        query: &HistoryQuery,
+        history_id: u64,
+    ) -> Result<Option<ListInfo>, ApiError> {
+        let mut page = 1;
+        loop {
+            let res = self.list_history_page(query, Some(page)).await?;
+            let max_pages = res.history_max_pages;
+            if let Some(entry) = res
+                .history_list
+                .into_iter()
+                .find(|entry| entry.history_id == history_id)
+            {
+                return Ok(Some(entry));
+            }
+            if page >= max_pages {
+                return Ok(None);
+            }
+            page += 1;
+        }
+    }
+
+    async fn collect_history(
+        &self,
+        query: &HistoryQuery,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<ListInfo>, ApiError> {
+        let mut entries = Vec::new();
+        let mut page = 1;
+        loop {
+            let res = match cancel {
+                Some(cancel) => tokio::select! {
+                    biased;
+                    _ = cancel.cancelled() => break,
+                    res = self.list_history_page(query, Some(page)) => res?,
+                },
+                None => self.list_history_page(query, Some(page)).await?,
+            };
+            entries.extend(res.history_list);
+            if page >= res.history_max_pages {
+                break;
+            }
+            page += 1;
+        }
+        entries.sort_by_key(|entry| entry.history_id);
+        Ok(entries)
+    }
+
+    async fn purchase_command(
+        &self,
+        command: &str,
+        proxy_info: &ProxyInfo,
+        kind: PurchaseKind,
+    ) -> Result<ApiResponse<PurchaseResult>, ApiError> {
+        let cost = self.reserve_budget(proxy_info, kind)?;
+        let result = self
+            .execute_command::<PurchaseResult>(command, Some(proxy_id_params(proxy_info.proxy_id)))
+            .await;
+        if matches!(&result, Err(err) if err.is_definite_rejection()) {
+            self.release_budget(cost);
+        }
+        result
+    }
+
+    // Reserve the cost of a purchase against the budget guard, 402 if it does not fit
+    pub(crate) fn reserve_budget(
+        &self,
+        proxy_info: &ProxyInfo,
+        kind: PurchaseKind,
+    ) -> Result<Credits, ApiError> {
+        match &self.inner.budget {
+            Some(budget) => {
+                let cost = proxy_info
+                    .cost(kind)
+                    .ok_or(PurchaseValidationError::PrivateRentUnavailable)?;
+                budget.reserve(cost)?;
+                Ok(cost)
+            }
+            None => Ok(Credits::ZERO),
+        }
+    }
+
+    pub(crate) fn release_budget(&self, cost: Credits) {
+        if let Some(budget) = &self.inner.budget {
+            budget.release(cost);
+        }
+    }
+
+    pub(crate) fn recent_commands(&self) -> &RecentCommands {
+        &self.inner.recent
+    }
+
+    // Configuration without the API key, for support bundles
+    pub(crate) fn summary(&self) -> ClientSummary {
+        let mut status_handling: Vec<(u64, String)> = self
+            .inner
+            .status_handling
+            .iter()
+            .map(|(code, handling)| (*code, format!("{:?}", handling)))
+            .collect();
+        status_handling.sort();
+        ClientSummary {
+            api_url: self.inner.api_url.clone(),
+            key_transport: format!("{:?}", self.inner.key_transport),
+            status_handling,
+            debug_logging: self.inner.debug_logging,
+            hooks: self.inner.hooks.len(),
+            tap: self.inner.tap.is_some(),
+            rate_limits: self.inner.rate_limiter.describe(),
+            budget: self
+                .inner
+                .budget
+                .as_ref()
+                .map(|budget| (budget.limit(), budget.spent())),
+        }
+    }
+
+    pub(crate) fn budget(&self) -> Option<&BudgetGuard> {
+        self.inner.budget.as_ref()
+    }
+
+    // A client sharing this one's transport, hooks and tap under another key
+    #[cfg_attr(target_arch = "wasm32", allow(clippy::arc_with_non_send_sync))]
+    pub(crate) fn with_scope(
+        &self,
+        api_key: SecretString,
+        rate_limits: RateLimits,
+        budget: Option<BudgetGuard>,
+    ) -> TrueSocksClient {
+        TrueSocksClient {
+            inner: Arc::new(ClientInner {
+                api_key,
+                api_url: self.inner.api_url.clone(),
+                key_transport: self.inner.key_transport.clone(),
+                timeout: self.inner.timeout,
+                command_timeouts: self.inner.command_timeouts.clone(),
+                backend: self.inner.backend.clone(),
+                status_retry: self.inner.status_retry.clone(),
+                coalesced: self.inner.coalesced.clone(),
+                in_flight: SingleFlight::default(),
+                disk_cache: self.inner.disk_cache.clone(),
+                tap: self.inner.tap.clone(),
+                hooks: self.inner.hooks.clone(),
+                status_handling: self.inner.status_handling.clone(),
+                debug_logging: self.inner.debug_logging,
+                validate_purchases: self.inner.validate_purchases,
+                rate_limiter: RateLimiter::new(rate_limits),
+                budget,
+                recent: RecentCommands::default(),
+                last_warning: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Buy (`SharedBuy`) or rent (`PrivateRent`) a proxy with the command
+    /// matching its freshness. Status 400 without sending anything when the
+    /// proxy does not offer a private rental.
+    pub async fn purchase(
+        &self,
+        proxy_info: &ProxyInfo,
+        kind: PurchaseKind,
+    ) -> Result<PurchaseResult, ApiError> {
+        self.purchase_with_status(proxy_info, kind)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn purchase_with_status(
+        &self,
+        proxy_info: &ProxyInfo,
+        kind: PurchaseKind,
+    ) -> Result<ApiResponse<PurchaseResult>, ApiError> {
+        self.purchase_as(proxy_info, proxy_info.is_fresh, kind)
+            .await
+    }
+
+    pub async fn regular_proxy_rent(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<PurchaseResult, ApiError> {
+        self.purchase_as(proxy_info, false, PurchaseKind::SharedBuy)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn regular_proxy_private_rent(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<PurchaseResult, ApiError> {
+        self.purchase_as(proxy_info, false, PurchaseKind::PrivateRent)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn fresh_proxy_rent(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<PurchaseResult, ApiError> {
+        self.purchase_as(proxy_info, true, PurchaseKind::SharedBuy)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn fresh_proxy_private_rent(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<PurchaseResult, ApiError> {
+        self.purchase_as(proxy_info, true, PurchaseKind::PrivateRent)
+            .await
+            .map(|res| res.result)
+    }
+
+    // Fails with 400 when `fresh` does not match the proxy
+    async fn purchase_as(
+        &self,
+        proxy_info: &ProxyInfo,
+        fresh: bool,
+        kind: PurchaseKind,
+    ) -> Result<ApiResponse<PurchaseResult>, ApiError> {
+        let command = purchase_command_name(proxy_info, fresh, kind)?;
+        if self.inner.validate_purchases {
+            self.validate_purchase(proxy_info, kind).await?;
+        }
+        self.purchase_command(command, proxy_info, kind).await
+    }
+
+    pub async fn check_purchased_proxy(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<ProxyCheckResult, ApiError> {
+        self.check_purchased_proxy_with_status(proxy_info)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn check_purchased_proxy_with_status(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<ApiResponse<ProxyCheckResult>, ApiError> {
+        self.execute_command::<ProxyCheckResult>(
+            "BoughtProxyCheck",
+            Some(proxy_id_params(proxy_info.proxy_id)),
+        )
+        .await
+    }
+
+    pub async fn refund_purchased_proxy(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<TestAndRefundResult, ApiError> {
+        self.refund_purchased_proxy_with_status(proxy_info)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn refund_purchased_proxy_with_status(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<ApiResponse<TestAndRefundResult>, ApiError> {
+        self.execute_command::<TestAndRefundResult>(
+            "BoughtProxyRefund",
+            Some(proxy_id_params(proxy_info.proxy_id)),
+        )
+        .await
+    }
+
+    pub async fn bought_proxy_renew_enable(
+        &self,
+        history_id: u32,
+    ) -> Result<EnableProxyRenewalResult, ApiError> {
+        self.bought_proxy_renew_enable_with_status(history_id)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn bought_proxy_renew_enable_with_status(
+        &self,
+        history_id: u32,
+    ) -> Result<ApiResponse<EnableProxyRenewalResult>, ApiError> {
+        self.execute_command::<EnableProxyRenewalResult>(
+            "BoughtProxyRenewEnable",
+            Some(history_id_params(history_id.into())),
+        )
+        .await
+    }
+
+    pub async fn bought_proxy_renew_disable(
+        &self,
+        history_id: u32,
+    ) -> Result<DisableProxyRenewalResult, ApiError> {
+        self.bought_proxy_renew_disable_with_status(history_id)
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn bought_proxy_renew_disable_with_status(
+        &self,
+        history_id: u32,
+    ) -> Result<ApiResponse<DisableProxyRenewalResult>, ApiError> {
+        self.execute_command::<DisableProxyRenewalResult>(
+            "BoughtProxyRenewDisable",
+            Some(history_id_params(history_id.into())),
+        )
+        .await
+    }
+
+    // Keep note as None if you want to set it to empty string/remove it
+    // Returns Ok(()) if successful
+    pub async fn history_entry_change_note(
+        &self,
+        history_id: u64,
+        note: Option<&str>,
+    ) -> Result<(), ApiError> {
+        self.history_entry_change_note_with_status(history_id, note)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn history_entry_change_note_with_status(
+        &self,
+        history_id: u64,
+        note: Option<&str>,
+    ) -> Result<ApiResponse<Option<bool>>, ApiError> {
+        self.execute_command::<Option<bool>>(
+            "HistoryEntryChangeNote",
+            Some(note_params(history_id, note)),
+        )
+        .await
+    }
+
+    pub async fn get_account_status(&self) -> Result<AccountStatusResult, ApiError> {
+        self.get_account_status_with_status()
+            .await
+            .map(|res| res.result)
+    }
+
+    pub async fn get_account_status_with_status(
+        &self,
+    ) -> Result<ApiResponse<AccountStatusResult>, ApiError> {
+        let res = self
+            .execute_command::<AccountStatusResult>("AccountStatus", None)
+            .await?;
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_credits(res.result.credits.0);
+        Ok(res)
+    }
+}
+
+// The browser negotiates compression and connections itself on wasm32
+#[cfg(not(target_arch = "wasm32"))]
+fn http_client(connect_timeout: Duration) -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br"),
+    );
+    reqwest::Client::builder()
+        .gzip(true)
+        .connect_timeout(connect_timeout)
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn http_client(_connect_timeout: Duration) -> reqwest::Client {
+    reqwest::Client::new()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn with_transport_retries(http: ClientBuilder, max_retries: u32) -> ClientBuilder {
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(max_retries);
+    http.with(IdempotentOnly(
+        RetryTransientMiddleware::new_with_policy_and_strategy(retry_policy, TransportOnly),
+    ))
+}
+
+// The retry middleware does not build for wasm32, transport failures are
+// retried by the status retry policy there
+#[cfg(target_arch = "wasm32")]
+fn with_transport_retries(http: ClientBuilder, _max_retries: u32) -> ClientBuilder {
+    http
+}
+
+pub(crate) fn zip_search_params(
+    country_code: &str,
+    zip_code: &str,
+    units: Option<&str>,
+    range: Option<u32>,
+) -> Value {
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("countrycode", country_code.to_string());
+    params.insert("zipcode", zip_code.to_string());
+
+    if let Some(units_value) = units {
+        params.insert("units", units_value.to_string());
+    }
+
+    if let Some(range_value) = range {
+        params.insert("range", range_value.to_string());
+    }
+    serde_json::to_value(params).unwrap()
+}
+
+pub(crate) fn history_params(active_only: bool, page: Option<u32>) -> Value {
+    let mut params: HashMap<&str, String> = HashMap::new();
+
+    if active_only {
+        params.insert("onlyactive", "1".to_string());
+    }
+
+    if let Some(page_value) = page {
+        params.insert("page", page_value.to_string());
+    }
+    serde_json::to_value(params).unwrap()
+}
+
+pub(crate) fn proxy_id_params(proxy_id: u32) -> Value {
+    json!({ "proxyid": proxy_id.to_string() })
+}
+
+pub(crate) fn history_id_params(history_id: u64) -> Value {
+    json!({ "historyid": history_id.to_string() })
+}
+
+pub(crate) fn note_params(history_id: u64, note: Option<&str>) -> Value {
+    let mut params = history_id_params(history_id);
+    if let Some(note_value) = note {
+        params["note"] = Value::from(note_value);
+    }
+    params
+}
+
+// Buy/rent command for a proxy of the given freshness, a validation error if the
+// proxy does not match it or does not offer the purchase kind
+pub(crate) fn purchase_command_name(
+    proxy_info: &ProxyInfo,
+    fresh: bool,
+    kind: PurchaseKind,
+) -> Result<&'static str, ApiError> {
+    if proxy_info.is_fresh != fresh {
+        return Err(PurchaseValidationError::WrongFreshness {
+            proxy_is_fresh: proxy_info.is_fresh,
+        }
+        .into());
+    }
+    let private_offered = !proxy_info.private_rent_cost.is_zero();
+    match (fresh, kind) {
+        (false, PurchaseKind::SharedBuy) => Ok("RegularProxyBuy"),
+        (false, PurchaseKind::PrivateRent) if private_offered => Ok("RegularProxyRent"),
+        (true, PurchaseKind::SharedBuy) => Ok("FreshProxyBuy"),
+        (true, PurchaseKind::PrivateRent) if private_offered => Ok("FreshProxyRent"),
+        _ => Err(PurchaseValidationError::PrivateRentUnavailable.into()),
+    }
+}
+
+// Renewal commands take 32-bit history IDs while history entries carry 64-bit ones
+pub(crate) fn renewal_history_id(history_id: u64) -> Result<u32, ApiError> {
+    u32::try_from(history_id).map_err(|_| ApiError::from(BAD_REQUEST))
+}
+
+// Decode a response body, keeping the serde path and the redacted body on failure
+pub(crate) fn decode_response<T: DeserializeOwned>(
+    command: &str,
+    value: Value,
+) -> Result<T, ApiError> {
+    serde_path_to_error::deserialize(&value).map_err(|err| {
+        let body = redact_value(&value).to_string();
+        let path = err.path().to_string();
+        let path = if path == "." { String::new() } else { path };
+        ApiError::from(DecodeError::new(
+            command,
+            path,
+            err.into_inner().to_string(),
+            &body,
+        ))
+    })
+}
+
+// Decode a response body from its text, keeping the serde path and the
+// redacted body on failure
+pub(crate) fn decode_body<'de, T: Deserialize<'de>>(
+    command: &str,
+    body: &'de str,
+) -> Result<T, ApiError> {
+    let mut deserializer = serde_json::Deserializer::from_str(body);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        let path = if path == "." { String::new() } else { path };
+        let body = match serde_json::from_str::<Value>(body) {
+            Ok(value) => redact_value(&value).to_string(),
+            Err(_) => body.to_string(),
+        };
+        ApiError::from(DecodeError::new(
+            command,
+            path,
+            err.into_inner().to_string(),
+            &body,
+        ))
+    })
+}
+
+// Strings are sent as-is, other scalars in their JSON form, nulls are left out
+fn params_to_pairs(params: Value) -> Vec<(String, String)> {
+    let map: Map<String, Value> = params.as_object().unwrap().clone();
+    map.into_iter()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| match v {
+            Value::String(s) => (k, s),
+            other => (k, other.to_string()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        history_page, list_info, list_info_json, ok_response, proxy_info_json, serve, serve_once,
+        serve_with_status,
+    };
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_params_survive_url_encoding(
+            extra in proptest::collection::hash_map("[a-z]{1,12}", ".*", 0..6),
+            key in ".*",
+        ) {
+            let extra: HashMap<String, String> = extra
+                .into_iter()
+                .filter(|(name, _)| name != "key" && name != "cmd")
+                .collect();
+            let merged = merge_values(
+                json!({ "key": key, "cmd": "Ping" }),
+                serde_json::to_value(&extra).unwrap(),
+            );
+            let url = reqwest::Url::parse_with_params(API_URL, params_to_pairs(merged)).unwrap();
+            let decoded: HashMap<String, String> = url.query_pairs().into_owned().collect();
+
+            prop_assert_eq!(decoded.get("key"), Some(&key));
+            prop_assert_eq!(decoded.get("cmd").map(String::as_str), Some("Ping"));
+            for (name, value) in &extra {
+                prop_assert_eq!(decoded.get(name), Some(value));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_transport() {
+        let (url, request) = serve_once(ok_response(json!(true)));
+        let client = TrueSocksClient::builder("secret")
+            .base_url(url)
+            .key_transport(KeyTransport::Header("X-Api-Key".to_string()))
+            .build();
+        assert!(client.ping().await.unwrap());
+        let request = request.join().unwrap().to_lowercase();
+        assert!(request.starts_with("get /?cmd=ping "));
+        assert!(request.contains("x-api-key: secret\r\n"));
+
+        let (url, request) = serve_once(ok_response(json!(true)));
+        let client = TrueSocksClient::builder("secret")
+            .base_url(url)
+            .key_transport(KeyTransport::PostForm)
+            .build();
+        assert!(client.ping().await.unwrap());
+        let request = request.join().unwrap();
+        assert!(request.starts_with("POST / "));
+        assert!(request.ends_with("\r\n\r\ncmd=Ping&key=secret"));
+    }
+
+    #[tokio::test]
+    async fn test_api_key_is_never_rendered() {
+        let key = "b7f3c1e9-live-api-key";
+        // An undecodable result echoing the key back
+        let (url, _) = serve_once(ok_response(json!({ "key": key, "Credits": "many" })));
+        let client = TrueSocksClient::builder(key).base_url(url).build();
+        assert!(!format!("{:?}", client).contains(key));
+
+        let err = client.get_account_status().await.unwrap_err();
+        assert!(matches!(err, ApiError::DecodeError(_)));
+        assert!(!format!("{:?}", err).contains(key));
+    }
+
+    #[tokio::test]
+    async fn test_with_warnings_collects_history_pages() {
+        let partial = |page| {
+            json!({
+                "status": { "code": 209, "message": "partial page" },
+                "result": history_page(vec![list_info_json(page as u64, page)], page, 2)
+            })
+        };
+        let (url, _) = serve(vec![partial(1), partial(2)]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let entries = with_warnings(client.list_all_history(&HistoryQuery::new()))
+            .await
+            .unwrap();
+        assert_eq!(entries.result.len(), 2);
+        assert_eq!(
+            entries.warnings,
+            vec![
+                Warning {
+                    code: ACCEPTED_WITH_WARNING as u64,
+                    message: "partial page".to_string()
+                };
+                2
+            ]
+        );
+
+        let (url, _) = serve_once(ok_response(json!(true)));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        assert!(!with_warnings(client.ping()).await.unwrap().has_warnings());
+    }
+
+    #[tokio::test]
+    async fn test_with_status_keeps_message() {
+        let (url, _) = serve_once(json!({
+            "status": { "code": 0, "message": "maintenance at 02:00 UTC" },
+            "result": crate::fixtures::account_status(5)
+        }));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let res = client.get_account_status_with_status().await.unwrap();
+        assert_eq!(res.status.message, "maintenance at 02:00 UTC");
+        assert_eq!(res.result.credits, Credits(5));
+    }
+
+    #[tokio::test]
+    async fn test_status_handling_overrides() {
+        let reply = |code| json!({"status": {"code": code, "message": "note"}, "result": true});
+        let (url, _) = serve(vec![reply(209), reply(7)]);
+        let client = TrueSocksClient::builder("test")
+            .base_url(url)
+            .status_handling(209, StatusHandling::Error)
+            .status_handling(7, StatusHandling::Warning)
+            .build();
+
+        let err = client.ping_with_status().await.unwrap_err();
+        assert!(matches!(err, ApiError::RequestError(ref status) if status.code == 209));
+
+        let res = client.ping_with_status().await.unwrap();
+        let warning = Warning {
+            code: 7,
+            message: "note".to_string(),
+        };
+        assert_eq!(res.warning, Some(warning.clone()));
+        assert_eq!(client.last_warning(), Some(warning));
+    }
+
+    #[tokio::test]
+    async fn test_status_retry_honors_retry_after() {
+        let (url, server) = serve_with_status(vec![
+            ("429 Too Many Requests\r\nRetry-After: 0", json!({})),
+            ("503 Service Unavailable", json!({})),
+            ("200 OK", ok_response(json!(true))),
+        ]);
+        let client = TrueSocksClient::builder("secret")
+            .base_url(url)
+            .status_retry(
+                StatusRetryPolicy::default()
+                    .backoff(Duration::from_millis(10), Duration::from_millis(10)),
+            )
+            .build();
+        assert!(client.ping().await.unwrap());
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_calls_are_coalesced() {
+        #[derive(Default)]
+        struct Counts {
+            requests: AtomicU32,
+            retries: AtomicU32,
+        }
+
+        impl ApiHooks for Arc<Counts> {
+            fn on_request(&self, _command: &str, _params: &[(String, String)]) {
+                self.requests.fetch_add(1, Ordering::SeqCst);
+            }
+
+            fn on_retry(&self, _command: &str, _attempt: u32) {
+                self.retries.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        // A single response: the second call must reuse the first request
+        let (url, server) = serve(vec![ok_response(json!(true))]);
+        let client = TrueSocksClient::builder("secret").base_url(url).build();
+        let (a, b) = tokio::join!(client.ping(), client.ping());
+        assert!(a.unwrap() && b.unwrap());
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        // The retry of the shared run is reported once, not once per caller
+        let (url, server) = serve_with_status(vec![
+            ("503 Service Unavailable", json!({})),
+            ("200 OK", ok_response(json!(true))),
+        ]);
+        let counts = Arc::new(Counts::default());
+        let client = TrueSocksClient::builder("secret")
+            .base_url(url)
+            .status_retry(
+                StatusRetryPolicy::default()
+                    .backoff(Duration::from_millis(1), Duration::from_millis(1)),
+            )
+            .hook(counts.clone())
+            .build();
+        let (a, b) = tokio::join!(client.ping(), client.ping());
+        assert!(a.unwrap() && b.unwrap());
+        assert_eq!(server.join().unwrap().len(), 2);
+        assert_eq!(counts.requests.load(Ordering::SeqCst), 2);
+        assert_eq!(counts.retries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_status_retry_stops_on_terminal_status() {
+        let (url, server) = serve_with_status(vec![("403 Forbidden", json!({}))]);
+        let client = TrueSocksClient::builder("secret").base_url(url).build();
+        assert_eq!(client.ping().await.unwrap_err().code(), 403);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_purchases_skip_transport_retries() {
+        #[derive(Default)]
+        struct Retries(AtomicU32);
+
+        impl ApiHooks for Arc<Retries> {
+            fn on_retry(&self, _command: &str, _attempt: u32) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let retries = Arc::new(Retries::default());
+        let client = TrueSocksClient::builder("secret")
+            .base_url(url)
+            .max_retries(1)
+            .status_retry(StatusRetryPolicy::disabled())
+            .hook(retries.clone())
+            .build();
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(7)).unwrap();
+
+        client
+            .purchase(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap_err();
+        assert_eq!(retries.0.load(Ordering::SeqCst), 0);
+        client.ping().await.unwrap_err();
+        assert_eq!(retries.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_command_timeout() {
+        // Accepts connections but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TrueSocksClient::builder("secret")
+            .base_url(format!("http://{}/", listener.local_addr().unwrap()))
+            .max_retries(0)
+            .status_retry(StatusRetryPolicy::disabled())
+            .command_timeout("Ping", Duration::from_millis(100))
+            .build();
+        let started = std::time::Instant::now();
+        assert_eq!(client.ping().await.unwrap_err().code(), 408);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_connect_info() {
+        let mut changed = list_info_json(2, 2);
+        changed["IPHasChanged"] = json!(true);
+        changed["ConnectInfo"]["ConnectIP"] = json!("203.0.113.7");
+        let history = json!({
+            "ServerTime": 1,
+            "HistoryCount": 2,
+            "HistoryEntriesPerPage": 50,
+            "HistoryCurrentPage": 1,
+            "HistoryMaxPages": 1,
+            "HistoryList": [list_info_json(1, 1), changed],
+        });
+        let stale: ListInfo = serde_json::from_value(history["HistoryList"][1].clone()).unwrap();
+        assert!(stale.has_stale_session());
+        assert!(!list_info(1).has_stale_session());
+
+        let (url, _) = serve_once(ok_response(history.clone()));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let connect_info = client.refresh_connect_info(2).await.unwrap();
+        assert_eq!(connect_info.connect_ip, "203.0.113.7");
+
+        let (url, _) = serve_once(ok_response(history));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let err = client.refresh_connect_info(3).await.unwrap_err();
+        assert_eq!(err.code(), NOT_FOUND as u64);
+    }
+
+    #[tokio::test]
+    async fn test_get_history_entry_stops_at_match() {
+        let (url, requests) = serve(vec![
+            ok_response(history_page(vec![list_info_json(1, 1)], 1, 3)),
+            ok_response(history_page(vec![list_info_json(2, 2)], 2, 3)),
+        ]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let entry = client.get_history_entry(2).await.unwrap().unwrap();
+        assert_eq!(entry.history_id, 2);
+        let requests = requests.join().unwrap();
+        assert!(requests[1].contains("page=2"));
+        assert!(!requests[1].contains("onlyactive"));
+
+        let (url, _) = serve(vec![ok_response(history_page(vec![], 1, 1))]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        assert!(client.get_history_entry(2).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_purchase_picks_command() {
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(7)).unwrap();
+        proxy.is_fresh = true;
+        let (url, request) = serve_once(ok_response(json!({ "CreditsLeft": 10 })));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let result = client
+            .purchase(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap();
+        assert_eq!(result.credits_left, Some(Credits::from(10)));
+        assert!(request.join().unwrap().contains("cmd=FreshProxyBuy"));
+
+        // The old entry points still check freshness before sending anything
+        let err = client.regular_proxy_rent(&proxy).await.unwrap_err();
+        assert_eq!(err.code(), BAD_REQUEST as u64);
+    }
+
+    #[test]
+    fn test_decode_error_keeps_path_and_body() {
+        let value = json!({
+            "status": { "code": 0, "message": "OK" },
+            "result": { "Credits": "lots", "ConnectSessionID": "secret" },
+        });
+        let err =
+            decode_response::<ApiResponse<AccountStatusResult>>("AccountStatus", value.clone())
+                .unwrap_err();
+        assert_eq!(err.code(), 418);
+        let ApiError::DecodeError(err) = err else {
+            panic!("expected a decode error");
+        };
+        assert!(err.path.starts_with("result."));
+        assert!(err.body.contains("lots"));
+        assert!(!err.body.contains("secret"));
+
+        // Decoding from the body text reports the same path
+        let text_err =
+            decode_body::<ApiResponse<AccountStatusResult>>("AccountStatus", &value.to_string())
+                .unwrap_err();
+        let ApiError::DecodeError(text_err) = text_err else {
+            panic!("expected a decode error");
+        };
+        assert_eq!(text_err.path, err.path);
+        assert!(!text_err.body.contains("secret"));
+    }
+}
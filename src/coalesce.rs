@@ -0,0 +1,79 @@
+use crate::runtime::{boxed, MaybeSend, MaybeSendFuture};
+use futures::future::{FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+// Calls sharing one in-flight future per key (single flight): callers arriving
+// while a call with the same key runs get its output instead of starting
+// another one
+pub(crate) struct SingleFlight<T: Clone> {
+    pending: Mutex<HashMap<String, Shared<MaybeSendFuture<T>>>>,
+}
+
+impl<T: Clone> Default for SingleFlight<T> {
+    fn default() -> Self {
+        SingleFlight {
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone + MaybeSend + 'static> SingleFlight<T> {
+    // Output of the call running under `key`, or of `start()` when none is
+    pub(crate) async fn run<F>(&self, key: String, start: impl FnOnce() -> F) -> T
+    where
+        F: Future<Output = T> + MaybeSend + 'static,
+    {
+        let shared = {
+            let mut pending = self.pending.lock().unwrap();
+            pending
+                .entry(key.clone())
+                .or_insert_with(|| boxed(start()).shared())
+                .clone()
+        };
+        let output = shared.clone().await;
+        let mut pending = self.pending.lock().unwrap();
+        if pending
+            .get(&key)
+            .is_some_and(|current| current.ptr_eq(&shared))
+        {
+            pending.remove(&key);
+        }
+        output
+    }
+
+    #[cfg(test)]
+    pub(crate) fn in_flight(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_run() {
+        let flight = SingleFlight::<u32>::default();
+        let runs = Arc::new(AtomicU32::new(0));
+        let call = |key: &str| {
+            let runs = runs.clone();
+            flight.run(key.to_string(), move || async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                runs.fetch_add(1, Ordering::SeqCst) + 1
+            })
+        };
+        let (a, b, c) = tokio::join!(call("ListOnline"), call("ListOnline"), call("Ping"));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(flight.in_flight(), 0);
+
+        // A later call starts a new run
+        assert_eq!(call("ListOnline").await, 3);
+    }
+}
@@ -0,0 +1,209 @@
+//! Every API command the crate supports, with its parameter and result types.
+//!
+//! Each command is a zero-sized type implementing [`Command`], so generic code
+//! can be written over the whole API surface, and [`CommandKind::ALL`] lists
+//! them at runtime.
+#![deny(missing_docs)]
+
+use crate::client::TrueSocksClient;
+use crate::models::{
+    AccountStatusResult, ApiError, DisableProxyRenewalResult, EnableProxyRenewalResult,
+    ListHistoryResult, ListOnlineResult, ListZipSearchResult, ProxyCheckResult, PurchaseResult,
+    TestAndRefundResult,
+};
+use crate::status_codes::BAD_REQUEST;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// An API command, identified by its `cmd` name.
+pub trait Command {
+    /// Value sent as the `cmd` query parameter.
+    const NAME: &'static str;
+    /// Query parameters besides `key` and `cmd`.
+    type Params: Serialize;
+    /// Type of the `result` field of a successful response.
+    type Output: DeserializeOwned;
+}
+
+/// Parameters of commands that take none.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct NoParams {}
+
+/// Parameters of commands acting on a single proxy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ProxyIdParams {
+    /// `ProxyID` of the proxy.
+    #[serde(rename = "proxyid")]
+    pub proxy_id: u32,
+}
+
+/// Parameters of commands acting on a single history entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct HistoryIdParams {
+    /// `HistoryID` of the entry.
+    #[serde(rename = "historyid")]
+    pub history_id: u64,
+}
+
+/// Parameters of `ListZipSearch`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ZipSearchParams {
+    /// Two letter country code.
+    #[serde(rename = "countrycode")]
+    pub country_code: String,
+    /// Zip code to search around.
+    #[serde(rename = "zipcode")]
+    pub zip_code: String,
+    /// Distance units, as accepted by the API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub units: Option<String>,
+    /// Search radius in `units`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<u32>,
+}
+
+/// Parameters of `ListHistory`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct HistoryParams {
+    /// 1 to only list active entries.
+    #[serde(rename = "onlyactive", skip_serializing_if = "Option::is_none")]
+    pub only_active: Option<u32>,
+    /// Page number, starting at 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<u32>,
+}
+
+/// Parameters of `HistoryEntryChangeNote`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct ChangeNoteParams {
+    /// `HistoryID` of the entry.
+    #[serde(rename = "historyid")]
+    pub history_id: u64,
+    /// New note, `None` clears it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+macro_rules! commands {
+    ($($(#[$doc:meta])* $name:ident => $params:ty, $output:ty;)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            pub struct $name;
+
+            impl Command for $name {
+                const NAME: &'static str = stringify!($name);
+                type Params = $params;
+                type Output = $output;
+            }
+        )*
+
+        /// Runtime list of the supported commands.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum CommandKind {
+            $(
+                $(#[$doc])*
+                $name,
+            )*
+        }
+
+        impl CommandKind {
+            /// Every supported command.
+            pub const ALL: &'static [CommandKind] = &[$(CommandKind::$name),*];
+
+            /// The `cmd` name of the command.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(CommandKind::$name => <$name as Command>::NAME,)*
+                }
+            }
+
+            /// Look a command up by its `cmd` name, case-insensitively.
+            pub fn from_name(name: &str) -> Option<CommandKind> {
+                Self::ALL
+                    .iter()
+                    .copied()
+                    .find(|kind| kind.name().eq_ignore_ascii_case(name))
+            }
+
+            /// Schema of the parameters of the command.
+            #[cfg(feature = "schemars")]
+            pub fn params_schema(self, generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+                match self {
+                    $(CommandKind::$name => generator.subschema_for::<$params>(),)*
+                }
+            }
+
+            /// Schema of the `result` field of a successful response.
+            #[cfg(feature = "schemars")]
+            pub fn result_schema(self, generator: &mut schemars::SchemaGenerator) -> schemars::Schema {
+                match self {
+                    $(CommandKind::$name => generator.subschema_for::<$output>(),)*
+                }
+            }
+        }
+    };
+}
+
+commands! {
+    /// Check the API key and connectivity.
+    Ping => NoParams, bool;
+    /// List every proxy currently online.
+    ListOnline => NoParams, ListOnlineResult;
+    /// List proxies near a zip code.
+    ListZipSearch => ZipSearchParams, ListZipSearchResult;
+    /// List purchase history, one page at a time.
+    ListHistory => HistoryParams, ListHistoryResult;
+    /// Shared purchase of a regular proxy.
+    RegularProxyBuy => ProxyIdParams, PurchaseResult;
+    /// Private rental of a regular proxy.
+    RegularProxyRent => ProxyIdParams, PurchaseResult;
+    /// Shared purchase of a fresh proxy.
+    FreshProxyBuy => ProxyIdParams, PurchaseResult;
+    /// Private rental of a fresh proxy.
+    FreshProxyRent => ProxyIdParams, PurchaseResult;
+    /// Run the server side tests on a purchased proxy.
+    BoughtProxyCheck => ProxyIdParams, ProxyCheckResult;
+    /// Test a purchased proxy and refund it if it fails.
+    BoughtProxyRefund => ProxyIdParams, TestAndRefundResult;
+    /// Enable automatic renewal of a history entry.
+    BoughtProxyRenewEnable => HistoryIdParams, EnableProxyRenewalResult;
+    /// Disable automatic renewal of a history entry.
+    BoughtProxyRenewDisable => HistoryIdParams, DisableProxyRenewalResult;
+    /// Set or clear the note of a history entry.
+    HistoryEntryChangeNote => ChangeNoteParams, Option<bool>;
+    /// Account details and credit balance.
+    AccountStatus => NoParams, AccountStatusResult;
+}
+
+impl TrueSocksClient {
+    /// Run any command with typed parameters and result.
+    pub async fn execute<C: Command>(&self, params: &C::Params) -> Result<C::Output, ApiError> {
+        let params = serde_json::to_value(params).map_err(|_| ApiError::from(BAD_REQUEST))?;
+        self.execute_command::<C::Output>(C::NAME, Some(params))
+            .await
+            .map(|res| res.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_names() {
+        assert_eq!(CommandKind::ALL.len(), 14);
+        assert_eq!(CommandKind::ListOnline.name(), "ListOnline");
+        assert_eq!(
+            CommandKind::from_name("boughtproxycheck"),
+            Some(CommandKind::BoughtProxyCheck)
+        );
+        assert_eq!(<ListZipSearch as Command>::NAME, "ListZipSearch");
+    }
+}
@@ -0,0 +1,189 @@
+use crate::client::{TrueSocksClient, TrueSocksClientBuilder};
+use secrecy::SecretString;
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+/// Client settings read from `TRUESOCKS_*` environment variables or a TOML file.
+///
+/// ```toml
+/// api_key = "..."
+/// base_url = "https://api.truesocks.net/"
+/// connect_timeout_ms = 3000
+/// timeout_ms = 30000
+/// max_retries = 3
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub api_key: SecretString,
+    pub base_url: Option<String>,
+    pub connect_timeout_ms: Option<u64>,
+    pub timeout_ms: Option<u64>,
+    pub max_retries: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // Neither set in the environment nor in the file
+    MissingApiKey,
+    Invalid { key: String, message: String },
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingApiKey => write!(f, "no API key configured"),
+            ConfigError::Invalid { key, message } => write!(f, "invalid {}: {}", key, message),
+            ConfigError::Io(err) => write!(f, "could not read config file: {}", err),
+            ConfigError::Parse(message) => write!(f, "could not parse config file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn parse_var<T: std::str::FromStr>(
+    key: &str,
+    value: Option<String>,
+) -> Result<Option<T>, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    value
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .map_err(|err: T::Err| ConfigError::Invalid {
+                    key: key.to_string(),
+                    message: err.to_string(),
+                })
+        })
+        .transpose()
+}
+
+impl ClientConfig {
+    /// Read `TRUESOCKS_API_KEY`, `TRUESOCKS_BASE_URL`, `TRUESOCKS_CONNECT_TIMEOUT_MS`,
+    /// `TRUESOCKS_TIMEOUT_MS` and `TRUESOCKS_MAX_RETRIES`. Only the key is required.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|key| std::env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let var = |key: &str| var(key).filter(|value| !value.is_empty());
+        Ok(ClientConfig {
+            api_key: var("TRUESOCKS_API_KEY")
+                .ok_or(ConfigError::MissingApiKey)?
+                .into(),
+            base_url: var("TRUESOCKS_BASE_URL"),
+            connect_timeout_ms: parse_var(
+                "TRUESOCKS_CONNECT_TIMEOUT_MS",
+                var("TRUESOCKS_CONNECT_TIMEOUT_MS"),
+            )?,
+            timeout_ms: parse_var("TRUESOCKS_TIMEOUT_MS", var("TRUESOCKS_TIMEOUT_MS"))?,
+            max_retries: parse_var("TRUESOCKS_MAX_RETRIES", var("TRUESOCKS_MAX_RETRIES"))?,
+        })
+    }
+
+    /// Parse a TOML config, see the type documentation for the keys.
+    #[cfg(feature = "config")]
+    pub fn from_toml(config: &str) -> Result<Self, ConfigError> {
+        use secrecy::ExposeSecret;
+
+        let config: ClientConfig =
+            toml::from_str(config).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        if config.api_key.expose_secret().is_empty() {
+            return Err(ConfigError::MissingApiKey);
+        }
+        Ok(config)
+    }
+
+    #[cfg(feature = "config")]
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self, ConfigError> {
+        let config = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::from_toml(&config)
+    }
+
+    /// A client builder with these settings applied, for further customization.
+    pub fn builder(self) -> TrueSocksClientBuilder {
+        let mut builder = TrueSocksClient::builder(self.api_key);
+        if let Some(url) = self.base_url {
+            builder = builder.base_url(url);
+        }
+        if let Some(ms) = self.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(ms));
+        }
+        if let Some(retries) = self.max_retries {
+            builder = builder.max_retries(retries);
+        }
+        builder
+    }
+}
+
+impl TrueSocksClient {
+    /// Client configured from `TRUESOCKS_*` environment variables, see [`ClientConfig::from_env`].
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(ClientConfig::from_env()?.builder().build())
+    }
+
+    /// Client configured from a TOML file, see [`ClientConfig`].
+    #[cfg(feature = "config")]
+    pub fn from_config_file(path: impl AsRef<std::path::Path>) -> Result<Self, ConfigError> {
+        Ok(ClientConfig::from_file(path)?.builder().build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_config_from_vars() {
+        let vars = HashMap::from([
+            ("TRUESOCKS_API_KEY", "b7f3c1e9-live-api-key"),
+            ("TRUESOCKS_TIMEOUT_MS", "2500"),
+            ("TRUESOCKS_BASE_URL", ""),
+        ]);
+        let config =
+            ClientConfig::from_vars(|key| vars.get(key).map(|value| value.to_string())).unwrap();
+        assert_eq!(config.api_key.expose_secret(), "b7f3c1e9-live-api-key");
+        assert_eq!(config.timeout_ms, Some(2500));
+        assert_eq!(config.base_url, None);
+        assert!(!format!("{:?}", config).contains("b7f3c1e9-live-api-key"));
+        let client = config.builder().build();
+        assert!(!format!("{:?}", client).contains("b7f3c1e9-live-api-key"));
+
+        let vars = HashMap::from([("TRUESOCKS_API_KEY", "key"), ("TRUESOCKS_MAX_RETRIES", "x")]);
+        let res = ClientConfig::from_vars(|key| vars.get(key).map(|value| value.to_string()));
+        assert!(
+            matches!(res, Err(ConfigError::Invalid { key, .. }) if key == "TRUESOCKS_MAX_RETRIES")
+        );
+        assert!(matches!(
+            ClientConfig::from_vars(|_| None),
+            Err(ConfigError::MissingApiKey)
+        ));
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_config_from_toml() {
+        let config = ClientConfig::from_toml(
+            "api_key = \"key\"\nbase_url = \"http://localhost:8080/\"\nmax_retries = 0\n",
+        )
+        .unwrap();
+        assert_eq!(config.base_url.as_deref(), Some("http://localhost:8080/"));
+        assert_eq!(config.max_retries, Some(0));
+        assert!(matches!(
+            ClientConfig::from_toml("api_key = \"key\"\nretries = 1\n"),
+            Err(ConfigError::Parse(_))
+        ));
+    }
+}
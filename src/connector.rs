@@ -0,0 +1,235 @@
+use crate::models::ConnectInfo;
+use crate::pool::{PoolCheckout, ProxyPool};
+use crate::socks::{dial, SocksError};
+use hyper::client::connect::{Connected, Connection};
+use hyper::Uri;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use tokio_socks::tcp::Socks5Stream;
+use tower_service::Service;
+
+#[derive(Clone)]
+enum Route {
+    Fixed(ConnectInfo),
+    // Every connection checks out a member, rotating over the pool
+    Pool(ProxyPool),
+}
+
+/// hyper connector dialing every connection through a purchased proxy, either
+/// one fixed session or a [`ProxyPool`]. Connections are plain TCP, wrap the
+/// connector with `hyper-tls` or `hyper-rustls` for `https` URIs.
+#[derive(Clone)]
+pub struct TrueSocksConnector {
+    route: Route,
+    timeout: Option<Duration>,
+}
+
+impl TrueSocksConnector {
+    pub fn new(connect_info: ConnectInfo) -> Self {
+        TrueSocksConnector {
+            route: Route::Fixed(connect_info),
+            timeout: None,
+        }
+    }
+
+    /// Connector taking a member from `pool` for each connection, the least
+    /// used first. The member stays checked out while the connection is open.
+    pub fn with_pool(pool: ProxyPool) -> Self {
+        TrueSocksConnector {
+            route: Route::Pool(pool),
+            timeout: None,
+        }
+    }
+
+    /// Give up on connections not established within `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+fn target(uri: &Uri) -> Result<(String, u16), SocksError> {
+    let host = uri
+        .host()
+        .ok_or(tokio_socks::Error::InvalidTargetAddress("URI without host"))?;
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        });
+    // IPv6 literals come bracketed
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Ok((host.to_string(), port))
+}
+
+impl Service<Uri> for TrueSocksConnector {
+    type Response = ProxyStream;
+    type Error = SocksError;
+    type Future = Pin<Box<dyn Future<Output = Result<ProxyStream, SocksError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), SocksError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let route = self.route.clone();
+        let timeout = self.timeout;
+        Box::pin(async move {
+            let (host, port) = target(&uri)?;
+            let (connect_info, checkout) = match route {
+                Route::Fixed(connect_info) => (connect_info, None),
+                Route::Pool(pool) => {
+                    let checkout = pool.checkout().map_err(SocksError::Pool)?;
+                    (checkout.connect_info().clone(), Some(checkout))
+                }
+            };
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let stream = dial(&connect_info, (host.as_str(), port), deadline).await?;
+            Ok(ProxyStream { stream, checkout })
+        })
+    }
+}
+
+/// Connection made by [`TrueSocksConnector`].
+pub struct ProxyStream {
+    stream: Socks5Stream<TcpStream>,
+    // Returns the pool member once the connection is dropped
+    checkout: Option<PoolCheckout>,
+}
+
+impl ProxyStream {
+    /// History entry the connection goes through, None for a fixed session.
+    pub fn history_id(&self) -> Option<u64> {
+        self.checkout.as_ref().map(PoolCheckout::history_id)
+    }
+}
+
+impl Connection for ProxyStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+impl AsyncRead for ProxyStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxyStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::list_info;
+    use crate::pool::PoolError;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // SOCKS5 server without authentication answering one HTTP request
+    async fn http_proxy() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            socket.read_exact(&mut greeting).await.unwrap();
+            socket.write_all(&[5, 0]).await.unwrap();
+            let mut request = [0u8; 5];
+            socket.read_exact(&mut request).await.unwrap();
+            let mut host = vec![0u8; request[4] as usize + 2];
+            socket.read_exact(&mut host).await.unwrap();
+            socket
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            let mut http = [0u8; 1024];
+            let _ = socket.read(&mut http).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_connector_dials_through_proxy() {
+        let connect_info = ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: http_proxy().await,
+            connect_session_id: String::new(),
+        };
+        let client = hyper::Client::builder().build::<_, hyper::Body>(
+            TrueSocksConnector::new(connect_info).connect_timeout(Duration::from_secs(5)),
+        );
+        let response = client
+            .get("http://example.com/".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"ok");
+    }
+
+    #[tokio::test]
+    async fn test_pool_connector_holds_member() {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        let mut connector = TrueSocksConnector::with_pool(pool.clone());
+        let res = connector.call("http://example.com/".parse().unwrap()).await;
+        assert!(matches!(res, Err(SocksError::Pool(PoolError::Empty))));
+
+        let mut entry = list_info(1);
+        entry.connect_info = Some(ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: http_proxy().await,
+            connect_session_id: String::new(),
+        });
+        pool.insert(entry);
+        let stream = connector
+            .call("http://example.com/".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(stream.history_id(), Some(1));
+        assert_eq!(pool.outstanding(), 1);
+        drop(stream);
+        assert_eq!(pool.outstanding(), 0);
+    }
+
+    #[test]
+    fn test_target_defaults_port() {
+        let uri: Uri = "https://[2001:db8::1]/path".parse().unwrap();
+        assert_eq!(target(&uri).unwrap(), ("2001:db8::1".to_string(), 443));
+        let uri: Uri = "http://example.com:8080".parse().unwrap();
+        assert_eq!(target(&uri).unwrap(), ("example.com".to_string(), 8080));
+    }
+}
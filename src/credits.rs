@@ -0,0 +1,134 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+/// An amount of TrueSocks credits, as used for proxy costs and account balances.
+///
+/// Subtraction saturates at zero, use [`Credits::checked_sub`] when going below
+/// zero has to be detected.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[cfg_attr(
+    feature = "schemars",
+    derive(schemars::JsonSchema),
+    schemars(description = "An amount of TrueSocks credits")
+)]
+#[serde(transparent)]
+pub struct Credits(pub u32);
+
+impl Credits {
+    pub const ZERO: Credits = Credits(0);
+
+    pub fn new(amount: u32) -> Self {
+        Credits(amount)
+    }
+
+    pub fn amount(self) -> u32 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_sub(self, other: Credits) -> Option<Credits> {
+        self.0.checked_sub(other.0).map(Credits)
+    }
+
+    pub fn checked_add(self, other: Credits) -> Option<Credits> {
+        self.0.checked_add(other.0).map(Credits)
+    }
+}
+
+impl From<u32> for Credits {
+    fn from(amount: u32) -> Self {
+        Credits(amount)
+    }
+}
+
+impl From<Credits> for u32 {
+    fn from(credits: Credits) -> Self {
+        credits.0
+    }
+}
+
+impl fmt::Display for Credits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} credits", self.0)
+    }
+}
+
+impl Add for Credits {
+    type Output = Credits;
+
+    fn add(self, other: Credits) -> Credits {
+        Credits(self.0.saturating_add(other.0))
+    }
+}
+
+impl AddAssign for Credits {
+    fn add_assign(&mut self, other: Credits) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Credits {
+    type Output = Credits;
+
+    fn sub(self, other: Credits) -> Credits {
+        Credits(self.0.saturating_sub(other.0))
+    }
+}
+
+impl SubAssign for Credits {
+    fn sub_assign(&mut self, other: Credits) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Credits {
+    fn sum<I: Iterator<Item = Credits>>(iter: I) -> Credits {
+        iter.fold(Credits::ZERO, Add::add)
+    }
+}
+
+impl PartialEq<u32> for Credits {
+    fn eq(&self, other: &u32) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialOrd<u32> for Credits {
+    fn partial_cmp(&self, other: &u32) -> Option<std::cmp::Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic_saturates() {
+        assert_eq!(Credits(5) + Credits(7), Credits(12));
+        assert_eq!(Credits(5) - Credits(7), Credits::ZERO);
+        assert_eq!(Credits(5).checked_sub(Credits(7)), None);
+        assert_eq!(Credits(u32::MAX).checked_add(Credits(1)), None);
+    }
+
+    #[test]
+    fn test_sum_and_compare() {
+        let total: Credits = [Credits(1), Credits(2), Credits(3)].into_iter().sum();
+        assert_eq!(total, 6);
+        assert!(total > 5);
+    }
+
+    #[test]
+    fn test_serde_transparent() {
+        let credits: Credits = serde_json::from_str("42").unwrap();
+        assert_eq!(credits, Credits(42));
+        assert_eq!(serde_json::to_string(&credits).unwrap(), "42");
+    }
+}
@@ -0,0 +1,187 @@
+use crate::models::{ListOnlineResult, ProxyInfo};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A part of a [`ProxyInfo`] that can change between two listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ProxyField {
+    RentCost,
+    PrivateRentCost,
+    Fresh,
+    Ip,
+    Hostname,
+    Isp,
+    // Country, region, city or zip code
+    Location,
+    Timezone,
+    ConnectionType,
+    Ping,
+    Speed,
+    UptimeQuality,
+    Blacklist,
+    Distance,
+    // Fields this version does not know
+    Extra,
+}
+
+/// The fields in which `after` differs from `before`.
+pub fn changed_fields(before: &ProxyInfo, after: &ProxyInfo) -> Vec<ProxyField> {
+    let checks = [
+        (ProxyField::RentCost, before.rent_cost != after.rent_cost),
+        (
+            ProxyField::PrivateRentCost,
+            before.private_rent_cost != after.private_rent_cost,
+        ),
+        (ProxyField::Fresh, before.is_fresh != after.is_fresh),
+        (ProxyField::Ip, before.ip != after.ip),
+        (ProxyField::Hostname, before.hostname != after.hostname),
+        (ProxyField::Isp, before.isp != after.isp),
+        (
+            ProxyField::Location,
+            before.country_code != after.country_code
+                || before.country != after.country
+                || before.region != after.region
+                || before.city != after.city
+                || before.zip_code != after.zip_code,
+        ),
+        (ProxyField::Timezone, before.timezone != after.timezone),
+        (
+            ProxyField::ConnectionType,
+            before.connection_type != after.connection_type,
+        ),
+        (ProxyField::Ping, before.ping != after.ping),
+        (ProxyField::Speed, before.speed != after.speed),
+        (
+            ProxyField::UptimeQuality,
+            before.uptime_quality != after.uptime_quality,
+        ),
+        (ProxyField::Blacklist, before.blacklist != after.blacklist),
+        (ProxyField::Distance, before.distance != after.distance),
+        (ProxyField::Extra, before.extra != after.extra),
+    ];
+    checks
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProxyChange {
+    pub before: ProxyInfo,
+    pub after: ProxyInfo,
+    // Never empty
+    pub fields: Vec<ProxyField>,
+}
+
+impl ProxyChange {
+    pub fn proxy_id(&self) -> u32 {
+        self.after.proxy_id
+    }
+
+    pub fn changed(&self, field: ProxyField) -> bool {
+        self.fields.contains(&field)
+    }
+}
+
+/// Difference between two proxy listings keyed by ProxyID, each list sorted
+/// by ProxyID.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProxyListDiff {
+    pub added: Vec<ProxyInfo>,
+    pub removed: Vec<ProxyInfo>,
+    pub changed: Vec<ProxyChange>,
+}
+
+impl ProxyListDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Diff two sets of proxies, `before` being the older one. A ProxyID listed
+/// more than once counts with its last record.
+pub fn diff_proxies<'a>(
+    before: impl IntoIterator<Item = &'a ProxyInfo>,
+    after: impl IntoIterator<Item = &'a ProxyInfo>,
+) -> ProxyListDiff {
+    let by_id = |proxies: &mut dyn Iterator<Item = &'a ProxyInfo>| -> BTreeMap<u32, &'a ProxyInfo> {
+        proxies.map(|proxy| (proxy.proxy_id, proxy)).collect()
+    };
+    let before = by_id(&mut before.into_iter());
+    let after = by_id(&mut after.into_iter());
+
+    let mut diff = ProxyListDiff::default();
+    for (proxy_id, proxy) in &before {
+        if !after.contains_key(proxy_id) {
+            diff.removed.push((*proxy).clone());
+        }
+    }
+    for (proxy_id, proxy) in &after {
+        match before.get(proxy_id) {
+            None => diff.added.push((*proxy).clone()),
+            Some(previous) => {
+                let fields = changed_fields(previous, proxy);
+                if !fields.is_empty() {
+                    diff.changed.push(ProxyChange {
+                        before: (*previous).clone(),
+                        after: (*proxy).clone(),
+                        fields,
+                    });
+                }
+            }
+        }
+    }
+    diff
+}
+
+impl ListOnlineResult {
+    /// What changed from this listing to `other`, the newer one.
+    pub fn diff(&self, other: &ListOnlineResult) -> ProxyListDiff {
+        diff_proxies(&self.proxy_list, &other.proxy_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+    use serde_json::json;
+
+    fn listing(proxies: Vec<serde_json::Value>) -> ListOnlineResult {
+        serde_json::from_value(json!({
+            "LastUpdate": 1,
+            "ProxyCount": proxies.len(),
+            "ProxyList": proxies
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_listings() {
+        let mut slower = proxy_info_json(2);
+        slower["Ping"] = json!(480.0);
+        slower["Speed"] = json!(10);
+        let mut listed = proxy_info_json(3);
+        listed["Blacklist"] = json!([{
+            "ID": "a", "Name": "A", "Type": "Web Abuse", "Desc": "", "Link": ""
+        }]);
+        let before = listing(vec![
+            proxy_info_json(1),
+            proxy_info_json(2),
+            proxy_info_json(3),
+        ]);
+        let after = listing(vec![slower, listed, proxy_info_json(4)]);
+
+        let diff = before.diff(&after);
+        let ids = |proxies: &[ProxyInfo]| proxies.iter().map(|p| p.proxy_id).collect::<Vec<_>>();
+        assert_eq!(ids(&diff.removed), vec![1]);
+        assert_eq!(ids(&diff.added), vec![4]);
+        assert_eq!(diff.changed.len(), 2);
+        assert_eq!(
+            diff.changed[0].fields,
+            vec![ProxyField::Ping, ProxyField::Speed]
+        );
+        assert!(diff.changed[1].changed(ProxyField::Blacklist));
+        assert!(before.diff(&before).is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+//! Response cache kept on disk, so jobs re-run several times an hour reuse
+//! list data fetched by earlier runs instead of asking the API again.
+
+use crate::logging::{sublog, Subsystem};
+use crate::state::write_atomic;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::Level;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Lists that only change when proxies come and go, cached unless configured otherwise
+const DEFAULT_TTLS: [(&str, Duration); 2] = [
+    ("ListOnline", Duration::from_secs(60)),
+    ("ListZipSearch", Duration::from_secs(60)),
+];
+const EXTENSION: &str = "json.gz";
+
+/// Gzipped response bodies stored in a directory, one file per command and
+/// parameters. Only commands with a TTL are cached: `ListOnline` and
+/// `ListZipSearch` for 60 seconds by default. Responses downgraded to a
+/// warning are not stored.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    ttls: HashMap<String, Duration>,
+}
+
+impl DiskCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DiskCache {
+            dir: dir.into(),
+            ttls: DEFAULT_TTLS
+                .iter()
+                .map(|(command, ttl)| (command.to_string(), *ttl))
+                .collect(),
+        }
+    }
+
+    /// Cache responses of `command` for `ttl`.
+    pub fn ttl(mut self, command: impl Into<String>, ttl: Duration) -> Self {
+        self.ttls.insert(command.into(), ttl);
+        self
+    }
+
+    /// Stop caching responses of `command`.
+    pub fn skip(mut self, command: &str) -> Self {
+        self.ttls.remove(command);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Remove every cached response.
+    pub fn clear(&self) -> io::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(EXTENSION) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn caches(&self, command: &str) -> bool {
+        self.ttls.contains_key(command)
+    }
+
+    // File of a command, `scope` keeps the responses of different API keys apart
+    fn path(&self, scope: &str, command: &str, params: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        (scope, command, params).hash(&mut hasher);
+        self.dir.join(format!(
+            "{}-{:016x}.{}",
+            command,
+            hasher.finish(),
+            EXTENSION
+        ))
+    }
+
+    // Body stored for the call if it is younger than the command's TTL
+    pub(crate) fn get(&self, scope: &str, command: &str, params: &str) -> Option<String> {
+        let ttl = *self.ttls.get(command)?;
+        let path = self.path(scope, command, params);
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or(Duration::ZERO);
+        if age > ttl {
+            return None;
+        }
+        let mut body = String::new();
+        match File::open(&path).and_then(|file| GzDecoder::new(file).read_to_string(&mut body)) {
+            Ok(_) => Some(body),
+            Err(err) => {
+                sublog!(
+                    Subsystem::Transport,
+                    Level::Debug,
+                    "ignoring unreadable cache file {}: {}",
+                    path.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    pub(crate) fn put(&self, scope: &str, command: &str, params: &str, body: &str) {
+        if !self.caches(command) {
+            return;
+        }
+        let path = self.path(scope, command, params);
+        let written = fs::create_dir_all(&self.dir).and_then(|_| {
+            write_atomic(&path, |writer| {
+                let mut encoder = GzEncoder::new(writer, Compression::default());
+                io::Write::write_all(&mut encoder, body.as_bytes())?;
+                encoder.finish().map(|_| ())
+            })
+        });
+        if let Err(err) = written {
+            sublog!(
+                Subsystem::Transport,
+                Level::Warn,
+                "failed to cache {} response in {}: {}",
+                command,
+                path.display(),
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::{history_page, list_info_json, ok_response, serve};
+    use serde_json::json;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("truesocks-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = DiskCache::new(temp_dir("disk-cache-ttl")).ttl("ListOnline", Duration::ZERO);
+        cache.put("key", "ListOnline", "{}", "{\"status\":{}}");
+        cache.put("key", "BoughtProxyCheck", "{}", "{}");
+        assert_eq!(cache.get("key", "ListOnline", "{}"), None);
+        assert_eq!(cache.get("key", "BoughtProxyCheck", "{}"), None);
+
+        let cache = cache.ttl("ListOnline", Duration::from_secs(60));
+        assert_eq!(
+            cache.get("key", "ListOnline", "{}").as_deref(),
+            Some("{\"status\":{}}")
+        );
+        assert_eq!(cache.get("other key", "ListOnline", "{}"), None);
+        cache.clear().unwrap();
+        assert_eq!(cache.get("key", "ListOnline", "{}"), None);
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_client_reuses_cached_response() {
+        let dir = temp_dir("disk-cache-client");
+        let body = ok_response(json!({
+            "LastUpdate": 1,
+            "ProxyCount": 0,
+            "ProxyList": [],
+        }));
+        let history = ok_response(history_page(vec![list_info_json(7, 1)], 1, 1));
+        // The second ListOnline comes from the cache, a new client included
+        let (url, requests) = serve(vec![body, history.clone(), history]);
+        let build = || {
+            TrueSocksClient::builder("test")
+                .base_url(url.clone())
+                .disk_cache(DiskCache::new(&dir))
+                .build()
+        };
+        build().list_online_proxies().await.unwrap();
+        let list = build().list_online_proxies().await.unwrap();
+        assert_eq!(list.last_update, 1);
+
+        let client = build();
+        let query = Default::default();
+        client.list_history(&query).await.unwrap();
+        client.list_history(&query).await.unwrap();
+        let requests = requests.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[1].contains("ListHistory"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use crate::isp::{IspNormalizer, IspResolver};
+use crate::models::ProxyInfo;
+use crate::subnet::Cidr;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// Prefix lengths two proxies have to share to count as one network
+const IPV4_SUBNET: u8 = 24;
+const IPV6_SUBNET: u8 = 64;
+
+/// The network a proxy's IP belongs to, a /24 for IPv4 and a /64 for IPv6.
+pub fn subnet_of(proxy: &ProxyInfo) -> Option<Cidr> {
+    let address: IpAddr = proxy.ip.as_deref()?.trim().parse().ok()?;
+    let prefix_len = if address.is_ipv4() {
+        IPV4_SUBNET
+    } else {
+        IPV6_SUBNET
+    };
+    Cidr::new(address, prefix_len)
+}
+
+/// Limits on how concentrated the proxies handed out by a
+/// [`ProxyPool`](crate::pool::ProxyPool) may be, counted over the distinct
+/// members currently checked out.
+///
+/// The maximums are hard: a member that would exceed one is skipped, and a
+/// member already checked out is shared instead. `min_countries` is a
+/// preference: while fewer countries are in use, members from a new country
+/// are taken first when there are any.
+#[derive(Clone, Default)]
+pub struct DiversityConstraints {
+    pub max_per_subnet: Option<usize>,
+    pub max_per_isp: Option<usize>,
+    pub max_per_city: Option<usize>,
+    pub min_countries: Option<usize>,
+    // Groups members by ISP, `IspNormalizer` when unset
+    isp_resolver: Option<Arc<dyn IspResolver>>,
+}
+
+impl fmt::Debug for DiversityConstraints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DiversityConstraints")
+            .field("max_per_subnet", &self.max_per_subnet)
+            .field("max_per_isp", &self.max_per_isp)
+            .field("max_per_city", &self.max_per_city)
+            .field("min_countries", &self.min_countries)
+            .field("custom_isp_resolver", &self.isp_resolver.is_some())
+            .finish()
+    }
+}
+
+impl DiversityConstraints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_per_subnet(mut self, max: usize) -> Self {
+        self.max_per_subnet = Some(max);
+        self
+    }
+
+    pub fn max_per_isp(mut self, max: usize) -> Self {
+        self.max_per_isp = Some(max);
+        self
+    }
+
+    pub fn max_per_city(mut self, max: usize) -> Self {
+        self.max_per_city = Some(max);
+        self
+    }
+
+    pub fn min_countries(mut self, min: usize) -> Self {
+        self.min_countries = Some(min);
+        self
+    }
+
+    /// Decide which ISP a proxy belongs to with `resolver`, such as an
+    /// `AsnDatabase`.
+    pub fn isp_resolver<R: IspResolver + 'static>(mut self, resolver: R) -> Self {
+        self.isp_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    pub fn is_unconstrained(&self) -> bool {
+        self.max_per_subnet.is_none()
+            && self.max_per_isp.is_none()
+            && self.max_per_city.is_none()
+            && self.min_countries.is_none()
+    }
+
+    fn isp_key(&self, proxy: &ProxyInfo) -> String {
+        match &self.isp_resolver {
+            Some(resolver) => resolver.isp_key(proxy),
+            None => IspNormalizer::new().isp_key(proxy),
+        }
+    }
+
+    /// Whether taking `candidate` next to the proxies `in_use` keeps every
+    /// maximum. Proxies without a known IP are not limited per subnet.
+    pub fn allows(&self, candidate: &ProxyInfo, in_use: &[&ProxyInfo]) -> bool {
+        let under = |max: Option<usize>, same: &dyn Fn(&ProxyInfo) -> bool| {
+            max.is_none_or(|max| in_use.iter().filter(|proxy| same(proxy)).count() < max)
+        };
+        let subnet = subnet_of(candidate);
+        let isp = self.max_per_isp.map(|_| self.isp_key(candidate));
+        under(self.max_per_subnet, &|proxy| {
+            subnet.is_some() && subnet_of(proxy) == subnet
+        }) && under(self.max_per_isp, &|proxy| {
+            isp.as_ref() == Some(&self.isp_key(proxy))
+        }) && under(self.max_per_city, &|proxy| {
+            proxy
+                .country_code
+                .eq_ignore_ascii_case(&candidate.country_code)
+                && proxy.city.eq_ignore_ascii_case(&candidate.city)
+        })
+    }
+
+    /// Countries of `in_use` when there are fewer than `min_countries` of
+    /// them, None once the minimum is met.
+    pub(crate) fn missing_countries(&self, in_use: &[&ProxyInfo]) -> Option<HashSet<String>> {
+        let min = self.min_countries?;
+        let countries: HashSet<String> = in_use
+            .iter()
+            .map(|proxy| proxy.country_code.to_ascii_uppercase())
+            .collect();
+        (countries.len() < min).then_some(countries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::filter::ProxyFilter;
+    use crate::fixtures::list_info;
+    use crate::models::ListInfo;
+    use crate::pool::{PoolError, ProxyPool};
+
+    fn member(history_id: u64, country_code: &str, city: &str, ip: &str) -> ListInfo {
+        let mut entry = list_info(history_id);
+        entry.proxy_info.country_code = country_code.to_string();
+        entry.proxy_info.city = city.to_string();
+        entry.proxy_info.ip = Some(ip.to_string());
+        entry
+    }
+
+    #[test]
+    fn test_diversity_at_checkout() {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        pool.insert(member(1, "US", "New York", "203.0.113.7"));
+        pool.insert(member(2, "US", "Boston", "203.0.113.8"));
+        pool.insert(member(3, "DE", "Berlin", "198.51.100.1"));
+        pool.set_diversity(DiversityConstraints::new().max_per_subnet(1));
+
+        let first = pool.checkout().unwrap();
+        assert_eq!(first.history_id(), 1);
+        let second = pool.checkout().unwrap();
+        assert_eq!(second.history_id(), 3);
+        // Member 2 shares the /24 of member 1, which is shared instead
+        let third = pool.checkout().unwrap();
+        assert_eq!(third.history_id(), 1);
+
+        let boston = ProxyFilter::new().city("Boston");
+        assert_eq!(
+            pool.checkout_matching(&boston).err(),
+            Some(PoolError::Diversity)
+        );
+        drop((first, third));
+        assert_eq!(pool.checkout_matching(&boston).unwrap().history_id(), 2);
+    }
+
+    #[test]
+    fn test_min_countries_prefers_new_country() {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        pool.insert(member(1, "US", "New York", "203.0.113.7"));
+        pool.insert(member(2, "US", "Boston", "192.0.2.1"));
+        pool.insert(member(3, "DE", "Berlin", "198.51.100.1"));
+        pool.set_diversity(DiversityConstraints::new().min_countries(2));
+
+        let _first = pool.checkout().unwrap();
+        assert_eq!(pool.checkout().unwrap().history_id(), 3);
+
+        let isp = DiversityConstraints::new().max_per_isp(1);
+        let candidate = member(4, "FR", "Paris", "192.0.2.9").proxy_info;
+        let in_use = member(5, "US", "Boston", "192.0.2.10").proxy_info;
+        assert!(!isp.allows(&candidate, &[&in_use]));
+        assert!(isp.allows(&candidate, &[]));
+    }
+}
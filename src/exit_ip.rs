@@ -0,0 +1,228 @@
+use crate::models::{ConnectInfo, ListInfo};
+use serde_json::Value;
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Default echo endpoint, answers with the caller's IP as plain text.
+pub const DEFAULT_ECHO_URL: &str = "https://api.ipify.org";
+
+/// Where and how long to ask for the exit IP of a proxy.
+#[derive(Debug, Clone)]
+pub struct ExitIpOptions {
+    // Endpoint answering with the caller's IP, as plain text or JSON with an
+    // "ip" or "origin" field
+    pub echo_url: String,
+    pub timeout: Duration,
+}
+
+impl Default for ExitIpOptions {
+    fn default() -> Self {
+        ExitIpOptions {
+            echo_url: DEFAULT_ECHO_URL.to_string(),
+            timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+impl ExitIpOptions {
+    pub fn echo_url(mut self, echo_url: impl Into<String>) -> Self {
+        self.echo_url = echo_url.into();
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum ExitIpError {
+    // The history entry has no connect info to route through
+    NoConnectInfo,
+    Http(reqwest::Error),
+    // The echo endpoint answered with a non-success HTTP status
+    Status(u16),
+    // The echo endpoint answered with something that is not an IP
+    InvalidResponse(String),
+}
+
+impl fmt::Display for ExitIpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitIpError::NoConnectInfo => write!(f, "entry has no connect info"),
+            ExitIpError::Http(err) => write!(f, "echo request failed: {}", err),
+            ExitIpError::Status(status) => write!(f, "echo endpoint answered with HTTP {}", status),
+            ExitIpError::InvalidResponse(body) => {
+                write!(f, "echo endpoint answered without an IP: {:?}", body)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExitIpError {}
+
+/// Outcome of [`verify_exit_ip`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitIpReport {
+    // IP the echo endpoint saw
+    pub exit_ip: IpAddr,
+    // IP the API reports for the proxy, None when it reports none
+    pub expected: Option<IpAddr>,
+    // IPHasChanged flag of the history entry, false for bare connect info
+    pub ip_has_changed: bool,
+}
+
+impl ExitIpReport {
+    pub fn matches(&self) -> bool {
+        self.expected == Some(self.exit_ip)
+    }
+
+    /// Traffic left from a different IP than the one the API reports.
+    pub fn is_misrouted(&self) -> bool {
+        self.expected
+            .is_some_and(|expected| expected != self.exit_ip)
+    }
+
+    /// Whether the proxy should not be used as is: it exits from the wrong IP
+    /// or the API flagged its IP as changed.
+    pub fn needs_attention(&self) -> bool {
+        self.is_misrouted() || self.ip_has_changed
+    }
+}
+
+/// Asks the echo endpoint of `options` for the exit IP of `connect_info` and
+/// compares it with `expected`, usually [`ProxyInfo::ip`].
+///
+/// [`ProxyInfo::ip`]: crate::models::ProxyInfo::ip
+pub async fn verify_exit_ip(
+    connect_info: &ConnectInfo,
+    expected: Option<&str>,
+    options: &ExitIpOptions,
+) -> Result<ExitIpReport, ExitIpError> {
+    let exit_ip = connect_info.exit_ip(options).await?;
+    Ok(ExitIpReport {
+        exit_ip,
+        expected: expected.and_then(|ip| ip.trim().parse().ok()),
+        ip_has_changed: false,
+    })
+}
+
+impl ConnectInfo {
+    /// HTTP client sending every request through this proxy, with DNS resolved
+    /// on the proxy side.
+    pub fn http_client(&self, timeout: Duration) -> reqwest::Result<reqwest::Client> {
+        let proxy_url = match self.credentials() {
+            Some((username, password)) => format!(
+                "socks5h://{}:{}@{}:{}",
+                username, password, self.connect_ip, self.connect_port
+            ),
+            None => format!("socks5h://{}:{}", self.connect_ip, self.connect_port),
+        };
+        reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all(proxy_url)?)
+            .timeout(timeout)
+            .build()
+    }
+
+    /// The IP traffic through this proxy leaves from, as seen by the echo
+    /// endpoint of `options`.
+    pub async fn exit_ip(&self, options: &ExitIpOptions) -> Result<IpAddr, ExitIpError> {
+        let client = self
+            .http_client(options.timeout)
+            .map_err(ExitIpError::Http)?;
+        let response = client
+            .get(&options.echo_url)
+            .send()
+            .await
+            .map_err(ExitIpError::Http)?;
+        if !response.status().is_success() {
+            return Err(ExitIpError::Status(response.status().as_u16()));
+        }
+        let body = response.text().await.map_err(ExitIpError::Http)?;
+        parse_echo(&body).ok_or(ExitIpError::InvalidResponse(body))
+    }
+}
+
+impl ListInfo {
+    /// [`verify_exit_ip`] for this entry, against its `ProxyInfo::ip` and
+    /// carrying its `IPHasChanged` flag.
+    pub async fn verify_exit_ip(
+        &self,
+        options: &ExitIpOptions,
+    ) -> Result<ExitIpReport, ExitIpError> {
+        let connect_info = self
+            .connect_info
+            .as_ref()
+            .ok_or(ExitIpError::NoConnectInfo)?;
+        let mut report =
+            verify_exit_ip(connect_info, self.proxy_info.ip.as_deref(), options).await?;
+        report.ip_has_changed = self.ip_has_changed;
+        Ok(report)
+    }
+}
+
+// Plain text IP, or JSON with "ip" (ipify, ifconfig.co) or "origin" (httpbin)
+fn parse_echo(body: &str) -> Option<IpAddr> {
+    let body = body.trim();
+    if let Ok(ip) = body.parse() {
+        return Some(ip);
+    }
+    let value: Value = serde_json::from_str(body).ok()?;
+    let ip = value.get("ip").or_else(|| value.get("origin"))?.as_str()?;
+    // httpbin lists every hop, the first one is the client
+    ip.split(',').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{list_info, serve_socks};
+
+    fn entry(port: u16, ip: &str) -> ListInfo {
+        let mut entry = list_info(1);
+        entry.connect_info = Some(ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: port,
+            connect_session_id: "session".to_string(),
+        });
+        entry.proxy_info.ip = Some(ip.to_string());
+        entry
+    }
+
+    #[tokio::test]
+    async fn test_verify_exit_ip_match() {
+        let (port, handle) = serve_socks("203.0.113.7\n".into());
+        let options = ExitIpOptions::default().echo_url("http://echo.test/");
+        let report = entry(port, "203.0.113.7")
+            .verify_exit_ip(&options)
+            .await
+            .unwrap();
+        assert!(report.matches());
+        assert!(!report.needs_attention());
+        assert_eq!(handle.join().unwrap(), "session");
+    }
+
+    #[tokio::test]
+    async fn test_verify_exit_ip_misroute() {
+        let (port, _) = serve_socks(r#"{"ip": "198.51.100.1"}"#.into());
+        let options = ExitIpOptions::default().echo_url("http://echo.test/");
+        let mut entry = entry(port, "203.0.113.7");
+        entry.ip_has_changed = true;
+        let report = entry.verify_exit_ip(&options).await.unwrap();
+        assert_eq!(report.exit_ip, "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert!(report.is_misrouted());
+        assert!(report.ip_has_changed);
+    }
+
+    #[test]
+    fn test_parse_echo() {
+        assert_eq!(parse_echo("2001:db8::1"), "2001:db8::1".parse().ok());
+        assert_eq!(
+            parse_echo(r#"{"origin": "192.0.2.1, 10.0.0.1"}"#),
+            "192.0.2.1".parse().ok()
+        );
+        assert_eq!(parse_echo("<html>"), None);
+    }
+}
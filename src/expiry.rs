@@ -0,0 +1,314 @@
+use crate::models::{ApiError, EnableProxyRenewalResult, ListInfo, PurchaseResult};
+use std::time::Duration;
+// Used by the background task only, which is not built for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::cache::OnlineCache,
+    crate::client::{renewal_history_id, TrueSocksClient},
+    crate::filter::ProxyFilter,
+    crate::history::HistoryQuery,
+    crate::models::PurchaseKind,
+    crate::status_codes::NOT_FOUND,
+    std::collections::HashSet,
+    tokio::sync::broadcast,
+    tokio::task::JoinHandle,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_CAPACITY: usize = 64;
+
+/// What to do with an active entry once it is about to expire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryAction {
+    // Enable automatic renewal of the entry
+    Renew,
+    // Buy the cheapest online proxy in the same country and city with the same
+    // connection type, the same way the entry was bought
+    Repurchase,
+    // Only report it with `ExpiryEvent::Expiring`
+    Notify,
+    Ignore,
+}
+
+impl ExpiryAction {
+    fn parse(action: &str) -> Option<Self> {
+        match action {
+            "renew" => Some(ExpiryAction::Renew),
+            "repurchase" => Some(ExpiryAction::Repurchase),
+            "notify" => Some(ExpiryAction::Notify),
+            "ignore" => Some(ExpiryAction::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// Picks the [`ExpiryAction`] of an entry about to expire.
+pub trait ExpiryPolicy: Send + Sync + 'static {
+    fn action(&self, entry: &ListInfo) -> ExpiryAction;
+}
+
+impl<F> ExpiryPolicy for F
+where
+    F: Fn(&ListInfo) -> ExpiryAction + Send + Sync + 'static,
+{
+    fn action(&self, entry: &ListInfo) -> ExpiryAction {
+        self(entry)
+    }
+}
+
+/// Reads the action from an `expiry:<action>` word or tag in the note, e.g.
+/// `scraper #expiry:repurchase`, falling back to `default`.
+#[derive(Debug, Clone)]
+pub struct NotePolicy {
+    pub default: ExpiryAction,
+}
+
+impl ExpiryPolicy for NotePolicy {
+    fn action(&self, entry: &ListInfo) -> ExpiryAction {
+        entry
+            .note
+            .as_deref()
+            .unwrap_or_default()
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .find_map(|word| {
+                let word = word.strip_prefix('#').unwrap_or(word);
+                ExpiryAction::parse(word.strip_prefix("expiry:")?)
+            })
+            .unwrap_or(self.default)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExpiryOptions {
+    pub interval: Duration,
+    // How long before expiry the action is taken
+    pub lead_time: Duration,
+}
+
+impl Default for ExpiryOptions {
+    fn default() -> Self {
+        ExpiryOptions {
+            interval: Duration::from_secs(60),
+            lead_time: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpiryEvent {
+    Expiring {
+        entry: Box<ListInfo>,
+    },
+    RenewalEnabled {
+        history_id: u64,
+        result: EnableProxyRenewalResult,
+    },
+    Repurchased {
+        history_id: u64,
+        result: Box<PurchaseResult>,
+    },
+    // No online proxy matches the expiring one
+    NoReplacement {
+        history_id: u64,
+    },
+    Failed {
+        history_id: u64,
+        error: ApiError,
+    },
+    PollFailed(ApiError),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn replacement_filter(entry: &ListInfo) -> ProxyFilter {
+    let proxy = &entry.proxy_info;
+    ProxyFilter::new()
+        .label(format!("replacement of {}", entry.history_id))
+        .country(proxy.country_code.clone())
+        .city(proxy.city.clone())
+        .connection_type(proxy.connection_type.clone())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Scheduler<P> {
+    client: TrueSocksClient,
+    online: OnlineCache,
+    policy: P,
+    lead_time: Duration,
+    // Entries already acted on, each is handled once
+    handled: HashSet<u64>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<P: ExpiryPolicy> Scheduler<P> {
+    async fn check(&mut self) -> Vec<ExpiryEvent> {
+        let entries = match self.client.list_all_history(&HistoryQuery::active()).await {
+            Ok(entries) => entries,
+            Err(err) => return vec![ExpiryEvent::PollFailed(err)],
+        };
+        self.handled
+            .retain(|history_id| entries.iter().any(|entry| entry.history_id == *history_id));
+
+        let mut events = Vec::new();
+        for entry in entries {
+            if entry.remaining_time == 0
+                || entry.remaining_time > self.lead_time.as_secs()
+                || self.handled.contains(&entry.history_id)
+            {
+                continue;
+            }
+            self.handled.insert(entry.history_id);
+            if let Some(event) = self.apply(entry).await {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    async fn apply(&self, entry: ListInfo) -> Option<ExpiryEvent> {
+        let history_id = entry.history_id;
+        let event = match self.policy.action(&entry) {
+            ExpiryAction::Ignore => return None,
+            ExpiryAction::Renew if entry.renew_enabled => return None,
+            ExpiryAction::Notify => ExpiryEvent::Expiring {
+                entry: Box::new(entry),
+            },
+            ExpiryAction::Renew => match renewal_history_id(history_id) {
+                Ok(id) => match self.client.bought_proxy_renew_enable(id).await {
+                    Ok(result) => ExpiryEvent::RenewalEnabled { history_id, result },
+                    Err(error) => ExpiryEvent::Failed { history_id, error },
+                },
+                Err(error) => ExpiryEvent::Failed { history_id, error },
+            },
+            ExpiryAction::Repurchase => {
+                let kind = if entry.is_rented {
+                    PurchaseKind::PrivateRent
+                } else {
+                    PurchaseKind::SharedBuy
+                };
+                match self
+                    .online
+                    .buy_cheapest(&replacement_filter(&entry), kind)
+                    .await
+                {
+                    Ok(result) => ExpiryEvent::Repurchased {
+                        history_id,
+                        result: Box::new(result),
+                    },
+                    Err(error) if error.code() == NOT_FOUND as u64 => {
+                        ExpiryEvent::NoReplacement { history_id }
+                    }
+                    Err(error) => ExpiryEvent::Failed { history_id, error },
+                }
+            }
+        };
+        Some(event)
+    }
+}
+
+/// Background task acting on active entries once their remaining time drops
+/// below the lead time, as decided by an [`ExpiryPolicy`]. The task stops
+/// when the scheduler is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ExpiryScheduler {
+    events: broadcast::Sender<ExpiryEvent>,
+    handle: JoinHandle<()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ExpiryScheduler {
+    pub fn spawn<P: ExpiryPolicy>(
+        client: TrueSocksClient,
+        policy: P,
+        options: ExpiryOptions,
+    ) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let sender = events.clone();
+        let mut scheduler = Scheduler {
+            online: OnlineCache::new(client.clone()),
+            client,
+            policy,
+            lead_time: options.lead_time,
+            handled: HashSet::new(),
+        };
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(options.interval);
+            loop {
+                ticker.tick().await;
+                for event in scheduler.check().await {
+                    let _ = sender.send(event);
+                }
+            }
+        });
+        ExpiryScheduler { events, handle }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ExpiryEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for ExpiryScheduler {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_page, list_info, list_info_json, ok_response, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_note_policy() {
+        let policy = NotePolicy {
+            default: ExpiryAction::Notify,
+        };
+        let mut entry = list_info(1);
+        assert_eq!(policy.action(&entry), ExpiryAction::Notify);
+        entry.note = Some("scraper,expiry:repurchase".to_string());
+        assert_eq!(policy.action(&entry), ExpiryAction::Repurchase);
+        entry.note = Some("scraper #expiry:renew".to_string());
+        assert_eq!(policy.action(&entry), ExpiryAction::Renew);
+        entry.note = Some("expiry:later".to_string());
+        assert_eq!(policy.action(&entry), ExpiryAction::Notify);
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_acts_once_within_lead_time() {
+        let mut expiring = list_info_json(1, 1);
+        expiring["RemainingTime"] = json!(300);
+        expiring["Note"] = json!("expiry:renew");
+        let later = list_info_json(2, 2);
+        let page = history_page(vec![expiring, later], 1, 1);
+        let renewed = json!({ "HistoryID": 1, "Enabled": true, "CreditsLeft": 90, "Cost": 10 });
+        let (url, requests) = serve(vec![
+            ok_response(page.clone()),
+            ok_response(renewed),
+            ok_response(page),
+        ]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let mut scheduler = Scheduler {
+            online: OnlineCache::new(client.clone()),
+            client,
+            policy: NotePolicy {
+                default: ExpiryAction::Ignore,
+            },
+            lead_time: Duration::from_secs(600),
+            handled: HashSet::new(),
+        };
+
+        let events = scheduler.check().await;
+        assert!(matches!(
+            events[..],
+            [ExpiryEvent::RenewalEnabled { history_id: 1, .. }]
+        ));
+        assert!(scheduler.check().await.is_empty());
+        assert!(requests.join().unwrap()[1].contains("BoughtProxyRenewEnable"));
+    }
+}
@@ -0,0 +1,249 @@
+//! C ABI over the core commands, for tools written in other languages. The
+//! declarations are in `include/truesocks.h`; build the shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//!
+//! Every command returns a JSON string that the caller owns and releases
+//! with [`truesocks_string_free`]: `{"ok": <result>}` on success, or
+//! `{"error": {"code": <code>, "message": <text>}}`. Proxies are passed in
+//! as JSON objects from the `ListOnline` result.
+
+use crate::client::TrueSocksClient;
+use crate::models::{ApiError, ProxyInfo, PurchaseKind};
+use crate::status_codes::BAD_REQUEST;
+use serde::Serialize;
+use serde_json::json;
+use std::ffi::{c_char, CStr, CString};
+use std::future::Future;
+use std::ptr;
+use tokio::runtime::{Builder, Runtime};
+
+/// A client with the runtime its commands block on.
+pub struct TrueSocksHandle {
+    runtime: Runtime,
+    client: TrueSocksClient,
+}
+
+impl TrueSocksHandle {
+    fn run<T: Serialize>(&self, command: impl Future<Output = Result<T, ApiError>>) -> *mut c_char {
+        respond(self.runtime.block_on(command))
+    }
+}
+
+/// A client for `api_key`, sending to `base_url` unless it is NULL. Returns
+/// NULL when a string is not valid UTF-8.
+///
+/// # Safety
+///
+/// `api_key` must be a NUL-terminated string, `base_url` one or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_client_new(
+    api_key: *const c_char,
+    base_url: *const c_char,
+) -> *mut TrueSocksHandle {
+    let Some(api_key) = read_str(api_key) else {
+        return ptr::null_mut();
+    };
+    let mut builder = TrueSocksClient::builder(api_key);
+    if !base_url.is_null() {
+        let Some(base_url) = read_str(base_url) else {
+            return ptr::null_mut();
+        };
+        builder = builder.base_url(base_url);
+    }
+    let Ok(runtime) = Builder::new_current_thread().enable_all().build() else {
+        return ptr::null_mut();
+    };
+    let handle = TrueSocksHandle {
+        runtime,
+        client: builder.build(),
+    };
+    Box::into_raw(Box::new(handle))
+}
+
+/// # Safety
+///
+/// `handle` must come from [`truesocks_client_new`] and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_client_free(handle: *mut TrueSocksHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// # Safety
+///
+/// `value` must come from this library and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+/// `ListOnline`.
+///
+/// # Safety
+///
+/// `handle` must come from [`truesocks_client_new`].
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_list_online(handle: *const TrueSocksHandle) -> *mut c_char {
+    match handle.as_ref() {
+        Some(handle) => handle.run(handle.client.list_online_proxies()),
+        None => respond::<()>(Err(invalid_argument())),
+    }
+}
+
+/// Buy `proxy`, or rent it privately when `private_rent` is true.
+///
+/// # Safety
+///
+/// `handle` must come from [`truesocks_client_new`] and `proxy` be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_purchase(
+    handle: *const TrueSocksHandle,
+    proxy: *const c_char,
+    private_rent: bool,
+) -> *mut c_char {
+    let kind = if private_rent {
+        PurchaseKind::PrivateRent
+    } else {
+        PurchaseKind::SharedBuy
+    };
+    match (handle.as_ref(), read_proxy(proxy)) {
+        (Some(handle), Some(proxy)) => handle.run(handle.client.purchase(&proxy, kind)),
+        _ => respond::<()>(Err(invalid_argument())),
+    }
+}
+
+/// `BoughtProxyCheck` of a purchased proxy.
+///
+/// # Safety
+///
+/// As for [`truesocks_purchase`].
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_check(
+    handle: *const TrueSocksHandle,
+    proxy: *const c_char,
+) -> *mut c_char {
+    match (handle.as_ref(), read_proxy(proxy)) {
+        (Some(handle), Some(proxy)) => handle.run(handle.client.check_purchased_proxy(&proxy)),
+        _ => respond::<()>(Err(invalid_argument())),
+    }
+}
+
+/// `BoughtProxyRefund` of a purchased proxy.
+///
+/// # Safety
+///
+/// As for [`truesocks_purchase`].
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_refund(
+    handle: *const TrueSocksHandle,
+    proxy: *const c_char,
+) -> *mut c_char {
+    match (handle.as_ref(), read_proxy(proxy)) {
+        (Some(handle), Some(proxy)) => handle.run(handle.client.refund_purchased_proxy(&proxy)),
+        _ => respond::<()>(Err(invalid_argument())),
+    }
+}
+
+/// `AccountStatus`.
+///
+/// # Safety
+///
+/// `handle` must come from [`truesocks_client_new`].
+#[no_mangle]
+pub unsafe extern "C" fn truesocks_account_status(handle: *const TrueSocksHandle) -> *mut c_char {
+    match handle.as_ref() {
+        Some(handle) => handle.run(handle.client.get_account_status()),
+        None => respond::<()>(Err(invalid_argument())),
+    }
+}
+
+unsafe fn read_str<'a>(value: *const c_char) -> Option<&'a str> {
+    if value.is_null() {
+        return None;
+    }
+    CStr::from_ptr(value).to_str().ok()
+}
+
+unsafe fn read_proxy(proxy: *const c_char) -> Option<ProxyInfo> {
+    serde_json::from_str(read_str(proxy)?).ok()
+}
+
+// A NULL handle, or a proxy that is missing or not a `ProxyList` object
+fn invalid_argument() -> ApiError {
+    ApiError::from(BAD_REQUEST)
+}
+
+fn respond<T: Serialize>(result: Result<T, ApiError>) -> *mut c_char {
+    let body = match result {
+        Ok(result) => json!({ "ok": result }),
+        Err(err) => {
+            let message = match &err {
+                ApiError::RequestError(status) => status.message.clone(),
+                err => format!("{:?}", err),
+            };
+            json!({ "error": { "code": err.code(), "message": message } })
+        }
+    };
+    // JSON escapes NUL, so the conversion cannot fail
+    CString::new(body.to_string())
+        .map(CString::into_raw)
+        .unwrap_or(ptr::null_mut())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{account_status, ok_response, proxy_info_json, serve};
+    use serde_json::Value;
+
+    unsafe fn take(value: *mut c_char) -> Value {
+        let json = serde_json::from_str(CStr::from_ptr(value).to_str().unwrap()).unwrap();
+        truesocks_string_free(value);
+        json
+    }
+
+    #[test]
+    fn test_commands_return_json() {
+        let list = ok_response(json!({
+            "LastUpdate": 1,
+            "ProxyCount": 1,
+            "ProxyList": [proxy_info_json(4)],
+        }));
+        let status = json!({
+            "status": { "code": 5, "message": "not enough credits" },
+            "result": null,
+        });
+        let account = ok_response(serde_json::to_value(account_status(12)).unwrap());
+        let (url, _) = serve(vec![list, status, account]);
+        let api_key = CString::new("test").unwrap();
+        let url = CString::new(url).unwrap();
+
+        unsafe {
+            let handle = truesocks_client_new(api_key.as_ptr(), url.as_ptr());
+            assert!(!handle.is_null());
+
+            let list = take(truesocks_list_online(handle));
+            let proxy = &list["ok"]["ProxyList"][0];
+            assert_eq!(proxy["ProxyID"], 4);
+
+            let proxy = CString::new(proxy.to_string()).unwrap();
+            let bought = take(truesocks_purchase(handle, proxy.as_ptr(), false));
+            assert_eq!(bought["error"]["code"], 5);
+            assert_eq!(bought["error"]["message"], "not enough credits");
+
+            let account = take(truesocks_account_status(handle));
+            assert!(account["ok"].is_object());
+
+            let invalid = CString::new("{}").unwrap();
+            let refund = take(truesocks_refund(handle, invalid.as_ptr()));
+            assert_eq!(refund["error"]["code"], 400);
+            let check = take(truesocks_check(ptr::null(), proxy.as_ptr()));
+            assert_eq!(check["error"]["code"], 400);
+            truesocks_client_free(handle);
+        }
+    }
+}
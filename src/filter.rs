@@ -0,0 +1,334 @@
+use crate::blacklist::BlacklistPolicy;
+use crate::client::TrueSocksClient;
+use crate::credits::Credits;
+use crate::models::{ApiError, ConnectionType, ListOnlineResult, ProxyInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Criteria a proxy has to meet. Empty lists and `None` bounds match anything.
+///
+/// Countries can be required (`country`), ruled out (`exclude_country`) or
+/// only preferred (`prefer_country`). Preferences never reject a proxy, they
+/// are applied when ranking through [`ProxyScorer::country_preferences`].
+///
+/// [`ProxyScorer::country_preferences`]: crate::score::ProxyScorer::country_preferences
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProxyFilter {
+    // Name used to group telemetry, defaults to `describe()`
+    pub label: Option<String>,
+    pub country_codes: Vec<String>,
+    #[serde(default)]
+    pub excluded_country_codes: Vec<String>,
+    // Country codes with the weight of the preference
+    #[serde(default)]
+    pub preferred_country_codes: Vec<(String, f64)>,
+    pub cities: Vec<String>,
+    pub connection_types: Vec<ConnectionType>,
+    pub fresh: Option<bool>,
+    pub max_ping: Option<f64>,
+    pub min_speed: Option<u32>,
+    pub min_uptime_quality: Option<u32>,
+    // Upper bound on the shared purchase cost (`CostBuy`)
+    pub max_cost: Option<Credits>,
+    pub exclude_blacklisted: bool,
+    // Finer-grained limits on blacklist listings by category
+    #[serde(default)]
+    pub blacklist_policy: Option<BlacklistPolicy>,
+}
+
+impl ProxyFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn country(mut self, country_code: impl Into<String>) -> Self {
+        self.country_codes.push(country_code.into());
+        self
+    }
+
+    pub fn exclude_country(mut self, country_code: impl Into<String>) -> Self {
+        self.excluded_country_codes.push(country_code.into());
+        self
+    }
+
+    /// Favour proxies in the country by `weight` when ranking, without
+    /// excluding others.
+    pub fn prefer_country(mut self, country_code: impl Into<String>, weight: f64) -> Self {
+        self.preferred_country_codes
+            .push((country_code.into(), weight));
+        self
+    }
+
+    /// Weight of the preference matching the proxy's country, 0 if none does.
+    pub fn country_preference(&self, proxy: &ProxyInfo) -> f64 {
+        preference_for(&self.preferred_country_codes, &proxy.country_code)
+    }
+
+    pub fn city(mut self, city: impl Into<String>) -> Self {
+        self.cities.push(city.into());
+        self
+    }
+
+    pub fn connection_type(mut self, connection_type: ConnectionType) -> Self {
+        self.connection_types.push(connection_type);
+        self
+    }
+
+    pub fn fresh(mut self, fresh: bool) -> Self {
+        self.fresh = Some(fresh);
+        self
+    }
+
+    pub fn max_ping(mut self, ping: f64) -> Self {
+        self.max_ping = Some(ping);
+        self
+    }
+
+    pub fn min_speed(mut self, speed: u32) -> Self {
+        self.min_speed = Some(speed);
+        self
+    }
+
+    pub fn min_uptime_quality(mut self, uptime_quality: u32) -> Self {
+        self.min_uptime_quality = Some(uptime_quality);
+        self
+    }
+
+    pub fn max_cost(mut self, cost: Credits) -> Self {
+        self.max_cost = Some(cost);
+        self
+    }
+
+    pub fn exclude_blacklisted(mut self) -> Self {
+        self.exclude_blacklisted = true;
+        self
+    }
+
+    pub fn blacklist_policy(mut self, policy: BlacklistPolicy) -> Self {
+        self.blacklist_policy = Some(policy);
+        self
+    }
+
+    pub fn matches(&self, proxy: &ProxyInfo) -> bool {
+        (self.country_codes.is_empty()
+            || self
+                .country_codes
+                .iter()
+                .any(|code| code.eq_ignore_ascii_case(&proxy.country_code)))
+            && !self
+                .excluded_country_codes
+                .iter()
+                .any(|code| code.eq_ignore_ascii_case(&proxy.country_code))
+            && (self.cities.is_empty()
+                || self
+                    .cities
+                    .iter()
+                    .any(|city| city.eq_ignore_ascii_case(&proxy.city)))
+            && (self.connection_types.is_empty()
+                || self.connection_types.contains(&proxy.connection_type))
+            && self.fresh.is_none_or(|fresh| fresh == proxy.is_fresh)
+            && self.max_ping.is_none_or(|ping| proxy.ping <= ping)
+            && self.min_speed.is_none_or(|speed| proxy.speed >= speed)
+            && self
+                .min_uptime_quality
+                .is_none_or(|quality| proxy.uptime_quality >= quality)
+            && self.max_cost.is_none_or(|cost| proxy.rent_cost <= cost)
+            && !(self.exclude_blacklisted && proxy.is_blacklisted())
+            && self
+                .blacklist_policy
+                .as_ref()
+                .is_none_or(|policy| policy.allows(proxy))
+    }
+
+    /// Hash of every field including the label, equal filters share a key.
+    pub fn cache_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self).unwrap().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The label if set, otherwise a stable description of the criteria.
+    pub fn describe(&self) -> String {
+        if let Some(label) = &self.label {
+            return label.clone();
+        }
+        let mut parts = Vec::new();
+        if !self.country_codes.is_empty() {
+            parts.push(format!("country={}", self.country_codes.join("|")));
+        }
+        if !self.excluded_country_codes.is_empty() {
+            parts.push(format!(
+                "country!={}",
+                self.excluded_country_codes.join("|")
+            ));
+        }
+        if !self.preferred_country_codes.is_empty() {
+            let preferences: Vec<String> = self
+                .preferred_country_codes
+                .iter()
+                .map(|(code, weight)| format!("{}:{}", code, weight))
+                .collect();
+            parts.push(format!("prefer={}", preferences.join("|")));
+        }
+        if !self.cities.is_empty() {
+            parts.push(format!("city={}", self.cities.join("|")));
+        }
+        if !self.connection_types.is_empty() {
+            let types: Vec<String> = self
+                .connection_types
+                .iter()
+                .map(ConnectionType::to_string)
+                .collect();
+            parts.push(format!("type={}", types.join("|")));
+        }
+        if let Some(fresh) = self.fresh {
+            parts.push(format!("fresh={}", fresh));
+        }
+        if let Some(ping) = self.max_ping {
+            parts.push(format!("ping<={}", ping));
+        }
+        if let Some(speed) = self.min_speed {
+            parts.push(format!("speed>={}", speed));
+        }
+        if let Some(quality) = self.min_uptime_quality {
+            parts.push(format!("uptime>={}", quality));
+        }
+        if let Some(cost) = self.max_cost {
+            parts.push(format!("cost<={}", cost.amount()));
+        }
+        if self.exclude_blacklisted {
+            parts.push("clean".to_string());
+        }
+        if let Some(policy) = &self.blacklist_policy {
+            parts.push(format!("blacklist={}", policy.describe()));
+        }
+        if parts.is_empty() {
+            "any".to_string()
+        } else {
+            parts.join(",")
+        }
+    }
+}
+
+impl ListOnlineResult {
+    /// Keep only the proxies matching `filter`. `proxy_count` is updated to
+    /// the number kept.
+    pub fn retain_matching(&mut self, filter: &ProxyFilter) {
+        self.proxy_list.retain(|proxy| filter.matches(proxy));
+        self.proxy_count = self.proxy_list.len() as u32;
+    }
+
+    pub fn filtered(&self, filter: &ProxyFilter) -> ListOnlineResult {
+        let proxy_list: Vec<ProxyInfo> = self
+            .proxy_list
+            .iter()
+            .filter(|proxy| filter.matches(proxy))
+            .cloned()
+            .collect();
+        ListOnlineResult {
+            last_update: self.last_update,
+            proxy_count: proxy_list.len() as u32,
+            proxy_list,
+            duplicates_removed: self.duplicates_removed,
+            extra: self.extra.clone(),
+        }
+    }
+}
+
+impl TrueSocksClient {
+    /// Online proxies matching `filter`. `ListOnline` takes no paging or
+    /// filter parameters, so the whole list is still downloaded and filtered
+    /// here; only the matching proxies are kept in memory. Concurrent calls
+    /// share one download, see [`coalesce`]. To narrow by country on the
+    /// server side, use [`list_zip_search`](Self::list_zip_search).
+    ///
+    /// [`coalesce`]: crate::client::TrueSocksClientBuilder::coalesce
+    pub async fn list_online_filtered(
+        &self,
+        filter: &ProxyFilter,
+    ) -> Result<ListOnlineResult, ApiError> {
+        let mut list = self.list_online_proxies().await?;
+        list.retain_matching(filter);
+        Ok(list)
+    }
+}
+
+// Strongest preference for `country_code`, 0 if it is not preferred
+pub(crate) fn preference_for(preferences: &[(String, f64)], country_code: &str) -> f64 {
+    preferences
+        .iter()
+        .filter(|(code, _)| code.eq_ignore_ascii_case(country_code))
+        .map(|(_, weight)| *weight)
+        .fold(0.0, f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{list_info, ok_response, proxy_info_json, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_matches() {
+        let proxy = list_info(1).proxy_info;
+        assert!(ProxyFilter::new().matches(&proxy));
+        assert!(ProxyFilter::new()
+            .country("us")
+            .max_ping(200.0)
+            .matches(&proxy));
+        assert!(!ProxyFilter::new().country("DE").matches(&proxy));
+        assert!(!ProxyFilter::new()
+            .connection_type(ConnectionType::Mobile)
+            .matches(&proxy));
+        assert!(!ProxyFilter::new().exclude_country("us").matches(&proxy));
+    }
+
+    #[test]
+    fn test_country_preference() {
+        let proxy = list_info(1).proxy_info;
+        let filter = ProxyFilter::new()
+            .exclude_country("DE")
+            .prefer_country("US", 2.0)
+            .prefer_country("GB", 1.0);
+        assert!(filter.matches(&proxy));
+        assert_eq!(filter.country_preference(&proxy), 2.0);
+        assert_eq!(filter.describe(), "country!=DE,prefer=US:2|GB:1");
+    }
+
+    #[test]
+    fn test_describe() {
+        let filter = ProxyFilter::new()
+            .country("US")
+            .country("CA")
+            .exclude_blacklisted();
+        assert_eq!(filter.describe(), "country=US|CA,clean");
+        assert_eq!(filter.label("north-america").describe(), "north-america");
+        assert_eq!(ProxyFilter::new().describe(), "any");
+    }
+
+    #[tokio::test]
+    async fn test_list_online_filtered() {
+        let mut mobile = proxy_info_json(2);
+        mobile["Connect"] = json!("Mobile");
+        let (url, _) = serve(vec![ok_response(json!({
+            "LastUpdate": 1,
+            "ProxyCount": 2,
+            "ProxyList": [proxy_info_json(1), mobile],
+        }))]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let filter = ProxyFilter::new().connection_type(ConnectionType::Mobile);
+        let list = client.list_online_filtered(&filter).await.unwrap();
+        assert_eq!(list.proxy_count, 1);
+        assert_eq!(list.proxy_list[0].proxy_id, 2);
+        assert_eq!(
+            list.filtered(&ProxyFilter::new().country("DE")).proxy_count,
+            0
+        );
+    }
+}
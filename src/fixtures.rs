@@ -0,0 +1,205 @@
+use crate::credits::Credits;
+use crate::models::{AccountStatusResult, ListInfo};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+pub(crate) fn proxy_info_json(proxy_id: u32) -> Value {
+    json!({
+        "ProxyID": proxy_id,
+        "CostBuy": 10,
+        "CostRent": 40,
+        "IsFresh": false,
+        "IP": "203.0.113.7",
+        "Hostname": "host.example.net",
+        "ISP": "Example ISP",
+        "CountryCode": "US",
+        "Country": "United States",
+        "Region": "New York",
+        "City": "New York",
+        "ZipCode": "10001",
+        "Timezone": "America/New_York",
+        "Connect": "DSL",
+        "Ping": 120.5,
+        "Speed": 2048,
+        "UpTimeQuality": 90,
+        "Blacklist": false,
+        "Distance": null
+    })
+}
+
+pub(crate) fn list_info_json(history_id: u64, proxy_id: u32) -> Value {
+    json!({
+        "HistoryID": history_id,
+        "ConnectInfo": {
+            "ConnectIP": "198.51.100.1",
+            "ConnectPort": 20000 + (proxy_id % 1000) as u16,
+            "ConnectSessionID": format!("session{}", history_id)
+        },
+        "ProxyInfo": proxy_info_json(proxy_id),
+        "LastBought": 1_700_000_000,
+        "RemainingTime": 3600,
+        "IsOnline": true,
+        "IsFresh": false,
+        "IsRented": false,
+        "RefundAvailable": true,
+        "RenewEnabled": false,
+        "RenewCountRemaining": 0,
+        "IPHasChanged": false,
+        "Note": ""
+    })
+}
+
+pub(crate) fn list_info(history_id: u64) -> ListInfo {
+    serde_json::from_value(list_info_json(history_id, history_id as u32)).unwrap()
+}
+
+pub(crate) fn account_status(credits: u32) -> AccountStatusResult {
+    AccountStatusResult {
+        created: 0,
+        user_id: "user".to_string(),
+        email: "user@example.com".to_string(),
+        active: true,
+        plan: "Basic".to_string(),
+        expires: 0,
+        credits: Credits(credits),
+        extra: Default::default(),
+    }
+}
+
+// Answer one HTTP request on a local port with `body`, returning the base URL
+// and a handle yielding the raw request as received
+pub(crate) fn serve_once(body: Value) -> (String, JoinHandle<String>) {
+    let (url, handle) = serve(vec![body]);
+    (url, thread::spawn(move || handle.join().unwrap().remove(0)))
+}
+
+// Answer one request per body, in order, each on its own connection
+pub(crate) fn serve(bodies: Vec<Value>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        bodies
+            .into_iter()
+            .map(|body| answer(&listener, "200 OK", body))
+            .collect()
+    });
+    (url, handle)
+}
+
+// Like `serve`, with the status line and extra headers of each response, e.g.
+// "503 Service Unavailable\r\nRetry-After: 1"
+pub(crate) fn serve_with_status(
+    responses: Vec<(&'static str, Value)>,
+) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        responses
+            .into_iter()
+            .map(|(head, body)| answer(&listener, head, body))
+            .collect()
+    });
+    (url, handle)
+}
+
+fn answer(listener: &TcpListener, head: &str, body: Value) -> String {
+    let (stream, _) = listener.accept().unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+        request.push_str(&line);
+        if line == "\r\n" || line.is_empty() {
+            break;
+        }
+    }
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content).unwrap();
+    request.push_str(&String::from_utf8(content).unwrap());
+
+    let body = body.to_string();
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        head,
+        body.len(),
+        body
+    )
+    .unwrap();
+    request
+}
+
+// SOCKS5 server answering every CONNECT itself with an HTTP response
+// carrying `body`; returns its port and the username it was given
+pub(crate) fn serve_socks(body: Vec<u8>) -> (u16, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        let mut methods = vec![0u8; header[1] as usize];
+        stream.read_exact(&mut methods).unwrap();
+        stream.write_all(&[5, 2]).unwrap();
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte).unwrap();
+        stream.read_exact(&mut byte).unwrap();
+        let mut username = vec![0u8; byte[0] as usize];
+        stream.read_exact(&mut username).unwrap();
+        stream.read_exact(&mut byte).unwrap();
+        let mut password = vec![0u8; byte[0] as usize];
+        stream.read_exact(&mut password).unwrap();
+        stream.write_all(&[1, 0]).unwrap();
+        let mut request = [0u8; 4];
+        stream.read_exact(&mut request).unwrap();
+        let skip = match request[3] {
+            1 => 4,
+            4 => 16,
+            _ => {
+                stream.read_exact(&mut byte).unwrap();
+                byte[0] as usize
+            }
+        };
+        let mut target = vec![0u8; skip + 2];
+        stream.read_exact(&mut target).unwrap();
+        stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf).unwrap();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        // The client may hang up before the whole body is sent
+        let _ = stream.write_all(&body);
+        String::from_utf8(username).unwrap()
+    });
+    (port, handle)
+}
+
+// `ListHistory` result holding one page of `entries`
+pub(crate) fn history_page(entries: Vec<Value>, page: u32, max_pages: u32) -> Value {
+    json!({
+        "ServerTime": 1,
+        "HistoryCount": entries.len(),
+        "HistoryEntriesPerPage": 50,
+        "HistoryCurrentPage": page,
+        "HistoryMaxPages": max_pages,
+        "HistoryList": entries,
+    })
+}
+
+pub(crate) fn ok_response(result: Value) -> Value {
+    json!({"status": {"code": 0, "message": "OK"}, "result": result})
+}
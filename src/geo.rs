@@ -0,0 +1,538 @@
+use crate::cache::OnlineCache;
+use crate::client::TrueSocksClient;
+use crate::filter::ProxyFilter;
+use crate::models::{ApiError, ListOnlineResult, ListZipSearchResult, ProxyInfo};
+use std::collections::HashMap;
+use std::fmt;
+
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+const KM_PER_MILE: f64 = 1.609344;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Coordinates {
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Coordinates { lat, lon }
+    }
+
+    /// Great-circle distance in kilometres (haversine formula).
+    pub fn distance_km(&self, other: &Coordinates) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    pub fn distance_miles(&self, other: &Coordinates) -> f64 {
+        self.distance_km(other) / KM_PER_MILE
+    }
+}
+
+/// Resolves where a proxy is. The API only reports country, region, city and
+/// zip code, so coordinates come from a lookup supplied by the caller.
+pub trait Geocoder {
+    fn locate(&self, proxy: &ProxyInfo) -> Option<Coordinates>;
+
+    // Place a zip code, used as the origin of zip search fallbacks
+    fn locate_zip(&self, _country_code: &str, _zip_code: &str) -> Option<Coordinates> {
+        None
+    }
+}
+
+impl<F> Geocoder for F
+where
+    F: Fn(&ProxyInfo) -> Option<Coordinates>,
+{
+    fn locate(&self, proxy: &ProxyInfo) -> Option<Coordinates> {
+        self(proxy)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoTableError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for GeoTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for GeoTableError {}
+
+/// Geocoder backed by zip code and city tables, keyed by country code.
+/// Zip codes are tried first, then the city name, both case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct TableGeocoder {
+    zip_codes: HashMap<(String, String), Coordinates>,
+    cities: HashMap<(String, String), Coordinates>,
+}
+
+fn table_key(country_code: &str, name: &str) -> (String, String) {
+    (
+        country_code.trim().to_ascii_lowercase(),
+        normalize_place(name),
+    )
+}
+
+// Base letter of accented Latin letters, already lowercased
+fn fold_diacritic(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'ď' | 'đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'ĥ' | 'ħ' => "h",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'ĵ' => "j",
+        'ķ' => "k",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'œ' => "oe",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s",
+        'ß' => "ss",
+        'ţ' | 'ť' | 'ŧ' | 'ț' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'ŵ' => "w",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+/// Form of a city or region name used for matching: lowercased, accents on
+/// Latin letters removed, and punctuation and runs of whitespace collapsed to
+/// single spaces, so "São Paulo", "sao  paulo" and "Sao-Paulo" are equal.
+pub fn normalize_place(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            match fold_diacritic(c) {
+                Some(base) => normalized.push_str(base),
+                None => normalized.push(c),
+            }
+        } else if !normalized.is_empty() && !normalized.ends_with(' ') {
+            normalized.push(' ');
+        }
+    }
+    normalized.truncate(normalized.trim_end().len());
+    normalized
+}
+
+impl TableGeocoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_zip(&mut self, country_code: &str, zip_code: &str, coordinates: Coordinates) {
+        self.zip_codes
+            .insert(table_key(country_code, zip_code), coordinates);
+    }
+
+    pub fn insert_city(&mut self, country_code: &str, city: &str, coordinates: Coordinates) {
+        self.cities
+            .insert(table_key(country_code, city), coordinates);
+    }
+
+    /// Load `country_code,zip_code,city,lat,lon` rows, e.g. from a file embedded
+    /// with `include_str!`. Either the zip code or the city may be empty; blank
+    /// lines and lines starting with `#` are skipped.
+    pub fn from_csv(data: &str) -> Result<Self, GeoTableError> {
+        let mut table = TableGeocoder::new();
+        for (index, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| GeoTableError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [country_code, zip_code, city, lat, lon] = fields[..] else {
+                return Err(error("expected 5 fields"));
+            };
+            let lat: f64 = lat.parse().map_err(|_| error("invalid latitude"))?;
+            let lon: f64 = lon.parse().map_err(|_| error("invalid longitude"))?;
+            let coordinates = Coordinates::new(lat, lon);
+            if !zip_code.is_empty() {
+                table.insert_zip(country_code, zip_code, coordinates);
+            }
+            if !city.is_empty() {
+                table.insert_city(country_code, city, coordinates);
+            }
+        }
+        Ok(table)
+    }
+}
+
+impl Geocoder for TableGeocoder {
+    fn locate(&self, proxy: &ProxyInfo) -> Option<Coordinates> {
+        proxy
+            .zip_code
+            .as_ref()
+            .and_then(|zip_code| {
+                self.zip_codes
+                    .get(&table_key(&proxy.country_code, zip_code))
+            })
+            .or_else(|| {
+                self.cities
+                    .get(&table_key(&proxy.country_code, &proxy.city))
+            })
+            .copied()
+    }
+
+    fn locate_zip(&self, country_code: &str, zip_code: &str) -> Option<Coordinates> {
+        self.zip_codes
+            .get(&table_key(country_code, zip_code))
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NearestProxy<'a> {
+    pub proxy: &'a ProxyInfo,
+    pub coordinates: Coordinates,
+    pub distance_km: f64,
+}
+
+impl ListOnlineResult {
+    /// The proxy matching `filter` closest to `origin`. Proxies the geocoder
+    /// cannot place are ignored.
+    pub fn find_nearest<G: Geocoder>(
+        &self,
+        geocoder: &G,
+        origin: Coordinates,
+        filter: &ProxyFilter,
+    ) -> Option<NearestProxy<'_>> {
+        self.nearest(geocoder, origin, filter).into_iter().next()
+    }
+
+    /// Every proxy matching `filter` that the geocoder can place, closest first
+    /// and by ProxyID at equal distance.
+    pub fn nearest<G: Geocoder>(
+        &self,
+        geocoder: &G,
+        origin: Coordinates,
+        filter: &ProxyFilter,
+    ) -> Vec<NearestProxy<'_>> {
+        let mut found: Vec<NearestProxy<'_>> = self
+            .proxy_list
+            .iter()
+            .filter(|proxy| filter.matches(proxy))
+            .filter_map(|proxy| {
+                let coordinates = geocoder.locate(proxy)?;
+                Some(NearestProxy {
+                    proxy,
+                    coordinates,
+                    distance_km: origin.distance_km(&coordinates),
+                })
+            })
+            .collect();
+        found.sort_by(|a, b| {
+            a.distance_km
+                .total_cmp(&b.distance_km)
+                .then(a.proxy.proxy_id.cmp(&b.proxy.proxy_id))
+        });
+        found
+    }
+}
+
+fn in_country(proxy: &ProxyInfo, country_code: &str) -> bool {
+    let country_code = country_code.trim();
+    country_code.is_empty() || proxy.country_code.eq_ignore_ascii_case(country_code)
+}
+
+impl ListOnlineResult {
+    /// Proxies in the city, compared through [`normalize_place`]. An empty
+    /// country code matches every country.
+    pub fn city_search(&self, country_code: &str, city: &str) -> Vec<&ProxyInfo> {
+        let city = normalize_place(city);
+        self.proxy_list
+            .iter()
+            .filter(|proxy| in_country(proxy, country_code) && normalize_place(&proxy.city) == city)
+            .collect()
+    }
+
+    /// Proxies in the region (state, province), compared through
+    /// [`normalize_place`]. An empty country code matches every country.
+    pub fn region_search(&self, country_code: &str, region: &str) -> Vec<&ProxyInfo> {
+        let region = normalize_place(region);
+        self.proxy_list
+            .iter()
+            .filter(|proxy| {
+                in_country(proxy, country_code) && normalize_place(&proxy.region) == region
+            })
+            .collect()
+    }
+}
+
+impl OnlineCache {
+    /// City search over the cached online list, the API only searches by zip
+    /// code. See [`ListOnlineResult::city_search`].
+    pub async fn list_city_search(
+        &self,
+        country_code: &str,
+        city: &str,
+    ) -> Result<Vec<ProxyInfo>, ApiError> {
+        let list = self.get().await?;
+        Ok(list
+            .city_search(country_code, city)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    /// Region search over the cached online list. See
+    /// [`ListOnlineResult::region_search`].
+    pub async fn list_region_search(
+        &self,
+        country_code: &str,
+        region: &str,
+    ) -> Result<Vec<ProxyInfo>, ApiError> {
+        let list = self.get().await?;
+        Ok(list
+            .region_search(country_code, region)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ZipSearchOutcome {
+    Exact(ListZipSearchResult),
+    // The zip search found nothing, the list holds the closest proxies in the
+    // same country from the cached online list, `distance` filled in client-side
+    FallbackNearest(ListZipSearchResult),
+}
+
+impl ZipSearchOutcome {
+    pub fn result(&self) -> &ListZipSearchResult {
+        match self {
+            ZipSearchOutcome::Exact(result) | ZipSearchOutcome::FallbackNearest(result) => result,
+        }
+    }
+
+    pub fn into_result(self) -> ListZipSearchResult {
+        match self {
+            ZipSearchOutcome::Exact(result) | ZipSearchOutcome::FallbackNearest(result) => result,
+        }
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        matches!(self, ZipSearchOutcome::FallbackNearest(_))
+    }
+}
+
+/// Where an empty zip search looks instead.
+pub struct ZipFallback<'a, G> {
+    pub cache: &'a OnlineCache,
+    pub geocoder: &'a G,
+    pub max_results: usize,
+}
+
+// Units reported by the zip search, anything starting with "m" is taken as miles
+fn distance_in_units(origin: &Coordinates, other: &Coordinates, units: &str) -> f64 {
+    if units.to_ascii_lowercase().starts_with('m') {
+        origin.distance_miles(other)
+    } else {
+        origin.distance_km(other)
+    }
+}
+
+fn nearest_in_country<G: Geocoder>(
+    list: &ListOnlineResult,
+    geocoder: &G,
+    origin: Coordinates,
+    search: &ListZipSearchResult,
+    max_results: usize,
+) -> ListZipSearchResult {
+    let filter = ProxyFilter::new().country(search.search_country_code.as_str());
+    let proxy_list: Vec<ProxyInfo> = list
+        .nearest(geocoder, origin, &filter)
+        .into_iter()
+        .take(max_results)
+        .map(|nearest| {
+            let mut proxy = nearest.proxy.clone();
+            proxy.distance = Some(distance_in_units(
+                &origin,
+                &nearest.coordinates,
+                &search.search_units,
+            ));
+            proxy
+        })
+        .collect();
+    ListZipSearchResult {
+        proxy_count: proxy_list.len() as u32,
+        proxy_list,
+        ..search.clone()
+    }
+}
+
+impl TrueSocksClient {
+    /// Zip search that, when it comes back empty, falls back to the proxies in
+    /// the same country closest to the zip code. Without coordinates for the
+    /// zip code the empty result is returned as is.
+    pub async fn list_zip_search_or_nearest<G: Geocoder>(
+        &self,
+        country_code: &str,
+        zip_code: &str,
+        units: Option<&str>,
+        range: Option<u32>,
+        fallback: &ZipFallback<'_, G>,
+    ) -> Result<ZipSearchOutcome, ApiError> {
+        let search = self
+            .list_zip_search(country_code, zip_code, units, range)
+            .await?;
+        if !search.proxy_list.is_empty() {
+            return Ok(ZipSearchOutcome::Exact(search));
+        }
+        let Some(origin) = fallback.geocoder.locate_zip(country_code, zip_code) else {
+            return Ok(ZipSearchOutcome::Exact(search));
+        };
+        let list = fallback.cache.get().await?;
+        Ok(ZipSearchOutcome::FallbackNearest(nearest_in_country(
+            &list,
+            fallback.geocoder,
+            origin,
+            &search,
+            fallback.max_results,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+
+    fn proxy(proxy_id: u32, country_code: &str, city: &str) -> ProxyInfo {
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(proxy_id)).unwrap();
+        proxy.country_code = country_code.to_string();
+        proxy.city = city.to_string();
+        proxy.zip_code = None;
+        proxy
+    }
+
+    #[test]
+    fn test_city_and_region_search() {
+        assert_eq!(normalize_place("  São-Paulo "), "sao paulo");
+        assert_eq!(normalize_place("Düsseldorf"), normalize_place("dusseldorf"));
+        assert_eq!(normalize_place("St. Louis"), "st louis");
+
+        let mut munich = proxy(3, "DE", "München");
+        munich.region = "Bayern".to_string();
+        let list = ListOnlineResult {
+            last_update: 0,
+            proxy_count: 3,
+            proxy_list: vec![
+                proxy(1, "BR", "São Paulo"),
+                proxy(2, "BR", "Sao Paulo"),
+                munich,
+            ],
+            duplicates_removed: 0,
+            extra: Default::default(),
+        };
+        let ids = |found: Vec<&ProxyInfo>| found.iter().map(|p| p.proxy_id).collect::<Vec<_>>();
+        assert_eq!(ids(list.city_search("br", "SAO PAULO")), vec![1, 2]);
+        assert!(list.city_search("PT", "Sao Paulo").is_empty());
+        assert_eq!(ids(list.city_search("", "munchen")), vec![3]);
+        assert_eq!(ids(list.region_search("DE", "bayern")), vec![3]);
+    }
+
+    #[test]
+    fn test_distance_km() {
+        let paris = Coordinates::new(48.8566, 2.3522);
+        let london = Coordinates::new(51.5074, -0.1278);
+        let distance = paris.distance_km(&london);
+        assert!((distance - 343.5).abs() < 2.0, "{}", distance);
+    }
+
+    #[test]
+    fn test_find_nearest() {
+        let geocoder = TableGeocoder::from_csv(
+            "# country,zip,city,lat,lon\n\
+             FR,,Paris,48.8566,2.3522\n\
+             DE,,Berlin,52.5200,13.4050\n",
+        )
+        .unwrap();
+        let list = ListOnlineResult {
+            last_update: 0,
+            proxy_count: 3,
+            proxy_list: vec![
+                proxy(1, "DE", "Berlin"),
+                proxy(2, "FR", "paris"),
+                proxy(3, "US", "Nowhere"),
+            ],
+            duplicates_removed: 0,
+            extra: Default::default(),
+        };
+        let brussels = Coordinates::new(50.8503, 4.3517);
+        let nearest = list
+            .find_nearest(&geocoder, brussels, &ProxyFilter::default())
+            .unwrap();
+        assert_eq!(nearest.proxy.proxy_id, 2);
+        assert_eq!(
+            list.nearest(&geocoder, brussels, &ProxyFilter::default())
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_nearest_in_country_fills_distance() {
+        let geocoder = TableGeocoder::from_csv(
+            "US,10001,New York,40.7506,-73.9972\n\
+             US,,Boston,42.3601,-71.0589\n\
+             US,,Chicago,41.8781,-87.6298\n\
+             CA,,Montreal,45.5017,-73.5673\n",
+        )
+        .unwrap();
+        let list = ListOnlineResult {
+            last_update: 0,
+            proxy_count: 3,
+            proxy_list: vec![
+                proxy(1, "US", "Chicago"),
+                proxy(2, "US", "Boston"),
+                proxy(3, "CA", "Montreal"),
+            ],
+            duplicates_removed: 0,
+            extra: Default::default(),
+        };
+        let search = ListZipSearchResult {
+            server_time: 0,
+            search_country_code: "US".to_string(),
+            search_units: "miles".to_string(),
+            search_range: 10,
+            search_zip_code: "10001".to_string(),
+            proxy_count: 0,
+            proxy_list: Vec::new(),
+            extra: Default::default(),
+        };
+        let origin = geocoder.locate_zip("us", "10001").unwrap();
+        let result = nearest_in_country(&list, &geocoder, origin, &search, 1);
+        assert_eq!(result.proxy_count, 1);
+        assert_eq!(result.proxy_list[0].proxy_id, 2);
+        let distance = result.proxy_list[0].distance.unwrap();
+        assert!((distance - 190.0).abs() < 10.0, "{}", distance);
+    }
+
+    #[test]
+    fn test_from_csv_rejects_bad_rows() {
+        let err = TableGeocoder::from_csv("FR,,Paris,north,2.35").unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+}
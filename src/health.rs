@@ -0,0 +1,177 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ApiError, ListInfo, ProxyCheckResult, ProxyInfo};
+use futures::stream::{self, StreamExt};
+use log::Level;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone)]
+pub struct HealthCheckItem {
+    pub proxy_id: u32,
+    // Set when the check was started from a history entry
+    pub history_id: Option<u64>,
+    pub result: Result<ProxyCheckResult, ApiError>,
+}
+
+impl HealthCheckItem {
+    pub fn passed(&self) -> bool {
+        self.result.as_ref().is_ok_and(ProxyCheckResult::passed)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    // One entry per checked proxy, in the order they were passed in
+    pub items: Vec<HealthCheckItem>,
+}
+
+impl HealthReport {
+    pub fn passed(&self) -> impl Iterator<Item = &HealthCheckItem> {
+        self.items.iter().filter(|item| item.passed())
+    }
+
+    // Proxies that failed a test or whose check could not be run
+    pub fn failed(&self) -> impl Iterator<Item = &HealthCheckItem> {
+        self.items.iter().filter(|item| !item.passed())
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.items.iter().all(HealthCheckItem::passed)
+    }
+}
+
+impl TrueSocksClient {
+    /// Run `BoughtProxyCheck` on every proxy, at most `concurrency` at a time.
+    pub async fn check_many(&self, proxies: &[&ProxyInfo], concurrency: usize) -> HealthReport {
+        let targets = proxies.iter().map(|proxy| (*proxy, None)).collect();
+        self.check_targets(targets, concurrency, None).await
+    }
+
+    /// Like [`check_many`](Self::check_many), but checks still running when
+    /// `cancel` is triggered are dropped and no new ones are started. The report
+    /// only holds the checks that finished.
+    pub async fn check_many_cancellable(
+        &self,
+        proxies: &[&ProxyInfo],
+        concurrency: usize,
+        cancel: &CancellationToken,
+    ) -> HealthReport {
+        let targets = proxies.iter().map(|proxy| (*proxy, None)).collect();
+        self.check_targets(targets, concurrency, Some(cancel)).await
+    }
+
+    /// Check the proxies of the given history entries.
+    pub async fn check_entries(&self, entries: &[ListInfo], concurrency: usize) -> HealthReport {
+        let targets = entries
+            .iter()
+            .map(|entry| (&entry.proxy_info, Some(entry.history_id)))
+            .collect();
+        self.check_targets(targets, concurrency, None).await
+    }
+
+    /// Check every active purchase.
+    pub async fn check_active(&self, concurrency: usize) -> Result<HealthReport, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::active()).await?;
+        Ok(self.check_entries(&entries, concurrency).await)
+    }
+
+    /// Like [`check_active`](Self::check_active) with the partial results of
+    /// [`check_many_cancellable`](Self::check_many_cancellable). Only entries on
+    /// the history pages fetched before cancellation are checked.
+    pub async fn check_active_cancellable(
+        &self,
+        concurrency: usize,
+        cancel: &CancellationToken,
+    ) -> Result<HealthReport, ApiError> {
+        let entries = self
+            .list_all_history_cancellable(&HistoryQuery::active(), cancel)
+            .await?;
+        let targets = entries
+            .iter()
+            .map(|entry| (&entry.proxy_info, Some(entry.history_id)))
+            .collect();
+        Ok(self.check_targets(targets, concurrency, Some(cancel)).await)
+    }
+
+    async fn check_targets(
+        &self,
+        targets: Vec<(&ProxyInfo, Option<u64>)>,
+        concurrency: usize,
+        cancel: Option<&CancellationToken>,
+    ) -> HealthReport {
+        let cancelled = async {
+            match cancel {
+                Some(cancel) => cancel.cancelled().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let mut results: Vec<(usize, HealthCheckItem)> =
+            stream::iter(targets.into_iter().enumerate())
+                .map(|(index, (proxy, history_id))| async move {
+                    let item = HealthCheckItem {
+                        proxy_id: proxy.proxy_id,
+                        history_id,
+                        result: self.check_purchased_proxy(proxy).await,
+                    };
+                    if !item.passed() {
+                        sublog!(
+                            Subsystem::Health,
+                            Level::Debug,
+                            "check of proxy {} failed: {:?}",
+                            proxy.proxy_id,
+                            item.result
+                        );
+                    }
+                    (index, item)
+                })
+                .buffer_unordered(concurrency.max(1))
+                .take_until(cancelled)
+                .collect()
+                .await;
+        results.sort_by_key(|(index, _)| *index);
+
+        HealthReport {
+            items: results.into_iter().map(|(_, item)| item).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(proxy_id: u32, passed: u32) -> HealthCheckItem {
+        HealthCheckItem {
+            proxy_id,
+            history_id: None,
+            result: Ok(ProxyCheckResult {
+                tests_passed: passed,
+                tests_total: 3,
+                test_result: String::new(),
+                test_result_long: String::new(),
+                extra: Default::default(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_report_splits_passed_and_failed() {
+        let report = HealthReport {
+            items: vec![
+                item(1, 3),
+                item(2, 1),
+                HealthCheckItem {
+                    proxy_id: 3,
+                    history_id: None,
+                    result: Err(ApiError::from(418_u16)),
+                },
+            ],
+        };
+        let passed: Vec<u32> = report.passed().map(|item| item.proxy_id).collect();
+        let failed: Vec<u32> = report.failed().map(|item| item.proxy_id).collect();
+        assert_eq!(passed, vec![1]);
+        assert_eq!(failed, vec![2, 3]);
+        assert!(!report.is_healthy());
+    }
+}
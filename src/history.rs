@@ -0,0 +1,111 @@
+use crate::client::history_params;
+use crate::models::ListInfo;
+use serde_json::Value;
+
+/// Which history entries `ListHistory` calls return.
+///
+/// Only `active_only` and `page` are sent to the API; the note, country and
+/// tag criteria are applied to the returned entries. The API does not accept a
+/// page size.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub active_only: bool,
+    // Page number starting at 1, the first page when None. Ignored when every
+    // page is fetched.
+    pub page: Option<u32>,
+    // Case-insensitive substring of the note
+    pub note_contains: Option<String>,
+    // Any of these countries, any country when empty
+    pub country_codes: Vec<String>,
+    // Tags in the note, all of them have to be present
+    pub tags: Vec<String>,
+}
+
+impl HistoryQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Entries that have not expired yet.
+    pub fn active() -> Self {
+        Self::new().active_only(true)
+    }
+
+    pub fn active_only(mut self, active_only: bool) -> Self {
+        self.active_only = active_only;
+        self
+    }
+
+    pub fn page(mut self, page: u32) -> Self {
+        self.page = Some(page);
+        self
+    }
+
+    pub fn note_contains(mut self, text: impl Into<String>) -> Self {
+        self.note_contains = Some(text.into());
+        self
+    }
+
+    pub fn country(mut self, country_code: impl Into<String>) -> Self {
+        self.country_codes.push(country_code.into());
+        self
+    }
+
+    /// Entries tagged with `tag`, see [`crate::tags`].
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Whether `entry` meets the criteria applied on the client side.
+    pub fn matches(&self, entry: &ListInfo) -> bool {
+        (self.country_codes.is_empty()
+            || self
+                .country_codes
+                .iter()
+                .any(|code| code.eq_ignore_ascii_case(&entry.proxy_info.country_code)))
+            && self.note_contains.as_ref().is_none_or(|text| {
+                entry
+                    .note
+                    .as_ref()
+                    .is_some_and(|note| note.to_lowercase().contains(&text.to_lowercase()))
+            })
+            && self.tags.iter().all(|tag| entry.has_tag(tag))
+    }
+
+    pub(crate) fn params(&self, page: Option<u32>) -> Value {
+        history_params(self.active_only, page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+    use serde_json::json;
+
+    #[test]
+    fn test_history_query() {
+        let mut entry = list_info(1);
+        entry.note = Some("Scraper EU".to_string());
+        entry.proxy_info.country_code = "DE".to_string();
+
+        assert!(HistoryQuery::new().matches(&entry));
+        assert!(HistoryQuery::new()
+            .note_contains("scraper")
+            .country("de")
+            .matches(&entry));
+        assert!(!HistoryQuery::new().country("US").matches(&entry));
+        entry.note = Some("Scraper EU #campaign:a".to_string());
+        assert!(HistoryQuery::new().tag("campaign:a").matches(&entry));
+        assert!(!HistoryQuery::new().tag("campaign:b").matches(&entry));
+        entry.note = None;
+        assert!(!HistoryQuery::new().note_contains("scraper").matches(&entry));
+
+        assert_eq!(
+            HistoryQuery::active().params(Some(2)),
+            json!({ "onlyactive": "1", "page": "2" })
+        );
+        assert_eq!(HistoryQuery::new().params(None), json!({}));
+    }
+}
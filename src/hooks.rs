@@ -0,0 +1,179 @@
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ApiError, Status};
+use log::Level;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use task_local_extensions::Extensions;
+
+/// Callbacks invoked around every command, for logging, metrics or alerting.
+///
+/// All methods default to doing nothing. `params` never contains the API key.
+/// A hook that panics is logged and skipped, the command and the other hooks
+/// carry on.
+pub trait ApiHooks: Send + Sync + 'static {
+    fn on_request(&self, _command: &str, _params: &[(String, String)]) {}
+
+    fn on_response(&self, _command: &str, _status: &Status, _duration: Duration) {}
+
+    // `attempt` is the 1-based number of the retry about to be sent
+    fn on_retry(&self, _command: &str, _attempt: u32) {}
+
+    fn on_api_error(&self, _command: &str, _error: &ApiError) {}
+}
+
+// Request extension carrying the command through the middleware stack
+#[derive(Clone)]
+pub(crate) struct CommandContext {
+    pub(crate) command: Arc<str>,
+    // Shared with the caller so the attempt count survives the request
+    pub(crate) attempts: Arc<AtomicU32>,
+    // Whether the first attempt, counted by the client, already went through
+    pub(crate) sent: bool,
+}
+
+// Runs `call` on every hook in order, a panicking hook is logged and skipped
+pub(crate) fn run_hooks(hooks: &[Arc<dyn ApiHooks>], command: &str, call: impl Fn(&dyn ApiHooks)) {
+    for hook in hooks {
+        if panic::catch_unwind(AssertUnwindSafe(|| call(hook.as_ref()))).is_err() {
+            sublog!(
+                Subsystem::Transport,
+                Level::Warn,
+                "a hook panicked while handling {}",
+                command
+            );
+        }
+    }
+}
+
+// Counts an attempt and runs the retry hooks for every one but the first
+pub(crate) fn count_attempt(hooks: &[Arc<dyn ApiHooks>], command: &str, attempts: &AtomicU32) {
+    let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+    if attempt > 0 {
+        run_hooks(hooks, command, |hook| hook.on_retry(command, attempt));
+    }
+}
+
+// Sits inside the retry middleware so it sees the transport retries of a request
+pub(crate) struct RetryObserver {
+    pub(crate) hooks: Vec<Arc<dyn ApiHooks>>,
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+impl Middleware for RetryObserver {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if let Some(context) = extensions.get_mut::<CommandContext>() {
+            if context.sent {
+                count_attempt(&self.hooks, &context.command, &context.attempts);
+            }
+            context.sent = true;
+        }
+        next.run(req, extensions).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::{ok_response, serve};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    struct Recorder {
+        name: &'static str,
+        events: Arc<Mutex<Vec<String>>>,
+        panics: bool,
+    }
+
+    impl Recorder {
+        fn record(&self, event: &str, command: &str) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("{} {} {}", self.name, event, command));
+            if self.panics {
+                panic!("hook {} failed", self.name);
+            }
+        }
+    }
+
+    impl ApiHooks for Recorder {
+        fn on_request(&self, command: &str, _params: &[(String, String)]) {
+            self.record("request", command);
+        }
+
+        fn on_response(&self, command: &str, _status: &Status, _duration: Duration) {
+            self.record("response", command);
+        }
+
+        fn on_api_error(&self, command: &str, _error: &ApiError) {
+            self.record("error", command);
+        }
+    }
+
+    fn client(url: String, events: &Arc<Mutex<Vec<String>>>, panics: bool) -> TrueSocksClient {
+        TrueSocksClient::builder("test")
+            .base_url(url)
+            .hook(Recorder {
+                name: "first",
+                events: events.clone(),
+                panics,
+            })
+            .hook(Recorder {
+                name: "second",
+                events: events.clone(),
+                panics: false,
+            })
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_hooks_run_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let failure = json!({"status": {"code": 3, "message": "failed"}, "result": null});
+        let (url, _) = serve(vec![ok_response(json!(true)), failure]);
+        let client = client(url, &events, false);
+        assert!(client.ping().await.unwrap());
+        client.ping().await.unwrap_err();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "first request Ping",
+                "second request Ping",
+                "first response Ping",
+                "second response Ping",
+                "first request Ping",
+                "second request Ping",
+                "first error Ping",
+                "second error Ping",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_panicking_hook_does_not_break_dispatch() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (url, _) = serve(vec![ok_response(json!(true))]);
+        let client = client(url, &events, true);
+        assert!(client.ping().await.unwrap());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "first request Ping",
+                "second request Ping",
+                "first response Ping",
+                "second response Ping",
+            ]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::filter::ProxyFilter;
+use crate::models::{ConnectionType, ListOnlineResult, ProxyInfo};
+use std::collections::{HashMap, HashSet};
+
+type Postings = HashMap<String, HashSet<usize>>;
+
+/// Exact-match conditions for [`ProxyIndex::query`]. Text keys compare
+/// case-insensitively, `None` matches anything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndexQuery {
+    pub country_code: Option<String>,
+    pub city: Option<String>,
+    pub isp: Option<String>,
+    pub connection_type: Option<ConnectionType>,
+}
+
+impl IndexQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn country(mut self, country_code: impl Into<String>) -> Self {
+        self.country_code = Some(country_code.into());
+        self
+    }
+
+    pub fn city(mut self, city: impl Into<String>) -> Self {
+        self.city = Some(city.into());
+        self
+    }
+
+    pub fn isp(mut self, isp: impl Into<String>) -> Self {
+        self.isp = Some(isp.into());
+        self
+    }
+
+    pub fn connection_type(mut self, connection_type: ConnectionType) -> Self {
+        self.connection_type = Some(connection_type);
+        self
+    }
+}
+
+/// Hash indexes over an online list by country code, city, ISP and
+/// connection type. Lookups return proxies in listing order.
+#[derive(Debug, Clone)]
+pub struct ProxyIndex<'a> {
+    proxies: &'a [ProxyInfo],
+    by_country: Postings,
+    by_city: Postings,
+    by_isp: Postings,
+    by_connection_type: HashMap<ConnectionType, HashSet<usize>>,
+}
+
+fn key(value: &str) -> String {
+    value.to_lowercase()
+}
+
+impl<'a> ProxyIndex<'a> {
+    pub fn build(list: &'a ListOnlineResult) -> Self {
+        Self::from_proxies(&list.proxy_list)
+    }
+
+    pub fn from_proxies(proxies: &'a [ProxyInfo]) -> Self {
+        let mut index = Self {
+            proxies,
+            by_country: HashMap::new(),
+            by_city: HashMap::new(),
+            by_isp: HashMap::new(),
+            by_connection_type: HashMap::new(),
+        };
+        for (position, proxy) in proxies.iter().enumerate() {
+            index
+                .by_country
+                .entry(key(&proxy.country_code))
+                .or_default()
+                .insert(position);
+            index
+                .by_city
+                .entry(key(&proxy.city))
+                .or_default()
+                .insert(position);
+            index
+                .by_isp
+                .entry(key(&proxy.isp))
+                .or_default()
+                .insert(position);
+            index
+                .by_connection_type
+                .entry(proxy.connection_type.clone())
+                .or_default()
+                .insert(position);
+        }
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.proxies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    pub fn by_country(&self, country_code: &str) -> Vec<&'a ProxyInfo> {
+        self.query(&IndexQuery::new().country(country_code))
+    }
+
+    pub fn by_city(&self, city: &str) -> Vec<&'a ProxyInfo> {
+        self.query(&IndexQuery::new().city(city))
+    }
+
+    pub fn by_isp(&self, isp: &str) -> Vec<&'a ProxyInfo> {
+        self.query(&IndexQuery::new().isp(isp))
+    }
+
+    pub fn by_connection_type(&self, connection_type: &ConnectionType) -> Vec<&'a ProxyInfo> {
+        self.query(&IndexQuery::new().connection_type(connection_type.clone()))
+    }
+
+    /// Distinct country codes, cities or ISPs present, lowercased.
+    pub fn country_codes(&self) -> impl Iterator<Item = &str> {
+        self.by_country.keys().map(String::as_str)
+    }
+
+    pub fn cities(&self) -> impl Iterator<Item = &str> {
+        self.by_city.keys().map(String::as_str)
+    }
+
+    pub fn isps(&self) -> impl Iterator<Item = &str> {
+        self.by_isp.keys().map(String::as_str)
+    }
+
+    /// Proxies meeting every condition, the intersection of the matching
+    /// postings starting from the smallest.
+    pub fn query(&self, query: &IndexQuery) -> Vec<&'a ProxyInfo> {
+        let empty = HashSet::new();
+        let mut sets: Vec<&HashSet<usize>> = Vec::new();
+        let lookups = [
+            (&self.by_country, &query.country_code),
+            (&self.by_city, &query.city),
+            (&self.by_isp, &query.isp),
+        ];
+        for (postings, value) in lookups {
+            if let Some(value) = value {
+                sets.push(postings.get(&key(value)).unwrap_or(&empty));
+            }
+        }
+        if let Some(connection_type) = &query.connection_type {
+            sets.push(
+                self.by_connection_type
+                    .get(connection_type)
+                    .unwrap_or(&empty),
+            );
+        }
+        self.collect(intersect(sets))
+    }
+
+    /// Proxies matching the filter, narrowed down through the country, city
+    /// and connection type indexes before checking the remaining criteria.
+    pub fn matching(&self, filter: &ProxyFilter) -> Vec<&'a ProxyInfo> {
+        let union = |postings: &Postings, values: &[String]| -> HashSet<usize> {
+            values
+                .iter()
+                .filter_map(|value| postings.get(&key(value)))
+                .flatten()
+                .copied()
+                .collect()
+        };
+        let mut sets = Vec::new();
+        if !filter.country_codes.is_empty() {
+            sets.push(union(&self.by_country, &filter.country_codes));
+        }
+        if !filter.cities.is_empty() {
+            sets.push(union(&self.by_city, &filter.cities));
+        }
+        if !filter.connection_types.is_empty() {
+            sets.push(
+                filter
+                    .connection_types
+                    .iter()
+                    .filter_map(|connection_type| self.by_connection_type.get(connection_type))
+                    .flatten()
+                    .copied()
+                    .collect(),
+            );
+        }
+        let mut found = self.collect(intersect(sets.iter().collect()));
+        found.retain(|proxy| filter.matches(proxy));
+        found
+    }
+
+    // `None` stands for every proxy
+    fn collect(&self, positions: Option<HashSet<usize>>) -> Vec<&'a ProxyInfo> {
+        match positions {
+            None => self.proxies.iter().collect(),
+            Some(positions) => {
+                let mut positions: Vec<usize> = positions.into_iter().collect();
+                positions.sort_unstable();
+                positions
+                    .into_iter()
+                    .map(|position| &self.proxies[position])
+                    .collect()
+            }
+        }
+    }
+}
+
+fn intersect(mut sets: Vec<&HashSet<usize>>) -> Option<HashSet<usize>> {
+    sets.sort_by_key(|set| set.len());
+    let (smallest, rest) = sets.split_first()?;
+    Some(
+        smallest
+            .iter()
+            .filter(|position| rest.iter().all(|set| set.contains(position)))
+            .copied()
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+    use serde_json::json;
+
+    fn listing() -> ListOnlineResult {
+        let mut proxies = Vec::new();
+        for (proxy_id, country, city, isp, connect) in [
+            (1, "US", "New York", "Example ISP", "DSL"),
+            (2, "US", "Dallas", "Example ISP", "Mobile"),
+            (3, "DE", "Berlin", "Other ISP", "DSL"),
+            (4, "US", "New York", "Other ISP", "DSL"),
+        ] {
+            let mut proxy = proxy_info_json(proxy_id);
+            proxy["CountryCode"] = json!(country);
+            proxy["City"] = json!(city);
+            proxy["ISP"] = json!(isp);
+            proxy["Connect"] = json!(connect);
+            proxies.push(proxy);
+        }
+        serde_json::from_value(json!({
+            "LastUpdate": 1,
+            "ProxyCount": proxies.len(),
+            "ProxyList": proxies
+        }))
+        .unwrap()
+    }
+
+    fn ids(proxies: Vec<&ProxyInfo>) -> Vec<u32> {
+        proxies.into_iter().map(|proxy| proxy.proxy_id).collect()
+    }
+
+    #[test]
+    fn test_index_queries() {
+        let list = listing();
+        let index = ProxyIndex::build(&list);
+
+        assert_eq!(ids(index.by_country("us")), vec![1, 2, 4]);
+        assert_eq!(ids(index.by_isp("other isp")), vec![3, 4]);
+        assert_eq!(
+            ids(index.by_connection_type(&ConnectionType::Mobile)),
+            vec![2]
+        );
+        assert_eq!(
+            ids(index.query(
+                &IndexQuery::new()
+                    .country("US")
+                    .city("New York")
+                    .connection_type(ConnectionType::DSL)
+            )),
+            vec![1, 4]
+        );
+        assert!(index.query(&IndexQuery::new().country("FR")).is_empty());
+        assert_eq!(ids(index.query(&IndexQuery::new())).len(), 4);
+
+        let filter = ProxyFilter::new()
+            .country("US")
+            .country("DE")
+            .city("Berlin");
+        assert_eq!(ids(index.matching(&filter)), vec![3]);
+        assert_eq!(
+            ids(index.matching(&ProxyFilter::new().max_ping(1.0))),
+            Vec::<u32>::new()
+        );
+    }
+}
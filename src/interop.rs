@@ -0,0 +1,102 @@
+use crate::models::{ConnectInfo, ListInfo, ProxyInfo};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const VENDOR: &str = "truesocks";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocol {
+    Http,
+    Https,
+    Socks4,
+    Socks5,
+}
+
+/// Vendor-neutral description of a usable proxy, for tools mixing several
+/// providers. TrueSocks proxies are always SOCKS5.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyRecord {
+    pub vendor: String,
+    // Vendor specific identifier, the ProxyID for TrueSocks
+    pub id: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub protocol: ProxyProtocol,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // ISO 3166-1 alpha-2
+    pub country: Option<String>,
+    pub city: Option<String>,
+    // Address traffic leaves from, when different from `host`
+    pub exit_ip: Option<String>,
+    pub latency: Option<Duration>,
+}
+
+impl ProxyRecord {
+    /// Fill in the location, exit address and latency from the proxy listing.
+    pub fn with_info(mut self, proxy: &ProxyInfo) -> Self {
+        self.id = Some(proxy.proxy_id.to_string());
+        self.country = Some(proxy.country_code.clone()).filter(|code| !code.is_empty());
+        self.city = Some(proxy.city.clone()).filter(|city| !city.is_empty());
+        self.exit_ip = proxy.ip.clone();
+        self.latency = (proxy.ping.is_finite() && proxy.ping > 0.0)
+            .then(|| Duration::from_secs_f64(proxy.ping / 1000.0));
+        self
+    }
+}
+
+impl From<&ConnectInfo> for ProxyRecord {
+    fn from(connect_info: &ConnectInfo) -> Self {
+        let credentials = connect_info.credentials();
+        ProxyRecord {
+            vendor: VENDOR.to_string(),
+            id: None,
+            host: connect_info.connect_ip.clone(),
+            port: connect_info.connect_port,
+            protocol: ProxyProtocol::Socks5,
+            username: credentials.map(|(username, _)| username.to_string()),
+            password: credentials.map(|(_, password)| password.to_string()),
+            country: None,
+            city: None,
+            exit_ip: None,
+            latency: None,
+        }
+    }
+}
+
+impl From<(&ProxyInfo, &ConnectInfo)> for ProxyRecord {
+    fn from((proxy, connect_info): (&ProxyInfo, &ConnectInfo)) -> Self {
+        ProxyRecord::from(connect_info).with_info(proxy)
+    }
+}
+
+/// Fails for history entries without connect info, such as expired ones.
+impl TryFrom<&ListInfo> for ProxyRecord {
+    type Error = ();
+
+    fn try_from(entry: &ListInfo) -> Result<Self, ()> {
+        let connect_info = entry.connect_info.as_ref().ok_or(())?;
+        Ok(ProxyRecord::from((&entry.proxy_info, connect_info)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+
+    #[test]
+    fn test_record_from_entry() {
+        let mut entry = list_info(7);
+        let record = ProxyRecord::try_from(&entry).unwrap();
+        assert_eq!(record.vendor, VENDOR);
+        assert_eq!(record.id.as_deref(), Some("7"));
+        assert_eq!(record.protocol, ProxyProtocol::Socks5);
+        assert_eq!(record.username.as_deref(), Some("session7"));
+        assert_eq!(record.latency, Some(Duration::from_micros(120_500)));
+
+        entry.connect_info = None;
+        assert!(ProxyRecord::try_from(&entry).is_err());
+    }
+}
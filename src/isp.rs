@@ -0,0 +1,185 @@
+use crate::geo::normalize_place;
+use crate::models::{ListInfo, ListOnlineResult, ProxyInfo};
+use crate::pool::ProxyPool;
+use std::collections::{BTreeMap, HashMap};
+
+// Words that tell business forms and product lines apart, not networks
+const NOISE_WORDS: &[&str] = &[
+    "ag",
+    "bv",
+    "broadband",
+    "cable",
+    "co",
+    "communication",
+    "communications",
+    "company",
+    "corp",
+    "corporation",
+    "gmbh",
+    "holdings",
+    "inc",
+    "internet",
+    "isp",
+    "limited",
+    "llc",
+    "ltd",
+    "network",
+    "networks",
+    "plc",
+    "pty",
+    "sa",
+    "services",
+    "srl",
+    "telecom",
+    "telecommunications",
+    "the",
+];
+
+// "7922" or "as7922"
+fn is_asn_word(word: &str) -> bool {
+    let digits = word.strip_prefix("as").unwrap_or(word);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Key grouping the ISP names the API reports for one network: lowercased,
+/// accents and punctuation dropped, AS numbers and business-form words such
+/// as "Cable" or "LLC" removed. "Comcast Cable" and "COMCAST-7922" both give
+/// "comcast". A name made only of such words is kept whole.
+pub fn normalize_isp(isp: &str) -> String {
+    let normalized = normalize_place(isp);
+    let kept: Vec<&str> = normalized
+        .split(' ')
+        .filter(|word| !is_asn_word(word) && !NOISE_WORDS.contains(word))
+        .collect();
+    if kept.is_empty() {
+        normalized
+    } else {
+        kept.join(" ")
+    }
+}
+
+/// Decides which network a proxy belongs to.
+pub trait IspResolver: Send + Sync {
+    fn isp_key(&self, proxy: &ProxyInfo) -> String;
+}
+
+/// [`normalize_isp`] with aliases merging names that normalize differently,
+/// e.g. a brand and its parent company.
+#[derive(Debug, Clone, Default)]
+pub struct IspNormalizer {
+    aliases: HashMap<String, String>,
+}
+
+impl IspNormalizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Group ISPs normalizing to the same key as `name` under `key`.
+    pub fn alias(mut self, name: &str, key: impl Into<String>) -> Self {
+        self.aliases.insert(normalize_isp(name), key.into());
+        self
+    }
+
+    pub fn normalize(&self, isp: &str) -> String {
+        let key = normalize_isp(isp);
+        self.aliases.get(&key).cloned().unwrap_or(key)
+    }
+}
+
+impl IspResolver for IspNormalizer {
+    fn isp_key(&self, proxy: &ProxyInfo) -> String {
+        self.normalize(&proxy.isp)
+    }
+}
+
+impl ListOnlineResult {
+    /// Listed proxies by normalized ISP, see [`normalize_isp`].
+    pub fn group_by_isp(&self) -> BTreeMap<String, Vec<&ProxyInfo>> {
+        self.group_by_isp_with(&IspNormalizer::new())
+    }
+
+    pub fn group_by_isp_with<R: IspResolver + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> BTreeMap<String, Vec<&ProxyInfo>> {
+        let mut groups: BTreeMap<String, Vec<&ProxyInfo>> = BTreeMap::new();
+        for proxy in &self.proxy_list {
+            groups
+                .entry(resolver.isp_key(proxy))
+                .or_default()
+                .push(proxy);
+        }
+        groups
+    }
+}
+
+impl ProxyPool {
+    /// Members by normalized ISP, see [`normalize_isp`].
+    pub fn group_by_isp(&self) -> BTreeMap<String, Vec<ListInfo>> {
+        self.group_by_isp_with(&IspNormalizer::new())
+    }
+
+    pub fn group_by_isp_with<R: IspResolver + ?Sized>(
+        &self,
+        resolver: &R,
+    ) -> BTreeMap<String, Vec<ListInfo>> {
+        let mut groups: BTreeMap<String, Vec<ListInfo>> = BTreeMap::new();
+        for entry in self.members() {
+            groups
+                .entry(resolver.isp_key(&entry.proxy_info))
+                .or_default()
+                .push(entry);
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_isp() {
+        assert_eq!(normalize_isp("Comcast Cable"), "comcast");
+        assert_eq!(normalize_isp("COMCAST-7922"), "comcast");
+        assert_eq!(
+            normalize_isp("Comcast Cable Communications, LLC"),
+            "comcast"
+        );
+        assert_eq!(
+            normalize_isp("AS3320 Deutsche Telekom AG"),
+            "deutsche telekom"
+        );
+        assert_eq!(normalize_isp("Internet Services"), "internet services");
+
+        let normalizer = IspNormalizer::new().alias("Spectrum", "charter");
+        assert_eq!(normalizer.normalize("Charter Communications"), "charter");
+        assert_eq!(normalizer.normalize("SPECTRUM"), "charter");
+    }
+
+    #[test]
+    fn test_group_by_isp() {
+        let proxies: Vec<_> = ["Comcast Cable", "COMCAST-7922", "Verizon Business"]
+            .iter()
+            .enumerate()
+            .map(|(index, isp)| {
+                let mut proxy = proxy_info_json(index as u32 + 1);
+                proxy["ISP"] = json!(isp);
+                proxy
+            })
+            .collect();
+        let list: ListOnlineResult = serde_json::from_value(json!({
+            "LastUpdate": 1,
+            "ProxyCount": 3,
+            "ProxyList": proxies
+        }))
+        .unwrap();
+        let groups = list.group_by_isp();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["comcast"].len(), 2);
+        assert_eq!(groups["verizon business"][0].proxy_id, 3);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::logging::{sublog, Subsystem};
+use log::Level;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Something that happened to a pool member, as written to the journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEvent {
+    Added {
+        history_id: u64,
+        proxy_id: u32,
+    },
+    // Connect info changed on refresh
+    Updated {
+        history_id: u64,
+    },
+    Removed {
+        history_id: u64,
+        reason: String,
+    },
+    CheckedOut {
+        history_id: u64,
+        // Label of the filter the checkout was made with
+        filter: String,
+    },
+    CheckoutFailed {
+        filter: String,
+        reason: String,
+    },
+    Returned {
+        history_id: u64,
+    },
+    Unhealthy {
+        history_id: u64,
+        reason: String,
+    },
+    Healthy {
+        history_id: u64,
+    },
+    // Recorded by callers through `ProxyPool::journal`, the pool itself never buys
+    Purchased {
+        history_id: u64,
+        proxy_id: u32,
+    },
+    Renewed {
+        history_id: u64,
+    },
+    RenewalDisabled {
+        history_id: u64,
+    },
+    Draining,
+    Resumed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    // Unix timestamp in seconds
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+/// Destination of pool journal records. `append` is called while the pool is
+/// locked so records arrive in order, it should not block for long.
+pub trait JournalSink: Send + Sync + 'static {
+    fn append(&self, record: &JournalRecord);
+}
+
+impl<F> JournalSink for F
+where
+    F: Fn(&JournalRecord) + Send + Sync + 'static,
+{
+    fn append(&self, record: &JournalRecord) {
+        self(record)
+    }
+}
+
+/// Append-only journal file with one JSON record per line.
+pub struct FileJournal {
+    file: Mutex<File>,
+}
+
+impl FileJournal {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileJournal {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl JournalSink for FileJournal {
+    fn append(&self, record: &JournalRecord) {
+        let mut line = serde_json::to_vec(record).unwrap();
+        line.push(b'\n');
+        if let Err(err) = self.file.lock().unwrap().write_all(&line) {
+            sublog!(
+                Subsystem::Pool,
+                Level::Warn,
+                "could not write pool journal record: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Read a journal written by [`FileJournal`].
+pub fn read_journal(path: impl AsRef<Path>) -> io::Result<Vec<JournalRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line).map_err(io::Error::from)?);
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct MemberSnapshot {
+    pub proxy_id: Option<u32>,
+    pub checked_out: usize,
+    pub healthy: bool,
+    // Reason given by the last unhealthy record
+    pub last_failure: Option<String>,
+    pub renew_disabled: bool,
+}
+
+/// Pool state reconstructed from a journal.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct PoolSnapshot {
+    pub timestamp: u64,
+    pub members: BTreeMap<u64, MemberSnapshot>,
+    pub draining: bool,
+    pub failed_checkouts: usize,
+}
+
+fn member(members: &mut BTreeMap<u64, MemberSnapshot>, history_id: u64) -> &mut MemberSnapshot {
+    members.entry(history_id).or_insert_with(|| MemberSnapshot {
+        healthy: true,
+        ..MemberSnapshot::default()
+    })
+}
+
+/// Rebuild the pool as it was at `timestamp` from the records up to and
+/// including that second. Records are applied in journal order.
+pub fn replay_journal(records: &[JournalRecord], timestamp: u64) -> PoolSnapshot {
+    let mut snapshot = PoolSnapshot {
+        timestamp,
+        ..PoolSnapshot::default()
+    };
+    let members = &mut snapshot.members;
+    for record in records
+        .iter()
+        .filter(|record| record.timestamp <= timestamp)
+    {
+        match &record.event {
+            JournalEvent::Added {
+                history_id,
+                proxy_id,
+            }
+            | JournalEvent::Purchased {
+                history_id,
+                proxy_id,
+            } => member(members, *history_id).proxy_id = Some(*proxy_id),
+            JournalEvent::Updated { history_id } | JournalEvent::Healthy { history_id } => {
+                let entry = member(members, *history_id);
+                entry.healthy = true;
+            }
+            JournalEvent::Removed { history_id, .. } => {
+                members.remove(history_id);
+            }
+            JournalEvent::CheckedOut { history_id, .. } => {
+                member(members, *history_id).checked_out += 1;
+            }
+            JournalEvent::CheckoutFailed { .. } => snapshot.failed_checkouts += 1,
+            JournalEvent::Returned { history_id } => {
+                if let Some(entry) = members.get_mut(history_id) {
+                    entry.checked_out = entry.checked_out.saturating_sub(1);
+                }
+            }
+            JournalEvent::Unhealthy { history_id, reason } => {
+                let entry = member(members, *history_id);
+                entry.healthy = false;
+                entry.last_failure = Some(reason.clone());
+            }
+            JournalEvent::Renewed { history_id } => {
+                member(members, *history_id).renew_disabled = false;
+            }
+            JournalEvent::RenewalDisabled { history_id } => {
+                member(members, *history_id).renew_disabled = true;
+            }
+            JournalEvent::Draining => snapshot.draining = true,
+            JournalEvent::Resumed => snapshot.draining = false,
+        }
+    }
+    snapshot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::list_info;
+    use crate::pool::ProxyPool;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_pool_journal_replay() {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+        let pool = ProxyPool::with_journal(
+            TrueSocksClient::new("test"),
+            move |record: &JournalRecord| sink.lock().unwrap().push(record.clone()),
+        );
+        pool.insert(list_info(1));
+        pool.insert(list_info(2));
+        let held = pool.checkout().unwrap();
+        drop(pool.checkout().unwrap());
+        pool.journal(JournalEvent::Purchased {
+            history_id: 3,
+            proxy_id: 30,
+        });
+
+        let mut records = records.lock().unwrap().clone();
+        assert_eq!(records.len(), 6);
+        let snapshot = replay_journal(&records, u64::MAX);
+        assert_eq!(snapshot.members.len(), 3);
+        assert_eq!(snapshot.members[&held.history_id()].checked_out, 1);
+        assert_eq!(snapshot.members[&3].proxy_id, Some(30));
+
+        // Only records up to the requested second are applied
+        for (timestamp, record) in records.iter_mut().enumerate() {
+            record.timestamp = timestamp as u64;
+        }
+        assert_eq!(replay_journal(&records, 0).members.len(), 1);
+        let outstanding: usize = replay_journal(&records, 3)
+            .members
+            .values()
+            .map(|member| member.checked_out)
+            .sum();
+        assert_eq!(outstanding, 2);
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let record = JournalRecord {
+            timestamp: 5,
+            event: JournalEvent::Unhealthy {
+                history_id: 1,
+                reason: "probe failed".to_string(),
+            },
+        };
+        let line = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            line,
+            r#"{"timestamp":5,"event":"unhealthy","history_id":1,"reason":"probe failed"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<JournalRecord>(&line).unwrap(),
+            record
+        );
+    }
+}
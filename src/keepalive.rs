@@ -0,0 +1,256 @@
+use crate::logging::{sublog, Subsystem};
+use crate::models::ApiError;
+use crate::pool::ProxyPool;
+use crate::socks::{dial, ConnectPhase, SocksError};
+use futures::stream::{self, StreamExt};
+use log::Level;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+const EVENT_CAPACITY: usize = 64;
+// Number of probes in flight at once during a tick
+const PROBE_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone)]
+pub struct KeepAliveOptions {
+    pub interval: Duration,
+    // Host and port a tunnel is opened to through every checked-out proxy
+    pub target: (String, u16),
+    pub timeout: Duration,
+    // Consecutive failed probes before a proxy is taken out of rotation
+    pub max_failures: u32,
+    // Reload connect info from the history when a proxy is taken out of rotation
+    pub refresh_on_failure: bool,
+}
+
+impl Default for KeepAliveOptions {
+    fn default() -> Self {
+        KeepAliveOptions {
+            interval: Duration::from_secs(60),
+            target: ("1.1.1.1".to_string(), 80),
+            timeout: Duration::from_secs(10),
+            max_failures: 2,
+            refresh_on_failure: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ProbeFailure {
+    TimedOut(ConnectPhase),
+    Socks(Arc<SocksError>),
+}
+
+#[derive(Debug, Clone)]
+pub enum KeepAliveEvent {
+    Alive {
+        history_id: u64,
+        latency: Duration,
+    },
+    Failed {
+        history_id: u64,
+        failure: ProbeFailure,
+        consecutive: u32,
+    },
+    // Taken out of rotation, checkouts report it as unhealthy
+    Dead {
+        history_id: u64,
+    },
+    // Answered again after being taken out of rotation
+    Recovered {
+        history_id: u64,
+    },
+    // New connect info was loaded for a dead proxy, it is back in rotation
+    Refreshed {
+        history_id: u64,
+    },
+    RefreshFailed(ApiError),
+}
+
+/// Background task probing checked-out pool members so dead tunnels are found
+/// before user traffic hits them. The task stops when dropped.
+pub struct KeepAlive {
+    events: broadcast::Sender<KeepAliveEvent>,
+    handle: JoinHandle<()>,
+}
+
+impl KeepAlive {
+    pub fn subscribe(&self) -> broadcast::Receiver<KeepAliveEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+impl Drop for KeepAlive {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+impl ProxyPool {
+    pub fn spawn_keep_alive(&self, options: KeepAliveOptions) -> KeepAlive {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let sender = events.clone();
+        let pool = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut failures = HashMap::new();
+            let mut ticker = tokio::time::interval(options.interval);
+            loop {
+                ticker.tick().await;
+                probe_members(&pool, &options, &mut failures, &sender).await;
+            }
+        });
+        KeepAlive { events, handle }
+    }
+}
+
+async fn probe(
+    pool: &ProxyPool,
+    history_id: u64,
+    options: &KeepAliveOptions,
+) -> Result<Duration, ProbeFailure> {
+    let entry = pool
+        .members()
+        .into_iter()
+        .find(|entry| entry.history_id == history_id);
+    let Some(connect_info) = entry.and_then(|entry| entry.connect_info) else {
+        return Err(ProbeFailure::Socks(Arc::new(SocksError::NoCandidates)));
+    };
+    let started = Instant::now();
+    let target = (options.target.0.as_str(), options.target.1);
+    match dial(&connect_info, target, Some(started + options.timeout)).await {
+        Ok(_stream) => Ok(started.elapsed()),
+        Err(SocksError::TimedOut(phase)) => Err(ProbeFailure::TimedOut(phase)),
+        Err(err) => Err(ProbeFailure::Socks(Arc::new(err))),
+    }
+}
+
+async fn probe_members(
+    pool: &ProxyPool,
+    options: &KeepAliveOptions,
+    failures: &mut HashMap<u64, u32>,
+    events: &broadcast::Sender<KeepAliveEvent>,
+) {
+    let history_ids: Vec<u64> = pool
+        .checked_out_members()
+        .into_iter()
+        .map(|entry| entry.history_id)
+        .collect();
+    failures.retain(|history_id, _| history_ids.contains(history_id));
+
+    let results: Vec<(u64, Result<Duration, ProbeFailure>)> = stream::iter(history_ids)
+        .map(|history_id| async move { (history_id, probe(pool, history_id, options).await) })
+        .buffer_unordered(PROBE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut dead = Vec::new();
+    for (history_id, result) in results {
+        match result {
+            Ok(latency) => {
+                failures.remove(&history_id);
+                pool.report_latency(history_id, latency);
+                let _ = events.send(KeepAliveEvent::Alive {
+                    history_id,
+                    latency,
+                });
+                if pool.set_healthy(history_id, true, "") {
+                    sublog!(Subsystem::Health, Level::Info, "{} recovered", history_id);
+                    let _ = events.send(KeepAliveEvent::Recovered { history_id });
+                }
+            }
+            Err(failure) => {
+                let consecutive = failures.entry(history_id).or_insert(0);
+                *consecutive += 1;
+                let reason = format!(
+                    "{} failed keep-alive probes, last: {:?}",
+                    consecutive, failure
+                );
+                sublog!(
+                    Subsystem::Health,
+                    Level::Debug,
+                    "{}: {}",
+                    history_id,
+                    reason
+                );
+                let _ = events.send(KeepAliveEvent::Failed {
+                    history_id,
+                    failure,
+                    consecutive: *consecutive,
+                });
+                if *consecutive >= options.max_failures
+                    && !pool.set_healthy(history_id, false, &reason)
+                {
+                    sublog!(
+                        Subsystem::Health,
+                        Level::Warn,
+                        "{} marked dead: {}",
+                        history_id,
+                        reason
+                    );
+                    let _ = events.send(KeepAliveEvent::Dead { history_id });
+                    dead.push(history_id);
+                }
+            }
+        }
+    }
+
+    if dead.is_empty() || !options.refresh_on_failure {
+        return;
+    }
+    if let Err(err) = pool.refresh().await {
+        let _ = events.send(KeepAliveEvent::RefreshFailed(err));
+        return;
+    }
+    for history_id in dead {
+        if pool.is_member_healthy(history_id) {
+            failures.remove(&history_id);
+            let _ = events.send(KeepAliveEvent::Refreshed { history_id });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::list_info;
+    use crate::pool::PoolError;
+
+    #[tokio::test]
+    async fn test_dead_member_leaves_rotation() {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        let mut entry = list_info(1);
+        entry.connect_info.as_mut().unwrap().connect_ip = "127.0.0.1".to_string();
+        entry.connect_info.as_mut().unwrap().connect_port = 1;
+        pool.insert(entry);
+        let checkout = pool.checkout().unwrap();
+
+        let options = KeepAliveOptions {
+            timeout: Duration::from_millis(500),
+            max_failures: 1,
+            refresh_on_failure: false,
+            ..KeepAliveOptions::default()
+        };
+        let (events, mut received) = broadcast::channel(EVENT_CAPACITY);
+        probe_members(&pool, &options, &mut HashMap::new(), &events).await;
+
+        assert!(matches!(
+            received.try_recv(),
+            Ok(KeepAliveEvent::Failed { history_id: 1, .. })
+        ));
+        assert!(matches!(
+            received.try_recv(),
+            Ok(KeepAliveEvent::Dead { history_id: 1 })
+        ));
+        assert!(!checkout.is_healthy());
+        assert_eq!(pool.checkout().err(), Some(PoolError::Empty));
+    }
+}
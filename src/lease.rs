@@ -0,0 +1,258 @@
+use crate::filter::ProxyFilter;
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ConnectInfo, ListInfo};
+use crate::pool::{PoolCheckout, PoolError, ProxyPool};
+use crate::runtime::{spawn, Instant};
+use log::Level;
+use std::time::Duration;
+
+/// How members are rested and quarantined when leases end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseOptions {
+    // Time a member stays out of rotation after a lease ends
+    pub cooldown: Duration,
+    // Failed leases in a row that quarantine a member, 0 never quarantines
+    pub max_failures: u32,
+}
+
+impl Default for LeaseOptions {
+    fn default() -> Self {
+        LeaseOptions {
+            cooldown: Duration::from_secs(10),
+            max_failures: 3,
+        }
+    }
+}
+
+/// A proxy leased from the pool for a while. Dropping the lease returns the
+/// proxy, which rests for the pool's cooldown before it is handed out again.
+///
+/// A lease counts as successful unless it is ended with [`fail`](Self::fail).
+/// A member failing `max_failures` leases in a row is quarantined and checked
+/// with `BoughtProxyCheck`; it comes back once every test passes.
+///
+/// A lease still held past its duration is returned by the pool on its next
+/// checkout, as a successful one; ending it afterwards has no effect.
+pub struct Lease {
+    pool: ProxyPool,
+    checkout: PoolCheckout,
+    lease_id: u64,
+    expires_at: Instant,
+    failed: bool,
+}
+
+impl Lease {
+    pub fn history_id(&self) -> u64 {
+        self.checkout.history_id()
+    }
+
+    pub fn entry(&self) -> &ListInfo {
+        self.checkout.entry()
+    }
+
+    pub fn connect_info(&self) -> &ConnectInfo {
+        self.checkout.connect_info()
+    }
+
+    /// Time left of the requested duration, zero once it is over and the pool
+    /// may take the proxy back.
+    pub fn remaining(&self) -> Duration {
+        self.expires_at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// End the lease reporting that the proxy did not work.
+    pub fn fail(mut self) {
+        self.failed = true;
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let history_id = self.history_id();
+        let Some(entry) = self.pool.end_lease(self.lease_id, self.failed) else {
+            return;
+        };
+        sublog!(
+            Subsystem::Lease,
+            Level::Warn,
+            "member {} quarantined after failed leases",
+            history_id
+        );
+        // Without a runtime the check waits for `recheck_quarantined`
+        #[cfg(not(target_arch = "wasm32"))]
+        if tokio::runtime::Handle::try_current().is_err() {
+            return;
+        }
+        let pool = self.pool.clone();
+        spawn(async move {
+            check_quarantined(&pool, &entry).await;
+        });
+    }
+}
+
+// Lift the quarantine when every BoughtProxyCheck test passes
+async fn check_quarantined(pool: &ProxyPool, entry: &ListInfo) -> bool {
+    match pool.client().check_purchased_proxy(&entry.proxy_info).await {
+        Ok(result) if result.tests_total > 0 && result.tests_passed == result.tests_total => {
+            sublog!(
+                Subsystem::Lease,
+                Level::Info,
+                "member {} passed its check, back in rotation",
+                entry.history_id
+            );
+            pool.lift_quarantine(entry.history_id);
+            true
+        }
+        Ok(result) => {
+            sublog!(
+                Subsystem::Lease,
+                Level::Warn,
+                "member {} stays quarantined, {}/{} tests passed",
+                entry.history_id,
+                result.tests_passed,
+                result.tests_total
+            );
+            false
+        }
+        Err(err) => {
+            sublog!(
+                Subsystem::Lease,
+                Level::Warn,
+                "check of quarantined member {} failed: API error {}",
+                entry.history_id,
+                err.code()
+            );
+            false
+        }
+    }
+}
+
+impl ProxyPool {
+    /// Check out a member for `duration`, see [`Lease`].
+    pub fn lease(&self, duration: Duration) -> Result<Lease, PoolError> {
+        self.lease_matching(&ProxyFilter::default(), duration)
+    }
+
+    pub fn lease_matching(
+        &self,
+        filter: &ProxyFilter,
+        duration: Duration,
+    ) -> Result<Lease, PoolError> {
+        let expires_at = Instant::now() + duration;
+        let (checkout, lease_id) = self.checkout_leased(filter, expires_at)?;
+        Ok(Lease {
+            pool: self.clone(),
+            checkout,
+            lease_id,
+            expires_at,
+            failed: false,
+        })
+    }
+
+    /// Check every quarantined member again, returning the HistoryIDs put
+    /// back into rotation.
+    pub async fn recheck_quarantined(&self) -> Vec<u64> {
+        let mut lifted = Vec::new();
+        for entry in self.quarantined_members() {
+            if check_quarantined(self, &entry).await {
+                lifted.push(entry.history_id);
+            }
+        }
+        lifted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::{list_info, ok_response, serve};
+    use serde_json::json;
+
+    fn check_result(passed: u32) -> serde_json::Value {
+        ok_response(json!({
+            "tests_passed": passed,
+            "tests_total": 3,
+            "tests_result": "",
+            "tests_result_str": ""
+        }))
+    }
+
+    #[test]
+    fn test_lease_cooldown() {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        pool.insert(list_info(1));
+        pool.set_lease_options(LeaseOptions {
+            cooldown: Duration::from_secs(3600),
+            max_failures: 3,
+        });
+        let lease = pool.lease(Duration::from_secs(60)).unwrap();
+        assert!(!lease.is_expired());
+        drop(lease);
+        assert_eq!(pool.outstanding(), 0);
+        assert_eq!(pool.lease(Duration::ZERO).err(), Some(PoolError::Empty));
+
+        pool.set_lease_options(LeaseOptions {
+            cooldown: Duration::ZERO,
+            max_failures: 3,
+        });
+        pool.insert(list_info(2));
+        assert_eq!(pool.lease(Duration::ZERO).unwrap().history_id(), 2);
+    }
+
+    #[test]
+    fn test_expired_leases_are_reclaimed() {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        pool.insert(list_info(1));
+        pool.insert(list_info(2));
+        pool.set_lease_options(LeaseOptions {
+            cooldown: Duration::from_secs(3600),
+            max_failures: 1,
+        });
+        let held = pool.lease(Duration::from_secs(3600)).unwrap();
+        let expired = pool.lease(Duration::ZERO).unwrap();
+        assert!(expired.is_expired());
+
+        // Taken back without being dropped, then resting like a returned lease
+        assert_eq!(expired.history_id(), 2);
+        assert_eq!(pool.outstanding(), 1);
+        assert_eq!(pool.lease(Duration::ZERO).unwrap().history_id(), 1);
+
+        // Failing it late is not counted against the member
+        expired.fail();
+        let stats = pool.stats();
+        assert_eq!(stats[1].usage.leases, 1);
+        assert_eq!(stats[1].usage.failed_leases, 0);
+        assert_eq!(pool.quarantined_members().len(), 0);
+
+        drop(held);
+        assert_eq!(pool.outstanding(), 0);
+    }
+
+    #[test]
+    fn test_failed_leases_quarantine() {
+        let (url, _requests) = serve(vec![check_result(2), check_result(3)]);
+        let pool = ProxyPool::new(TrueSocksClient::builder("test").base_url(url).build());
+        pool.insert(list_info(1));
+        pool.set_lease_options(LeaseOptions {
+            cooldown: Duration::ZERO,
+            max_failures: 2,
+        });
+        // Outside a runtime, so no check is spawned
+        pool.lease(Duration::ZERO).unwrap().fail();
+        pool.lease(Duration::ZERO).unwrap().fail();
+        assert_eq!(pool.lease(Duration::ZERO).err(), Some(PoolError::Empty));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        assert!(runtime.block_on(pool.recheck_quarantined()).is_empty());
+        assert_eq!(runtime.block_on(pool.recheck_quarantined()), vec![1]);
+        assert_eq!(pool.lease(Duration::ZERO).unwrap().history_id(), 1);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::client::TrueSocksClient;
+use crate::credits::Credits;
+use crate::history::HistoryQuery;
+use crate::models::{AccountStatusResult, ApiError, ListInfo, PurchaseKind};
+use crate::unix_now;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LedgerKind {
+    Spend,
+    TopUp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    // Unix timestamp in seconds of the poll that observed the change
+    pub timestamp: u64,
+    pub kind: LedgerKind,
+    pub amount: Credits,
+    // Balance after the change
+    pub balance: Credits,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, entry: LedgerEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    pub fn total(&self, kind: LedgerKind) -> Credits {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == kind)
+            .map(|entry| entry.amount)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountEvent {
+    CreditsToppedUp { amount: Credits },
+    CreditsSpent { amount: Credits },
+}
+
+/// Compares successive `AccountStatus` polls and books balance changes into a
+/// [`Ledger`]: a higher balance is a top-up, a lower one is spending.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceTracker {
+    last_balance: Option<Credits>,
+    ledger: Ledger,
+}
+
+impl BalanceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    pub fn last_balance(&self) -> Option<Credits> {
+        self.last_balance
+    }
+
+    pub fn observe(&mut self, account: &AccountStatusResult) -> Option<AccountEvent> {
+        self.observe_at(account, unix_now())
+    }
+
+    pub fn observe_at(
+        &mut self,
+        account: &AccountStatusResult,
+        timestamp: u64,
+    ) -> Option<AccountEvent> {
+        let balance = account.credits;
+        let previous = self.last_balance.replace(balance)?;
+
+        let (kind, amount, event) = if balance > previous {
+            let amount = balance - previous;
+            (
+                LedgerKind::TopUp,
+                amount,
+                AccountEvent::CreditsToppedUp { amount },
+            )
+        } else if balance < previous {
+            let amount = previous - balance;
+            (
+                LedgerKind::Spend,
+                amount,
+                AccountEvent::CreditsSpent { amount },
+            )
+        } else {
+            return None;
+        };
+
+        self.ledger.record(LedgerEntry {
+            timestamp,
+            kind,
+            amount,
+            balance,
+        });
+        Some(event)
+    }
+}
+
+const DAY: u64 = 24 * 60 * 60;
+// 1970-01-05, the first Monday after the epoch
+const FIRST_MONDAY: u64 = 4 * DAY;
+
+/// Length of the time buckets of a [`SpendReport`], in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpendPeriod {
+    Day,
+    // Weeks starting on Monday
+    Week,
+}
+
+impl SpendPeriod {
+    /// Unix timestamp in seconds of the start of the bucket `timestamp` is in.
+    pub fn bucket_start(self, timestamp: u64) -> u64 {
+        match self {
+            SpendPeriod::Day => timestamp - timestamp % DAY,
+            SpendPeriod::Week if timestamp < FIRST_MONDAY => 0,
+            SpendPeriod::Week => timestamp - (timestamp - FIRST_MONDAY) % (7 * DAY),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendTotal {
+    pub credits: Credits,
+    pub purchases: u32,
+}
+
+impl SpendTotal {
+    fn add(&mut self, cost: Credits) {
+        self.credits += cost;
+        self.purchases += 1;
+    }
+}
+
+/// Credits spent per history entry, aggregated for billing dashboards.
+///
+/// The history only keeps the latest purchase of each entry and no price, so
+/// every entry counts once at the current cost of its proxy for the way it was
+/// acquired. Renewals are not included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendReport {
+    pub period: SpendPeriod,
+    pub total: SpendTotal,
+    // Keyed by the unix timestamp in seconds the bucket starts at
+    pub by_period: BTreeMap<u64, SpendTotal>,
+    pub by_country: BTreeMap<String, SpendTotal>,
+    pub by_isp: BTreeMap<String, SpendTotal>,
+    pub by_kind: BTreeMap<PurchaseKind, SpendTotal>,
+}
+
+impl SpendReport {
+    pub fn from_entries(entries: &[ListInfo], period: SpendPeriod) -> Self {
+        let mut report = SpendReport {
+            period,
+            total: SpendTotal::default(),
+            by_period: BTreeMap::new(),
+            by_country: BTreeMap::new(),
+            by_isp: BTreeMap::new(),
+            by_kind: BTreeMap::new(),
+        };
+        for entry in entries {
+            let kind = entry.purchase_kind();
+            let cost = entry.proxy_info.cost(kind).unwrap_or_default();
+            report.total.add(cost);
+            report
+                .by_period
+                .entry(period.bucket_start(entry.last_bought))
+                .or_default()
+                .add(cost);
+            report
+                .by_country
+                .entry(entry.proxy_info.country_code.clone())
+                .or_default()
+                .add(cost);
+            report
+                .by_isp
+                .entry(entry.proxy_info.isp.clone())
+                .or_default()
+                .add(cost);
+            report.by_kind.entry(kind).or_default().add(cost);
+        }
+        report
+    }
+}
+
+impl TrueSocksClient {
+    /// Walk every history page and aggregate spending, see [`SpendReport`].
+    pub async fn spend_report(&self, period: SpendPeriod) -> Result<SpendReport, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::new()).await?;
+        Ok(SpendReport::from_entries(&entries, period))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{account_status, list_info};
+
+    #[test]
+    fn test_balance_tracker_detects_top_up() {
+        let mut tracker = BalanceTracker::new();
+        assert_eq!(tracker.observe_at(&account_status(100), 1), None);
+        assert_eq!(
+            tracker.observe_at(&account_status(70), 2),
+            Some(AccountEvent::CreditsSpent {
+                amount: Credits(30)
+            })
+        );
+        assert_eq!(
+            tracker.observe_at(&account_status(570), 3),
+            Some(AccountEvent::CreditsToppedUp {
+                amount: Credits(500)
+            })
+        );
+        assert_eq!(tracker.observe_at(&account_status(570), 4), None);
+        assert_eq!(tracker.ledger().total(LedgerKind::TopUp), Credits(500));
+        assert_eq!(tracker.ledger().total(LedgerKind::Spend), Credits(30));
+    }
+
+    #[test]
+    fn test_spend_report() {
+        // Wednesday 2023-11-15 and Friday 2023-11-17, then Monday 2023-11-20
+        let mut first = list_info(1);
+        first.last_bought = 1_700_006_400;
+        first.proxy_info.rent_cost = Credits(10);
+        let mut second = list_info(2);
+        second.last_bought = 1_700_179_200;
+        second.is_rented = true;
+        second.proxy_info.private_rent_cost = Credits(25);
+        second.proxy_info.country_code = "DE".to_string();
+        let mut third = list_info(3);
+        third.last_bought = 1_700_438_400;
+        third.proxy_info.rent_cost = Credits(5);
+
+        let entries = [first, second, third];
+        let weekly = SpendReport::from_entries(&entries, SpendPeriod::Week);
+        assert_eq!(weekly.total.credits, Credits(40));
+        assert_eq!(weekly.total.purchases, 3);
+        let weeks: Vec<(u64, Credits)> = weekly
+            .by_period
+            .iter()
+            .map(|(start, total)| (*start, total.credits))
+            .collect();
+        assert_eq!(
+            weeks,
+            vec![(1_699_833_600, Credits(35)), (1_700_438_400, Credits(5))]
+        );
+        assert_eq!(weekly.by_country["DE"].credits, Credits(25));
+        assert_eq!(
+            weekly.by_kind[&PurchaseKind::SharedBuy],
+            SpendTotal {
+                credits: Credits(15),
+                purchases: 2
+            }
+        );
+
+        let daily = SpendReport::from_entries(&entries, SpendPeriod::Day);
+        assert_eq!(daily.by_period.len(), 3);
+        assert!(daily.by_period.contains_key(&1_700_006_400));
+    }
+}
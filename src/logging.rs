@@ -0,0 +1,197 @@
+use log::{Level, LevelFilter};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// Part of the crate logging under its own `truesocks::<name>` target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Subsystem {
+    // Requests and responses, see `TrueSocksClientBuilder::debug_logging`
+    Transport,
+    Pool,
+    Lease,
+    // Health checks and keep-alive probes
+    Health,
+    Renewal,
+    Cache,
+    // Delivery of notifications to webhooks and other notifiers
+    Notify,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 7] = [
+        Subsystem::Transport,
+        Subsystem::Pool,
+        Subsystem::Lease,
+        Subsystem::Health,
+        Subsystem::Renewal,
+        Subsystem::Cache,
+        Subsystem::Notify,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Transport => "transport",
+            Subsystem::Pool => "pool",
+            Subsystem::Lease => "lease",
+            Subsystem::Health => "health",
+            Subsystem::Renewal => "renewal",
+            Subsystem::Cache => "cache",
+            Subsystem::Notify => "notify",
+        }
+    }
+
+    pub fn target(self) -> &'static str {
+        match self {
+            Subsystem::Transport => "truesocks::transport",
+            Subsystem::Pool => "truesocks::pool",
+            Subsystem::Lease => "truesocks::lease",
+            Subsystem::Health => "truesocks::health",
+            Subsystem::Renewal => "truesocks::renewal",
+            Subsystem::Cache => "truesocks::cache",
+            Subsystem::Notify => "truesocks::notify",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFilterError {
+    UnknownSubsystem(String),
+    InvalidLevel(String),
+}
+
+impl fmt::Display for LogFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFilterError::UnknownSubsystem(name) => write!(f, "unknown subsystem {:?}", name),
+            LogFilterError::InvalidLevel(level) => write!(f, "invalid log level {:?}", level),
+        }
+    }
+}
+
+impl std::error::Error for LogFilterError {}
+
+impl FromStr for Subsystem {
+    type Err = LogFilterError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Subsystem::ALL
+            .into_iter()
+            .find(|subsystem| subsystem.name() == name)
+            .ok_or_else(|| LogFilterError::UnknownSubsystem(name.to_string()))
+    }
+}
+
+/// Most verbose level logged per subsystem, on top of the filtering done by
+/// the installed logger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    // Level of subsystems without their own entry
+    pub default: LevelFilter,
+    pub subsystems: BTreeMap<Subsystem, LevelFilter>,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter {
+            default: LevelFilter::Trace,
+            subsystems: BTreeMap::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn level(&self, subsystem: Subsystem) -> LevelFilter {
+        self.subsystems
+            .get(&subsystem)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, LogFilterError> {
+    level
+        .parse()
+        .map_err(|_| LogFilterError::InvalidLevel(level.to_string()))
+}
+
+impl FromStr for LogFilter {
+    type Err = LogFilterError;
+
+    /// Comma separated `subsystem=level` directives, a bare level sets the
+    /// default, e.g. `warn,pool=debug,transport=off`.
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter::default();
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((name, level)) => {
+                    filter
+                        .subsystems
+                        .insert(name.trim().parse()?, parse_level(level.trim())?);
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+static FILTER: RwLock<Option<LogFilter>> = RwLock::new(None);
+
+/// Replace the log filter of every subsystem at runtime, e.g.
+/// `set_log_filter("pool=debug,transport=warn")`. Subsystems left out log at
+/// every level again. The previous filter stays when `spec` is invalid.
+pub fn set_log_filter(spec: &str) -> Result<(), LogFilterError> {
+    let filter = spec.parse()?;
+    *FILTER.write().unwrap() = Some(filter);
+    Ok(())
+}
+
+/// The filter set by [`set_log_filter`], everything enabled when none was set.
+pub fn log_filter() -> LogFilter {
+    FILTER.read().unwrap().clone().unwrap_or_default()
+}
+
+pub(crate) fn enabled(subsystem: Subsystem, level: Level) -> bool {
+    match FILTER.read().unwrap().as_ref() {
+        Some(filter) => level <= filter.level(subsystem),
+        None => true,
+    }
+}
+
+// Log under the subsystem's target when the runtime filter allows the level
+macro_rules! sublog {
+    ($subsystem:expr, $level:expr, $($arg:tt)+) => {
+        if $crate::logging::enabled($subsystem, $level) {
+            log::log!(target: $subsystem.target(), $level, $($arg)+);
+        }
+    };
+}
+
+pub(crate) use sublog;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_filter() {
+        let filter: LogFilter = "warn, pool=debug,transport=off".parse().unwrap();
+        assert_eq!(filter.level(Subsystem::Pool), LevelFilter::Debug);
+        assert_eq!(filter.level(Subsystem::Transport), LevelFilter::Off);
+        assert_eq!(filter.level(Subsystem::Health), LevelFilter::Warn);
+        assert_eq!(
+            "pool=loud".parse::<LogFilter>(),
+            Err(LogFilterError::InvalidLevel("loud".to_string()))
+        );
+        assert_eq!(
+            "network=debug".parse::<LogFilter>(),
+            Err(LogFilterError::UnknownSubsystem("network".to_string()))
+        );
+        assert_eq!(
+            LogFilter::default().level(Subsystem::Lease),
+            LevelFilter::Trace
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Usage metrics reported through the [`metrics`](::metrics) facade, install a
+//! recorder such as `metrics-exporter-prometheus` to scrape them.
+//!
+//! | Name | Type | Labels |
+//! |------|------|--------|
+//! | `truesocks_api_calls_total` | counter | `command` |
+//! | `truesocks_api_errors_total` | counter | `command`, `code` |
+//! | `truesocks_request_duration_seconds` | histogram | `command` |
+//! | `truesocks_credits_remaining` | gauge | |
+//! | `truesocks_active_proxies` | gauge | |
+//!
+//! The gauges are updated whenever the account status or the unfiltered list
+//! of active history entries is fetched.
+
+use crate::models::ApiError;
+use ::metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram};
+use std::time::Duration;
+
+pub const API_CALLS: &str = "truesocks_api_calls_total";
+pub const API_ERRORS: &str = "truesocks_api_errors_total";
+pub const REQUEST_DURATION: &str = "truesocks_request_duration_seconds";
+pub const CREDITS_REMAINING: &str = "truesocks_credits_remaining";
+pub const ACTIVE_PROXIES: &str = "truesocks_active_proxies";
+
+/// Register units and descriptions of every metric with the installed recorder.
+pub fn describe_metrics() {
+    describe_counter!(API_CALLS, "Commands sent to the TrueSocks API.");
+    describe_counter!(API_ERRORS, "Commands that failed, by status code.");
+    describe_histogram!(
+        REQUEST_DURATION,
+        ::metrics::Unit::Seconds,
+        "Time until a command completed, retries included."
+    );
+    describe_gauge!(CREDITS_REMAINING, "Credits left in the account.");
+    describe_gauge!(ACTIVE_PROXIES, "History entries that have not expired.");
+}
+
+pub(crate) fn record_command(command: &str, result: Result<(), &ApiError>, duration: Duration) {
+    counter!(API_CALLS, "command" => command.to_string()).increment(1);
+    histogram!(REQUEST_DURATION, "command" => command.to_string()).record(duration.as_secs_f64());
+    if let Err(err) = result {
+        counter!(
+            API_ERRORS,
+            "command" => command.to_string(),
+            "code" => err.code().to_string()
+        )
+        .increment(1);
+    }
+}
+
+pub(crate) fn record_credits(credits: u32) {
+    gauge!(CREDITS_REMAINING).set(credits);
+}
+
+pub(crate) fn record_active_proxies(count: usize) {
+    gauge!(ACTIVE_PROXIES).set(count as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status_codes::RATE_LIMITED;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use metrics_util::MetricKind;
+
+    #[test]
+    fn test_record_metrics() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        ::metrics::with_local_recorder(&recorder, || {
+            record_command("Ping", Ok(()), Duration::from_millis(250));
+            record_command(
+                "Ping",
+                Err(&ApiError::from(RATE_LIMITED)),
+                Duration::from_millis(10),
+            );
+            record_credits(42);
+            record_active_proxies(3);
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |kind: MetricKind, name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, ..)| key.kind() == kind && key.key().name() == name)
+                .map(|(key, _, _, value)| (key.key().labels().cloned().collect::<Vec<_>>(), value))
+                .unwrap()
+        };
+        assert!(matches!(
+            value(MetricKind::Counter, API_CALLS).1,
+            DebugValue::Counter(2)
+        ));
+        let (labels, errors) = value(MetricKind::Counter, API_ERRORS);
+        assert!(matches!(errors, DebugValue::Counter(1)));
+        assert!(labels
+            .iter()
+            .any(|label| label.key() == "code" && label.value() == "429"));
+        assert!(
+            matches!(value(MetricKind::Histogram, REQUEST_DURATION).1, DebugValue::Histogram(samples) if samples.len() == 2)
+        );
+        assert!(
+            matches!(value(MetricKind::Gauge, CREDITS_REMAINING).1, DebugValue::Gauge(credits) if credits.0 == 42.0)
+        );
+        assert!(
+            matches!(value(MetricKind::Gauge, ACTIVE_PROXIES).1, DebugValue::Gauge(count) if count.0 == 3.0)
+        );
+    }
+}
@@ -1,11 +1,111 @@
-use serde::de::{Deserializer, Error, Unexpected};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use crate::credits::Credits;
+use crate::purchase::PurchaseValidationError;
+use crate::status_codes::{is_retryable, ACCEPTED_WITH_WARNING, TRANSPORT};
+use serde::de::{DeserializeOwned, Deserializer, Error, Unexpected};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+// Longest response body kept in a `DecodeError`, in bytes
+const DECODE_BODY_LIMIT: usize = 4096;
 
 #[derive(Debug, Clone)]
 pub enum ApiError {
     RequestError(Status),
     StatusError(u16),
+    // The response could not be decoded, reported with code 418
+    DecodeError(Box<DecodeError>),
+    // Rejected before the purchase command was sent
+    PurchaseValidation(PurchaseValidationError),
+}
+
+impl ApiError {
+    // API status code or HTTP status code, whichever caused the error
+    pub fn code(&self) -> u64 {
+        match self {
+            ApiError::RequestError(status) => status.code,
+            ApiError::StatusError(code) => *code as u64,
+            ApiError::DecodeError(_) => TRANSPORT as u64,
+            ApiError::PurchaseValidation(err) => err.code() as u64,
+        }
+    }
+
+    /// Whether sending the command again may succeed, see [`is_retryable`].
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ApiError::DecodeError(_) | ApiError::PurchaseValidation(_) => false,
+            _ => is_retryable(self.code()),
+        }
+    }
+
+    // Whether a failed purchase certainly did not charge the account: the API
+    // answered with an error status or the purchase was never sent. Timeouts,
+    // transport failures and HTTP errors may have come after the API took it.
+    pub(crate) fn is_definite_rejection(&self) -> bool {
+        match self {
+            ApiError::RequestError(status) => status.code != ACCEPTED_WITH_WARNING as u64,
+            ApiError::PurchaseValidation(_) => true,
+            ApiError::StatusError(_) | ApiError::DecodeError(_) => false,
+        }
+    }
+}
+
+/// A response that did not match the expected schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub command: String,
+    // Path to the offending field, e.g. `result.ProxyList[3].Speed`, empty when
+    // the body is not valid JSON
+    pub path: String,
+    pub message: String,
+    // Response body with credentials redacted, cut to a few kilobytes
+    pub body: String,
+    pub truncated: bool,
+}
+
+impl DecodeError {
+    pub(crate) fn new(command: &str, path: String, message: String, body: &str) -> Self {
+        let mut end = body.len().min(DECODE_BODY_LIMIT);
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        DecodeError {
+            command: command.to_string(),
+            path,
+            message,
+            body: body[..end].to_string(),
+            truncated: end < body.len(),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{} response: {}", self.command, self.message)
+        } else {
+            write!(
+                f,
+                "{} response at `{}`: {}",
+                self.command, self.path, self.message
+            )
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for ApiError {
+    fn from(err: DecodeError) -> Self {
+        ApiError::DecodeError(Box::new(err))
+    }
+}
+
+impl From<PurchaseValidationError> for ApiError {
+    fn from(err: PurchaseValidationError) -> Self {
+        ApiError::PurchaseValidation(err)
+    }
 }
 
 impl From<u16> for ApiError {
@@ -21,15 +121,107 @@ impl From<Status> for ApiError {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Status {
     pub code: u64,
     pub message: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ApiResponse<T> {
     pub status: Status,
     pub result: T,
+    // Set when the status code is configured to be treated as a warning (e.g. 209)
+    #[serde(skip)]
+    pub warning: Option<Warning>,
+}
+
+// A non-zero status code the client was configured to accept instead of failing
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct Warning {
+    pub code: u64,
+    pub message: String,
+}
+
+impl From<Status> for Warning {
+    fn from(status: Status) -> Self {
+        Warning {
+            code: status.code,
+            message: status.message,
+        }
+    }
+}
+
+/// A result along with the warnings of the commands sent to produce it, see
+/// [`with_warnings`](crate::client::with_warnings).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultWithWarnings<T> {
+    pub result: T,
+    // In the order the commands completed
+    pub warnings: Vec<Warning>,
+}
+
+impl<T> ResultWithWarnings<T> {
+    pub fn has_warnings(&self) -> bool {
+        !self.warnings.is_empty()
+    }
+
+    pub fn into_result(self) -> T {
+        self.result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusHandling {
+    Warning,
+    Error,
+}
+
+/// How the API key is sent with each command.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum KeyTransport {
+    // `?key=` query parameter, the only form every API version accepts
+    #[default]
+    Query,
+    // Request header with this name, keeps the key out of access logs
+    Header(String),
+    // POST with the command and its parameters as a form body
+    PostForm,
+}
+
+// Accepts the value or a string holding it, e.g. `"42"` for a number
+pub(crate) fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(s) => serde_json::from_str(s.trim()).map_err(|_| {
+            Error::invalid_value(Unexpected::Str(&s), &"a value or a string holding one")
+        }),
+        value => T::deserialize(value).map_err(Error::custom),
+    }
+}
+
+// Accepts booleans, 0 and 1, and those as strings
+pub(crate) fn lenient_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    let flag = match &value {
+        Value::Bool(flag) => Some(*flag),
+        Value::Number(n) => n.as_u64().filter(|n| *n <= 1).map(|n| n == 1),
+        Value::String(s) => match s.trim() {
+            "true" | "1" => Some(true),
+            "false" | "0" => Some(false),
+            _ => None,
+        },
+        _ => None,
+    };
+    flag.ok_or_else(|| Error::invalid_type(Unexpected::Other(&value.to_string()), &"a boolean"))
 }
 
 fn empty_string_as_none<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
@@ -49,6 +241,7 @@ where
 {
     #[derive(Deserialize)]
     #[serde(untagged)]
+    #[allow(dead_code)]
     enum BlacklistField {
         False(bool),
         Blacklist(Vec<BlacklistInfo>),
@@ -97,6 +290,7 @@ where
 {
     #[derive(Deserialize)]
     #[serde(untagged)]
+    #[allow(dead_code)]
     enum ConnectInfoField {
         False(bool),
         ConnectInfo(ConnectInfo),
@@ -110,17 +304,84 @@ where
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+// Serializers mirroring the deserializers above, so models serialize back to the API's shape
+fn none_as_empty_string<S>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(value.as_deref().unwrap_or(""))
+}
+
+fn none_as_dash<S>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(value.as_deref().unwrap_or("-"))
+}
+
+fn none_as_false<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    match value {
+        Some(value) => value.serialize(serializer),
+        None => serializer.serialize_bool(false),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BlacklistType {
-    #[serde(rename = "Open Proxy")]
     OpenProxy,
-    #[serde(rename = "Web Abuse")]
     WebAbuse,
-    #[serde(rename = "Email Spam")]
     EmailSpam,
+    // A category this version does not know, as sent by the API
+    Other(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl BlacklistType {
+    /// The name the API uses for this category.
+    pub fn as_str(&self) -> &str {
+        match self {
+            BlacklistType::OpenProxy => "Open Proxy",
+            BlacklistType::WebAbuse => "Web Abuse",
+            BlacklistType::EmailSpam => "Email Spam",
+            BlacklistType::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for BlacklistType {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "Open Proxy" => BlacklistType::OpenProxy,
+            "Web Abuse" => BlacklistType::WebAbuse,
+            "Email Spam" => BlacklistType::EmailSpam,
+            _ => BlacklistType::Other(name),
+        }
+    }
+}
+
+impl fmt::Display for BlacklistType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for BlacklistType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlacklistType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(BlacklistType::from)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct BlacklistInfo {
     #[serde(rename = "ID")]
     pub id: String,
@@ -131,32 +392,91 @@ pub struct BlacklistInfo {
     #[serde(rename = "Desc")]
     pub desc: String,
     // Link to official blacklist documentation
-    #[serde(rename = "Link", deserialize_with = "empty_string_as_none")]
+    #[serde(
+        rename = "Link",
+        deserialize_with = "empty_string_as_none",
+        serialize_with = "none_as_empty_string"
+    )]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
     pub link: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum ConnectionType {
     Mobile,
     DSL,
     Hosting,
     Unknown,
-    #[serde(rename = "N/A")]
     NotAvailable,
+    // A type this version does not know, as sent by the API
+    Other(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl ConnectionType {
+    /// The name the API uses for this type.
+    pub fn as_str(&self) -> &str {
+        match self {
+            ConnectionType::Mobile => "Mobile",
+            ConnectionType::DSL => "DSL",
+            ConnectionType::Hosting => "Hosting",
+            ConnectionType::Unknown => "Unknown",
+            ConnectionType::NotAvailable => "N/A",
+            ConnectionType::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for ConnectionType {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "Mobile" => ConnectionType::Mobile,
+            "DSL" => ConnectionType::DSL,
+            "Hosting" => ConnectionType::Hosting,
+            "Unknown" => ConnectionType::Unknown,
+            "N/A" => ConnectionType::NotAvailable,
+            _ => ConnectionType::Other(name),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for ConnectionType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for ConnectionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(ConnectionType::from)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ProxyInfo {
-    #[serde(rename = "ProxyID")]
+    #[serde(rename = "ProxyID", deserialize_with = "lenient")]
     pub proxy_id: u32,
-    #[serde(rename = "CostBuy")]
-    pub rent_cost: u32,
-    #[serde(rename = "CostRent")]
-    pub private_rent_cost: u32,
-    #[serde(rename = "IsFresh")]
+    #[serde(rename = "CostBuy", deserialize_with = "lenient")]
+    pub rent_cost: Credits,
+    #[serde(rename = "CostRent", deserialize_with = "lenient")]
+    pub private_rent_cost: Credits,
+    #[serde(rename = "IsFresh", deserialize_with = "lenient_bool")]
     pub is_fresh: bool,
-    #[serde(rename = "IP", deserialize_with = "ip_field")]
+    #[serde(
+        rename = "IP",
+        deserialize_with = "ip_field",
+        serialize_with = "none_as_false"
+    )]
+    #[cfg_attr(
+        feature = "schemars",
+        schemars(schema_with = "crate::schema::or_false::<String>")
+    )]
     pub ip: Option<String>,
     #[serde(rename = "Hostname")]
     pub hostname: String,
@@ -170,25 +490,68 @@ pub struct ProxyInfo {
     pub region: String,
     #[serde(rename = "City")]
     pub city: String,
-    #[serde(rename = "ZipCode", deserialize_with = "zipcode_field")]
+    #[serde(
+        rename = "ZipCode",
+        deserialize_with = "zipcode_field",
+        serialize_with = "none_as_dash"
+    )]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
     pub zip_code: Option<String>,
     #[serde(rename = "Timezone")]
     pub timezone: String,
     #[serde(rename = "Connect")]
     pub connection_type: ConnectionType,
-    #[serde(rename = "Ping")]
+    #[serde(rename = "Ping", deserialize_with = "lenient")]
     pub ping: f64,
-    #[serde(rename = "Speed")]
+    #[serde(rename = "Speed", deserialize_with = "lenient")]
     pub speed: u32,
-    #[serde(rename = "UpTimeQuality")]
+    #[serde(rename = "UpTimeQuality", deserialize_with = "lenient")]
     pub uptime_quality: u32,
-    #[serde(rename = "Blacklist", deserialize_with = "blacklist_field")]
+    #[serde(
+        rename = "Blacklist",
+        deserialize_with = "blacklist_field",
+        serialize_with = "none_as_false"
+    )]
+    #[cfg_attr(
+        feature = "schemars",
+        schemars(schema_with = "crate::schema::or_false::<Vec<BlacklistInfo>>")
+    )]
     pub blacklist: Option<Vec<BlacklistInfo>>,
-    #[serde(rename = "Distance")]
+    #[serde(rename = "Distance", default, deserialize_with = "lenient")]
     pub distance: Option<f64>,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// How a proxy is acquired, the matching regular/fresh API command is picked from `ProxyInfo::is_fresh`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum PurchaseKind {
+    // Shared purchase, charged `CostBuy`
+    SharedBuy,
+    // Exclusive rental, charged `CostRent`, only available when it is non-zero
+    PrivateRent,
 }
 
 impl ProxyInfo {
+    pub fn is_blacklisted(&self) -> bool {
+        self.blacklist
+            .as_ref()
+            .is_some_and(|blacklist| !blacklist.is_empty())
+    }
+
+    // Cost of acquiring this proxy, None if the purchase kind is not offered for it
+    pub fn cost(&self, kind: PurchaseKind) -> Option<Credits> {
+        match kind {
+            PurchaseKind::SharedBuy => Some(self.rent_cost),
+            PurchaseKind::PrivateRent if !self.private_rent_cost.is_zero() => {
+                Some(self.private_rent_cost)
+            }
+            PurchaseKind::PrivateRent => None,
+        }
+    }
+
     pub fn get_formatted_speed(&self) -> String {
         const KILOBYTE: f64 = 1024.0;
         const MEGABYTE: f64 = KILOBYTE * 1024.0;
@@ -208,47 +571,94 @@ impl ProxyInfo {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ConnectInfo {
     #[serde(rename = "ConnectIP")]
     pub connect_ip: String,
-    #[serde(rename = "ConnectPort")]
+    #[serde(rename = "ConnectPort", deserialize_with = "lenient")]
     pub connect_port: u16,
     #[serde(rename = "ConnectSessionID")]
     pub connect_session_id: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl ConnectInfo {
+    // SOCKS5 username/password for this session, None when the session has no ID
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        if self.connect_session_id.is_empty() {
+            None
+        } else {
+            Some((&self.connect_session_id, &self.connect_session_id))
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ListInfo {
-    #[serde(rename = "HistoryID")]
+    #[serde(rename = "HistoryID", deserialize_with = "lenient")]
     pub history_id: u64,
-    #[serde(rename = "ConnectInfo", deserialize_with = "connect_info_field")]
+    #[serde(
+        rename = "ConnectInfo",
+        deserialize_with = "connect_info_field",
+        serialize_with = "none_as_false"
+    )]
+    #[cfg_attr(
+        feature = "schemars",
+        schemars(schema_with = "crate::schema::or_false::<ConnectInfo>")
+    )]
     pub connect_info: Option<ConnectInfo>,
     #[serde(rename = "ProxyInfo")]
     pub proxy_info: ProxyInfo,
-    #[serde(rename = "LastBought")]
+    #[serde(rename = "LastBought", deserialize_with = "lenient")]
     pub last_bought: u64,
-    #[serde(rename = "RemainingTime")]
+    #[serde(rename = "RemainingTime", deserialize_with = "lenient")]
     pub remaining_time: u64,
-    #[serde(rename = "IsOnline")]
+    #[serde(rename = "IsOnline", deserialize_with = "lenient_bool")]
     pub is_online: bool,
-    #[serde(rename = "IsFresh")]
+    #[serde(rename = "IsFresh", deserialize_with = "lenient_bool")]
     pub is_fresh: bool,
-    #[serde(rename = "IsRented")]
+    #[serde(rename = "IsRented", deserialize_with = "lenient_bool")]
     pub is_rented: bool,
-    #[serde(rename = "RefundAvailable")]
+    #[serde(rename = "RefundAvailable", deserialize_with = "lenient_bool")]
     pub refund_available: bool,
-    #[serde(rename = "RenewEnabled")]
+    #[serde(rename = "RenewEnabled", deserialize_with = "lenient_bool")]
     pub renew_enabled: bool,
-    #[serde(rename = "RenewCountRemaining")]
+    #[serde(rename = "RenewCountRemaining", deserialize_with = "lenient")]
     pub renew_count_remaining: u64,
-    #[serde(rename = "IPHasChanged")]
+    #[serde(rename = "IPHasChanged", deserialize_with = "lenient_bool")]
     pub ip_has_changed: bool,
-    #[serde(rename = "Note", deserialize_with = "empty_string_as_none")]
+    #[serde(
+        rename = "Note",
+        deserialize_with = "empty_string_as_none",
+        serialize_with = "none_as_empty_string"
+    )]
+    #[cfg_attr(feature = "schemars", schemars(with = "String"))]
     pub note: Option<String>,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl ListInfo {
+    /// How the proxy of this entry was bought.
+    pub fn purchase_kind(&self) -> PurchaseKind {
+        if self.is_rented {
+            PurchaseKind::PrivateRent
+        } else {
+            PurchaseKind::SharedBuy
+        }
+    }
+
+    /// Whether the connect details of this entry are out of date and should be
+    /// re-read with [`TrueSocksClient::refresh_connect_info`]: the proxy IP
+    /// changed, or the entry is active but came without connect info.
+    ///
+    /// [`TrueSocksClient::refresh_connect_info`]: crate::client::TrueSocksClient::refresh_connect_info
+    pub fn has_stale_session(&self) -> bool {
+        self.ip_has_changed || (self.remaining_time > 0 && self.connect_info.is_none())
+    }
+
     #[allow(dead_code)]
     fn formatted_remaining_time(&self) -> String {
         let hours = self.remaining_time / 3600;
@@ -265,7 +675,10 @@ impl ListInfo {
     }
 }
 
+// `proxy_list` holds each ProxyID once (see `dedupe_proxies`), sorted by ProxyID
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(from = "RawListOnlineResult")]
 pub struct ListOnlineResult {
     #[serde(rename = "LastUpdate")]
     pub last_update: u64,
@@ -273,65 +686,167 @@ pub struct ListOnlineResult {
     pub proxy_count: u32,
     #[serde(rename = "ProxyList")]
     pub proxy_list: Vec<ProxyInfo>,
+    // Number of repeated ProxyID records dropped while decoding
+    #[serde(skip)]
+    pub duplicates_removed: usize,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub(crate) struct RawListOnlineResult {
+    #[serde(rename = "LastUpdate", deserialize_with = "lenient")]
+    pub(crate) last_update: u64,
+    #[serde(rename = "ProxyCount", deserialize_with = "lenient")]
+    pub(crate) proxy_count: u32,
+    #[serde(rename = "ProxyList")]
+    pub(crate) proxy_list: Vec<ProxyInfo>,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub(crate) extra: Map<String, Value>,
+}
+
+impl From<RawListOnlineResult> for ListOnlineResult {
+    fn from(raw: RawListOnlineResult) -> Self {
+        let (mut proxy_list, duplicates_removed) = dedupe_proxies(raw.proxy_list);
+        proxy_list.sort_by_key(|proxy| proxy.proxy_id);
+        ListOnlineResult {
+            last_update: raw.last_update,
+            proxy_count: raw.proxy_count,
+            proxy_list,
+            duplicates_removed,
+            extra: raw.extra,
+        }
+    }
+}
+
+// List outputs are sorted by ID so repeated runs produce the same order
+fn history_list_by_id<'de, D>(deserializer: D) -> Result<Vec<ListInfo>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut entries = Vec::<ListInfo>::deserialize(deserializer)?;
+    entries.sort_by_key(|entry| entry.history_id);
+    Ok(entries)
+}
+
+// Higher uptime quality wins, then lower ping, then higher speed
+fn is_better_record(candidate: &ProxyInfo, current: &ProxyInfo) -> bool {
+    if candidate.uptime_quality != current.uptime_quality {
+        return candidate.uptime_quality > current.uptime_quality;
+    }
+    if candidate.ping != current.ping {
+        return candidate.ping < current.ping;
+    }
+    candidate.speed > current.speed
+}
+
+// Keeps one record per ProxyID at the position it first appeared, returns the number dropped
+pub(crate) fn dedupe_proxies(proxies: Vec<ProxyInfo>) -> (Vec<ProxyInfo>, usize) {
+    let mut positions: HashMap<u32, usize> = HashMap::with_capacity(proxies.len());
+    let mut unique: Vec<ProxyInfo> = Vec::with_capacity(proxies.len());
+    let mut duplicates = 0;
+
+    for proxy in proxies {
+        match positions.get(&proxy.proxy_id) {
+            Some(&index) => {
+                duplicates += 1;
+                if is_better_record(&proxy, &unique[index]) {
+                    unique[index] = proxy;
+                }
+            }
+            None => {
+                positions.insert(proxy.proxy_id, unique.len());
+                unique.push(proxy);
+            }
+        }
+    }
+    (unique, duplicates)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ListZipSearchResult {
-    #[serde(rename = "ServerTime")]
+    #[serde(rename = "ServerTime", deserialize_with = "lenient")]
     pub server_time: u64,
     #[serde(rename = "SearchCountryCode")]
     pub search_country_code: String,
     #[serde(rename = "SearchUnits")]
     pub search_units: String,
-    #[serde(rename = "SearchRange")]
+    #[serde(rename = "SearchRange", deserialize_with = "lenient")]
     pub search_range: u32,
     #[serde(rename = "SearchZipCode")]
     pub search_zip_code: String,
-    #[serde(rename = "ProxyCount")]
+    #[serde(rename = "ProxyCount", deserialize_with = "lenient")]
     pub proxy_count: u32,
+    // In the order the API returned them, nearest to the zip code first; not
+    // sorted by ProxyID like the other lists
     #[serde(rename = "ProxyList")]
     pub proxy_list: Vec<ProxyInfo>,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ListHistoryResult {
-    #[serde(rename = "ServerTime")]
+    #[serde(rename = "ServerTime", deserialize_with = "lenient")]
     pub server_time: u64,
-    #[serde(rename = "HistoryCount")]
+    #[serde(rename = "HistoryCount", deserialize_with = "lenient")]
     pub history_count: u32,
-    #[serde(rename = "HistoryEntriesPerPage")]
+    #[serde(rename = "HistoryEntriesPerPage", deserialize_with = "lenient")]
     pub history_entries_per_page: u32,
-    #[serde(rename = "HistoryCurrentPage")]
+    #[serde(rename = "HistoryCurrentPage", deserialize_with = "lenient")]
     pub history_current_page: u32,
-    #[serde(rename = "HistoryMaxPages")]
+    #[serde(rename = "HistoryMaxPages", deserialize_with = "lenient")]
     pub history_max_pages: u32,
-    #[serde(rename = "HistoryList")]
+    // Sorted by HistoryID
+    #[serde(rename = "HistoryList", deserialize_with = "history_list_by_id")]
     pub history_list: Vec<ListInfo>,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct PurchaseResult {
-    #[serde(rename = "ServerTime")]
+    #[serde(rename = "ServerTime", default, deserialize_with = "lenient")]
     pub server_time: Option<u64>,
-    #[serde(rename = "CreditsLeft")]
-    pub credits_left: Option<u32>,
+    #[serde(rename = "CreditsLeft", default, deserialize_with = "lenient")]
+    pub credits_left: Option<Credits>,
     #[serde(rename = "HistoryEntry")]
     pub history_entry: Option<ListInfo>,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ProxyCheckResult {
+    #[serde(deserialize_with = "lenient")]
     pub tests_passed: u32,
+    #[serde(deserialize_with = "lenient")]
     pub tests_total: u32,
     #[serde(rename = "tests_result")]
     pub test_result: String,
     #[serde(rename = "tests_result_str")]
     pub test_result_long: String,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct TestAndRefundResult {
+    #[serde(deserialize_with = "lenient")]
     pub tests_passed: u32,
+    #[serde(deserialize_with = "lenient")]
     pub tests_total: u32,
     #[serde(rename = "tests_result")]
     pub test_result: String,
@@ -340,45 +855,232 @@ pub struct TestAndRefundResult {
     pub refund_result: String,
     #[serde(rename = "refund_result_str")]
     pub refund_result_long: String,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct EnableProxyRenewalResult {
-    #[serde(rename = "HistoryID")]
+    #[serde(rename = "HistoryID", deserialize_with = "lenient")]
     pub history_id: u32,
-    #[serde(rename = "Enabled")]
+    #[serde(rename = "Enabled", deserialize_with = "lenient_bool")]
     pub enabled: bool,
-    #[serde(rename = "CreditsLeft")]
-    pub credits_left: u32,
-    #[serde(rename = "Cost")]
-    pub cost: u32,
+    #[serde(rename = "CreditsLeft", deserialize_with = "lenient")]
+    pub credits_left: Credits,
+    #[serde(rename = "Cost", deserialize_with = "lenient")]
+    pub cost: Credits,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct DisableProxyRenewalResult {
-    #[serde(rename = "HistoryID")]
+    #[serde(rename = "HistoryID", deserialize_with = "lenient")]
     pub history_id: u32,
-    #[serde(rename = "Enabled")]
+    #[serde(rename = "Enabled", deserialize_with = "lenient_bool")]
     pub enabled: bool,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct AccountStatusResult {
     // account creation unix timestamp in milliseconds
-    #[serde(rename = "Created")]
+    #[serde(rename = "Created", deserialize_with = "lenient")]
     pub created: u64,
     #[serde(rename = "UserID")]
     pub user_id: String,
     #[serde(rename = "Email")]
     pub email: String,
-    #[serde(rename = "Active")]
+    #[serde(rename = "Active", deserialize_with = "lenient_bool")]
     pub active: bool,
     #[serde(rename = "Plan")]
     pub plan: String,
     // credits expiration unix timestamp in milliseconds
-    #[serde(rename = "Expires")]
+    #[serde(rename = "Expires", deserialize_with = "lenient")]
     pub expires: u64,
     // Credits left in account
-    #[serde(rename = "Credits")]
-    pub credits: u32,
+    #[serde(rename = "Credits", deserialize_with = "lenient")]
+    pub credits: Credits,
+    // Fields this version does not know, kept so they can still be read
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl AccountStatusResult {
+    // Whether the account is active and has enough credits left for this purchase
+    pub fn can_afford(&self, proxy_info: &ProxyInfo, kind: PurchaseKind) -> bool {
+        match proxy_info.cost(kind) {
+            Some(cost) => self.active && cost <= self.credits,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+    use proptest::prelude::*;
+    use serde_json::json;
+
+    proptest! {
+        #[test]
+        fn test_proxy_info_round_trip(proxy in any::<ProxyInfo>()) {
+            let value = serde_json::to_value(&proxy).unwrap();
+            let decoded: ProxyInfo = serde_json::from_value(value).unwrap();
+            prop_assert_eq!(decoded, proxy);
+        }
+
+        #[test]
+        fn test_list_info_round_trip(entry in any::<ListInfo>()) {
+            let value = serde_json::to_value(&entry).unwrap();
+            let decoded: ListInfo = serde_json::from_value(value).unwrap();
+            prop_assert_eq!(decoded, entry);
+        }
+
+        #[test]
+        fn test_ip_field_accepts_any_string(ip in ".*") {
+            let decoded = ip_field(json!(ip)).unwrap();
+            prop_assert_eq!(decoded, Some(ip));
+        }
+
+        #[test]
+        fn test_zipcode_field_keeps_real_codes(zip in "[0-9A-Z -]{2,10}") {
+            let decoded = zipcode_field(json!(zip)).unwrap();
+            prop_assert_eq!(decoded, Some(zip));
+        }
+
+        #[test]
+        fn test_ip_field_rejects_numbers(n in any::<i64>()) {
+            prop_assert!(ip_field(json!(n)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_proxy_info_lenient_fields() {
+        let mut value = proxy_info_json(3);
+        value["ProxyID"] = json!("3");
+        value["CostBuy"] = json!(" 12 ");
+        value["Ping"] = json!("41.5");
+        value["IsFresh"] = json!(1);
+        value["Distance"] = json!("2.5");
+        value["Rating"] = json!({ "stars": 4 });
+        let proxy: ProxyInfo = serde_json::from_value(value).unwrap();
+        assert_eq!(proxy.proxy_id, 3);
+        assert_eq!(proxy.rent_cost, Credits(12));
+        assert_eq!(proxy.ping, 41.5);
+        assert!(proxy.is_fresh);
+        assert_eq!(proxy.distance, Some(2.5));
+        assert_eq!(proxy.extra["Rating"], json!({ "stars": 4 }));
+        assert_eq!(serde_json::to_value(&proxy).unwrap()["Rating"]["stars"], 4);
+
+        let mut value = proxy_info_json(3);
+        value["Speed"] = json!("fast");
+        assert!(serde_json::from_value::<ProxyInfo>(value).is_err());
+        let mut value = proxy_info_json(3);
+        value["IsFresh"] = json!("yes");
+        assert!(serde_json::from_value::<ProxyInfo>(value).is_err());
+        let mut value = proxy_info_json(3);
+        value.as_object_mut().unwrap().remove("Distance");
+        assert_eq!(
+            serde_json::from_value::<ProxyInfo>(value).unwrap().distance,
+            None
+        );
+    }
+
+    #[test]
+    fn test_unknown_connection_type() {
+        let mut value = proxy_info_json(3);
+        value["Connect"] = json!("Satellite");
+        let proxy: ProxyInfo = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            proxy.connection_type,
+            ConnectionType::Other("Satellite".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(&proxy).unwrap()["Connect"],
+            "Satellite"
+        );
+        assert_eq!(
+            serde_json::from_value::<ConnectionType>(json!("N/A")).unwrap(),
+            ConnectionType::NotAvailable
+        );
+    }
+
+    #[test]
+    fn test_unknown_blacklist_type() {
+        let mut value = proxy_info_json(3);
+        value["Blacklist"] = json!([
+            { "ID": "a", "Name": "A", "Type": "Web Abuse", "Desc": "", "Link": "" },
+            { "ID": "b", "Name": "B", "Type": "Botnet", "Desc": "", "Link": "" }
+        ]);
+        let proxy: ProxyInfo = serde_json::from_value(value).unwrap();
+        let types: Vec<&BlacklistType> = proxy
+            .blacklist
+            .iter()
+            .flatten()
+            .map(|entry| &entry.blacklist_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                &BlacklistType::WebAbuse,
+                &BlacklistType::Other("Botnet".to_string())
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(&proxy).unwrap()["Blacklist"][1]["Type"],
+            "Botnet"
+        );
+    }
+
+    #[test]
+    fn test_list_online_dedupes_proxy_ids() {
+        let mut better = proxy_info_json(2);
+        better["UpTimeQuality"] = json!(99);
+        let value = json!({
+            "LastUpdate": 1,
+            "ProxyCount": 4,
+            "ProxyList": [proxy_info_json(2), proxy_info_json(1), better, proxy_info_json(1)]
+        });
+        let result: ListOnlineResult = serde_json::from_value(value).unwrap();
+        let ids: Vec<u32> = result.proxy_list.iter().map(|p| p.proxy_id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(result.proxy_list[1].uptime_quality, 99);
+        assert_eq!(result.duplicates_removed, 2);
+    }
+
+    #[test]
+    fn test_zip_search_keeps_distance_order() {
+        let value = json!({
+            "ServerTime": 1,
+            "SearchCountryCode": "US",
+            "SearchUnits": "mi",
+            "SearchRange": 50,
+            "SearchZipCode": "10001",
+            "ProxyCount": 3,
+            "ProxyList": [proxy_info_json(9), proxy_info_json(2), proxy_info_json(5)]
+        });
+        let result: ListZipSearchResult = serde_json::from_value(value).unwrap();
+        let ids: Vec<u32> = result.proxy_list.iter().map(|p| p.proxy_id).collect();
+        assert_eq!(ids, vec![9, 2, 5]);
+    }
+
+    #[test]
+    fn test_sentinel_values() {
+        assert_eq!(ip_field(json!(false)).unwrap(), None);
+        assert!(ip_field(json!(true)).is_err());
+        assert_eq!(zipcode_field(json!("-")).unwrap(), None);
+        assert_eq!(blacklist_field(json!(false)).unwrap(), None);
+        assert_eq!(blacklist_field(json!([])).unwrap(), Some(vec![]));
+        assert_eq!(connect_info_field(json!(false)).unwrap(), None);
+        assert_eq!(empty_string_as_none(json!("")).unwrap(), None);
+    }
 }
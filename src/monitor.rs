@@ -0,0 +1,217 @@
+use crate::credits::Credits;
+use crate::models::ApiError;
+use std::time::Duration;
+// Used by the background task only, which is not built for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::client::TrueSocksClient, crate::models::AccountStatusResult, tokio::sync::broadcast,
+    tokio::task::JoinHandle,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct CreditMonitorOptions {
+    pub interval: Duration,
+    // Alert once the balance drops below this
+    pub min_credits: Credits,
+    // Alert once the credits expire within this long
+    pub expiry_warning: Duration,
+}
+
+impl Default for CreditMonitorOptions {
+    fn default() -> Self {
+        CreditMonitorOptions {
+            interval: Duration::from_secs(300),
+            min_credits: Credits(100),
+            expiry_warning: Duration::from_secs(3 * 24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CreditAlert {
+    LowBalance {
+        credits: Credits,
+        threshold: Credits,
+    },
+    // `remaining` is zero when the credits have already expired
+    ExpiringSoon {
+        expires: u64,
+        remaining: Duration,
+    },
+    PollFailed(ApiError),
+}
+
+// Alerts fire when a condition starts to hold and again only after it cleared
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+struct AlertState {
+    low_balance: bool,
+    expiring: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AlertState {
+    fn evaluate(
+        &mut self,
+        options: &CreditMonitorOptions,
+        account: &AccountStatusResult,
+        now_ms: u64,
+    ) -> Vec<CreditAlert> {
+        let mut alerts = Vec::new();
+        let low_balance = account.credits < options.min_credits;
+        if low_balance && !self.low_balance {
+            alerts.push(CreditAlert::LowBalance {
+                credits: account.credits,
+                threshold: options.min_credits,
+            });
+        }
+        self.low_balance = low_balance;
+
+        // An expiry of 0 means the account reports none
+        let remaining = Duration::from_millis(account.expires.saturating_sub(now_ms));
+        let expiring = account.expires != 0 && remaining <= options.expiry_warning;
+        if expiring && !self.expiring {
+            alerts.push(CreditAlert::ExpiringSoon {
+                expires: account.expires,
+                remaining,
+            });
+        }
+        self.expiring = expiring;
+        alerts
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now_ms() -> u64 {
+    crate::runtime::since_epoch().as_millis() as u64
+}
+
+/// Background task polling `AccountStatus` and alerting on a low balance or
+/// credits about to expire, so automated purchasers can stop before failing.
+/// The task stops when the monitor is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct CreditMonitor {
+    events: broadcast::Sender<CreditAlert>,
+    handle: JoinHandle<()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CreditMonitor {
+    pub fn spawn(client: TrueSocksClient, options: CreditMonitorOptions) -> Self {
+        Self::spawn_with_callback(client, options, |_: &CreditAlert| {})
+    }
+
+    /// Like [`CreditMonitor::spawn`], also calling `on_alert` with every alert
+    /// before it is sent to subscribers.
+    pub fn spawn_with_callback<F>(
+        client: TrueSocksClient,
+        options: CreditMonitorOptions,
+        on_alert: F,
+    ) -> Self
+    where
+        F: Fn(&CreditAlert) + Send + Sync + 'static,
+    {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let sender = events.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(options.interval);
+            let mut state = AlertState::default();
+            loop {
+                ticker.tick().await;
+                let alerts = match client.get_account_status().await {
+                    Ok(account) => state.evaluate(&options, &account, now_ms()),
+                    Err(err) => vec![CreditAlert::PollFailed(err)],
+                };
+                for alert in alerts {
+                    on_alert(&alert);
+                    let _ = sender.send(alert);
+                }
+            }
+        });
+        CreditMonitor { events, handle }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<CreditAlert> {
+        self.events.subscribe()
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for CreditMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{account_status, ok_response, serve};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_alerts_fire_once_per_crossing() {
+        let options = CreditMonitorOptions {
+            min_credits: Credits(50),
+            expiry_warning: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let mut state = AlertState::default();
+        let mut account = account_status(10);
+        account.expires = 100_000;
+
+        let alerts = state.evaluate(&options, &account, 70_000);
+        assert!(matches!(
+            alerts[..],
+            [
+                CreditAlert::LowBalance {
+                    credits: Credits(10),
+                    ..
+                },
+                CreditAlert::ExpiringSoon { remaining, .. }
+            ] if remaining == Duration::from_secs(30)
+        ));
+        assert!(state.evaluate(&options, &account, 80_000).is_empty());
+
+        account.credits = Credits(60);
+        assert!(state.evaluate(&options, &account, 80_000).is_empty());
+        account.credits = Credits(40);
+        assert_eq!(state.evaluate(&options, &account, 80_000).len(), 1);
+
+        account.expires = 0;
+        account.credits = Credits(60);
+        assert!(state.evaluate(&options, &account, 80_000).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_credit_monitor_callback() {
+        let account = serde_json::to_value(account_status(5)).unwrap();
+        let (url, _) = serve(vec![ok_response(account)]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let monitor = CreditMonitor::spawn_with_callback(
+            client,
+            CreditMonitorOptions::default(),
+            move |alert: &CreditAlert| recorded.lock().unwrap().push(alert.clone()),
+        );
+        let mut alerts = monitor.subscribe();
+        let alert = alerts.recv().await.unwrap();
+        assert!(matches!(
+            alert,
+            CreditAlert::LowBalance {
+                credits: Credits(5),
+                ..
+            }
+        ));
+        assert_eq!(seen.lock().unwrap().len(), 1);
+        monitor.stop();
+    }
+}
@@ -0,0 +1,175 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::models::{ApiError, ListInfo};
+use crate::unix_now;
+use std::time::Duration;
+// Used by the background task only, which is not built for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+use {tokio::sync::broadcast, tokio::task::JoinHandle};
+
+#[cfg(not(target_arch = "wasm32"))]
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteAction {
+    Clear,
+    // Keep the note behind this prefix so it is recognizable as archived
+    Prefix(String),
+}
+
+/// Which notes of expired history entries are cleaned up.
+#[derive(Debug, Clone)]
+pub struct NoteRetention {
+    // Minimum time since the entry was bought, the API does not report when it expired
+    pub older_than: Duration,
+    pub action: NoteAction,
+}
+
+impl Default for NoteRetention {
+    fn default() -> Self {
+        NoteRetention {
+            older_than: Duration::from_secs(30 * 24 * 60 * 60),
+            action: NoteAction::Clear,
+        }
+    }
+}
+
+impl NoteRetention {
+    // The note to set on `entry`, outer None when it is kept and inner None to clear it
+    fn apply(&self, entry: &ListInfo, now: u64) -> Option<Option<String>> {
+        let note = entry.note.as_deref().filter(|note| !note.is_empty())?;
+        if entry.remaining_time > 0
+            || now.saturating_sub(entry.last_bought) < self.older_than.as_secs()
+        {
+            return None;
+        }
+        match &self.action {
+            NoteAction::Clear => Some(None),
+            NoteAction::Prefix(prefix) if note.starts_with(prefix.as_str()) => None,
+            NoteAction::Prefix(prefix) => Some(Some(format!("{}{}", prefix, note))),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum NoteCleanupEvent {
+    // The previous note is included so it can be archived elsewhere
+    Cleared { history_id: u64, note: String },
+    Archived { history_id: u64, note: String },
+    Failed { history_id: u64, error: ApiError },
+    PollFailed(ApiError),
+}
+
+impl TrueSocksClient {
+    /// Clean up the notes of expired history entries once, by HistoryID.
+    pub async fn clean_up_notes(&self, retention: &NoteRetention) -> Vec<NoteCleanupEvent> {
+        let entries = match self.list_all_history(&HistoryQuery::new()).await {
+            Ok(entries) => entries,
+            Err(err) => return vec![NoteCleanupEvent::PollFailed(err)],
+        };
+        let now = unix_now();
+        let mut events = Vec::new();
+        for entry in entries {
+            let Some(new_note) = retention.apply(&entry, now) else {
+                continue;
+            };
+            let history_id = entry.history_id;
+            let note = entry.note.unwrap_or_default();
+            let event = match self
+                .history_entry_change_note(history_id, new_note.as_deref())
+                .await
+            {
+                Ok(()) if new_note.is_none() => NoteCleanupEvent::Cleared { history_id, note },
+                Ok(()) => NoteCleanupEvent::Archived { history_id, note },
+                Err(error) => NoteCleanupEvent::Failed { history_id, error },
+            };
+            events.push(event);
+        }
+        events
+    }
+}
+
+/// Background task cleaning up notes of expired history entries, once a day
+/// by default. The task stops when dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct NoteCleanup {
+    events: broadcast::Sender<NoteCleanupEvent>,
+    handle: JoinHandle<()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl NoteCleanup {
+    pub fn spawn(client: TrueSocksClient, retention: NoteRetention) -> Self {
+        Self::spawn_with_interval(client, retention, DEFAULT_INTERVAL)
+    }
+
+    pub fn spawn_with_interval(
+        client: TrueSocksClient,
+        retention: NoteRetention,
+        interval: Duration,
+    ) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let sender = events.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                for event in client.clean_up_notes(&retention).await {
+                    let _ = sender.send(event);
+                }
+            }
+        });
+        NoteCleanup { events, handle }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NoteCleanupEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for NoteCleanup {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+
+    #[test]
+    fn test_retention() {
+        let day = 24 * 60 * 60;
+        let mut entry = list_info(1);
+        entry.note = Some("scraper".to_string());
+        entry.remaining_time = 0;
+        entry.last_bought = 0;
+        let retention = NoteRetention {
+            older_than: Duration::from_secs(7 * day),
+            action: NoteAction::Prefix("archived:".to_string()),
+        };
+        assert_eq!(retention.apply(&entry, 6 * day), None);
+        assert_eq!(
+            retention.apply(&entry, 7 * day),
+            Some(Some("archived:scraper".to_string()))
+        );
+
+        entry.note = Some("archived:scraper".to_string());
+        assert_eq!(retention.apply(&entry, 7 * day), None);
+        entry.remaining_time = 60;
+        assert_eq!(NoteRetention::default().apply(&entry, 365 * day), None);
+        entry.remaining_time = 0;
+        assert_eq!(
+            NoteRetention::default().apply(&entry, 365 * day),
+            Some(None)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use crate::blacklist::BlacklistEvent;
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ListInfo, ProxyInfo};
+use crate::monitor::CreditAlert;
+use crate::watch::ProxyAvailabilityEvent;
+use futures::{Stream, StreamExt};
+use log::Level;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, Write};
+
+/// A message for a person, built from watch, monitor, blacklist or expiry
+/// events.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub title: String,
+    pub message: String,
+    // Structured event data for notifiers that can show it
+    pub details: Value,
+}
+
+impl Notification {
+    pub fn new(title: impl Into<String>, message: impl Into<String>) -> Self {
+        Notification {
+            title: title.into(),
+            message: message.into(),
+            details: Value::Null,
+        }
+    }
+
+    pub fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    /// "Proxy expires soon" notification for an active history entry.
+    pub fn expiring(entry: &ListInfo) -> Self {
+        Notification::new(
+            "Proxy expiring",
+            format!(
+                "proxy {} expires in {} minutes",
+                entry.history_id,
+                entry.remaining_time.div_ceil(60)
+            ),
+        )
+        .details(json!({
+            "history_id": entry.history_id,
+            "proxy_id": entry.proxy_info.proxy_id,
+            "remaining_seconds": entry.remaining_time,
+        }))
+    }
+}
+
+impl From<&ProxyAvailabilityEvent> for Notification {
+    fn from(event: &ProxyAvailabilityEvent) -> Self {
+        let describe = |verb: &str, proxy: &ProxyInfo| {
+            Notification::new(
+                format!("Proxy {}", verb),
+                format!(
+                    "{} {} proxy {} {} ({}, {})",
+                    proxy.country_code,
+                    proxy.connection_type,
+                    proxy.proxy_id,
+                    verb,
+                    proxy.city,
+                    proxy.isp
+                ),
+            )
+            .details(serde_json::to_value(proxy).unwrap_or_default())
+        };
+        match event {
+            ProxyAvailabilityEvent::Appeared(proxy) => describe("appeared", proxy),
+            ProxyAvailabilityEvent::Disappeared(proxy) => describe("disappeared", proxy),
+            ProxyAvailabilityEvent::Changed { after, .. } => describe("changed", after),
+            ProxyAvailabilityEvent::PollFailed(err) => Notification::new(
+                "Proxy watch failed",
+                format!("could not list online proxies: API error {}", err.code()),
+            ),
+        }
+    }
+}
+
+impl From<&CreditAlert> for Notification {
+    fn from(alert: &CreditAlert) -> Self {
+        match alert {
+            CreditAlert::LowBalance { credits, threshold } => Notification::new(
+                "Low balance",
+                format!("{} left, below {}", credits, threshold),
+            )
+            .details(json!({ "credits": credits.0, "threshold": threshold.0 })),
+            CreditAlert::ExpiringSoon { expires, remaining } => Notification::new(
+                "Credits expiring",
+                format!("credits expire in {} hours", remaining.as_secs() / 3600),
+            )
+            .details(json!({ "expires": expires })),
+            CreditAlert::PollFailed(err) => Notification::new(
+                "Credit monitor failed",
+                format!(
+                    "could not fetch the account status: API error {}",
+                    err.code()
+                ),
+            ),
+        }
+    }
+}
+
+impl From<&BlacklistEvent> for Notification {
+    fn from(event: &BlacklistEvent) -> Self {
+        match event {
+            BlacklistEvent::Blacklisted { entry, listings } => {
+                let names: Vec<&str> = listings
+                    .iter()
+                    .map(|listing| listing.name.as_str())
+                    .collect();
+                Notification::new(
+                    "Proxy blacklisted",
+                    format!(
+                        "proxy {} is listed by {}",
+                        entry.proxy_info.proxy_id,
+                        names.join(", ")
+                    ),
+                )
+                .details(json!({ "history_id": entry.history_id, "blacklists": names }))
+            }
+            BlacklistEvent::Cleared { entry } => Notification::new(
+                "Proxy delisted",
+                format!(
+                    "proxy {} is no longer blacklisted",
+                    entry.proxy_info.proxy_id
+                ),
+            )
+            .details(json!({ "history_id": entry.history_id })),
+            BlacklistEvent::PollFailed(err) => Notification::new(
+                "Blacklist check failed",
+                format!("could not recheck blacklists: API error {}", err.code()),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    Http(reqwest::Error),
+    // The endpoint answered with a non-success HTTP status
+    Status(u16),
+    Io(io::Error),
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotifyError::Http(err) => write!(f, "webhook request failed: {}", err),
+            NotifyError::Status(status) => write!(f, "webhook answered with HTTP {}", status),
+            NotifyError::Io(err) => write!(f, "could not write notification: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+/// Destination of [`Notification`]s.
+#[async_trait::async_trait]
+pub trait Notifier: Send + Sync + 'static {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+/// Prints one line per notification to stdout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdoutNotifier;
+
+#[async_trait::async_trait]
+impl Notifier for StdoutNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        writeln!(
+            io::stdout().lock(),
+            "{}: {}",
+            notification.title,
+            notification.message
+        )
+        .map_err(NotifyError::Io)
+    }
+}
+
+/// POSTs every notification as JSON to a URL. Besides `title`, `message` and
+/// `details` the body carries the text as `text` and `content`, the fields
+/// Slack and Discord incoming webhooks read.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct WebhookNotifier {
+    http: reqwest::Client,
+    url: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WebhookNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        WebhookNotifier {
+            http: reqwest::Client::new(),
+            url: url.into(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait::async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let text = format!("{}: {}", notification.title, notification.message);
+        let body = json!({
+            "text": text,
+            "content": text,
+            "title": notification.title,
+            "message": notification.message,
+            "details": notification.details,
+        });
+        let response = self
+            .http
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(NotifyError::Http)?;
+        if !response.status().is_success() {
+            return Err(NotifyError::Status(response.status().as_u16()));
+        }
+        Ok(())
+    }
+}
+
+/// Send a notification for every event of `events` until the stream ends.
+/// Failed deliveries are logged and do not stop forwarding.
+pub async fn forward<S, T>(events: S, notifier: &dyn Notifier)
+where
+    S: Stream<Item = T>,
+    for<'a> &'a T: Into<Notification>,
+{
+    futures::pin_mut!(events);
+    while let Some(event) = events.next().await {
+        let notification: Notification = (&event).into();
+        if let Err(err) = notifier.notify(&notification).await {
+            sublog!(
+                Subsystem::Notify,
+                Level::Warn,
+                "could not deliver {:?}: {}",
+                notification.title,
+                err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::credits::Credits;
+    use crate::fixtures::{proxy_info_json, serve};
+
+    #[tokio::test]
+    async fn test_webhook_forwarding() {
+        let (url, requests) = serve(vec![json!({}), json!({})]);
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(7)).unwrap();
+        let events = futures::stream::iter(vec![
+            ProxyAvailabilityEvent::Appeared(proxy),
+            ProxyAvailabilityEvent::PollFailed(crate::models::ApiError::from(503_u16)),
+        ]);
+        forward(events, &WebhookNotifier::new(url)).await;
+
+        let requests = requests.join().unwrap();
+        assert!(requests[0].starts_with("POST / HTTP/1.1"));
+        let body: Value =
+            serde_json::from_str(requests[0].split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["title"], "Proxy appeared");
+        assert_eq!(body["details"]["ProxyID"], 7);
+        assert_eq!(body["text"], body["content"]);
+        assert!(requests[1].contains("Proxy watch failed"));
+
+        let alert = CreditAlert::LowBalance {
+            credits: Credits(5),
+            threshold: Credits(50),
+        };
+        assert_eq!(
+            Notification::from(&alert).message,
+            "5 credits left, below 50 credits"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::models::{AccountStatusResult, ApiError, ListInfo};
+use std::fmt::Write;
+
+/// Content type to serve [`render_openmetrics`] output with.
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn labels(entry: &ListInfo) -> String {
+    format!(
+        "history_id=\"{}\",proxy_id=\"{}\",country_code=\"{}\"",
+        entry.history_id,
+        entry.proxy_info.proxy_id,
+        escape_label(&entry.proxy_info.country_code)
+    )
+}
+
+fn family(out: &mut String, name: &str, unit: Option<&str>, help: &str) {
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    if let Some(unit) = unit {
+        writeln!(out, "# UNIT {} {}", name, unit).unwrap();
+    }
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+}
+
+fn per_entry(
+    out: &mut String,
+    entries: &[&ListInfo],
+    name: &str,
+    unit: Option<&str>,
+    help: &str,
+    value: impl Fn(&ListInfo) -> u64,
+) {
+    family(out, name, unit, help);
+    for entry in entries {
+        writeln!(out, "{}{{{}}} {}", name, labels(entry), value(entry)).unwrap();
+    }
+}
+
+/// OpenMetrics text exposition of rentals, one sample per history entry and
+/// metric, ordered by HistoryID. Account credits are included when given.
+pub fn render_openmetrics(entries: &[ListInfo], account: Option<&AccountStatusResult>) -> String {
+    let mut entries: Vec<&ListInfo> = entries.iter().collect();
+    entries.sort_by_key(|entry| entry.history_id);
+    let mut out = String::new();
+
+    family(&mut out, "truesocks_rentals", None, "Number of rentals.");
+    writeln!(out, "truesocks_rentals {}", entries.len()).unwrap();
+    per_entry(
+        &mut out,
+        &entries,
+        "truesocks_rental_remaining_seconds",
+        Some("seconds"),
+        "Time until the rental expires.",
+        |entry| entry.remaining_time,
+    );
+    per_entry(
+        &mut out,
+        &entries,
+        "truesocks_rental_online",
+        None,
+        "1 when the proxy is online.",
+        |entry| entry.is_online as u64,
+    );
+    per_entry(
+        &mut out,
+        &entries,
+        "truesocks_rental_renew_enabled",
+        None,
+        "1 when automatic renewal is enabled.",
+        |entry| entry.renew_enabled as u64,
+    );
+    per_entry(
+        &mut out,
+        &entries,
+        "truesocks_rental_renewals_remaining",
+        None,
+        "Automatic renewals left.",
+        |entry| entry.renew_count_remaining,
+    );
+    if let Some(account) = account {
+        family(
+            &mut out,
+            "truesocks_account_credits",
+            None,
+            "Credits left in the account.",
+        );
+        writeln!(out, "truesocks_account_credits {}", account.credits.0).unwrap();
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+impl TrueSocksClient {
+    /// OpenMetrics exposition of the active rentals and the account credits,
+    /// see [`render_openmetrics`].
+    pub async fn openmetrics(&self) -> Result<String, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::active()).await?;
+        let account = self.get_account_status().await?;
+        Ok(render_openmetrics(&entries, Some(&account)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{account_status, list_info};
+
+    #[test]
+    fn test_render_openmetrics() {
+        let mut first = list_info(1);
+        first.proxy_info.country_code = "U\"S".to_string();
+        first.renew_enabled = true;
+        let exposition = render_openmetrics(&[list_info(2), first], Some(&account_status(42)));
+
+        let lines: Vec<&str> = exposition.lines().collect();
+        assert_eq!(lines[0], "# TYPE truesocks_rentals gauge");
+        assert_eq!(lines[2], "truesocks_rentals 2");
+        assert!(exposition.contains(
+            "truesocks_rental_renew_enabled{history_id=\"1\",proxy_id=\"1\",country_code=\"U\\\"S\"} 1\n"
+        ));
+        let first_sample = lines
+            .iter()
+            .position(|line| line.starts_with("truesocks_rental_online{history_id=\"1\""));
+        let second_sample = lines
+            .iter()
+            .position(|line| line.starts_with("truesocks_rental_online{history_id=\"2\""));
+        assert!(first_sample < second_sample);
+        assert!(exposition.ends_with("truesocks_account_credits 42\n# EOF\n"));
+    }
+}
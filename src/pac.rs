@@ -0,0 +1,174 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::models::{ApiError, ListInfo};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// Where matching requests are sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PacTarget {
+    Direct,
+    // A purchased proxy by HistoryID
+    History(u64),
+    // Every active proxy in the country, the browser fails over in ProxyID order
+    Country(String),
+    Address { host: String, port: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacRule {
+    // `shExpMatch` patterns tested against the host, e.g. `*.example.co.uk`
+    pub hosts: Vec<String>,
+    pub target: PacTarget,
+}
+
+/// Rules rendered into a PAC file, the first rule matching a host wins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PacRules {
+    pub rules: Vec<PacRule>,
+    pub default: PacTarget,
+}
+
+impl Default for PacRules {
+    fn default() -> Self {
+        PacRules {
+            rules: Vec::new(),
+            default: PacTarget::Direct,
+        }
+    }
+}
+
+impl PacRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<I, S>(mut self, hosts: I, target: PacTarget) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rules.push(PacRule {
+            hosts: hosts.into_iter().map(Into::into).collect(),
+            target,
+        });
+        self
+    }
+
+    pub fn default_target(mut self, target: PacTarget) -> Self {
+        self.default = target;
+        self
+    }
+}
+
+fn socks_directive(host: &str, port: u16) -> String {
+    format!("SOCKS5 {0}:{1}; SOCKS {0}:{1}", host, port)
+}
+
+// PAC return value for a target, None when no active proxy backs it
+fn directive(target: &PacTarget, entries: &[ListInfo]) -> Option<String> {
+    let addresses: Vec<String> = match target {
+        PacTarget::Direct => return Some("DIRECT".to_string()),
+        PacTarget::Address { host, port } => vec![socks_directive(host, *port)],
+        PacTarget::History(history_id) => entries
+            .iter()
+            .filter(|entry| entry.history_id == *history_id)
+            .filter_map(|entry| entry.connect_info.as_ref())
+            .map(|info| socks_directive(&info.connect_ip, info.connect_port))
+            .collect(),
+        PacTarget::Country(country_code) => {
+            let mut matching: Vec<&ListInfo> = entries
+                .iter()
+                .filter(|entry| {
+                    entry
+                        .proxy_info
+                        .country_code
+                        .eq_ignore_ascii_case(country_code)
+                })
+                .collect();
+            matching.sort_by_key(|entry| (entry.proxy_info.proxy_id, entry.history_id));
+            matching
+                .into_iter()
+                .filter_map(|entry| entry.connect_info.as_ref())
+                .map(|info| socks_directive(&info.connect_ip, info.connect_port))
+                .collect()
+        }
+    };
+    if addresses.is_empty() {
+        None
+    } else {
+        Some(addresses.join("; "))
+    }
+}
+
+fn js_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// Render `rules` against the given history entries. Rules whose target has
+/// no usable proxy are left out, as is the default, which then becomes DIRECT.
+///
+/// Browsers do not send SOCKS credentials from PAC files, so the proxies
+/// need to accept the client without authentication (e.g. IP authorization).
+pub fn render_pac(rules: &PacRules, entries: &[ListInfo]) -> String {
+    let mut pac = String::from("function FindProxyForURL(url, host) {\n");
+    for rule in &rules.rules {
+        let Some(directive) = directive(&rule.target, entries) else {
+            continue;
+        };
+        if rule.hosts.is_empty() {
+            continue;
+        }
+        let conditions: Vec<String> = rule
+            .hosts
+            .iter()
+            .map(|pattern| format!("shExpMatch(host, {})", js_string(pattern)))
+            .collect();
+        let _ = writeln!(
+            pac,
+            "    if ({}) return {};",
+            conditions.join(" || "),
+            js_string(&directive)
+        );
+    }
+    let default = directive(&rules.default, entries).unwrap_or_else(|| "DIRECT".to_string());
+    let _ = writeln!(pac, "    return {};", js_string(&default));
+    pac.push_str("}\n");
+    pac
+}
+
+impl TrueSocksClient {
+    /// PAC file for `rules` over the active purchases.
+    pub async fn pac_file(&self, rules: &PacRules) -> Result<String, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::active()).await?;
+        Ok(render_pac(rules, &entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+
+    #[test]
+    fn test_render_pac() {
+        let mut uk = list_info(1);
+        uk.proxy_info.country_code = "GB".to_string();
+        let entries = vec![uk, list_info(2)];
+        let rules = PacRules::new()
+            .route(
+                ["*.example.co.uk", "example.co.uk"],
+                PacTarget::Country("gb".into()),
+            )
+            .route(["*.missing.test"], PacTarget::History(99))
+            .default_target(PacTarget::History(2));
+        let pac = render_pac(&rules, &entries);
+        assert_eq!(
+            pac,
+            "function FindProxyForURL(url, host) {\n    \
+             if (shExpMatch(host, \"*.example.co.uk\") || shExpMatch(host, \"example.co.uk\")) \
+             return \"SOCKS5 198.51.100.1:20001; SOCKS 198.51.100.1:20001\";\n    \
+             return \"SOCKS5 198.51.100.1:20002; SOCKS 198.51.100.1:20002\";\n}\n"
+        );
+    }
+}
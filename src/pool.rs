@@ -0,0 +1,1056 @@
+use crate::client::{renewal_history_id, TrueSocksClient};
+use crate::diversity::DiversityConstraints;
+use crate::filter::ProxyFilter;
+use crate::history::HistoryQuery;
+use crate::journal::{JournalEvent, JournalRecord, JournalSink};
+use crate::lease::LeaseOptions;
+use crate::logging::{sublog, Subsystem};
+use crate::models::{ApiError, ConnectInfo, ListInfo};
+use crate::pressure::{PressureReport, PressureTracker};
+use crate::runtime::{timeout, Instant};
+use crate::state::{read_state, write_state, SavedMember, SavedPool};
+use crate::stats::{Outcome, ProxyStats, UsageCounters};
+use crate::status_codes::CANCELLED;
+use crate::sticky::HashRing;
+use crate::unix_now;
+use log::Level;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolError {
+    // The pool is draining and no longer hands out proxies
+    Draining,
+    // No online member matching the request is available
+    Empty,
+    // Every available matching member would break the diversity constraints
+    Diversity,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Draining => write!(f, "pool is draining"),
+            PoolError::Empty => write!(f, "no proxy available in pool"),
+            PoolError::Diversity => {
+                write!(f, "no proxy in pool satisfies the diversity constraints")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+// Weight of the newest sample in the latency average
+const LATENCY_ALPHA: f64 = 0.3;
+
+struct PoolMember {
+    entry: ListInfo,
+    checked_out: usize,
+    // Exponentially weighted moving average of observed connect latencies
+    latency: Option<Duration>,
+    // Set when the keep-alive task finds the tunnel dead or the member is
+    // quarantined after failed leases, cleared once it answers again or the
+    // connect info changes
+    unhealthy: bool,
+    // Returned from a lease and resting until then
+    cooldown_until: Option<Instant>,
+    // Leases in a row reported as failed
+    failed_leases: u32,
+    // When the entry's remaining time runs out
+    expires_at: Instant,
+    usage: UsageCounters,
+}
+
+#[derive(Default)]
+struct PoolState {
+    members: BTreeMap<u64, PoolMember>,
+    draining: bool,
+    pressure: PressureTracker,
+    diversity: DiversityConstraints,
+    lease: LeaseOptions,
+    // Session key to the member it is pinned to, see `ProxyPool::assign`
+    assignments: HashMap<String, u64>,
+    // Leases by id, with their member and when they run out
+    leases: HashMap<u64, (u64, Instant)>,
+    next_lease: u64,
+}
+
+impl PoolMember {
+    fn expires_at(entry: &ListInfo) -> Instant {
+        Instant::now() + Duration::from_secs(entry.remaining_time)
+    }
+
+    fn check_out(&mut self) {
+        self.checked_out += 1;
+        self.usage.checkouts += 1;
+        self.usage.last_used = Some(unix_now());
+    }
+
+    // Online, healthy, not expired and with connect info to hand out
+    fn is_available(&self, now: Instant) -> bool {
+        self.entry.is_online
+            && self.entry.connect_info.is_some()
+            && !self.unhealthy
+            && self.expires_at > now
+    }
+
+    // Observed latency, or the listed ping until something was observed
+    fn estimated_latency(&self) -> Duration {
+        self.latency.unwrap_or_else(|| {
+            let ping = self.entry.proxy_info.ping;
+            if ping.is_finite() && ping > 0.0 {
+                Duration::from_secs_f64(ping / 1000.0)
+            } else {
+                Duration::MAX
+            }
+        })
+    }
+}
+
+impl PoolState {
+    fn outstanding(&self) -> usize {
+        self.members.values().map(|member| member.checked_out).sum()
+    }
+}
+
+struct PoolShared {
+    state: Mutex<PoolState>,
+    returned: Notify,
+    journal: Option<Box<dyn JournalSink>>,
+}
+
+impl PoolShared {
+    // Called with the state locked so records keep the order of the changes
+    fn record(&self, event: JournalEvent) {
+        sublog!(Subsystem::Pool, Level::Debug, "{:?}", event);
+        if let Some(journal) = &self.journal {
+            journal.append(&JournalRecord {
+                timestamp: unix_now(),
+                event,
+            });
+        }
+    }
+}
+
+/// Set of purchased proxies handed out to workers. Cloning is cheap, clones
+/// share the same members.
+#[derive(Clone)]
+pub struct ProxyPool {
+    client: TrueSocksClient,
+    shared: Arc<PoolShared>,
+}
+
+/// A proxy taken from the pool, it is returned when dropped.
+pub struct PoolCheckout {
+    shared: Arc<PoolShared>,
+    entry: ListInfo,
+    connect_info: ConnectInfo,
+    // Set when held by a lease, which the pool ends on drop or expiry
+    lease: Option<u64>,
+}
+
+impl PoolCheckout {
+    pub fn history_id(&self) -> u64 {
+        self.entry.history_id
+    }
+
+    pub fn entry(&self) -> &ListInfo {
+        &self.entry
+    }
+
+    pub fn connect_info(&self) -> &ConnectInfo {
+        &self.connect_info
+    }
+
+    /// Feed an observed connect latency into this member's estimate.
+    pub fn report_latency(&self, latency: Duration) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(member) = state.members.get_mut(&self.entry.history_id) {
+            record_latency(member, latency);
+        }
+    }
+
+    /// Count how this use went, see [`ProxyPool::stats`].
+    pub fn report_outcome(&self, outcome: Outcome) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(member) = state.members.get_mut(&self.entry.history_id) {
+            member.usage.record(outcome);
+        }
+    }
+
+    /// False once the keep-alive task has found this proxy dead, holders should
+    /// check out another one.
+    pub fn is_healthy(&self) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .members
+            .get(&self.entry.history_id)
+            .is_some_and(|member| !member.unhealthy)
+    }
+}
+
+impl Drop for PoolCheckout {
+    fn drop(&mut self) {
+        if self.lease.is_some() {
+            return;
+        }
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(member) = state.members.get_mut(&self.entry.history_id) {
+            member.checked_out = member.checked_out.saturating_sub(1);
+        }
+        self.shared.record(JournalEvent::Returned {
+            history_id: self.entry.history_id,
+        });
+        drop(state);
+        self.shared.returned.notify_waiters();
+    }
+}
+
+fn record_latency(member: &mut PoolMember, sample: Duration) {
+    member.latency = Some(match member.latency {
+        Some(average) => average.mul_f64(1.0 - LATENCY_ALPHA) + sample.mul_f64(LATENCY_ALPHA),
+        None => sample,
+    });
+}
+
+#[derive(Debug, Clone)]
+pub struct DrainOptions {
+    // How long to wait for outstanding checkouts to come back
+    pub timeout: Duration,
+    // Disable auto-renewal on every member once drained
+    pub disable_renewals: bool,
+}
+
+impl Default for DrainOptions {
+    fn default() -> Self {
+        DrainOptions {
+            timeout: Duration::from_secs(30),
+            disable_renewals: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DrainReport {
+    pub members: usize,
+    // Checkouts still held by callers when the drain finished
+    pub outstanding: usize,
+    pub timed_out: bool,
+    pub renewals_disabled: Vec<u64>,
+    pub renewal_errors: Vec<(u64, ApiError)>,
+}
+
+impl ProxyPool {
+    pub fn new(client: TrueSocksClient) -> Self {
+        Self::build(client, None)
+    }
+
+    /// Pool recording every membership, checkout, health and drain change to `journal`.
+    pub fn with_journal<J: JournalSink>(client: TrueSocksClient, journal: J) -> Self {
+        Self::build(client, Some(Box::new(journal)))
+    }
+
+    fn build(client: TrueSocksClient, journal: Option<Box<dyn JournalSink>>) -> Self {
+        ProxyPool {
+            client,
+            shared: Arc::new(PoolShared {
+                state: Mutex::new(PoolState::default()),
+                returned: Notify::new(),
+                journal,
+            }),
+        }
+    }
+
+    /// Record an event the pool does not see itself, such as a purchase or
+    /// renewal made for one of its members.
+    pub fn journal(&self, event: JournalEvent) {
+        let _state = self.shared.state.lock().unwrap();
+        self.shared.record(event);
+    }
+
+    pub fn client(&self) -> &TrueSocksClient {
+        &self.client
+    }
+
+    /// Add or update a member from a history entry.
+    pub fn insert(&self, entry: ListInfo) {
+        let mut state = self.shared.state.lock().unwrap();
+        match state.members.get_mut(&entry.history_id) {
+            Some(member) => {
+                if member.entry.connect_info != entry.connect_info {
+                    member.unhealthy = false;
+                    self.shared.record(JournalEvent::Updated {
+                        history_id: entry.history_id,
+                    });
+                }
+                member.expires_at = PoolMember::expires_at(&entry);
+                member.entry = entry;
+            }
+            None => {
+                self.shared.record(JournalEvent::Added {
+                    history_id: entry.history_id,
+                    proxy_id: entry.proxy_info.proxy_id,
+                });
+                state.members.insert(
+                    entry.history_id,
+                    PoolMember {
+                        checked_out: 0,
+                        latency: None,
+                        unhealthy: false,
+                        cooldown_until: None,
+                        failed_leases: 0,
+                        expires_at: PoolMember::expires_at(&entry),
+                        usage: UsageCounters::default(),
+                        entry,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Reload members from the active history entries. Members that are no
+    /// longer active are dropped unless they are still checked out.
+    pub async fn refresh(&self) -> Result<(), ApiError> {
+        let entries = self
+            .client
+            .list_all_history(&HistoryQuery::active())
+            .await?;
+        self.apply_refresh(entries);
+        Ok(())
+    }
+
+    /// Like [`refresh`](Self::refresh), failing with status 499 and leaving the
+    /// pool untouched when `cancel` is triggered before the history is fetched.
+    pub async fn refresh_cancellable(&self, cancel: &CancellationToken) -> Result<(), ApiError> {
+        let query = HistoryQuery::active();
+        let entries = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Err(ApiError::from(CANCELLED)),
+            entries = self.client.list_all_history(&query) => entries?,
+        };
+        self.apply_refresh(entries);
+        Ok(())
+    }
+
+    fn apply_refresh(&self, entries: Vec<ListInfo>) {
+        let mut state = self.shared.state.lock().unwrap();
+        let active: Vec<u64> = entries.iter().map(|entry| entry.history_id).collect();
+        let shared = &self.shared;
+        state.members.retain(|history_id, member| {
+            let keep = active.contains(history_id) || member.checked_out > 0;
+            if !keep {
+                shared.record(JournalEvent::Removed {
+                    history_id: *history_id,
+                    reason: "no longer active".to_string(),
+                });
+            }
+            keep
+        });
+        drop(state);
+        for entry in entries {
+            self.insert(entry);
+        }
+    }
+
+    /// Save members with their latency and health to `path`, see [`load_state`](Self::load_state).
+    pub fn save_state(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let saved = {
+            let state = self.shared.state.lock().unwrap();
+            SavedPool {
+                saved_at: unix_now(),
+                members: state
+                    .members
+                    .values()
+                    .map(|member| SavedMember {
+                        entry: member.entry.clone(),
+                        latency_ms: member.latency.map(|latency| latency.as_millis() as u64),
+                        unhealthy: member.unhealthy,
+                    })
+                    .collect(),
+            }
+        };
+        write_state(path.as_ref(), &saved)
+    }
+
+    /// Add the members saved by [`save_state`](Self::save_state), returning how
+    /// many were loaded. Remaining times are reduced by the time since saving
+    /// and members that expired meanwhile are skipped. Call
+    /// [`refresh`](Self::refresh) afterwards to catch changes made elsewhere.
+    pub fn load_state(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let saved: SavedPool = read_state(path.as_ref())?;
+        let elapsed = unix_now().saturating_sub(saved.saved_at);
+        let mut loaded = 0;
+        for mut member in saved.members {
+            if member.entry.remaining_time <= elapsed {
+                continue;
+            }
+            member.entry.remaining_time -= elapsed;
+            let history_id = member.entry.history_id;
+            self.insert(member.entry);
+            let mut state = self.shared.state.lock().unwrap();
+            if let Some(pooled) = state.members.get_mut(&history_id) {
+                pooled.latency = member.latency_ms.map(Duration::from_millis);
+                pooled.unhealthy = member.unhealthy;
+            }
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn members(&self) -> Vec<ListInfo> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .members
+            .values()
+            .map(|member| member.entry.clone())
+            .collect()
+    }
+
+    pub fn outstanding(&self) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        self.reclaim_expired_leases(&mut state);
+        state.outstanding()
+    }
+
+    /// Feed an observed connect latency for `history_id` into its estimate.
+    pub fn report_latency(&self, history_id: u64, latency: Duration) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(member) = state.members.get_mut(&history_id) {
+            record_latency(member, latency);
+        }
+    }
+
+    /// Count how a use of `history_id` went, see [`stats`](Self::stats).
+    pub fn report_outcome(&self, history_id: u64, outcome: Outcome) {
+        let mut state = self.shared.state.lock().unwrap();
+        if let Some(member) = state.members.get_mut(&history_id) {
+            member.usage.record(outcome);
+        }
+    }
+
+    /// Usage counters of every member by HistoryID, with the credits its proxy
+    /// costs. Counters start when a member joins and go when it leaves.
+    pub fn stats(&self) -> Vec<ProxyStats> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .members
+            .values()
+            .map(|member| ProxyStats {
+                history_id: member.entry.history_id,
+                proxy_id: member.entry.proxy_info.proxy_id,
+                cost: member
+                    .entry
+                    .proxy_info
+                    .cost(member.entry.purchase_kind())
+                    .unwrap_or_default(),
+                usage: member.usage.clone(),
+            })
+            .collect()
+    }
+
+    /// Smoothed latency of a member, None until a latency was reported.
+    pub fn latency(&self, history_id: u64) -> Option<Duration> {
+        let state = self.shared.state.lock().unwrap();
+        state.members.get(&history_id)?.latency
+    }
+
+    /// Smoothed latency of every member with reported latencies, by HistoryID.
+    pub fn latencies(&self) -> BTreeMap<u64, Duration> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .members
+            .iter()
+            .filter_map(|(history_id, member)| Some((*history_id, member.latency?)))
+            .collect()
+    }
+
+    /// Take the online member with the fewest outstanding checkouts, the
+    /// lowest estimated latency first among equally used ones.
+    pub fn checkout(&self) -> Result<PoolCheckout, PoolError> {
+        self.checkout_matching(&ProxyFilter::default())
+    }
+
+    /// Take the least used online member whose proxy matches `filter`. Every
+    /// call is counted towards the pressure report under the filter's label.
+    pub fn checkout_matching(&self, filter: &ProxyFilter) -> Result<PoolCheckout, PoolError> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.reclaim_expired_leases(&mut state);
+        let result = Self::select(&mut state, filter).map(|entry| PoolCheckout {
+            shared: self.shared.clone(),
+            connect_info: entry.connect_info.clone().unwrap(),
+            entry,
+            lease: None,
+        });
+        let outcome = result.as_ref().map(|_| ()).map_err(|err| *err);
+        self.shared.record(match &result {
+            Ok(checkout) => JournalEvent::CheckedOut {
+                history_id: checkout.history_id(),
+                filter: filter.describe(),
+            },
+            Err(err) => JournalEvent::CheckoutFailed {
+                filter: filter.describe(),
+                reason: err.to_string(),
+            },
+        });
+        state
+            .pressure
+            .record(filter.describe(), unix_now(), outcome);
+        result
+    }
+
+    fn select(state: &mut PoolState, filter: &ProxyFilter) -> Result<ListInfo, PoolError> {
+        if state.draining {
+            return Err(PoolError::Draining);
+        }
+        let now = Instant::now();
+        let candidates: Vec<&PoolMember> = state
+            .members
+            .values()
+            .filter(|member| member.is_available(now))
+            .filter(|member| member.cooldown_until.is_none_or(|until| until <= now))
+            .filter(|member| filter.matches(&member.entry.proxy_info))
+            .collect();
+        if candidates.is_empty() {
+            return Err(PoolError::Empty);
+        }
+        let history_id = Self::diverse(state, candidates)
+            .into_iter()
+            .min_by_key(|member| (member.checked_out, member.estimated_latency()))
+            .map(|member| member.entry.history_id)
+            .ok_or(PoolError::Diversity)?;
+        let member = state.members.get_mut(&history_id).unwrap();
+        member.check_out();
+        Ok(member.entry.clone())
+    }
+
+    // Candidates allowed by the diversity constraints. Members already checked
+    // out do not add to the concentration and are always allowed.
+    fn diverse<'a>(state: &'a PoolState, candidates: Vec<&'a PoolMember>) -> Vec<&'a PoolMember> {
+        let diversity = &state.diversity;
+        if diversity.is_unconstrained() {
+            return candidates;
+        }
+        let in_use: Vec<&_> = state
+            .members
+            .values()
+            .filter(|member| member.checked_out > 0)
+            .map(|member| &member.entry.proxy_info)
+            .collect();
+        let allowed: Vec<&PoolMember> = candidates
+            .into_iter()
+            .filter(|member| {
+                member.checked_out > 0 || diversity.allows(&member.entry.proxy_info, &in_use)
+            })
+            .collect();
+        let Some(countries) = diversity.missing_countries(&in_use) else {
+            return allowed;
+        };
+        let new_country: Vec<&PoolMember> = allowed
+            .iter()
+            .filter(|member| {
+                !countries.contains(&member.entry.proxy_info.country_code.to_ascii_uppercase())
+            })
+            .copied()
+            .collect();
+        if new_country.is_empty() {
+            allowed
+        } else {
+            new_country
+        }
+    }
+
+    /// Check out the member pinned to `key`, such as an account id or a target
+    /// domain. The first call picks a member by consistent hashing and later
+    /// calls return the same one until it expires, goes offline or unhealthy,
+    /// or leaves the pool; the key is then moved to another member. Diversity
+    /// constraints and cooldowns do not apply to pinned members.
+    pub fn assign(&self, key: &str) -> Result<PoolCheckout, PoolError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.draining {
+            return Err(PoolError::Draining);
+        }
+        self.reclaim_expired_leases(&mut state);
+        let now = Instant::now();
+        let pinned = state.assignments.get(key).copied().filter(|history_id| {
+            state
+                .members
+                .get(history_id)
+                .is_some_and(|member| member.is_available(now))
+        });
+        let history_id = match pinned {
+            Some(history_id) => history_id,
+            None => {
+                let ring = HashRing::new(
+                    state
+                        .members
+                        .values()
+                        .filter(|member| member.is_available(now))
+                        .filter(|member| member.cooldown_until.is_none_or(|until| until <= now))
+                        .map(|member| member.entry.history_id),
+                );
+                let history_id = ring.get(key).ok_or(PoolError::Empty)?;
+                if let Some(previous) = state.assignments.insert(key.to_string(), history_id) {
+                    sublog!(
+                        Subsystem::Pool,
+                        Level::Debug,
+                        "key {:?} moved from member {} to {}",
+                        key,
+                        previous,
+                        history_id
+                    );
+                }
+                history_id
+            }
+        };
+        let member = state.members.get_mut(&history_id).unwrap();
+        member.check_out();
+        let entry = member.entry.clone();
+        self.shared.record(JournalEvent::CheckedOut {
+            history_id,
+            filter: format!("assign:{}", key),
+        });
+        Ok(PoolCheckout {
+            shared: self.shared.clone(),
+            connect_info: entry.connect_info.clone().unwrap(),
+            entry,
+            lease: None,
+        })
+    }
+
+    /// Forget the member pinned to `key`, the next `assign` picks again.
+    pub fn unassign(&self, key: &str) -> Option<u64> {
+        self.shared.state.lock().unwrap().assignments.remove(key)
+    }
+
+    /// Current pins of session keys to HistoryIDs.
+    pub fn assignments(&self) -> BTreeMap<String, u64> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .assignments
+            .iter()
+            .map(|(key, history_id)| (key.clone(), *history_id))
+            .collect()
+    }
+
+    /// Constraints applied from the next checkout on, see
+    /// [`DiversityConstraints`].
+    pub fn set_diversity(&self, constraints: DiversityConstraints) {
+        self.shared.state.lock().unwrap().diversity = constraints;
+    }
+
+    pub fn diversity(&self) -> DiversityConstraints {
+        self.shared.state.lock().unwrap().diversity.clone()
+    }
+
+    /// Cooldown and quarantine settings for leases ending from now on, see
+    /// [`lease`](Self::lease).
+    pub fn set_lease_options(&self, options: LeaseOptions) {
+        self.shared.state.lock().unwrap().lease = options;
+    }
+
+    pub fn lease_options(&self) -> LeaseOptions {
+        self.shared.state.lock().unwrap().lease.clone()
+    }
+
+    // Check out a member held until the returned lease id is ended or
+    // `expires_at` has passed
+    pub(crate) fn checkout_leased(
+        &self,
+        filter: &ProxyFilter,
+        expires_at: Instant,
+    ) -> Result<(PoolCheckout, u64), PoolError> {
+        let mut checkout = self.checkout_matching(filter)?;
+        let mut state = self.shared.state.lock().unwrap();
+        let lease_id = state.next_lease;
+        state.next_lease += 1;
+        state
+            .leases
+            .insert(lease_id, (checkout.history_id(), expires_at));
+        checkout.lease = Some(lease_id);
+        Ok((checkout, lease_id))
+    }
+
+    // End a lease dropped by its holder, nothing to do when it was already
+    // reclaimed after expiring. Returns the entry when this failure put the
+    // member in quarantine.
+    pub(crate) fn end_lease(&self, lease_id: u64, failed: bool) -> Option<ListInfo> {
+        let mut state = self.shared.state.lock().unwrap();
+        let (history_id, _) = state.leases.remove(&lease_id)?;
+        let quarantined = self.return_leased(&mut state, history_id, failed);
+        drop(state);
+        self.shared.returned.notify_waiters();
+        quarantined
+    }
+
+    // Leases held past their duration go back to the pool as successful ones
+    fn reclaim_expired_leases(&self, state: &mut PoolState) {
+        let now = Instant::now();
+        let expired: Vec<(u64, u64)> = state
+            .leases
+            .iter()
+            .filter(|(_, (_, expires_at))| *expires_at <= now)
+            .map(|(lease_id, (history_id, _))| (*lease_id, *history_id))
+            .collect();
+        for (lease_id, history_id) in expired {
+            state.leases.remove(&lease_id);
+            sublog!(
+                Subsystem::Pool,
+                Level::Debug,
+                "lease of member {} expired, returned to the pool",
+                history_id
+            );
+            self.return_leased(state, history_id, false);
+        }
+    }
+
+    // Return a leased member, rest it and count the outcome
+    fn return_leased(
+        &self,
+        state: &mut PoolState,
+        history_id: u64,
+        failed: bool,
+    ) -> Option<ListInfo> {
+        let options = state.lease.clone();
+        let member = state.members.get_mut(&history_id)?;
+        member.checked_out = member.checked_out.saturating_sub(1);
+        self.shared.record(JournalEvent::Returned { history_id });
+        member.usage.leases += 1;
+        if failed {
+            member.usage.failed_leases += 1;
+        }
+        if !options.cooldown.is_zero() {
+            member.cooldown_until = Some(Instant::now() + options.cooldown);
+        }
+        if !failed {
+            member.failed_leases = 0;
+            return None;
+        }
+        member.failed_leases += 1;
+        if options.max_failures == 0
+            || member.failed_leases < options.max_failures
+            || member.unhealthy
+        {
+            return None;
+        }
+        member.unhealthy = true;
+        let entry = member.entry.clone();
+        let reason = format!("quarantined after {} failed leases", member.failed_leases);
+        self.shared
+            .record(JournalEvent::Unhealthy { history_id, reason });
+        Some(entry)
+    }
+
+    // Members taken out of rotation by failed leases
+    pub(crate) fn quarantined_members(&self) -> Vec<ListInfo> {
+        let state = self.shared.state.lock().unwrap();
+        let max_failures = state.lease.max_failures;
+        state
+            .members
+            .values()
+            .filter(|member| {
+                member.unhealthy && max_failures > 0 && member.failed_leases >= max_failures
+            })
+            .map(|member| member.entry.clone())
+            .collect()
+    }
+
+    pub(crate) fn lift_quarantine(&self, history_id: u64) {
+        if let Some(member) = self
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .members
+            .get_mut(&history_id)
+        {
+            member.failed_leases = 0;
+        }
+        self.set_healthy(history_id, true, "");
+    }
+
+    // Members currently held by at least one checkout
+    #[cfg(feature = "socks")]
+    pub(crate) fn checked_out_members(&self) -> Vec<ListInfo> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .members
+            .values()
+            .filter(|member| member.checked_out > 0)
+            .map(|member| member.entry.clone())
+            .collect()
+    }
+
+    // Returns whether the member was previously marked unhealthy
+    pub(crate) fn set_healthy(&self, history_id: u64, healthy: bool, reason: &str) -> bool {
+        let mut state = self.shared.state.lock().unwrap();
+        let Some(member) = state.members.get_mut(&history_id) else {
+            return false;
+        };
+        let was_unhealthy = std::mem::replace(&mut member.unhealthy, !healthy);
+        if was_unhealthy == healthy {
+            self.shared.record(if healthy {
+                JournalEvent::Healthy { history_id }
+            } else {
+                JournalEvent::Unhealthy {
+                    history_id,
+                    reason: reason.to_string(),
+                }
+            });
+        }
+        was_unhealthy
+    }
+
+    #[cfg(feature = "socks")]
+    pub(crate) fn is_member_healthy(&self, history_id: u64) -> bool {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .members
+            .get(&history_id)
+            .is_some_and(|member| !member.unhealthy)
+    }
+
+    /// Checkout requests for the UTC day containing `timestamp`.
+    pub fn pressure_report(&self, timestamp: u64) -> PressureReport {
+        self.shared.state.lock().unwrap().pressure.report(timestamp)
+    }
+
+    /// Reports for every retained day, oldest first.
+    pub fn pressure_reports(&self) -> Vec<PressureReport> {
+        self.shared.state.lock().unwrap().pressure.reports()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.shared.state.lock().unwrap().draining
+    }
+
+    /// Start handing out proxies again after a drain.
+    pub fn resume(&self) {
+        self.shared.state.lock().unwrap().draining = false;
+        self.journal(JournalEvent::Resumed);
+    }
+
+    /// Stop handing out proxies and wait for outstanding checkouts to be
+    /// returned, up to `options.timeout`.
+    pub async fn drain(&self, options: DrainOptions) -> DrainReport {
+        self.shared.state.lock().unwrap().draining = true;
+        self.journal(JournalEvent::Draining);
+
+        let wait = async {
+            loop {
+                let returned = self.shared.returned.notified();
+                if self.outstanding() == 0 {
+                    break;
+                }
+                returned.await;
+            }
+        };
+        let timed_out = timeout(options.timeout, wait).await.is_none();
+
+        let mut renewals_disabled = Vec::new();
+        let mut renewal_errors = Vec::new();
+        if options.disable_renewals {
+            for entry in self
+                .members()
+                .into_iter()
+                .filter(|entry| entry.renew_enabled)
+            {
+                let result = match renewal_history_id(entry.history_id) {
+                    Ok(history_id) => self.client.bought_proxy_renew_disable(history_id).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(_) => {
+                        self.journal(JournalEvent::RenewalDisabled {
+                            history_id: entry.history_id,
+                        });
+                        renewals_disabled.push(entry.history_id);
+                    }
+                    Err(err) => renewal_errors.push((entry.history_id, err)),
+                }
+            }
+        }
+
+        DrainReport {
+            members: self.len(),
+            outstanding: self.outstanding(),
+            timed_out,
+            renewals_disabled,
+            renewal_errors,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+
+    fn pool_with(history_ids: &[u64]) -> ProxyPool {
+        let pool = ProxyPool::new(TrueSocksClient::new("test"));
+        for history_id in history_ids {
+            pool.insert(list_info(*history_id));
+        }
+        pool
+    }
+
+    #[test]
+    fn test_checkout_spreads_load() {
+        let pool = pool_with(&[1, 2]);
+        let first = pool.checkout().unwrap();
+        let second = pool.checkout().unwrap();
+        assert_ne!(first.history_id(), second.history_id());
+        assert_eq!(pool.outstanding(), 2);
+        drop(first);
+        assert_eq!(pool.outstanding(), 1);
+    }
+
+    #[test]
+    fn test_checkout_skips_expired_members() {
+        let pool = pool_with(&[1]);
+        let mut expired = list_info(2);
+        expired.remaining_time = 0;
+        pool.insert(expired);
+        for _ in 0..3 {
+            assert_eq!(pool.checkout().unwrap().history_id(), 1);
+        }
+        let filter = ProxyFilter::new();
+        assert_eq!(pool.checkout_matching(&filter).unwrap().history_id(), 1);
+
+        let pool = pool_with(&[]);
+        let mut expired = list_info(3);
+        expired.remaining_time = 0;
+        pool.insert(expired);
+        assert_eq!(pool.checkout().err(), Some(PoolError::Empty));
+    }
+
+    #[test]
+    fn test_usage_stats() {
+        let pool = pool_with(&[1, 2]);
+        let checkout = pool.checkout().unwrap();
+        checkout.report_outcome(Outcome::success().bytes(100, 900));
+        pool.report_outcome(checkout.history_id(), Outcome::failure());
+        pool.report_outcome(checkout.history_id(), Outcome::failure());
+        drop(checkout);
+
+        let stats = pool.stats();
+        let used = &stats[0];
+        assert_eq!(used.usage.checkouts, 1);
+        assert_eq!(used.usage.bytes(), 1000);
+        assert!(used.usage.last_used.is_some());
+        assert!(used.usage.is_failing(3, 0.5));
+        assert!(!used.usage.is_failing(4, 0.5));
+        assert_eq!(used.cost, 10);
+        assert_eq!(used.bytes_per_credit(), Some(100.0));
+        assert_eq!(stats[1].usage, UsageCounters::default());
+        assert_eq!(stats[1].usage.failure_rate(), None);
+    }
+
+    #[test]
+    fn test_assign_is_sticky() {
+        let pool = pool_with(&[1, 2, 3]);
+        let first = pool.assign("account-7").unwrap().history_id();
+        for _ in 0..5 {
+            assert_eq!(pool.assign("account-7").unwrap().history_id(), first);
+        }
+        // A new member does not take over a pinned key
+        pool.insert(list_info(4));
+        assert_eq!(pool.assign("account-7").unwrap().history_id(), first);
+
+        let mut expired = list_info(first);
+        expired.remaining_time = 0;
+        pool.insert(expired);
+        let moved = pool.assign("account-7").unwrap().history_id();
+        assert_ne!(moved, first);
+        assert_eq!(pool.assignments()["account-7"], moved);
+        assert_eq!(pool.unassign("account-7"), Some(moved));
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let path = std::env::temp_dir().join(format!("truesocks-pool-{}.json", std::process::id()));
+        let pool = pool_with(&[1, 2]);
+        pool.report_latency(1, Duration::from_millis(80));
+        let mut expired = list_info(3);
+        expired.remaining_time = 0;
+        pool.insert(expired);
+        pool.save_state(&path).unwrap();
+
+        let restored = ProxyPool::new(TrueSocksClient::new("test"));
+        assert_eq!(restored.load_state(&path).unwrap(), 2);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restored.latency(1), Some(Duration::from_millis(80)));
+        let members = restored.members();
+        assert_eq!(
+            members
+                .iter()
+                .map(|entry| entry.history_id)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(members[0].connect_info, list_info(1).connect_info);
+    }
+
+    #[test]
+    fn test_latency_estimate_drives_selection() {
+        let pool = pool_with(&[1, 2]);
+        pool.report_latency(1, Duration::from_millis(400));
+        pool.report_latency(2, Duration::from_millis(100));
+        pool.report_latency(2, Duration::from_millis(200));
+        assert_eq!(pool.latency(2), Some(Duration::from_millis(130)));
+        assert_eq!(pool.checkout().unwrap().history_id(), 2);
+        assert_eq!(pool.latencies().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_checkouts() {
+        let pool = pool_with(&[1]);
+        let checkout = pool.checkout().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(checkout);
+        });
+        let report = pool.drain(DrainOptions::default()).await;
+        assert!(!report.timed_out);
+        assert_eq!(report.outstanding, 0);
+        assert_eq!(pool.checkout().err(), Some(PoolError::Draining));
+    }
+
+    #[tokio::test]
+    async fn test_drain_times_out() {
+        let pool = pool_with(&[1]);
+        let _checkout = pool.checkout().unwrap();
+        let report = pool
+            .drain(DrainOptions {
+                timeout: Duration::from_millis(10),
+                disable_renewals: false,
+            })
+            .await;
+        assert!(report.timed_out);
+        assert_eq!(report.outstanding, 1);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_cancelled() {
+        let pool = pool_with(&[1, 2]);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = pool.refresh_cancellable(&cancel).await.unwrap_err();
+        assert_eq!(err.code(), 499);
+        assert_eq!(pool.len(), 2);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::pool::PoolError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+// Days of counters kept before the oldest is discarded
+const RETENTION_DAYS: usize = 14;
+
+/// Checkout requests for one filter label over one day.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabelPressure {
+    pub requests: u64,
+    pub satisfied: u64,
+    // No member matched the filter
+    pub no_match: u64,
+    // The pool was draining
+    pub draining: u64,
+    // Matching members were held back by the diversity constraints
+    #[serde(default)]
+    pub constrained: u64,
+}
+
+impl LabelPressure {
+    pub fn unsatisfied(&self) -> u64 {
+        self.no_match + self.draining + self.constrained
+    }
+}
+
+/// Daily breakdown of checkout requests the pool could not satisfy, keyed by
+/// filter label (see `ProxyFilter::describe`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PressureReport {
+    // Unix timestamp of the start of the UTC day
+    pub day: u64,
+    pub labels: BTreeMap<String, LabelPressure>,
+}
+
+impl PressureReport {
+    /// Labels with at least one unsatisfied request, most starved first.
+    pub fn starved(&self) -> Vec<(&str, &LabelPressure)> {
+        let mut starved: Vec<(&str, &LabelPressure)> = self
+            .labels
+            .iter()
+            .filter(|(_, pressure)| pressure.unsatisfied() > 0)
+            .map(|(label, pressure)| (label.as_str(), pressure))
+            .collect();
+        starved.sort_by_key(|(_, pressure)| std::cmp::Reverse(pressure.unsatisfied()));
+        starved
+    }
+
+    pub fn total_unsatisfied(&self) -> u64 {
+        self.labels.values().map(LabelPressure::unsatisfied).sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PressureTracker {
+    days: BTreeMap<u64, BTreeMap<String, LabelPressure>>,
+}
+
+impl PressureTracker {
+    pub(crate) fn record(&mut self, label: String, timestamp: u64, outcome: Result<(), PoolError>) {
+        let day = timestamp - timestamp % SECONDS_PER_DAY;
+        let pressure = self.days.entry(day).or_default().entry(label).or_default();
+        pressure.requests += 1;
+        match outcome {
+            Ok(()) => pressure.satisfied += 1,
+            Err(PoolError::Empty) => pressure.no_match += 1,
+            Err(PoolError::Draining) => pressure.draining += 1,
+            Err(PoolError::Diversity) => pressure.constrained += 1,
+        }
+        while self.days.len() > RETENTION_DAYS {
+            self.days.pop_first();
+        }
+    }
+
+    pub(crate) fn report(&self, timestamp: u64) -> PressureReport {
+        let day = timestamp - timestamp % SECONDS_PER_DAY;
+        PressureReport {
+            day,
+            labels: self.days.get(&day).cloned().unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn reports(&self) -> Vec<PressureReport> {
+        self.days
+            .iter()
+            .map(|(day, labels)| PressureReport {
+                day: *day,
+                labels: labels.clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daily_buckets() {
+        let mut tracker = PressureTracker::default();
+        tracker.record("uk".to_string(), 100, Ok(()));
+        tracker.record("uk".to_string(), 200, Err(PoolError::Empty));
+        tracker.record("de".to_string(), 300, Err(PoolError::Draining));
+        tracker.record("uk".to_string(), SECONDS_PER_DAY + 1, Err(PoolError::Empty));
+
+        let report = tracker.report(500);
+        assert_eq!(report.day, 0);
+        assert_eq!(report.labels["uk"].requests, 2);
+        assert_eq!(report.labels["uk"].no_match, 1);
+        assert_eq!(report.total_unsatisfied(), 2);
+        assert_eq!(tracker.reports().len(), 2);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::client::TrueSocksClient;
+use crate::export::{entry_records, ExportRecord};
+use crate::history::HistoryQuery;
+use crate::models::ApiError;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainMode {
+    // Every proxy in order, fails if any is down
+    Strict,
+    // Every proxy in order, dead ones are skipped
+    Dynamic,
+    // `chain_len` proxies picked at random per connection
+    Random { chain_len: usize },
+    // `chain_len` proxies per connection, starting after the last one used
+    RoundRobin { chain_len: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxychainsOptions {
+    pub chain: ChainMode,
+    pub proxy_dns: bool,
+    pub quiet_mode: bool,
+    // Milliseconds
+    pub tcp_read_time_out: u32,
+    pub tcp_connect_time_out: u32,
+}
+
+impl Default for ProxychainsOptions {
+    fn default() -> Self {
+        ProxychainsOptions {
+            chain: ChainMode::Dynamic,
+            proxy_dns: true,
+            quiet_mode: false,
+            tcp_read_time_out: 15000,
+            tcp_connect_time_out: 8000,
+        }
+    }
+}
+
+/// Render a complete `proxychains.conf` (proxychains-ng) chaining `records`.
+pub fn proxychains_conf(records: &[ExportRecord], options: &ProxychainsOptions) -> String {
+    let mut conf = String::new();
+    match options.chain {
+        ChainMode::Strict => conf.push_str("strict_chain\n"),
+        ChainMode::Dynamic => conf.push_str("dynamic_chain\n"),
+        ChainMode::Random { chain_len } => {
+            let _ = write!(conf, "random_chain\nchain_len = {}\n", chain_len.max(1));
+        }
+        ChainMode::RoundRobin { chain_len } => {
+            let _ = write!(
+                conf,
+                "round_robin_chain\nchain_len = {}\n",
+                chain_len.max(1)
+            );
+        }
+    }
+    if options.quiet_mode {
+        conf.push_str("quiet_mode\n");
+    }
+    if options.proxy_dns {
+        conf.push_str("proxy_dns\n");
+    }
+    let _ = writeln!(conf, "tcp_read_time_out {}", options.tcp_read_time_out);
+    let _ = writeln!(
+        conf,
+        "tcp_connect_time_out {}",
+        options.tcp_connect_time_out
+    );
+    conf.push_str("\n[ProxyList]\n");
+    for record in records {
+        let _ = write!(conf, "socks5 {} {}", record.host, record.port);
+        if let (Some(username), Some(password)) = (&record.username, &record.password) {
+            let _ = write!(conf, " {} {}", username, password);
+        }
+        conf.push('\n');
+    }
+    conf
+}
+
+impl TrueSocksClient {
+    /// `proxychains.conf` for every active purchase with connect info, by ProxyID.
+    pub async fn proxychains_conf(&self, options: &ProxychainsOptions) -> Result<String, ApiError> {
+        let entries = self.list_all_history(&HistoryQuery::active()).await?;
+        Ok(proxychains_conf(&entry_records(&entries), options))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::list_info;
+
+    #[test]
+    fn test_proxychains_conf() {
+        let records = entry_records(&[list_info(2), list_info(1)]);
+        let options = ProxychainsOptions {
+            chain: ChainMode::Random { chain_len: 1 },
+            ..ProxychainsOptions::default()
+        };
+        let conf = proxychains_conf(&records, &options);
+        assert!(conf.starts_with("random_chain\nchain_len = 1\nproxy_dns\n"));
+        let list: Vec<&str> = conf
+            .lines()
+            .skip_while(|line| *line != "[ProxyList]")
+            .skip(1)
+            .collect();
+        assert_eq!(
+            list,
+            vec![
+                "socks5 198.51.100.1 20001 session1 session1",
+                "socks5 198.51.100.1 20002 session2 session2",
+            ]
+        );
+    }
+}
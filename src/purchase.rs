@@ -0,0 +1,297 @@
+use crate::client::{purchase_command_name, TrueSocksClient};
+use crate::credits::Credits;
+use crate::history::HistoryQuery;
+use crate::models::{
+    ApiError, ProxyCheckResult, ProxyInfo, PurchaseKind, PurchaseResult, TestAndRefundResult,
+};
+use crate::status_codes::{BAD_REQUEST, BUDGET_EXCEEDED, CONFLICT};
+use std::fmt;
+
+/// Why a purchase was refused before its command was sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PurchaseValidationError {
+    // A regular command was used for a fresh proxy or the reverse
+    WrongFreshness { proxy_is_fresh: bool },
+    // The proxy has no private rental cost
+    PrivateRentUnavailable,
+    // An active history entry already holds the proxy
+    AlreadyOwned { history_id: u64 },
+    InsufficientCredits { cost: Credits, available: Credits },
+}
+
+impl PurchaseValidationError {
+    pub fn code(&self) -> u16 {
+        match self {
+            PurchaseValidationError::WrongFreshness { .. }
+            | PurchaseValidationError::PrivateRentUnavailable => BAD_REQUEST,
+            PurchaseValidationError::AlreadyOwned { .. } => CONFLICT,
+            PurchaseValidationError::InsufficientCredits { .. } => BUDGET_EXCEEDED,
+        }
+    }
+}
+
+impl fmt::Display for PurchaseValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PurchaseValidationError::WrongFreshness {
+                proxy_is_fresh: true,
+            } => write!(f, "the proxy is fresh, use a fresh proxy command"),
+            PurchaseValidationError::WrongFreshness {
+                proxy_is_fresh: false,
+            } => write!(f, "the proxy is not fresh, use a regular proxy command"),
+            PurchaseValidationError::PrivateRentUnavailable => {
+                write!(f, "the proxy cannot be rented privately")
+            }
+            PurchaseValidationError::AlreadyOwned { history_id } => {
+                write!(
+                    f,
+                    "the proxy is already held by history entry {}",
+                    history_id
+                )
+            }
+            PurchaseValidationError::InsufficientCredits { cost, available } => {
+                write!(
+                    f,
+                    "the purchase costs {} but only {} are left",
+                    cost, available
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PurchaseValidationError {}
+
+#[derive(Debug, Clone)]
+pub enum VerifiedPurchase {
+    // The proxy passed every check after purchase
+    Working {
+        purchase: PurchaseResult,
+        check: ProxyCheckResult,
+    },
+    // The proxy failed its check, or the check could not be run, and was refunded
+    Refunded {
+        purchase: PurchaseResult,
+        check: Result<ProxyCheckResult, ApiError>,
+        refund: TestAndRefundResult,
+    },
+    // The proxy was bought but not verified, and the refund did not go through
+    RefundFailed {
+        purchase: PurchaseResult,
+        check: Result<ProxyCheckResult, ApiError>,
+        error: ApiError,
+    },
+}
+
+impl VerifiedPurchase {
+    pub fn is_working(&self) -> bool {
+        matches!(self, VerifiedPurchase::Working { .. })
+    }
+
+    pub fn purchase(&self) -> &PurchaseResult {
+        match self {
+            VerifiedPurchase::Working { purchase, .. }
+            | VerifiedPurchase::Refunded { purchase, .. }
+            | VerifiedPurchase::RefundFailed { purchase, .. } => purchase,
+        }
+    }
+}
+
+impl ProxyCheckResult {
+    pub fn passed(&self) -> bool {
+        self.tests_total > 0 && self.tests_passed == self.tests_total
+    }
+}
+
+impl TrueSocksClient {
+    /// Check a purchase against the account balance and the active history
+    /// without buying anything. Runs before every purchase when enabled with
+    /// [`TrueSocksClientBuilder::validate_purchases`].
+    ///
+    /// [`TrueSocksClientBuilder::validate_purchases`]: crate::client::TrueSocksClientBuilder::validate_purchases
+    pub async fn validate_purchase(
+        &self,
+        proxy_info: &ProxyInfo,
+        kind: PurchaseKind,
+    ) -> Result<(), ApiError> {
+        purchase_command_name(proxy_info, proxy_info.is_fresh, kind)?;
+        let cost = proxy_info
+            .cost(kind)
+            .ok_or(PurchaseValidationError::PrivateRentUnavailable)?;
+        let available = self.get_account_status().await?.credits;
+        if cost > available {
+            return Err(PurchaseValidationError::InsufficientCredits { cost, available }.into());
+        }
+        let owned = self
+            .list_all_history(&HistoryQuery::active())
+            .await?
+            .into_iter()
+            .find(|entry| entry.proxy_info.proxy_id == proxy_info.proxy_id);
+        if let Some(entry) = owned {
+            return Err(PurchaseValidationError::AlreadyOwned {
+                history_id: entry.history_id,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Buy a proxy, run `BoughtProxyCheck` on it straight away and refund it
+    /// if any test fails or the check cannot be run. Only a failed purchase is
+    /// an error, once bought the purchase is always part of the outcome.
+    pub async fn purchase_verified(
+        &self,
+        proxy_info: &ProxyInfo,
+        kind: PurchaseKind,
+    ) -> Result<VerifiedPurchase, ApiError> {
+        let purchase = self.purchase(proxy_info, kind).await?;
+        let check = match self.check_purchased_proxy(proxy_info).await {
+            Ok(check) if check.passed() => {
+                return Ok(VerifiedPurchase::Working { purchase, check })
+            }
+            check => check,
+        };
+
+        Ok(match self.refund_purchased_proxy(proxy_info).await {
+            Ok(refund) => VerifiedPurchase::Refunded {
+                purchase,
+                check,
+                refund,
+            },
+            Err(error) => VerifiedPurchase::RefundFailed {
+                purchase,
+                check,
+                error,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{
+        account_status, history_page, list_info_json, ok_response, proxy_info_json, serve,
+    };
+    use serde_json::{json, Value};
+
+    fn check(passed: u32) -> Value {
+        ok_response(json!({
+            "tests_passed": passed,
+            "tests_total": 3,
+            "tests_result": "",
+            "tests_result_str": ""
+        }))
+    }
+
+    fn refund() -> Value {
+        ok_response(json!({
+            "tests_passed": 0,
+            "tests_total": 3,
+            "tests_result": "",
+            "tests_result_str": "",
+            "refund_result": "OK",
+            "refund_result_str": "Refunded"
+        }))
+    }
+
+    async fn verify(responses: Vec<Value>) -> (VerifiedPurchase, Vec<String>) {
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(7)).unwrap();
+        let mut bodies = vec![ok_response(json!({ "CreditsLeft": 90 }))];
+        bodies.extend(responses);
+        let (url, server) = serve(bodies);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let outcome = client
+            .purchase_verified(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap();
+        (outcome, server.join().unwrap())
+    }
+
+    fn failure(code: u64) -> Value {
+        json!({"status": {"code": code, "message": "failed"}, "result": null})
+    }
+
+    #[tokio::test]
+    async fn test_purchase_verified_working() {
+        let (outcome, requests) = verify(vec![check(3)]).await;
+        assert!(outcome.is_working());
+        assert_eq!(outcome.purchase().credits_left, Some(Credits::from(90)));
+        assert_eq!(requests.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_purchase_verified_refunds_failed_check() {
+        let (outcome, requests) = verify(vec![check(1), refund()]).await;
+        assert!(matches!(
+            outcome,
+            VerifiedPurchase::Refunded { check: Ok(ref check), .. } if check.tests_passed == 1
+        ));
+        assert!(requests[2].contains("cmd=BoughtProxyRefund"));
+    }
+
+    #[tokio::test]
+    async fn test_purchase_verified_refunds_check_error() {
+        let (outcome, requests) = verify(vec![failure(3), refund()]).await;
+        assert!(matches!(
+            outcome,
+            VerifiedPurchase::Refunded { check: Err(_), .. }
+        ));
+        assert_eq!(outcome.purchase().credits_left, Some(Credits::from(90)));
+        assert!(requests[2].contains("cmd=BoughtProxyRefund"));
+    }
+
+    #[tokio::test]
+    async fn test_purchase_verified_keeps_purchase_when_refund_fails() {
+        let (outcome, _) = verify(vec![check(0), failure(3)]).await;
+        match outcome {
+            VerifiedPurchase::RefundFailed {
+                purchase, error, ..
+            } => {
+                assert_eq!(purchase.credits_left, Some(Credits::from(90)));
+                assert_eq!(error.code(), 3);
+            }
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purchase_validation() {
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(7)).unwrap();
+        proxy.rent_cost = Credits::from(5);
+        let client = TrueSocksClient::new("test");
+        let err = client.fresh_proxy_rent(&proxy).await.unwrap_err();
+        assert!(matches!(
+            err,
+            ApiError::PurchaseValidation(PurchaseValidationError::WrongFreshness {
+                proxy_is_fresh: false
+            })
+        ));
+        assert_eq!(err.code(), BAD_REQUEST as u64);
+
+        let account = serde_json::to_value(account_status(3)).unwrap();
+        let (url, _) = serve(vec![ok_response(account)]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let err = client
+            .validate_purchase(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), BUDGET_EXCEEDED as u64);
+
+        let account = serde_json::to_value(account_status(10)).unwrap();
+        let history = history_page(vec![list_info_json(4, 7)], 1, 1);
+        let (url, _) = serve(vec![ok_response(account), ok_response(history)]);
+        let client = TrueSocksClient::builder("test")
+            .base_url(url)
+            .validate_purchases(true)
+            .build();
+        let err = client
+            .purchase(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ApiError::PurchaseValidation(PurchaseValidationError::AlreadyOwned { history_id: 4 })
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::models::ApiError;
+use crate::runtime::{sleep, Instant};
+use crate::status_codes::RATE_LIMITED;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A token bucket: `burst` calls can go out at once, then one every `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl RateLimit {
+    pub fn per_second(requests: u32) -> Self {
+        Self::per(requests, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(requests: u32) -> Self {
+        Self::per(requests, Duration::from_secs(60))
+    }
+
+    // `requests` calls spread over `period`, with a burst of the same size
+    fn per(requests: u32, period: Duration) -> Self {
+        let requests = requests.max(1);
+        RateLimit {
+            burst: requests,
+            interval: period / requests,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+/// What a call does when no token is available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitMode {
+    // Wait for a token, calls go out in the order they were made
+    #[default]
+    Queue,
+    // Fail with status 429 without sending the request
+    Reject,
+}
+
+struct BucketState {
+    // Negative when queued calls have reserved tokens that are not refilled yet
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Bucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit) -> Self {
+        Bucket {
+            limit,
+            state: Mutex::new(BucketState {
+                tokens: limit.burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(&self, state: &mut BucketState) {
+        let now = Instant::now();
+        let refilled = now.duration_since(state.updated).as_secs_f64()
+            / self.limit.interval.as_secs_f64().max(f64::EPSILON);
+        state.tokens = (state.tokens + refilled).min(self.limit.burst as f64);
+        state.updated = now;
+    }
+
+    fn try_take(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state);
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn give_back(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.tokens = (state.tokens + 1.0).min(self.limit.burst as f64);
+    }
+
+    // Take a token now, returning how long the caller has to wait before it is usable
+    fn reserve(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        self.refill(&mut state);
+        state.tokens -= 1.0;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            self.limit.interval.mul_f64(-state.tokens)
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RateLimits {
+    pub(crate) global: Option<RateLimit>,
+    pub(crate) commands: HashMap<String, RateLimit>,
+    pub(crate) mode: RateLimitMode,
+}
+
+pub(crate) struct RateLimiter {
+    global: Option<Bucket>,
+    commands: HashMap<String, Bucket>,
+    mode: RateLimitMode,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: RateLimits) -> Self {
+        RateLimiter {
+            global: limits.global.map(Bucket::new),
+            commands: limits
+                .commands
+                .into_iter()
+                .map(|(command, limit)| (command, Bucket::new(limit)))
+                .collect(),
+            mode: limits.mode,
+        }
+    }
+
+    // Human readable limits, the client wide one first then by command name
+    pub(crate) fn describe(&self) -> Vec<String> {
+        let describe = |scope: &str, limit: &RateLimit| {
+            format!(
+                "{}: burst {}, one per {:?} ({:?})",
+                scope, limit.burst, limit.interval, self.mode
+            )
+        };
+        let mut commands: Vec<String> = self
+            .commands
+            .iter()
+            .map(|(command, bucket)| describe(command, &bucket.limit))
+            .collect();
+        commands.sort();
+        self.global
+            .iter()
+            .map(|bucket| describe("all", &bucket.limit))
+            .chain(commands)
+            .collect()
+    }
+
+    // Wait for or reject on both the command's bucket and the global one
+    pub(crate) async fn acquire(&self, command: &str) -> Result<(), ApiError> {
+        let buckets: Vec<&Bucket> = self
+            .global
+            .iter()
+            .chain(self.commands.get(command))
+            .collect();
+        match self.mode {
+            RateLimitMode::Reject => {
+                for (taken, bucket) in buckets.iter().enumerate() {
+                    if !bucket.try_take() {
+                        buckets[..taken]
+                            .iter()
+                            .for_each(|bucket| bucket.give_back());
+                        return Err(ApiError::from(RATE_LIMITED));
+                    }
+                }
+            }
+            RateLimitMode::Queue => {
+                let wait = buckets
+                    .iter()
+                    .map(|bucket| bucket.reserve())
+                    .max()
+                    .unwrap_or_default();
+                if !wait.is_zero() {
+                    sleep(wait).await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(mode: RateLimitMode, command: Option<RateLimit>) -> RateLimiter {
+        RateLimiter::new(RateLimits {
+            global: Some(RateLimit::per_second(20).with_burst(1)),
+            commands: command
+                .map(|limit| HashMap::from([("ListOnline".to_string(), limit)]))
+                .unwrap_or_default(),
+            mode,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_reject_when_empty() {
+        let limiter = limiter(RateLimitMode::Reject, Some(RateLimit::per_minute(1)));
+        assert!(limiter.acquire("ListOnline").await.is_ok());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let err = limiter.acquire("ListOnline").await.unwrap_err();
+        assert_eq!(err.code(), 429);
+        // The global token taken before the command bucket rejected is given back
+        assert!(limiter.acquire("Ping").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_queue_spaces_calls() {
+        let limiter = limiter(RateLimitMode::Queue, None);
+        let started = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("Ping").await.unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+}
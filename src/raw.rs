@@ -0,0 +1,157 @@
+use crate::client::{
+    history_id_params, note_params, proxy_id_params, purchase_command_name, zip_search_params,
+    TrueSocksClient,
+};
+use crate::history::HistoryQuery;
+use crate::models::{ApiError, ProxyInfo, PurchaseKind};
+use serde_json::Value;
+
+// Untouched JSON counterparts of the typed calls, for fields the models do not
+// know about yet. Arguments and validation match the typed calls.
+impl TrueSocksClient {
+    pub async fn ping_raw(&self) -> Result<Value, ApiError> {
+        self.execute_raw("Ping", None).await
+    }
+
+    pub async fn list_online_proxies_raw(&self) -> Result<Value, ApiError> {
+        self.execute_raw("ListOnline", None).await
+    }
+
+    pub async fn list_zip_search_raw(
+        &self,
+        country_code: &str,
+        zip_code: &str,
+        units: Option<&str>,
+        range: Option<u32>,
+    ) -> Result<Value, ApiError> {
+        let params = zip_search_params(country_code, zip_code, units, range);
+        self.execute_raw("ListZipSearch", Some(params)).await
+    }
+
+    /// One page of the history as sent by the API. Only `active_only` and
+    /// `page` of `query` are used, the client side criteria are not applied.
+    pub async fn list_history_raw(&self, query: &HistoryQuery) -> Result<Value, ApiError> {
+        self.execute_raw("ListHistory", Some(query.params(query.page)))
+            .await
+    }
+
+    async fn purchase_raw(
+        &self,
+        proxy_info: &ProxyInfo,
+        fresh: bool,
+        kind: PurchaseKind,
+    ) -> Result<Value, ApiError> {
+        let command = purchase_command_name(proxy_info, fresh, kind)?;
+        let cost = self.reserve_budget(proxy_info, kind)?;
+        let result = self
+            .execute_raw(command, Some(proxy_id_params(proxy_info.proxy_id)))
+            .await;
+        if matches!(&result, Err(err) if err.is_definite_rejection()) {
+            self.release_budget(cost);
+        }
+        result
+    }
+
+    pub async fn regular_proxy_rent_raw(&self, proxy_info: &ProxyInfo) -> Result<Value, ApiError> {
+        self.purchase_raw(proxy_info, false, PurchaseKind::SharedBuy)
+            .await
+    }
+
+    pub async fn regular_proxy_private_rent_raw(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<Value, ApiError> {
+        self.purchase_raw(proxy_info, false, PurchaseKind::PrivateRent)
+            .await
+    }
+
+    pub async fn fresh_proxy_rent_raw(&self, proxy_info: &ProxyInfo) -> Result<Value, ApiError> {
+        self.purchase_raw(proxy_info, true, PurchaseKind::SharedBuy)
+            .await
+    }
+
+    pub async fn fresh_proxy_private_rent_raw(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<Value, ApiError> {
+        self.purchase_raw(proxy_info, true, PurchaseKind::PrivateRent)
+            .await
+    }
+
+    pub async fn check_purchased_proxy_raw(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<Value, ApiError> {
+        let params = proxy_id_params(proxy_info.proxy_id);
+        self.execute_raw("BoughtProxyCheck", Some(params)).await
+    }
+
+    pub async fn refund_purchased_proxy_raw(
+        &self,
+        proxy_info: &ProxyInfo,
+    ) -> Result<Value, ApiError> {
+        let params = proxy_id_params(proxy_info.proxy_id);
+        self.execute_raw("BoughtProxyRefund", Some(params)).await
+    }
+
+    pub async fn bought_proxy_renew_enable_raw(&self, history_id: u32) -> Result<Value, ApiError> {
+        let params = history_id_params(history_id.into());
+        self.execute_raw("BoughtProxyRenewEnable", Some(params))
+            .await
+    }
+
+    pub async fn bought_proxy_renew_disable_raw(&self, history_id: u32) -> Result<Value, ApiError> {
+        let params = history_id_params(history_id.into());
+        self.execute_raw("BoughtProxyRenewDisable", Some(params))
+            .await
+    }
+
+    pub async fn history_entry_change_note_raw(
+        &self,
+        history_id: u64,
+        note: Option<&str>,
+    ) -> Result<Value, ApiError> {
+        let params = note_params(history_id, note);
+        self.execute_raw("HistoryEntryChangeNote", Some(params))
+            .await
+    }
+
+    pub async fn get_account_status_raw(&self) -> Result<Value, ApiError> {
+        self.execute_raw("AccountStatus", None).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{purchase_command_name, TrueSocksClient};
+    use crate::fixtures::{history_page, ok_response, proxy_info_json, serve_once};
+    use crate::history::HistoryQuery;
+    use crate::models::{ProxyInfo, PurchaseKind};
+
+    #[tokio::test]
+    async fn test_list_history_raw_sends_query() {
+        let (url, request) = serve_once(ok_response(history_page(vec![], 2, 2)));
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let raw = client
+            .list_history_raw(&HistoryQuery::active().page(2))
+            .await
+            .unwrap();
+        assert_eq!(raw["result"]["HistoryCurrentPage"], 2);
+        let request = request.join().unwrap();
+        assert!(request.contains("onlyactive=1"));
+        assert!(request.contains("page=2"));
+    }
+
+    #[test]
+    fn test_purchase_command_name() {
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        proxy.is_fresh = false;
+        assert_eq!(
+            purchase_command_name(&proxy, false, PurchaseKind::SharedBuy).unwrap(),
+            "RegularProxyBuy"
+        );
+        assert!(purchase_command_name(&proxy, true, PurchaseKind::SharedBuy).is_err());
+        proxy.private_rent_cost = 0.into();
+        assert!(purchase_command_name(&proxy, false, PurchaseKind::PrivateRent).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use reqwest::Url;
+use serde_json::Value;
+
+const REDACTED: &str = "[REDACTED]";
+// Query parameters and JSON keys that carry credentials
+const SECRET_PARAMS: &[&str] = &["key"];
+const SECRET_FIELDS: &[&str] = &["ConnectSessionID", "key"];
+
+pub(crate) fn redact_url(url: &Url) -> Url {
+    let mut redacted = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| {
+            let value = if SECRET_PARAMS.contains(&name.as_ref()) {
+                REDACTED.to_string()
+            } else {
+                value.into_owned()
+            };
+            (name.into_owned(), value)
+        })
+        .collect();
+    if !pairs.is_empty() {
+        redacted.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    redacted
+}
+
+pub(crate) fn redact_value(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(name, value)| {
+                    let value = if SECRET_FIELDS.contains(&name.as_str()) {
+                        Value::String(REDACTED.to_string())
+                    } else {
+                        redact_value(value)
+                    };
+                    (name.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.iter().map(redact_value).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_url() {
+        let url = Url::parse("https://api.truesocks.net/?key=secret&cmd=Ping").unwrap();
+        let redacted = redact_url(&url).to_string();
+        assert!(!redacted.contains("secret"));
+        assert!(redacted.contains("cmd=Ping"));
+    }
+
+    #[test]
+    fn test_redact_value() {
+        let value = json!({"result": {"HistoryList": [{"ConnectInfo": {"ConnectSessionID": "abc", "ConnectPort": 1}}]}});
+        let redacted = redact_value(&value).to_string();
+        assert!(!redacted.contains("abc"));
+        assert!(redacted.contains("ConnectPort"));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::credits::Credits;
+use crate::models::{AccountStatusResult, ApiError, EnableProxyRenewalResult, ListInfo};
+// Used by the background task only, which is not built for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::client::{renewal_history_id, TrueSocksClient},
+    crate::history::HistoryQuery,
+    crate::logging::{sublog, Subsystem},
+    log::Level,
+    std::time::Duration,
+    tokio::sync::broadcast,
+    tokio::task::JoinHandle,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(300);
+#[cfg(not(target_arch = "wasm32"))]
+const EVENT_CAPACITY: usize = 64;
+
+/// Decides whether an active history entry should keep auto-renewing.
+pub trait RenewalPolicy: Send + Sync + 'static {
+    fn should_renew(&self, entry: &ListInfo, account: &AccountStatusResult) -> bool;
+}
+
+impl<F> RenewalPolicy for F
+where
+    F: Fn(&ListInfo, &AccountStatusResult) -> bool + Send + Sync + 'static,
+{
+    fn should_renew(&self, entry: &ListInfo, account: &AccountStatusResult) -> bool {
+        self(entry, account)
+    }
+}
+
+/// Keep renewing while the proxy is blacklist-free and the balance stays above `min_credits`.
+#[derive(Debug, Clone)]
+pub struct ThresholdPolicy {
+    pub min_credits: Credits,
+    pub require_clean: bool,
+}
+
+impl RenewalPolicy for ThresholdPolicy {
+    fn should_renew(&self, entry: &ListInfo, account: &AccountStatusResult) -> bool {
+        account.credits > self.min_credits
+            && !(self.require_clean && entry.proxy_info.is_blacklisted())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RenewalEvent {
+    Enabled {
+        history_id: u64,
+        result: EnableProxyRenewalResult,
+    },
+    Disabled {
+        history_id: u64,
+    },
+    Failed {
+        history_id: u64,
+        error: ApiError,
+    },
+    // Fetching the account or history failed, the entries are retried next tick
+    PollFailed(ApiError),
+}
+
+/// Background task applying a [`RenewalPolicy`] to active history entries.
+/// The task stops when the manager is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RenewalManager {
+    events: broadcast::Sender<RenewalEvent>,
+    handle: JoinHandle<()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RenewalManager {
+    pub fn spawn<P: RenewalPolicy>(client: TrueSocksClient, policy: P) -> Self {
+        Self::spawn_with_interval(client, policy, DEFAULT_INTERVAL)
+    }
+
+    pub fn spawn_with_interval<P: RenewalPolicy>(
+        client: TrueSocksClient,
+        policy: P,
+        interval: Duration,
+    ) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let sender = events.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                apply_policy(&client, &policy, &sender).await;
+            }
+        });
+        RenewalManager { events, handle }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RenewalEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn stop(self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for RenewalManager {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn apply_policy<P: RenewalPolicy>(
+    client: &TrueSocksClient,
+    policy: &P,
+    events: &broadcast::Sender<RenewalEvent>,
+) {
+    let account = match client.get_account_status().await {
+        Ok(account) => account,
+        Err(err) => {
+            sublog!(
+                Subsystem::Renewal,
+                Level::Warn,
+                "renewal poll failed: {:?}",
+                err
+            );
+            let _ = events.send(RenewalEvent::PollFailed(err));
+            return;
+        }
+    };
+    let entries = match client.list_all_history(&HistoryQuery::active()).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            sublog!(
+                Subsystem::Renewal,
+                Level::Warn,
+                "renewal poll failed: {:?}",
+                err
+            );
+            let _ = events.send(RenewalEvent::PollFailed(err));
+            return;
+        }
+    };
+
+    for entry in entries {
+        let renew = policy.should_renew(&entry, &account);
+        if renew == entry.renew_enabled {
+            continue;
+        }
+        let history_id = entry.history_id;
+        let event = match renewal_history_id(history_id) {
+            Ok(id) if renew => match client.bought_proxy_renew_enable(id).await {
+                Ok(result) => RenewalEvent::Enabled { history_id, result },
+                Err(error) => RenewalEvent::Failed { history_id, error },
+            },
+            Ok(id) => match client.bought_proxy_renew_disable(id).await {
+                Ok(_) => RenewalEvent::Disabled { history_id },
+                Err(error) => RenewalEvent::Failed { history_id, error },
+            },
+            Err(error) => RenewalEvent::Failed { history_id, error },
+        };
+        match &event {
+            RenewalEvent::Failed { error, .. } => sublog!(
+                Subsystem::Renewal,
+                Level::Warn,
+                "could not change renewal of {}: {:?}",
+                history_id,
+                error
+            ),
+            _ => sublog!(
+                Subsystem::Renewal,
+                Level::Info,
+                "renewal of {} {}",
+                history_id,
+                if renew { "enabled" } else { "disabled" }
+            ),
+        }
+        let _ = events.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{account_status, list_info};
+
+    #[test]
+    fn test_threshold_policy() {
+        let policy = ThresholdPolicy {
+            min_credits: Credits(50),
+            require_clean: true,
+        };
+        let entry = list_info(1);
+        assert!(policy.should_renew(&entry, &account_status(100)));
+        assert!(!policy.should_renew(&entry, &account_status(10)));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::models::ApiError;
+use crate::runtime::since_epoch;
+use crate::status_codes::RATE_LIMITED;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::collections::HashSet;
+use std::time::{Duration, UNIX_EPOCH};
+
+// Commands that may have charged the account even when they failed with a
+// server error or a transport failure; they are only retried on 429
+const NON_IDEMPOTENT_COMMANDS: [&str; 5] = [
+    "RegularProxyBuy",
+    "RegularProxyRent",
+    "FreshProxyBuy",
+    "FreshProxyRent",
+    "BoughtProxyRefund",
+];
+
+/// Whether a failed command is sent again by the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    Retryable,
+    Terminal,
+}
+
+/// Retries of commands failing with an HTTP or API status, on top of the
+/// retries of transport failures. Codes accepted by
+/// [`status_codes::is_retryable`](crate::status_codes::is_retryable) are
+/// retried by default, other statuses are terminal. Purchases and refunds are
+/// only retried on 429.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusRetryPolicy {
+    pub max_retries: u32,
+    // Delay before the first retry, doubled on each following one
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Wait as long as the Retry-After header asks, up to `max_retry_after`
+    pub honor_retry_after: bool,
+    pub max_retry_after: Duration,
+    retry_codes: HashSet<u64>,
+    terminal_codes: HashSet<u64>,
+}
+
+impl Default for StatusRetryPolicy {
+    fn default() -> Self {
+        StatusRetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            honor_retry_after: true,
+            max_retry_after: Duration::from_secs(120),
+            retry_codes: HashSet::new(),
+            terminal_codes: HashSet::new(),
+        }
+    }
+}
+
+impl StatusRetryPolicy {
+    /// A policy retrying nothing.
+    pub fn disabled() -> Self {
+        StatusRetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    pub fn backoff(mut self, base_delay: Duration, max_delay: Duration) -> Self {
+        self.base_delay = base_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn honor_retry_after(mut self, enabled: bool, max_wait: Duration) -> Self {
+        self.honor_retry_after = enabled;
+        self.max_retry_after = max_wait;
+        self
+    }
+
+    /// Also retry commands failing with `code`.
+    pub fn retry_on(mut self, code: u64) -> Self {
+        self.terminal_codes.remove(&code);
+        self.retry_codes.insert(code);
+        self
+    }
+
+    /// Never retry commands failing with `code`.
+    pub fn never_retry(mut self, code: u64) -> Self {
+        self.retry_codes.remove(&code);
+        self.terminal_codes.insert(code);
+        self
+    }
+
+    pub fn classify(&self, command: &str, err: &ApiError) -> RetryClass {
+        let code = match err {
+            ApiError::RequestError(status) => status.code,
+            ApiError::StatusError(code) => *code as u64,
+            ApiError::DecodeError(_) | ApiError::PurchaseValidation(_) => {
+                return RetryClass::Terminal
+            }
+        };
+        let retryable = if self.terminal_codes.contains(&code) {
+            false
+        } else if self.retry_codes.contains(&code) {
+            true
+        } else if !is_idempotent(command) {
+            code == RATE_LIMITED as u64
+        } else {
+            err.is_retryable()
+        };
+        if retryable {
+            RetryClass::Retryable
+        } else {
+            RetryClass::Terminal
+        }
+    }
+
+    /// Wait before retry number `retry` (0 for the first), `retry_after` is the
+    /// delay the server asked for, if any.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        match retry_after {
+            Some(wait) if self.honor_retry_after => wait.min(self.max_retry_after),
+            _ => self
+                .base_delay
+                .saturating_mul(1 << retry.min(16))
+                .min(self.max_delay),
+        }
+    }
+}
+
+/// Delay asked for by a Retry-After header, in seconds or as an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    parse_retry_after(headers.get(RETRY_AFTER)?.to_str().ok()?)
+}
+
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value)
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?;
+    Some(date.saturating_sub(since_epoch()))
+}
+
+// Whether sending `command` twice cannot charge the account twice
+pub(crate) fn is_idempotent(command: &str) -> bool {
+    !NON_IDEMPOTENT_COMMANDS.contains(&command)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use transport::{IdempotentOnly, TransportOnly};
+
+// Transport retries through reqwest-retry, which does not build for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+mod transport {
+    use super::is_idempotent;
+    use crate::hooks::CommandContext;
+    use reqwest::{Request, Response};
+    use reqwest_middleware::{Error, Middleware, Next};
+    use reqwest_retry::{default_on_request_failure, Retryable, RetryableStrategy};
+    use task_local_extensions::Extensions;
+
+    // Leaves status based retries to `StatusRetryPolicy`, which also sees API statuses
+    pub(crate) struct TransportOnly;
+
+    impl RetryableStrategy for TransportOnly {
+        fn handle(&self, res: &Result<Response, Error>) -> Option<Retryable> {
+            match res {
+                Ok(_) => None,
+                Err(err) => default_on_request_failure(err),
+            }
+        }
+    }
+
+    // Runs the retry middleware it wraps for idempotent commands only, a
+    // purchase that failed in transport may still have gone through
+    pub(crate) struct IdempotentOnly<M>(pub(crate) M);
+
+    #[async_trait::async_trait]
+    impl<M: Middleware> Middleware for IdempotentOnly<M> {
+        async fn handle(
+            &self,
+            req: Request,
+            extensions: &mut Extensions,
+            next: Next<'_>,
+        ) -> reqwest_middleware::Result<Response> {
+            let idempotent = extensions
+                .get::<CommandContext>()
+                .is_none_or(|context| is_idempotent(&context.command));
+            if idempotent {
+                self.0.handle(req, extensions, next).await
+            } else {
+                next.run(req, extensions).await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::Status;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_classify() {
+        let policy = StatusRetryPolicy::default();
+        let api = |code| {
+            ApiError::RequestError(Status {
+                code,
+                message: String::new(),
+            })
+        };
+        assert_eq!(
+            policy.classify("ListOnline", &ApiError::from(503_u16)),
+            RetryClass::Retryable
+        );
+        assert_eq!(
+            policy.classify("ListOnline", &api(429)),
+            RetryClass::Retryable
+        );
+        assert_eq!(
+            policy.classify("ListOnline", &ApiError::from(403_u16)),
+            RetryClass::Terminal
+        );
+        // Agrees with `ApiError::is_retryable` for idempotent commands
+        for code in [408_u16, 418, 429, 500, 503] {
+            assert!(ApiError::from(code).is_retryable());
+            assert_eq!(
+                policy.classify("ListOnline", &ApiError::from(code)),
+                RetryClass::Retryable
+            );
+            assert_eq!(
+                policy.classify("FreshProxyBuy", &ApiError::from(code)),
+                if code == 429 {
+                    RetryClass::Retryable
+                } else {
+                    RetryClass::Terminal
+                }
+            );
+        }
+        assert_eq!(policy.classify("ListOnline", &api(3)), RetryClass::Terminal);
+        assert_eq!(
+            policy.classify("FreshProxyBuy", &ApiError::from(502_u16)),
+            RetryClass::Terminal
+        );
+        assert_eq!(
+            policy.classify("FreshProxyBuy", &ApiError::from(429_u16)),
+            RetryClass::Retryable
+        );
+
+        let custom = policy.retry_on(3).never_retry(503);
+        assert_eq!(custom.classify("Ping", &api(3)), RetryClass::Retryable);
+        assert_eq!(
+            custom.classify("Ping", &ApiError::from(503_u16)),
+            RetryClass::Terminal
+        );
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = StatusRetryPolicy::default()
+            .backoff(Duration::from_millis(100), Duration::from_millis(350))
+            .honor_retry_after(true, Duration::from_secs(5));
+        assert_eq!(policy.delay(0, None), Duration::from_millis(100));
+        assert_eq!(policy.delay(1, None), Duration::from_millis(200));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(350));
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(60))),
+            Duration::from_secs(5)
+        );
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,86 @@
+// Clock and timers of the client: std and tokio natively, the browser's on
+// wasm32 where neither works
+
+use std::future::Future;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Futures handed between callers, only required to be Send where threads exist
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) type MaybeSendFuture<T> = futures::future::BoxFuture<'static, T>;
+#[cfg(target_arch = "wasm32")]
+pub(crate) type MaybeSendFuture<T> = futures::future::LocalBoxFuture<'static, T>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+#[cfg(target_arch = "wasm32")]
+pub(crate) trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+pub(crate) fn boxed<F>(future: F) -> MaybeSendFuture<F::Output>
+where
+    F: Future + MaybeSend + 'static,
+{
+    Box::pin(future)
+}
+
+// Run `future` in the background on the current runtime, or the browser's event loop
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + MaybeSend + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::spawn(future);
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+// Output of the blocking `task`, run off the async threads natively and in
+// place on wasm32, which has no threads to move it to
+pub(crate) async fn blocking<T, F>(task: F) -> Option<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    return tokio::task::spawn_blocking(task).await.ok();
+    #[cfg(target_arch = "wasm32")]
+    Some(task())
+}
+
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::sleep(duration).await;
+}
+
+// Output of `future`, or None when `duration` elapses first
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return tokio::time::timeout(duration, future).await.ok();
+    #[cfg(target_arch = "wasm32")]
+    {
+        use futures::future::{self, Either};
+        use std::pin::pin;
+
+        match future::select(pin!(future), pin!(sleep(duration))).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+// Time elapsed since the Unix epoch, zero if the clock is set before it
+pub(crate) fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+}
@@ -0,0 +1,145 @@
+//! JSON Schema of the models, for services that receive TrueSocks data
+//! re-exported by this crate and want to validate it or generate clients in
+//! other languages.
+//!
+//! The schemas describe the JSON the models serialize to, which they also
+//! read back. The API itself is looser: it may send numbers as strings, which
+//! the models accept but never produce.
+
+use crate::commands::CommandKind;
+use crate::models::{BlacklistType, ConnectionType};
+use schemars::generate::SchemaSettings;
+use schemars::{json_schema, JsonSchema, Schema, SchemaGenerator};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Every model in one document: the parameters and result of each command
+/// under `commands`, as references into `$defs`.
+pub fn bundle() -> Value {
+    let mut generator = SchemaSettings::draft2020_12()
+        .for_serialize()
+        .into_generator();
+    let mut commands = Map::new();
+    for kind in CommandKind::ALL {
+        let params = kind.params_schema(&mut generator);
+        let result = kind.result_schema(&mut generator);
+        commands.insert(
+            kind.name().to_string(),
+            json!({ "params": params, "result": result }),
+        );
+    }
+    // Models no command returns on its own, shared by the results
+    generator.subschema_for::<crate::models::Status>();
+    generator.subschema_for::<crate::models::PurchaseKind>();
+    json!({
+        "$schema": DIALECT,
+        "title": "TrueSocks",
+        "commands": commands,
+        "$defs": generator.take_definitions(true),
+    })
+}
+
+/// Write [`bundle`] as pretty-printed JSON.
+pub fn write_bundle(mut writer: impl Write) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut writer, &bundle())?;
+    writeln!(writer)
+}
+
+// Fields written as `false` when empty, see `none_as_false`
+pub(crate) fn or_false<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    let schema = generator.subschema_for::<T>();
+    json_schema!({ "anyOf": [schema, { "const": false }] })
+}
+
+fn open_string(description: &str, known: &[&str]) -> Schema {
+    json_schema!({
+        "type": "string",
+        "description": description,
+        "examples": known,
+    })
+}
+
+impl JsonSchema for ConnectionType {
+    fn schema_name() -> Cow<'static, str> {
+        "ConnectionType".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        open_string(
+            "Connection type of a proxy, other values may be added by the API",
+            &["Mobile", "DSL", "Hosting", "Unknown", "N/A"],
+        )
+    }
+}
+
+impl JsonSchema for BlacklistType {
+    fn schema_name() -> Cow<'static, str> {
+        "BlacklistType".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        open_string(
+            "Category of a blacklist, other values may be added by the API",
+            &["Open Proxy", "Web Abuse", "Email Spam"],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+    use crate::models::{ListOnlineResult, ProxyInfo};
+
+    #[test]
+    fn test_bundle_references_resolve() {
+        let bundle = bundle();
+        let defs = bundle["$defs"].as_object().unwrap();
+        let commands = bundle["commands"].as_object().unwrap();
+        assert_eq!(commands.len(), CommandKind::ALL.len());
+        assert_eq!(
+            commands["ListOnline"]["result"]["$ref"],
+            "#/$defs/ListOnlineResult"
+        );
+
+        let text = bundle.to_string();
+        for reference in text.split("\"#/$defs/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(defs.contains_key(name), "missing definition {}", name);
+        }
+    }
+
+    #[test]
+    fn test_proxy_schema_matches_serialized_fields() {
+        let bundle = bundle();
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(3)).unwrap();
+        let mut list: ListOnlineResult = serde_json::from_value(json!({
+            "LastUpdate": 1,
+            "ProxyCount": 1,
+            "ProxyList": [],
+        }))
+        .unwrap();
+        list.proxy_list.push(proxy);
+        let value = serde_json::to_value(&list).unwrap();
+
+        for (name, value) in [
+            ("ListOnlineResult", &value),
+            ("ProxyInfo", &value["ProxyList"][0]),
+        ] {
+            let schema = &bundle["$defs"][name];
+            let properties = schema["properties"].as_object().unwrap();
+            let fields = value.as_object().unwrap();
+            for required in schema["required"].as_array().unwrap() {
+                assert!(fields.contains_key(required.as_str().unwrap()));
+            }
+            for field in fields.keys() {
+                assert!(properties.contains_key(field), "{} in {}", field, name);
+            }
+        }
+        let ip = &bundle["$defs"]["ProxyInfo"]["properties"]["IP"]["anyOf"];
+        assert_eq!(ip[1], json!({ "const": false }));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::client::TrueSocksClient;
+use crate::credits::Credits;
+use crate::models::ApiError;
+use crate::ratelimit::{RateLimit, RateLimitMode, RateLimits};
+use crate::status_codes::BUDGET_EXCEEDED;
+use secrecy::SecretString;
+use std::ops::Deref;
+use std::sync::Mutex;
+
+/// Spending cap shared by every purchase made through a scoped client.
+/// Costs are reserved before a purchase is sent and released only if the API
+/// rejects it, a purchase that timed out may still have been charged.
+#[derive(Debug)]
+pub(crate) struct BudgetGuard {
+    limit: Credits,
+    spent: Mutex<Credits>,
+}
+
+impl BudgetGuard {
+    pub(crate) fn new(limit: Credits) -> Self {
+        BudgetGuard {
+            limit,
+            spent: Mutex::new(Credits::ZERO),
+        }
+    }
+
+    // 402 when `cost` would take spending past the limit
+    pub(crate) fn reserve(&self, cost: Credits) -> Result<(), ApiError> {
+        let mut spent = self.spent.lock().unwrap();
+        match spent.checked_add(cost) {
+            Some(total) if total <= self.limit => {
+                *spent = total;
+                Ok(())
+            }
+            _ => Err(ApiError::from(BUDGET_EXCEEDED)),
+        }
+    }
+
+    pub(crate) fn release(&self, cost: Credits) {
+        *self.spent.lock().unwrap() -= cost;
+    }
+
+    pub(crate) fn spent(&self) -> Credits {
+        *self.spent.lock().unwrap()
+    }
+
+    pub(crate) fn limit(&self) -> Credits {
+        self.limit
+    }
+}
+
+/// A client bound to one tenant's API key, rate limits and budget, sharing the
+/// connection pool, hooks and tap of the client it was created from.
+///
+/// Derefs to [`TrueSocksClient`], so every command is available on it.
+pub struct ScopedClient {
+    tenant: String,
+    client: TrueSocksClient,
+}
+
+impl ScopedClient {
+    pub fn tenant(&self) -> &str {
+        &self.tenant
+    }
+
+    pub fn client(&self) -> &TrueSocksClient {
+        &self.client
+    }
+
+    // Credits spent through this client, None when it has no budget
+    pub fn budget_spent(&self) -> Option<Credits> {
+        self.client.budget().map(BudgetGuard::spent)
+    }
+
+    pub fn budget_remaining(&self) -> Option<Credits> {
+        self.client
+            .budget()
+            .map(|budget| budget.limit() - budget.spent())
+    }
+}
+
+impl Deref for ScopedClient {
+    type Target = TrueSocksClient;
+
+    fn deref(&self) -> &TrueSocksClient {
+        &self.client
+    }
+}
+
+pub struct ScopedClientBuilder<'a> {
+    parent: &'a TrueSocksClient,
+    tenant: String,
+    api_key: SecretString,
+    rate_limits: RateLimits,
+    budget: Option<Credits>,
+}
+
+impl ScopedClientBuilder<'_> {
+    /// Limit how fast this tenant sends commands, independently of other tenants.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limits.global = Some(limit);
+        self
+    }
+
+    pub fn command_rate_limit(mut self, command: impl Into<String>, limit: RateLimit) -> Self {
+        self.rate_limits.commands.insert(command.into(), limit);
+        self
+    }
+
+    pub fn rate_limit_mode(mut self, mode: RateLimitMode) -> Self {
+        self.rate_limits.mode = mode;
+        self
+    }
+
+    /// Fail purchases with status 402 once they would spend more than `limit`.
+    pub fn budget(mut self, limit: Credits) -> Self {
+        self.budget = Some(limit);
+        self
+    }
+
+    pub fn build(self) -> ScopedClient {
+        ScopedClient {
+            tenant: self.tenant,
+            client: self.parent.with_scope(
+                self.api_key,
+                self.rate_limits,
+                self.budget.map(BudgetGuard::new),
+            ),
+        }
+    }
+}
+
+impl TrueSocksClient {
+    /// Start a view of this client for another tenant. The parent's rate
+    /// limits and budget do not apply to it.
+    pub fn scoped(
+        &self,
+        tenant: impl Into<String>,
+        api_key: impl Into<SecretString>,
+    ) -> ScopedClientBuilder<'_> {
+        ScopedClientBuilder {
+            parent: self,
+            tenant: tenant.into(),
+            api_key: api_key.into(),
+            rate_limits: RateLimits::default(),
+            budget: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{proxy_info_json, serve_once};
+    use crate::models::{ProxyInfo, PurchaseKind};
+    use serde_json::json;
+
+    #[test]
+    fn test_budget_guard_reserves_and_releases() {
+        let parent = TrueSocksClient::new("parent");
+        let scoped = parent
+            .scoped("tenant-a", "tenant-key")
+            .budget(Credits(30))
+            .build();
+        assert_eq!(scoped.tenant(), "tenant-a");
+        assert_eq!(parent.budget().map(BudgetGuard::limit), None);
+
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+        proxy.rent_cost = Credits(20);
+        let cost = scoped
+            .reserve_budget(&proxy, PurchaseKind::SharedBuy)
+            .unwrap();
+        assert_eq!(scoped.budget_remaining(), Some(Credits(10)));
+        let err = scoped
+            .reserve_budget(&proxy, PurchaseKind::SharedBuy)
+            .unwrap_err();
+        assert_eq!(err.code(), 402);
+
+        scoped.release_budget(cost);
+        assert_eq!(scoped.budget_spent(), Some(Credits::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_budget_kept_on_transport_failures() {
+        let proxy: ProxyInfo = serde_json::from_value(proxy_info_json(1)).unwrap();
+
+        // Nothing listens there, the purchase may still have reached the API
+        // through a proxy or load balancer, so its cost stays reserved
+        let parent = TrueSocksClient::builder("parent")
+            .base_url("http://127.0.0.1:1/")
+            .build();
+        let scoped = parent.scoped("tenant-a", "key").budget(Credits(30)).build();
+        let err = scoped
+            .purchase(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), 418);
+        assert_eq!(scoped.budget_spent(), Some(Credits(10)));
+
+        // A rejection by the API releases it
+        let rejected = json!({"status": {"code": 3, "message": "Proxy offline"}, "result": null});
+        let (url, _) = serve_once(rejected);
+        let parent = TrueSocksClient::builder("parent").base_url(url).build();
+        let scoped = parent.scoped("tenant-a", "key").budget(Credits(30)).build();
+        let err = scoped
+            .purchase(&proxy, PurchaseKind::SharedBuy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), 3);
+        assert_eq!(scoped.budget_spent(), Some(Credits::ZERO));
+    }
+}
@@ -0,0 +1,259 @@
+use crate::filter::{preference_for, ProxyFilter};
+use crate::models::{ListOnlineResult, ProxyInfo};
+
+/// Weighted score of a proxy between 0 and 1, higher is better.
+///
+/// Each metric is mapped to 0..1 on its own (`scale / (scale + value)` for
+/// ping, blacklist count and cost, `value / (value + scale)` for speed, a
+/// percentage for uptime) and the results are averaged by weight. A weight of
+/// zero ignores the metric. Country preferences, when set, score the weight
+/// of the matching preference relative to the strongest one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyScorer {
+    pub ping_weight: f64,
+    pub speed_weight: f64,
+    pub uptime_weight: f64,
+    pub blacklist_weight: f64,
+    pub cost_weight: f64,
+    pub country_weight: f64,
+    // Country codes with the weight of the preference, see `ProxyFilter::prefer_country`
+    pub preferred_country_codes: Vec<(String, f64)>,
+    // Ping in ms scoring 0.5
+    pub ping_scale: f64,
+    // Speed in bytes per second scoring 0.5
+    pub speed_scale: f64,
+    // Shared purchase cost in credits scoring 0.5
+    pub cost_scale: f64,
+}
+
+impl Default for ProxyScorer {
+    fn default() -> Self {
+        ProxyScorer {
+            ping_weight: 1.0,
+            speed_weight: 1.0,
+            uptime_weight: 1.0,
+            blacklist_weight: 1.0,
+            cost_weight: 1.0,
+            country_weight: 1.0,
+            preferred_country_codes: Vec::new(),
+            ping_scale: 150.0,
+            speed_scale: 1024.0 * 1024.0,
+            cost_scale: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RankedProxy<'a> {
+    pub proxy: &'a ProxyInfo,
+    pub score: f64,
+}
+
+impl ProxyScorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ping(mut self, weight: f64) -> Self {
+        self.ping_weight = weight;
+        self
+    }
+
+    pub fn speed(mut self, weight: f64) -> Self {
+        self.speed_weight = weight;
+        self
+    }
+
+    pub fn uptime(mut self, weight: f64) -> Self {
+        self.uptime_weight = weight;
+        self
+    }
+
+    pub fn blacklist(mut self, weight: f64) -> Self {
+        self.blacklist_weight = weight;
+        self
+    }
+
+    pub fn cost(mut self, weight: f64) -> Self {
+        self.cost_weight = weight;
+        self
+    }
+
+    pub fn country(mut self, weight: f64) -> Self {
+        self.country_weight = weight;
+        self
+    }
+
+    /// Rank by the country preferences of `filter`.
+    pub fn country_preferences(mut self, filter: &ProxyFilter) -> Self {
+        self.preferred_country_codes = filter.preferred_country_codes.clone();
+        self
+    }
+
+    // Preference of the proxy's country relative to the strongest one, None without preferences
+    fn country_score(&self, proxy: &ProxyInfo) -> Option<f64> {
+        let strongest = self
+            .preferred_country_codes
+            .iter()
+            .map(|(_, weight)| *weight)
+            .fold(0.0, f64::max);
+        if strongest <= 0.0 {
+            return None;
+        }
+        let preference = preference_for(&self.preferred_country_codes, &proxy.country_code);
+        Some(preference / strongest)
+    }
+
+    pub fn score(&self, proxy: &ProxyInfo) -> f64 {
+        let blacklist_count = proxy.blacklist.as_ref().map_or(0, Vec::len) as f64;
+        let country = self.country_score(proxy);
+        let components = [
+            (self.ping_weight, decreasing(proxy.ping, self.ping_scale)),
+            (
+                self.speed_weight,
+                increasing(proxy.speed as f64, self.speed_scale),
+            ),
+            (
+                self.uptime_weight,
+                (proxy.uptime_quality as f64 / 100.0).clamp(0.0, 1.0),
+            ),
+            (self.blacklist_weight, decreasing(blacklist_count, 1.0)),
+            (
+                self.cost_weight,
+                decreasing(proxy.rent_cost.amount() as f64, self.cost_scale),
+            ),
+            (
+                if country.is_some() {
+                    self.country_weight
+                } else {
+                    0.0
+                },
+                country.unwrap_or(0.0),
+            ),
+        ];
+        let total_weight: f64 = components.iter().map(|(weight, _)| weight.max(0.0)).sum();
+        if total_weight == 0.0 {
+            return 0.0;
+        }
+        components
+            .iter()
+            .map(|(weight, value)| weight.max(0.0) * value)
+            .sum::<f64>()
+            / total_weight
+    }
+
+    /// Score every proxy and sort best-first, by ProxyID at equal score.
+    pub fn rank<'a>(
+        &self,
+        proxies: impl IntoIterator<Item = &'a ProxyInfo>,
+    ) -> Vec<RankedProxy<'a>> {
+        let mut ranked: Vec<RankedProxy<'a>> = proxies
+            .into_iter()
+            .map(|proxy| RankedProxy {
+                proxy,
+                score: self.score(proxy),
+            })
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(a.proxy.proxy_id.cmp(&b.proxy.proxy_id))
+        });
+        ranked
+    }
+}
+
+fn decreasing(value: f64, scale: f64) -> f64 {
+    if value <= 0.0 {
+        1.0
+    } else {
+        scale / (scale + value)
+    }
+}
+
+fn increasing(value: f64, scale: f64) -> f64 {
+    if value <= 0.0 {
+        0.0
+    } else {
+        value / (value + scale)
+    }
+}
+
+impl ListOnlineResult {
+    pub fn rank_by(&self, scorer: &ProxyScorer) -> Vec<RankedProxy<'_>> {
+        scorer.rank(&self.proxy_list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::proxy_info_json;
+
+    fn proxy(proxy_id: u32, ping: f64, speed: u32) -> ProxyInfo {
+        let mut proxy: ProxyInfo = serde_json::from_value(proxy_info_json(proxy_id)).unwrap();
+        proxy.ping = ping;
+        proxy.speed = speed;
+        proxy
+    }
+
+    #[test]
+    fn test_rank_by_weights() {
+        let list = ListOnlineResult {
+            last_update: 0,
+            proxy_count: 3,
+            proxy_list: vec![
+                proxy(1, 300.0, 4_000_000),
+                proxy(2, 40.0, 100_000),
+                proxy(3, 40.0, 100_000),
+            ],
+            duplicates_removed: 0,
+            extra: Default::default(),
+        };
+        let by_ping = ProxyScorer::new().speed(0.0);
+        let ids: Vec<u32> = list
+            .rank_by(&by_ping)
+            .iter()
+            .map(|ranked| ranked.proxy.proxy_id)
+            .collect();
+        assert_eq!(ids, vec![2, 3, 1]);
+
+        let by_speed = ProxyScorer::new().ping(0.0);
+        assert_eq!(list.rank_by(&by_speed)[0].proxy.proxy_id, 1);
+    }
+
+    #[test]
+    fn test_country_preferences() {
+        let mut gb = proxy(2, 100.0, 100_000);
+        gb.country_code = "GB".to_string();
+        let us = proxy(1, 100.0, 100_000);
+        let filter = ProxyFilter::new()
+            .prefer_country("GB", 3.0)
+            .prefer_country("US", 1.0);
+        let scorer = ProxyScorer::new().country_preferences(&filter);
+        let ranked = scorer.rank([&us, &gb]);
+        assert_eq!(ranked[0].proxy.proxy_id, 2);
+        assert_eq!(
+            ProxyScorer::new().rank([&us, &gb])[0].proxy.proxy_id,
+            1,
+            "no preference without preferred countries"
+        );
+    }
+
+    #[test]
+    fn test_score_is_bounded() {
+        let scorer = ProxyScorer::default();
+        let score = scorer.score(&proxy(1, 0.0, u32::MAX));
+        assert!((0.0..=1.0).contains(&score));
+        assert_eq!(
+            ProxyScorer::new()
+                .ping(0.0)
+                .speed(0.0)
+                .uptime(0.0)
+                .blacklist(0.0)
+                .cost(0.0)
+                .score(&proxy(1, 1.0, 1)),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Compact binary snapshots of the online list, so short-lived processes can
+//! reuse a recent list instead of downloading it again.
+//!
+//! A snapshot file starts with a magic number and a format version, followed
+//! by the postcard encoding of the save time and the list.
+
+use crate::credits::Credits;
+use crate::models::{BlacklistInfo, BlacklistType, ConnectionType, ListOnlineResult, ProxyInfo};
+use crate::state::write_atomic;
+use crate::unix_now;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+const MAGIC: &[u8; 4] = b"TSOL";
+// Bumped whenever the encoded layout changes, older files are rejected
+const FORMAT_VERSION: u8 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    // Not a snapshot file, or one written by an incompatible version
+    Format(String),
+    Encoding(postcard::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot I/O failed: {}", err),
+            SnapshotError::Format(reason) => write!(f, "not a usable snapshot: {}", reason),
+            SnapshotError::Encoding(err) => write!(f, "snapshot encoding failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<postcard::Error> for SnapshotError {
+    fn from(err: postcard::Error) -> Self {
+        SnapshotError::Encoding(err)
+    }
+}
+
+/// A list read back from a snapshot file.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    // Unix timestamp in seconds of the save
+    pub saved_at: u64,
+    pub list: ListOnlineResult,
+}
+
+impl Snapshot {
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.saved_at))
+    }
+
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        self.age() <= max_age
+    }
+}
+
+/// Write `list` to `path`, replacing any previous snapshot.
+pub fn save(list: &ListOnlineResult, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+    let encoded = postcard::to_stdvec(&EncodedList::from((unix_now(), list)))?;
+    write_atomic(path.as_ref(), |writer| {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[FORMAT_VERSION])?;
+        writer.write_all(&encoded)
+    })?;
+    Ok(())
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+    let bytes = fs::read(path)?;
+    let encoded = match bytes.split_at_checked(MAGIC.len() + 1) {
+        Some((header, encoded)) if header.starts_with(MAGIC) => {
+            let version = header[MAGIC.len()];
+            if version != FORMAT_VERSION {
+                return Err(SnapshotError::Format(format!(
+                    "format version {}, expected {}",
+                    version, FORMAT_VERSION
+                )));
+            }
+            encoded
+        }
+        _ => return Err(SnapshotError::Format("missing snapshot header".to_string())),
+    };
+    let list: EncodedList = postcard::from_bytes(encoded)?;
+    Ok(list.into())
+}
+
+/// The list of the snapshot at `path` if it is at most `max_age` old. A
+/// missing, stale or unreadable snapshot gives None, so callers fall back to
+/// downloading the list.
+pub fn load_fresh(path: impl AsRef<Path>, max_age: Duration) -> Option<ListOnlineResult> {
+    load(path)
+        .ok()
+        .filter(|snapshot| snapshot.is_fresh(max_age))
+        .map(|snapshot| snapshot.list)
+}
+
+// Postcard cannot encode the self-describing parts of the models (flattened
+// unknown fields, lenient numbers), so they are mirrored here with unknown
+// fields kept as JSON text
+#[derive(Serialize, Deserialize)]
+struct EncodedList {
+    saved_at: u64,
+    last_update: u64,
+    proxy_count: u32,
+    duplicates_removed: u64,
+    extra: String,
+    proxies: Vec<EncodedProxy>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedProxy {
+    proxy_id: u32,
+    rent_cost: u32,
+    private_rent_cost: u32,
+    is_fresh: bool,
+    ip: Option<String>,
+    hostname: String,
+    isp: String,
+    country_code: String,
+    country: String,
+    region: String,
+    city: String,
+    zip_code: Option<String>,
+    timezone: String,
+    connection_type: String,
+    ping: f64,
+    speed: u32,
+    uptime_quality: u32,
+    blacklist: Option<Vec<EncodedBlacklist>>,
+    distance: Option<f64>,
+    extra: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedBlacklist {
+    id: String,
+    name: String,
+    blacklist_type: String,
+    desc: String,
+    link: Option<String>,
+}
+
+fn extra_to_json(extra: &Map<String, Value>) -> String {
+    if extra.is_empty() {
+        String::new()
+    } else {
+        Value::Object(extra.clone()).to_string()
+    }
+}
+
+fn extra_from_json(extra: &str) -> Map<String, Value> {
+    serde_json::from_str(extra).unwrap_or_default()
+}
+
+impl From<(u64, &ListOnlineResult)> for EncodedList {
+    fn from((saved_at, list): (u64, &ListOnlineResult)) -> Self {
+        EncodedList {
+            saved_at,
+            last_update: list.last_update,
+            proxy_count: list.proxy_count,
+            duplicates_removed: list.duplicates_removed as u64,
+            extra: extra_to_json(&list.extra),
+            proxies: list.proxy_list.iter().map(EncodedProxy::from).collect(),
+        }
+    }
+}
+
+impl From<EncodedList> for Snapshot {
+    fn from(list: EncodedList) -> Self {
+        Snapshot {
+            saved_at: list.saved_at,
+            list: ListOnlineResult {
+                last_update: list.last_update,
+                proxy_count: list.proxy_count,
+                proxy_list: list.proxies.into_iter().map(ProxyInfo::from).collect(),
+                duplicates_removed: list.duplicates_removed as usize,
+                extra: extra_from_json(&list.extra),
+            },
+        }
+    }
+}
+
+impl From<&ProxyInfo> for EncodedProxy {
+    fn from(proxy: &ProxyInfo) -> Self {
+        EncodedProxy {
+            proxy_id: proxy.proxy_id,
+            rent_cost: proxy.rent_cost.amount(),
+            private_rent_cost: proxy.private_rent_cost.amount(),
+            is_fresh: proxy.is_fresh,
+            ip: proxy.ip.clone(),
+            hostname: proxy.hostname.clone(),
+            isp: proxy.isp.clone(),
+            country_code: proxy.country_code.clone(),
+            country: proxy.country.clone(),
+            region: proxy.region.clone(),
+            city: proxy.city.clone(),
+            zip_code: proxy.zip_code.clone(),
+            timezone: proxy.timezone.clone(),
+            connection_type: proxy.connection_type.to_string(),
+            ping: proxy.ping,
+            speed: proxy.speed,
+            uptime_quality: proxy.uptime_quality,
+            blacklist: proxy.blacklist.as_ref().map(|listings| {
+                listings
+                    .iter()
+                    .map(|listing| EncodedBlacklist {
+                        id: listing.id.clone(),
+                        name: listing.name.clone(),
+                        blacklist_type: listing.blacklist_type.as_str().to_string(),
+                        desc: listing.desc.clone(),
+                        link: listing.link.clone(),
+                    })
+                    .collect()
+            }),
+            distance: proxy.distance,
+            extra: extra_to_json(&proxy.extra),
+        }
+    }
+}
+
+impl From<EncodedProxy> for ProxyInfo {
+    fn from(proxy: EncodedProxy) -> Self {
+        ProxyInfo {
+            proxy_id: proxy.proxy_id,
+            rent_cost: Credits::from(proxy.rent_cost),
+            private_rent_cost: Credits::from(proxy.private_rent_cost),
+            is_fresh: proxy.is_fresh,
+            ip: proxy.ip,
+            hostname: proxy.hostname,
+            isp: proxy.isp,
+            country_code: proxy.country_code,
+            country: proxy.country,
+            region: proxy.region,
+            city: proxy.city,
+            zip_code: proxy.zip_code,
+            timezone: proxy.timezone,
+            connection_type: ConnectionType::from(proxy.connection_type),
+            ping: proxy.ping,
+            speed: proxy.speed,
+            uptime_quality: proxy.uptime_quality,
+            blacklist: proxy.blacklist.map(|listings| {
+                listings
+                    .into_iter()
+                    .map(|listing| BlacklistInfo {
+                        id: listing.id,
+                        name: listing.name,
+                        blacklist_type: BlacklistType::from(listing.blacklist_type),
+                        desc: listing.desc,
+                        link: listing.link,
+                    })
+                    .collect()
+            }),
+            distance: proxy.distance,
+            extra: extra_from_json(&proxy.extra),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ok_response, proxy_info_json};
+    use serde_json::json;
+
+    fn list() -> ListOnlineResult {
+        let mut listed = proxy_info_json(2);
+        listed["Blacklist"] =
+            json!([{"ID": "sbl", "Name": "SBL", "Type": "Email Spam", "Desc": "", "Link": ""}]);
+        listed["Carrier"] = json!("Example Mobile");
+        let body = ok_response(json!({
+            "LastUpdate": 9,
+            "ProxyCount": 2,
+            "ProxyList": [proxy_info_json(1), listed],
+            "Region": "eu",
+        }));
+        serde_json::from_value(body["result"].clone()).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("truesocks-snapshot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("online.bin");
+
+        let list = list();
+        save(&list, &path).unwrap();
+        let snapshot = load(&path).unwrap();
+        assert_eq!(snapshot.list.proxy_list, list.proxy_list);
+        assert_eq!(snapshot.list.extra, list.extra);
+        assert!(snapshot.is_fresh(Duration::from_secs(60)));
+        assert!(load_fresh(&path, Duration::from_secs(60)).is_some());
+
+        // Much smaller than the JSON form
+        let size = fs::metadata(&path).unwrap().len() as usize;
+        assert!(size < serde_json::to_vec(&list).unwrap().len());
+
+        fs::write(&path, b"{}").unwrap();
+        assert!(matches!(load(&path), Err(SnapshotError::Format(_))));
+        assert!(load_fresh(dir.join("missing.bin"), Duration::MAX).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+use crate::models::ConnectInfo;
+use crate::pool::PoolError;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{lookup_host, TcpStream};
+use tokio::time::{timeout_at, Instant};
+use tokio_socks::tcp::Socks5Stream;
+
+// Connection attempts kept in flight at once by `race_connect`
+const RACE_WIDTH: usize = 2;
+
+/// Step of a connection through a proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectPhase {
+    // Looking up the proxy address
+    Resolve,
+    // Opening the TCP connection to the proxy
+    Connect,
+    // SOCKS negotiation, including the proxy connecting to the target
+    Handshake,
+}
+
+impl fmt::Display for ConnectPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectPhase::Resolve => write!(f, "resolve"),
+            ConnectPhase::Connect => write!(f, "connect"),
+            ConnectPhase::Handshake => write!(f, "handshake"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SocksError {
+    NoCandidates,
+    // No pool member could be checked out to connect through
+    Pool(PoolError),
+    Socks(tokio_socks::Error),
+    // The deadline passed during this phase
+    TimedOut(ConnectPhase),
+}
+
+impl fmt::Display for SocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocksError::NoCandidates => write!(f, "no candidate proxies to connect through"),
+            SocksError::Pool(err) => write!(f, "{}", err),
+            SocksError::Socks(err) => write!(f, "socks error: {}", err),
+            SocksError::TimedOut(phase) => write!(f, "timed out during {}", phase),
+        }
+    }
+}
+
+impl std::error::Error for SocksError {}
+
+impl From<tokio_socks::Error> for SocksError {
+    fn from(err: tokio_socks::Error) -> Self {
+        SocksError::Socks(err)
+    }
+}
+
+pub struct RaceWinner<'a> {
+    pub connect_info: &'a ConnectInfo,
+    pub stream: Socks5Stream<TcpStream>,
+}
+
+async fn within<T, F>(
+    deadline: Option<Instant>,
+    phase: ConnectPhase,
+    fut: F,
+) -> Result<T, SocksError>
+where
+    F: Future<Output = Result<T, tokio_socks::Error>>,
+{
+    let res = match deadline {
+        Some(deadline) => timeout_at(deadline, fut)
+            .await
+            .map_err(|_| SocksError::TimedOut(phase))?,
+        None => fut.await,
+    };
+    Ok(res?)
+}
+
+/// Open a tunnel to `target` through the proxy. Resolving the proxy address,
+/// connecting to it and the SOCKS handshake all have to finish before
+/// `deadline`, the error names the phase that was cut short.
+pub async fn dial(
+    connect_info: &ConnectInfo,
+    target: (&str, u16),
+    deadline: Option<Instant>,
+) -> Result<Socks5Stream<TcpStream>, SocksError> {
+    let proxy = (connect_info.connect_ip.as_str(), connect_info.connect_port);
+    let addrs: Vec<SocketAddr> = within(deadline, ConnectPhase::Resolve, async {
+        Ok(lookup_host(proxy).await?.collect())
+    })
+    .await?;
+    let socket = within(deadline, ConnectPhase::Connect, async {
+        Ok(TcpStream::connect(&addrs[..]).await?)
+    })
+    .await?;
+    within(deadline, ConnectPhase::Handshake, async {
+        match connect_info.credentials() {
+            Some((username, password)) => {
+                Socks5Stream::connect_with_password_and_socket(socket, target, username, password)
+                    .await
+            }
+            None => Socks5Stream::connect_with_socket(socket, target).await,
+        }
+    })
+    .await
+}
+
+impl ConnectInfo {
+    /// Open a SOCKS5 tunnel through this session to `target_host:target_port`,
+    /// authenticating with the session credentials. The target host is
+    /// resolved by the proxy. The stream reads and writes like a `TcpStream`.
+    pub async fn connect(
+        &self,
+        target_host: &str,
+        target_port: u16,
+    ) -> Result<Socks5Stream<TcpStream>, SocksError> {
+        dial(self, (target_host, target_port), None).await
+    }
+
+    /// [`connect`](Self::connect) giving up after `timeout`.
+    pub async fn connect_timeout(
+        &self,
+        target_host: &str,
+        target_port: u16,
+        timeout: Duration,
+    ) -> Result<Socks5Stream<TcpStream>, SocksError> {
+        dial(
+            self,
+            (target_host, target_port),
+            Some(Instant::now() + timeout),
+        )
+        .await
+    }
+}
+
+async fn attempt<'a>(
+    connect_info: &'a ConnectInfo,
+    target: (&str, u16),
+    deadline: Option<Instant>,
+) -> (&'a ConnectInfo, Result<Socks5Stream<TcpStream>, SocksError>) {
+    (connect_info, dial(connect_info, target, deadline).await)
+}
+
+/// Connect to `target` through two candidate proxies at once and keep whichever
+/// succeeds first, the slower attempt is cancelled.
+///
+/// When an attempt fails the next candidate takes its place, the error of the
+/// last failed attempt is returned if every candidate fails.
+pub async fn race_connect<'a>(
+    candidates: &'a [ConnectInfo],
+    target: (&str, u16),
+) -> Result<RaceWinner<'a>, SocksError> {
+    race_connect_until(candidates, target, None).await
+}
+
+/// `race_connect` where every attempt has to finish before `deadline`.
+pub async fn race_connect_until<'a>(
+    candidates: &'a [ConnectInfo],
+    target: (&str, u16),
+    deadline: Option<Instant>,
+) -> Result<RaceWinner<'a>, SocksError> {
+    let mut remaining = candidates.iter();
+    let mut in_flight = FuturesUnordered::new();
+    for connect_info in remaining.by_ref().take(RACE_WIDTH) {
+        in_flight.push(attempt(connect_info, target, deadline));
+    }
+
+    let mut last_error = SocksError::NoCandidates;
+    while let Some((connect_info, result)) = in_flight.next().await {
+        match result {
+            Ok(stream) => {
+                return Ok(RaceWinner {
+                    connect_info,
+                    stream,
+                })
+            }
+            Err(err) => {
+                last_error = err;
+                if let Some(next) = remaining.next() {
+                    in_flight.push(attempt(next, target, deadline));
+                }
+            }
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unreachable_proxy() -> ConnectInfo {
+        ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: 1,
+            connect_session_id: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_authenticates_with_session() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 2];
+            socket.read_exact(&mut greeting).await.unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            socket.read_exact(&mut methods).await.unwrap();
+            assert!(methods.contains(&2));
+            socket.write_all(&[5, 2]).await.unwrap();
+
+            let mut header = [0u8; 2];
+            socket.read_exact(&mut header).await.unwrap();
+            let mut username = vec![0u8; header[1] as usize];
+            socket.read_exact(&mut username).await.unwrap();
+            let password_len = socket.read_u8().await.unwrap();
+            let mut password = vec![0u8; password_len as usize];
+            socket.read_exact(&mut password).await.unwrap();
+            socket.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 5];
+            socket.read_exact(&mut request).await.unwrap();
+            let mut host = vec![0u8; request[4] as usize];
+            socket.read_exact(&mut host).await.unwrap();
+            let target_port = socket.read_u16().await.unwrap();
+            socket
+                .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            socket.write_all(b"hello").await.unwrap();
+            (
+                username,
+                password,
+                String::from_utf8(host).unwrap(),
+                target_port,
+            )
+        });
+
+        let connect_info = ConnectInfo {
+            connect_port: port,
+            connect_session_id: "session1".to_string(),
+            ..unreachable_proxy()
+        };
+        let mut stream = connect_info
+            .connect_timeout("example.com", 443, Duration::from_secs(5))
+            .await
+            .unwrap();
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hello");
+
+        let (username, password, host, target_port) = server.await.unwrap();
+        assert_eq!(username, b"session1");
+        assert_eq!(password, b"session1");
+        assert_eq!(host, "example.com");
+        assert_eq!(target_port, 443);
+    }
+
+    #[tokio::test]
+    async fn test_race_connect_without_candidates() {
+        let res = race_connect(&[], ("example.com", 80)).await;
+        assert!(matches!(res, Err(SocksError::NoCandidates)));
+    }
+
+    #[tokio::test]
+    async fn test_race_connect_all_failed() {
+        let candidates = vec![
+            unreachable_proxy(),
+            unreachable_proxy(),
+            unreachable_proxy(),
+        ];
+        let res = race_connect(&candidates, ("example.com", 80)).await;
+        assert!(matches!(res, Err(SocksError::Socks(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dial_reports_timed_out_phase() {
+        // Accepts the connection but never answers the SOCKS greeting
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let connect_info = ConnectInfo {
+            connect_port: listener.local_addr().unwrap().port(),
+            ..unreachable_proxy()
+        };
+        let deadline = Instant::now() + std::time::Duration::from_millis(200);
+        let res = dial(&connect_info, ("example.com", 80), Some(deadline)).await;
+        assert!(matches!(
+            res,
+            Err(SocksError::TimedOut(ConnectPhase::Handshake))
+        ));
+    }
+}
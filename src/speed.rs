@@ -0,0 +1,142 @@
+use crate::models::{ConnectInfo, ProxyInfo};
+use reqwest::Url;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Time allowed for the SOCKS handshake and response headers, on top of the
+// download duration
+const SETUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub enum SpeedTestError {
+    Http(reqwest::Error),
+    // The test URL answered with a non-success HTTP status
+    Status(u16),
+}
+
+impl fmt::Display for SpeedTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpeedTestError::Http(err) => write!(f, "speed test download failed: {}", err),
+            SpeedTestError::Status(status) => write!(f, "test URL answered with HTTP {}", status),
+        }
+    }
+}
+
+impl std::error::Error for SpeedTestError {}
+
+/// Throughput measured by [`speed_test`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedTestResult {
+    // Body bytes received
+    pub bytes: u64,
+    // Time from the first body byte being awaited to the last one received
+    pub elapsed: Duration,
+    // Whether the whole body arrived before the duration ran out
+    pub completed: bool,
+}
+
+impl SpeedTestResult {
+    /// Measured throughput in bytes per second, the unit of `ProxyInfo::speed`.
+    pub fn bytes_per_second(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Measured throughput as a fraction of the speed the API claims for
+    /// `proxy`, None when the API claims no speed.
+    pub fn ratio_to_claimed(&self, proxy: &ProxyInfo) -> Option<f64> {
+        (proxy.speed > 0).then(|| self.bytes_per_second() / f64::from(proxy.speed))
+    }
+}
+
+/// Download `test_url` through `connect_info` for at most `duration` and
+/// measure the throughput. Pick a test file large enough to outlast the
+/// duration, or the result is skewed by connection setup.
+pub async fn speed_test(
+    connect_info: &ConnectInfo,
+    test_url: &Url,
+    duration: Duration,
+) -> Result<SpeedTestResult, SpeedTestError> {
+    let client = connect_info
+        .http_client(duration + SETUP_TIMEOUT)
+        .map_err(SpeedTestError::Http)?;
+    let mut response = client
+        .get(test_url.clone())
+        .send()
+        .await
+        .map_err(SpeedTestError::Http)?;
+    if !response.status().is_success() {
+        return Err(SpeedTestError::Status(response.status().as_u16()));
+    }
+
+    let started = Instant::now();
+    let deadline = tokio::time::Instant::from_std(started + duration);
+    let mut bytes = 0;
+    let completed = loop {
+        match tokio::time::timeout_at(deadline, response.chunk()).await {
+            Ok(Ok(Some(chunk))) => bytes += chunk.len() as u64,
+            Ok(Ok(None)) => break true,
+            Ok(Err(err)) => return Err(SpeedTestError::Http(err)),
+            Err(_) => break false,
+        }
+    };
+    Ok(SpeedTestResult {
+        bytes,
+        elapsed: started.elapsed(),
+        completed,
+    })
+}
+
+impl ConnectInfo {
+    /// See [`speed_test`].
+    pub async fn speed_test(
+        &self,
+        test_url: &Url,
+        duration: Duration,
+    ) -> Result<SpeedTestResult, SpeedTestError> {
+        speed_test(self, test_url, duration).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{list_info, serve_socks};
+
+    #[test]
+    fn test_ratio_to_claimed() {
+        let result = SpeedTestResult {
+            bytes: 4096,
+            elapsed: Duration::from_secs(2),
+            completed: true,
+        };
+        let mut proxy = list_info(1).proxy_info;
+        assert_eq!(result.bytes_per_second(), 2048.0);
+        proxy.speed = 4096;
+        assert_eq!(result.ratio_to_claimed(&proxy), Some(0.5));
+        proxy.speed = 0;
+        assert_eq!(result.ratio_to_claimed(&proxy), None);
+    }
+
+    #[tokio::test]
+    async fn test_speed_test_downloads_through_proxy() {
+        let (port, handle) = serve_socks(vec![7; 256 * 1024]);
+        let connect_info = ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: port,
+            connect_session_id: "session".to_string(),
+        };
+        let url = Url::parse("http://speed.test/file.bin").unwrap();
+        let result = connect_info
+            .speed_test(&url, Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert!(result.completed);
+        assert_eq!(result.bytes, 256 * 1024);
+        assert!(result.bytes_per_second() > 0.0);
+        assert_eq!(handle.join().unwrap(), "session");
+    }
+}
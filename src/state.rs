@@ -0,0 +1,57 @@
+//! On-disk state of [`ProxyPool`](crate::pool::ProxyPool) and
+//! [`OnlineCache`](crate::cache::OnlineCache), so a restarted process picks up
+//! the proxies it already owns instead of buying them again.
+
+use crate::models::{ListInfo, ListOnlineResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// A pool member as saved by `ProxyPool::save_state`. Checkouts are not saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedMember {
+    pub entry: ListInfo,
+    pub latency_ms: Option<u64>,
+    pub unhealthy: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedPool {
+    // Unix timestamp in seconds, remaining times are counted down from it on load
+    pub saved_at: u64,
+    pub members: Vec<SavedMember>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedOnlineList {
+    // Unix timestamp in seconds of the fetch, the TTL keeps running across restarts
+    pub fetched_at: u64,
+    pub list: ListOnlineResult,
+}
+
+// Written next to `path` first and renamed over it, so a crash mid-write keeps
+// the previous state
+pub(crate) fn write_state<T: Serialize>(path: &Path, state: &T) -> io::Result<()> {
+    write_atomic(path, |writer| Ok(serde_json::to_writer(writer, state)?))
+}
+
+// Fill a temporary file next to `path` with `write` and rename it over `path`
+pub(crate) fn write_atomic(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&partial)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&partial, path)
+}
+
+pub(crate) fn read_state<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
@@ -0,0 +1,111 @@
+use crate::credits::Credits;
+use serde::{Deserialize, Serialize};
+
+/// Result of one use of a pool member, with the traffic if the caller
+/// measured it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub success: bool,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl Outcome {
+    pub fn success() -> Self {
+        Outcome {
+            success: true,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    pub fn failure() -> Self {
+        Outcome {
+            success: false,
+            ..Outcome::success()
+        }
+    }
+
+    pub fn bytes(mut self, sent: u64, received: u64) -> Self {
+        self.bytes_sent = sent;
+        self.bytes_received = received;
+        self
+    }
+}
+
+/// Counters kept for each pool member while it is in the pool.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageCounters {
+    // Checkouts of any kind, leases and assignments included
+    pub checkouts: u64,
+    pub leases: u64,
+    // Leases ended with `Lease::fail`
+    pub failed_leases: u64,
+    // Outcomes given to `report_outcome`
+    pub successes: u64,
+    pub failures: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    // Unix timestamp of the last checkout
+    pub last_used: Option<u64>,
+}
+
+impl UsageCounters {
+    pub(crate) fn record(&mut self, outcome: Outcome) {
+        if outcome.success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        self.bytes_sent += outcome.bytes_sent;
+        self.bytes_received += outcome.bytes_received;
+    }
+
+    pub fn outcomes(&self) -> u64 {
+        self.successes + self.failures
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes_sent + self.bytes_received
+    }
+
+    /// Share of reported outcomes that failed, None before any was reported.
+    pub fn failure_rate(&self) -> Option<f64> {
+        let outcomes = self.outcomes();
+        (outcomes > 0).then(|| self.failures as f64 / outcomes as f64)
+    }
+
+    /// Whether at least `min_outcomes` were reported and more than
+    /// `max_failure_rate` of them failed, a candidate for retirement.
+    pub fn is_failing(&self, min_outcomes: u64, max_failure_rate: f64) -> bool {
+        self.outcomes() >= min_outcomes
+            && self
+                .failure_rate()
+                .is_some_and(|rate| rate > max_failure_rate)
+    }
+}
+
+/// Usage of one pool member, see `ProxyPool::stats`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProxyStats {
+    pub history_id: u64,
+    pub proxy_id: u32,
+    // Current price of the proxy for the way it was bought
+    pub cost: Credits,
+    pub usage: UsageCounters,
+}
+
+impl ProxyStats {
+    /// Successful uses per credit, None for a free proxy.
+    pub fn successes_per_credit(&self) -> Option<f64> {
+        self.per_credit(self.usage.successes)
+    }
+
+    pub fn bytes_per_credit(&self) -> Option<f64> {
+        self.per_credit(self.usage.bytes())
+    }
+
+    fn per_credit(&self, value: u64) -> Option<f64> {
+        (!self.cost.is_zero()).then(|| value as f64 / self.cost.amount() as f64)
+    }
+}
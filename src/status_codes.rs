@@ -0,0 +1,74 @@
+//! Known values of [`ApiError::code`](crate::models::ApiError::code) and of the
+//! `status.code` field of API responses.
+//!
+//! Codes reported by the API itself are passed through unchanged, so the list
+//! is not exhaustive. Codes in the 4xx range are also produced by the client
+//! for failures that never reached the API, as documented on each constant.
+
+/// The command succeeded.
+pub const OK: u16 = 0;
+
+/// The API accepted the command but reported a warning. Treated as success with
+/// a [`Warning`](crate::models::Warning) unless configured otherwise through
+/// [`TrueSocksClientBuilder::status_handling`](crate::client::TrueSocksClientBuilder::status_handling).
+pub const ACCEPTED_WITH_WARNING: u16 = 209;
+
+/// Invalid input rejected before sending: an unparsable base URL or header
+/// name, a proxy that does not offer the requested purchase, a HistoryID out of
+/// range for the API.
+pub const BAD_REQUEST: u16 = 400;
+
+/// A purchase would take spending past the budget of a scoped client, or past
+/// the account balance when purchases are validated first.
+pub const BUDGET_EXCEEDED: u16 = 402;
+
+/// The requested history entry or proxy was not found.
+pub const NOT_FOUND: u16 = 404;
+
+/// The proxy is already held by an active purchase.
+pub const CONFLICT: u16 = 409;
+
+/// The request did not complete within the configured timeout.
+pub const TIMEOUT: u16 = 408;
+
+/// The request could not be sent or its response could not be read or decoded.
+pub const TRANSPORT: u16 = 418;
+
+/// A client side rate limit was hit in
+/// [`RateLimitMode::Reject`](crate::ratelimit::RateLimitMode::Reject) mode.
+pub const RATE_LIMITED: u16 = 429;
+
+/// The operation was cancelled through its cancellation token.
+pub const CANCELLED: u16 = 499;
+
+/// Whether a failure with `code` may succeed when the command is sent again.
+/// Purchases that failed with [`TIMEOUT`] or [`TRANSPORT`] can still have gone
+/// through, check the history before retrying them.
+pub fn is_retryable(code: u64) -> bool {
+    code == TIMEOUT as u64
+        || code == TRANSPORT as u64
+        || code == RATE_LIMITED as u64
+        || (500..600).contains(&code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ApiError, DecodeError};
+
+    #[test]
+    fn test_retryable_codes() {
+        assert!(ApiError::from(TIMEOUT).is_retryable());
+        assert!(ApiError::from(503_u16).is_retryable());
+        assert!(!ApiError::from(BUDGET_EXCEEDED).is_retryable());
+        let decode = ApiError::DecodeError(Box::new(DecodeError {
+            command: "Ping".to_string(),
+            path: String::new(),
+            message: String::new(),
+            body: String::new(),
+            truncated: false,
+        }));
+        assert_eq!(decode.code(), TRANSPORT as u64);
+        assert!(!decode.is_retryable());
+    }
+}
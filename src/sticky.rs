@@ -0,0 +1,78 @@
+// Points each member occupies on the ring, more spread keys more evenly
+const REPLICAS: u32 = 64;
+
+// FNV-1a with the murmur3 finalizer spreading similar inputs over the ring,
+// stable across processes so keys map the same way after a restart
+fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Consistent hash ring over pool members. Adding or removing a member only
+/// moves the keys that land next to its points.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HashRing {
+    // (point, HistoryID) sorted by point
+    points: Vec<(u64, u64)>,
+}
+
+impl HashRing {
+    pub(crate) fn new(history_ids: impl IntoIterator<Item = u64>) -> Self {
+        let mut points: Vec<(u64, u64)> = history_ids
+            .into_iter()
+            .flat_map(|history_id| {
+                (0..REPLICAS).map(move |replica| {
+                    let point = stable_hash(format!("{}-{}", history_id, replica).as_bytes());
+                    (point, history_id)
+                })
+            })
+            .collect();
+        points.sort_unstable();
+        HashRing { points }
+    }
+
+    /// Member owning `key`, the first point at or after the key's hash.
+    pub(crate) fn get(&self, key: &str) -> Option<u64> {
+        let hash = stable_hash(key.as_bytes());
+        let position = self.points.partition_point(|(point, _)| *point < hash);
+        self.points
+            .get(position)
+            .or_else(|| self.points.first())
+            .map(|(_, history_id)| *history_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_moves_few_keys() {
+        let keys: Vec<String> = (0..1000)
+            .map(|index| format!("account-{}", index))
+            .collect();
+        let before = HashRing::new(1..=10);
+        let after = HashRing::new(1..=11);
+        let moved = keys
+            .iter()
+            .filter(|key| before.get(key) != after.get(key))
+            .count();
+        // About a tenth of the keys move to the new member, none elsewhere
+        assert!(moved < 200, "{}", moved);
+        assert!(keys
+            .iter()
+            .filter(|key| before.get(key) != after.get(key))
+            .all(|key| after.get(key) == Some(11)));
+        assert_eq!(
+            HashRing::new(1..=10).get("account-1"),
+            before.get("account-1")
+        );
+        assert_eq!(HashRing::default().get("account-1"), None);
+    }
+}
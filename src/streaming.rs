@@ -0,0 +1,430 @@
+use crate::backend::HttpResponse;
+use crate::client::{decode_response, TrueSocksClient};
+use crate::hooks::run_hooks;
+use crate::models::{
+    ApiError, DecodeError, ListOnlineResult, ProxyInfo, RawListOnlineResult, Status, Warning,
+};
+use crate::runtime::Instant;
+use futures::stream::{self, StreamExt};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::atomic::AtomicU32;
+use std::sync::Arc;
+
+const COMMAND: &str = "ListOnline";
+
+/// Proxies of `ListOnline`, decoded one at a time as the body arrives.
+#[cfg(not(target_arch = "wasm32"))]
+pub type ProxyInfoStream = stream::BoxStream<'static, Result<ProxyInfo, ApiError>>;
+#[cfg(target_arch = "wasm32")]
+pub type ProxyInfoStream = stream::LocalBoxStream<'static, Result<ProxyInfo, ApiError>>;
+
+enum Frame {
+    // Last key read in the object and whether the next string is a key
+    Object { key: Vec<u8>, expect_key: bool },
+    Array,
+}
+
+// Splits a `ListOnline` body fed in chunks into the raw `result.ProxyList`
+// items and a skeleton: the rest of the body with an empty `ProxyList`
+#[derive(Default)]
+pub(crate) struct ProxyListScanner {
+    stack: Vec<Frame>,
+    in_string: bool,
+    escaped: bool,
+    reading_key: bool,
+    key: Vec<u8>,
+    // Stack depth of the `ProxyList` array while the scanner is inside it
+    list_depth: Option<usize>,
+    item: Vec<u8>,
+    // Nesting inside the current item, 0 outside items
+    item_depth: usize,
+    skeleton: Vec<u8>,
+}
+
+impl ProxyListScanner {
+    // Items completed by `bytes`, in order
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut items = Vec::new();
+        for &byte in bytes {
+            if self.item_depth > 0 {
+                self.item.push(byte);
+                if self.scan_string(byte) {
+                    continue;
+                }
+                match byte {
+                    b'"' => self.in_string = true,
+                    b'{' | b'[' => self.item_depth += 1,
+                    b'}' | b']' => {
+                        self.item_depth -= 1;
+                        if self.item_depth == 0 {
+                            items.push(mem::take(&mut self.item));
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            self.skeleton.push(byte);
+            if self.scan_string(byte) {
+                if self.reading_key {
+                    if self.in_string {
+                        self.key.push(byte);
+                    } else {
+                        self.reading_key = false;
+                        if let Some(Frame::Object { key, .. }) = self.stack.last_mut() {
+                            *key = mem::take(&mut self.key);
+                        }
+                    }
+                }
+                continue;
+            }
+            match byte {
+                b'"' => {
+                    self.in_string = true;
+                    self.reading_key = matches!(
+                        self.stack.last(),
+                        Some(Frame::Object {
+                            expect_key: true,
+                            ..
+                        })
+                    );
+                    self.key.clear();
+                }
+                b'{' if self.in_list() => {
+                    self.skeleton.pop();
+                    self.item.push(byte);
+                    self.item_depth = 1;
+                }
+                b'{' => self.stack.push(Frame::Object {
+                    key: Vec::new(),
+                    expect_key: true,
+                }),
+                b'[' => {
+                    let proxy_list = self.at_proxy_list();
+                    self.stack.push(Frame::Array);
+                    if proxy_list {
+                        self.list_depth = Some(self.stack.len());
+                    }
+                }
+                b'}' | b']' => {
+                    if self.in_list() {
+                        self.list_depth = None;
+                    }
+                    self.stack.pop();
+                }
+                b':' => {
+                    if let Some(Frame::Object { expect_key, .. }) = self.stack.last_mut() {
+                        *expect_key = false;
+                    }
+                }
+                // Separators between items are dropped with the items
+                b',' if self.in_list() => {
+                    self.skeleton.pop();
+                }
+                b',' => {
+                    if let Some(Frame::Object { expect_key, .. }) = self.stack.last_mut() {
+                        *expect_key = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        items
+    }
+
+    pub(crate) fn into_skeleton(self) -> Vec<u8> {
+        self.skeleton
+    }
+
+    // Whether `byte` is part of a string, updating the string state
+    fn scan_string(&mut self, byte: u8) -> bool {
+        if !self.in_string {
+            return false;
+        }
+        if self.escaped {
+            self.escaped = false;
+        } else if byte == b'\\' {
+            self.escaped = true;
+        } else if byte == b'"' {
+            self.in_string = false;
+        }
+        true
+    }
+
+    fn in_list(&self) -> bool {
+        self.list_depth == Some(self.stack.len())
+    }
+
+    // Whether an array opened now is `result.ProxyList`
+    fn at_proxy_list(&self) -> bool {
+        let is_key = |frame: &Frame, name: &[u8]| match frame {
+            Frame::Object { key, .. } => key.as_slice() == name,
+            Frame::Array => false,
+        };
+        self.list_depth.is_none()
+            && self.stack.len() == 2
+            && is_key(&self.stack[0], b"result")
+            && is_key(&self.stack[1], b"ProxyList")
+    }
+}
+
+fn decode_item(index: usize, item: &[u8]) -> Result<ProxyInfo, ApiError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(item);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = format!("result.ProxyList[{}].{}", index, err.path());
+        ApiError::from(DecodeError::new(
+            COMMAND,
+            path,
+            err.into_inner().to_string(),
+            &String::from_utf8_lossy(item),
+        ))
+    })
+}
+
+// A `ListOnline` response whose body is being read
+struct BodyReader {
+    client: TrueSocksClient,
+    started: Instant,
+    response: HttpResponse,
+    scanner: ProxyListScanner,
+}
+
+impl BodyReader {
+    async fn open(client: &TrueSocksClient) -> Result<Self, ApiError> {
+        let started = Instant::now();
+        run_hooks(client.hooks(), COMMAND, |hook| {
+            hook.on_request(COMMAND, &[])
+        });
+        let attempts = Arc::new(AtomicU32::new(0));
+        match client
+            .send_request(
+                COMMAND,
+                Value::Object(Default::default()),
+                attempts,
+                &mut None,
+            )
+            .await
+        {
+            Ok(response) => Ok(BodyReader {
+                client: client.clone(),
+                started,
+                response,
+                scanner: ProxyListScanner::default(),
+            }),
+            Err(err) => {
+                client.report(COMMAND, Vec::new(), started, Err(&err)).await;
+                Err(err)
+            }
+        }
+    }
+
+    // Raw items of the next chunk, None at the end of the body
+    async fn next_items(&mut self) -> Result<Option<Vec<Vec<u8>>>, ApiError> {
+        match self.response.body.next().await {
+            Some(Ok(chunk)) => Ok(Some(self.scanner.feed(&chunk))),
+            None => Ok(None),
+            Some(Err(err)) => Err(self.fail(ApiError::from(err)).await),
+        }
+    }
+
+    async fn fail(&mut self, err: ApiError) -> ApiError {
+        self.client
+            .report(COMMAND, Vec::new(), self.started, Err(&err))
+            .await;
+        err
+    }
+
+    // Checks the status once the body is read, returns the skeleton
+    async fn finish(self) -> Result<(Status, Option<Warning>, Value), ApiError> {
+        let skeleton = self.scanner.into_skeleton();
+        let decoded = serde_json::from_slice::<Value>(&skeleton)
+            .map_err(|err| {
+                ApiError::from(DecodeError::new(
+                    COMMAND,
+                    String::new(),
+                    err.to_string(),
+                    &String::from_utf8_lossy(&skeleton),
+                ))
+            })
+            .and_then(|value| {
+                let (status, warning) = self
+                    .client
+                    .accept_status(COMMAND, value["status"].clone())?;
+                Ok((status, warning, value))
+            });
+        let outcome = match &decoded {
+            Ok((status, warning, _)) => Ok((status, warning.as_ref())),
+            Err(err) => Err(err),
+        };
+        self.client
+            .report(COMMAND, Vec::new(), self.started, outcome)
+            .await;
+        decoded
+    }
+}
+
+enum StreamState {
+    Reading {
+        reader: Box<BodyReader>,
+        pending: VecDeque<Vec<u8>>,
+        index: usize,
+    },
+    Done,
+}
+
+impl TrueSocksClient {
+    /// `ListOnline` as a stream of proxies, each decoded as soon as its bytes
+    /// arrive, without holding the whole body. The stream ends with an error
+    /// when the response status is not accepted; the proxies yielded before
+    /// it should then be discarded. Unlike [`list_online_proxies`], duplicate
+    /// records are not removed and the order is the one sent by the API.
+    ///
+    /// Commands sent this way are not retried by status or coalesced.
+    ///
+    /// [`list_online_proxies`]: Self::list_online_proxies
+    pub async fn list_online_stream(&self) -> Result<ProxyInfoStream, ApiError> {
+        let reader = BodyReader::open(self).await?;
+        let state = StreamState::Reading {
+            reader: Box::new(reader),
+            pending: VecDeque::new(),
+            index: 0,
+        };
+        Ok(Box::pin(stream::unfold(state, |state| async move {
+            let StreamState::Reading {
+                mut reader,
+                mut pending,
+                mut index,
+            } = state
+            else {
+                return None;
+            };
+            loop {
+                if let Some(item) = pending.pop_front() {
+                    return match decode_item(index, &item) {
+                        Ok(proxy) => {
+                            index += 1;
+                            let state = StreamState::Reading {
+                                reader,
+                                pending,
+                                index,
+                            };
+                            Some((Ok(proxy), state))
+                        }
+                        Err(err) => Some((Err(reader.fail(err).await), StreamState::Done)),
+                    };
+                }
+                match reader.next_items().await {
+                    Ok(Some(items)) => pending.extend(items),
+                    Ok(None) => {
+                        return match reader.finish().await {
+                            Ok(_) => None,
+                            Err(err) => Some((Err(err), StreamState::Done)),
+                        }
+                    }
+                    Err(err) => return Some((Err(err), StreamState::Done)),
+                }
+            }
+        })))
+    }
+
+    /// [`list_online_proxies`](Self::list_online_proxies), decoding the body
+    /// in one pass as it downloads instead of building a JSON tree of the
+    /// whole list first.
+    pub async fn list_online_proxies_streamed(&self) -> Result<ListOnlineResult, ApiError> {
+        let mut reader = BodyReader::open(self).await?;
+        let mut proxies = Vec::new();
+        while let Some(items) = reader.next_items().await? {
+            for item in items {
+                match decode_item(proxies.len(), &item) {
+                    Ok(proxy) => proxies.push(proxy),
+                    Err(err) => return Err(reader.fail(err).await),
+                }
+            }
+        }
+        let (_, _, mut skeleton) = reader.finish().await?;
+        let mut raw: RawListOnlineResult = decode_response(COMMAND, skeleton["result"].take())?;
+        raw.proxy_list = proxies;
+        Ok(raw.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ok_response, proxy_info_json, serve};
+    use serde_json::json;
+
+    fn body() -> Vec<u8> {
+        let mut tricky = proxy_info_json(3);
+        tricky["ISP"] = json!("Quote \" and } brace ]");
+        ok_response(json!({
+            "LastUpdate": 5,
+            "ProxyCount": 3,
+            "ProxyList": [proxy_info_json(2), tricky, proxy_info_json(1)],
+            "Note": {"ProxyList": [1, 2]},
+        }))
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_scanner_splits_items_across_chunks() {
+        let body = body();
+        for chunk_size in [1, 7, body.len()] {
+            let mut scanner = ProxyListScanner::default();
+            let items: Vec<Vec<u8>> = body
+                .chunks(chunk_size)
+                .flat_map(|chunk| scanner.feed(chunk))
+                .collect();
+            let ids: Vec<u32> = items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| decode_item(index, item).unwrap().proxy_id)
+                .collect();
+            assert_eq!(ids, vec![2, 3, 1]);
+            let skeleton: Value = serde_json::from_slice(&scanner.into_skeleton()).unwrap();
+            assert_eq!(skeleton["result"]["ProxyList"], json!([]));
+            assert_eq!(skeleton["result"]["Note"]["ProxyList"], json!([1, 2]));
+            assert_eq!(skeleton["status"]["code"], json!(0));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_online_stream() {
+        let body: Value = serde_json::from_slice(&body()).unwrap();
+        let (url, _) = serve(vec![body.clone(), body]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+
+        let proxies: Vec<ProxyInfo> = client
+            .list_online_stream()
+            .await
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(proxies.len(), 3);
+        assert_eq!(proxies[1].isp, "Quote \" and } brace ]");
+
+        let list = client.list_online_proxies_streamed().await.unwrap();
+        assert_eq!(list.last_update, 5);
+        let ids: Vec<u32> = list.proxy_list.iter().map(|proxy| proxy.proxy_id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert!(list.extra.contains_key("Note"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_ends_with_status_error() {
+        let (url, _) = serve(vec![json!({
+            "status": {"code": 3, "message": "invalid key"},
+            "result": false,
+        })]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let results: Vec<Result<ProxyInfo, ApiError>> =
+            client.list_online_stream().await.unwrap().collect().await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap_err().code(), 3);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::models::{ApiError, ListInfo, ListOnlineResult, ProxyInfo};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `203.0.113.0/24`. A bare
+/// address is a network of that single address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CidrParseError(pub String);
+
+impl fmt::Display for CidrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid CIDR {:?}", self.0)
+    }
+}
+
+impl std::error::Error for CidrParseError {}
+
+fn mask(address: IpAddr, prefix_len: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(v4) & u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V4(bits.into())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6) & u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+            IpAddr::V6(bits.into())
+        }
+    }
+}
+
+impl Cidr {
+    /// The network holding `address`, host bits cleared. `None` when the
+    /// prefix is longer than the address.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        (prefix_len <= max).then(|| Cidr {
+            network: mask(address, prefix_len),
+            prefix_len,
+        })
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        address.is_ipv4() == self.network.is_ipv4()
+            && mask(address, self.prefix_len) == self.network
+    }
+
+    /// Whether the textual address, as the API reports it, is in the network.
+    /// Missing or unparsable addresses are not.
+    pub fn contains_str(&self, address: Option<&str>) -> bool {
+        address
+            .and_then(|address| address.trim().parse().ok())
+            .is_some_and(|address| self.contains(address))
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(address: IpAddr) -> Self {
+        let prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        Cidr {
+            network: address,
+            prefix_len,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || CidrParseError(s.to_string());
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| error())?;
+        match prefix_len {
+            None => Ok(address.into()),
+            Some(prefix_len) => {
+                let prefix_len = prefix_len.parse().map_err(|_| error())?;
+                Cidr::new(address, prefix_len).ok_or_else(error)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Proxies found in a network, both on sale and owned.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SubnetMatches {
+    pub online: Vec<ProxyInfo>,
+    // Active history entries
+    pub owned: Vec<ListInfo>,
+}
+
+impl SubnetMatches {
+    pub fn is_empty(&self) -> bool {
+        self.online.is_empty() && self.owned.is_empty()
+    }
+}
+
+impl ListOnlineResult {
+    pub fn find_by_ip(&self, ip: IpAddr) -> Vec<&ProxyInfo> {
+        self.find_in_subnet(&ip.into())
+    }
+
+    /// Listed proxies whose IP is in the network. Proxies without a visible IP
+    /// never match.
+    pub fn find_in_subnet(&self, cidr: &Cidr) -> Vec<&ProxyInfo> {
+        self.proxy_list
+            .iter()
+            .filter(|proxy| cidr.contains_str(proxy.ip.as_deref()))
+            .collect()
+    }
+}
+
+impl TrueSocksClient {
+    pub async fn find_by_ip(&self, ip: IpAddr) -> Result<SubnetMatches, ApiError> {
+        self.find_in_subnet(&ip.into()).await
+    }
+
+    /// Online proxies and active history entries whose IP is in the network.
+    pub async fn find_in_subnet(&self, cidr: &Cidr) -> Result<SubnetMatches, ApiError> {
+        let list = self.list_online_proxies().await?;
+        let owned = self.list_all_history(&HistoryQuery::active()).await?;
+        Ok(SubnetMatches {
+            online: list.find_in_subnet(cidr).into_iter().cloned().collect(),
+            owned: owned
+                .into_iter()
+                .filter(|entry| cidr.contains_str(entry.proxy_info.ip.as_deref()))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_page, list_info_json, ok_response, proxy_info_json, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_cidr() {
+        let cidr: Cidr = "203.0.113.77/24".parse().unwrap();
+        assert_eq!(cidr.to_string(), "203.0.113.0/24");
+        assert!(cidr.contains("203.0.113.200".parse().unwrap()));
+        assert!(!cidr.contains("203.0.114.1".parse().unwrap()));
+        assert!(!cidr.contains("::1".parse().unwrap()));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains("2001:db8:ffff::1".parse().unwrap()));
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("198.51.100.1".parse().unwrap()));
+        assert_eq!("10.0.0.1".parse::<Cidr>().unwrap().prefix_len(), 32);
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+        assert!(!cidr.contains_str(None));
+    }
+
+    #[tokio::test]
+    async fn test_find_in_subnet() {
+        let mut elsewhere = proxy_info_json(2);
+        elsewhere["IP"] = json!("198.51.100.9");
+        let mut owned_elsewhere = list_info_json(11, 4);
+        owned_elsewhere["ProxyInfo"]["IP"] = json!("192.0.2.1");
+        let (url, _requests) = serve(vec![
+            ok_response(json!({
+                "LastUpdate": 1,
+                "ProxyCount": 2,
+                "ProxyList": [proxy_info_json(1), elsewhere]
+            })),
+            ok_response(history_page(
+                vec![list_info_json(10, 3), owned_elsewhere],
+                1,
+                1,
+            )),
+        ]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        let found = client
+            .find_in_subnet(&"203.0.113.0/24".parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(found.online.len(), 1);
+        assert_eq!(found.online[0].proxy_id, 1);
+        assert_eq!(found.owned.len(), 1);
+        assert_eq!(found.owned[0].history_id, 10);
+    }
+}
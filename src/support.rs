@@ -0,0 +1,190 @@
+use crate::client::TrueSocksClient;
+use crate::credits::Credits;
+use crate::tap::{TapEvent, TapOutcome};
+use crate::unix_now;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Commands kept in memory for support bundles
+const RECENT_CAPACITY: usize = 50;
+
+/// One command as recorded for a support bundle. The API key is never included.
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedCommand {
+    pub timestamp: u64,
+    pub command: String,
+    pub params: Vec<(String, String)>,
+    pub duration_ms: u64,
+    pub code: u64,
+    pub outcome: String,
+    pub failed: bool,
+}
+
+impl CapturedCommand {
+    fn new(event: &TapEvent, timestamp: u64) -> Self {
+        let (code, outcome, failed) = match &event.outcome {
+            TapOutcome::Success(status) => (status.code, status.message.clone(), false),
+            TapOutcome::Warning(warning) => (warning.code, warning.message.clone(), false),
+            TapOutcome::Failure(err) => (err.code(), format!("{:?}", err), true),
+        };
+        CapturedCommand {
+            timestamp,
+            command: event.command.clone(),
+            params: event.params.clone(),
+            duration_ms: event.duration.as_millis() as u64,
+            code,
+            outcome,
+            failed,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct RecentCommands {
+    commands: Mutex<VecDeque<CapturedCommand>>,
+}
+
+impl RecentCommands {
+    pub(crate) fn record(&self, event: &TapEvent) {
+        let mut commands = self.commands.lock().unwrap();
+        if commands.len() == RECENT_CAPACITY {
+            commands.pop_front();
+        }
+        commands.push_back(CapturedCommand::new(event, unix_now()));
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<CapturedCommand> {
+        self.commands.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Client configuration as written to a support bundle.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClientSummary {
+    pub api_url: String,
+    pub key_transport: String,
+    pub status_handling: Vec<(u64, String)>,
+    pub debug_logging: bool,
+    pub hooks: usize,
+    pub tap: bool,
+    pub rate_limits: Vec<String>,
+    // Limit and amount spent of a scoped client's budget
+    pub budget: Option<(Credits, Credits)>,
+}
+
+/// Account details safe to share, the user ID and email are left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountSummary {
+    pub active: bool,
+    pub plan: String,
+    pub expires: u64,
+    pub credits: Credits,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupportBundle {
+    pub crate_version: String,
+    pub generated_at: u64,
+    pub client: ClientSummary,
+    pub account: Option<AccountSummary>,
+    // Set when the account could not be fetched
+    pub account_error: Option<String>,
+    // Oldest first
+    pub recent_commands: Vec<CapturedCommand>,
+    pub last_errors: Vec<CapturedCommand>,
+}
+
+impl TrueSocksClient {
+    /// Collect diagnostics for a bug report: crate version, configuration,
+    /// account plan and the last commands sent by this client. Credentials
+    /// are not included.
+    pub async fn collect_support_bundle(&self) -> SupportBundle {
+        let (account, account_error) = match self.get_account_status().await {
+            Ok(account) => (
+                Some(AccountSummary {
+                    active: account.active,
+                    plan: account.plan,
+                    expires: account.expires,
+                    credits: account.credits,
+                }),
+                None,
+            ),
+            Err(err) => (None, Some(format!("{:?}", err))),
+        };
+        // Taken after the account fetch so its outcome is included
+        let recent_commands = self.recent_commands().snapshot();
+        let last_errors = recent_commands
+            .iter()
+            .filter(|command| command.failed)
+            .cloned()
+            .collect();
+        SupportBundle {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            generated_at: unix_now(),
+            client: self.summary(),
+            account,
+            account_error,
+            recent_commands,
+            last_errors,
+        }
+    }
+
+    /// Write [`TrueSocksClient::collect_support_bundle`] to `path` as one JSON document.
+    pub async fn support_bundle(&self, path: impl AsRef<Path>) -> io::Result<SupportBundle> {
+        let bundle = self.collect_support_bundle().await;
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, &bundle)?;
+        writer.flush()?;
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ApiError, Status};
+    use std::time::Duration;
+
+    fn event(command: &str, outcome: TapOutcome) -> TapEvent {
+        TapEvent {
+            command: command.to_string(),
+            params: Vec::new(),
+            duration: Duration::from_millis(5),
+            outcome,
+        }
+    }
+
+    #[test]
+    fn test_recent_commands_are_bounded() {
+        let recent = RecentCommands::default();
+        for _ in 0..RECENT_CAPACITY {
+            recent.record(&event(
+                "Ping",
+                TapOutcome::Success(Status {
+                    code: 0,
+                    message: "OK".to_string(),
+                }),
+            ));
+        }
+        recent.record(&event(
+            "ListOnline",
+            TapOutcome::Failure(ApiError::from(503_u16)),
+        ));
+        let snapshot = recent.snapshot();
+        assert_eq!(snapshot.len(), RECENT_CAPACITY);
+        let last = snapshot.last().unwrap();
+        assert!(last.failed);
+        assert_eq!(last.code, 503);
+    }
+
+    #[test]
+    fn test_summary_leaves_out_key() {
+        let client = TrueSocksClient::new("secret-key");
+        let summary = serde_json::to_string(&client.summary()).unwrap();
+        assert!(!summary.contains("secret-key"));
+    }
+}
@@ -0,0 +1,129 @@
+//! Tags stored in the note of history entries as `#tag` words, next to any
+//! free text, e.g. `scraper #campaign:a #region:eu`.
+
+use crate::client::TrueSocksClient;
+use crate::history::HistoryQuery;
+use crate::models::{ApiError, ListInfo};
+use crate::pool::ProxyPool;
+use crate::status_codes::{BAD_REQUEST, NOT_FOUND};
+
+const TAG_PREFIX: char = '#';
+
+fn words(note: &str) -> impl Iterator<Item = &str> {
+    note.split_whitespace()
+}
+
+/// Tags of `note` in the order they appear, without the `#`.
+pub fn parse_tags(note: &str) -> Vec<&str> {
+    words(note)
+        .filter_map(|word| word.strip_prefix(TAG_PREFIX))
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// `note` without its tags, whitespace collapsed.
+pub fn note_text(note: &str) -> String {
+    words(note)
+        .filter(|word| !word.starts_with(TAG_PREFIX))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A note holding `text` followed by `tags`. Tags must be non-empty and free
+/// of whitespace, status 400 otherwise.
+pub fn encode_note(text: &str, tags: &[&str]) -> Result<String, ApiError> {
+    if tags
+        .iter()
+        .any(|tag| tag.is_empty() || tag.contains(char::is_whitespace))
+    {
+        return Err(ApiError::from(BAD_REQUEST));
+    }
+    let text = note_text(text);
+    let tags = tags.iter().map(|tag| format!("{}{}", TAG_PREFIX, tag));
+    Ok(words(&text)
+        .map(str::to_string)
+        .chain(tags)
+        .collect::<Vec<_>>()
+        .join(" "))
+}
+
+impl ListInfo {
+    pub fn tags(&self) -> Vec<&str> {
+        self.note.as_deref().map(parse_tags).unwrap_or_default()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags().contains(&tag)
+    }
+}
+
+impl TrueSocksClient {
+    /// Replace the tags in the note of `history_id`, keeping its free text.
+    /// Status 404 when the entry does not exist.
+    pub async fn set_tags(&self, history_id: u64, tags: &[&str]) -> Result<(), ApiError> {
+        let entry = self
+            .get_history_entry(history_id)
+            .await?
+            .ok_or(ApiError::from(NOT_FOUND))?;
+        let note = encode_note(entry.note.as_deref().unwrap_or_default(), tags)?;
+        self.history_entry_change_note(
+            history_id,
+            Some(note.as_str()).filter(|note| !note.is_empty()),
+        )
+        .await
+    }
+
+    /// Every history entry tagged with `tag`, expired ones included.
+    pub async fn find_by_tag(&self, tag: &str) -> Result<Vec<ListInfo>, ApiError> {
+        self.list_all_history(&HistoryQuery::new().tag(tag)).await
+    }
+}
+
+impl ProxyPool {
+    /// Members tagged with `tag`.
+    pub fn members_with_tag(&self, tag: &str) -> Vec<ListInfo> {
+        self.members()
+            .into_iter()
+            .filter(|entry| entry.has_tag(tag))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{history_page, list_info, list_info_json, ok_response, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_and_parse_tags() {
+        let note = encode_note("scraper  #old", &["campaign:a", "region:eu"]).unwrap();
+        assert_eq!(note, "scraper #campaign:a #region:eu");
+        assert_eq!(parse_tags(&note), vec!["campaign:a", "region:eu"]);
+        assert_eq!(note_text(&note), "scraper");
+        assert_eq!(encode_note("", &[]).unwrap(), "");
+        assert_eq!(
+            encode_note("", &["two words"]).unwrap_err().code(),
+            BAD_REQUEST as u64
+        );
+
+        let mut entry = list_info(1);
+        entry.note = Some(note);
+        assert!(entry.has_tag("region:eu"));
+        assert!(!entry.has_tag("region"));
+    }
+
+    #[tokio::test]
+    async fn test_set_tags_keeps_text() {
+        let mut entry = list_info_json(4, 4);
+        entry["Note"] = json!("scraper #campaign:a");
+        let (url, requests) = serve(vec![
+            ok_response(history_page(vec![entry], 1, 1)),
+            ok_response(json!(true)),
+        ]);
+        let client = TrueSocksClient::builder("test").base_url(url).build();
+        client.set_tags(4, &["campaign:b"]).await.unwrap();
+        let requests = requests.join().unwrap();
+        assert!(requests[1].contains("note=scraper+%23campaign%3Ab"));
+    }
+}
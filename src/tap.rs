@@ -0,0 +1,99 @@
+use crate::models::{ApiError, Status, Warning};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// A single command as seen by a tap. The API key is never included in `params`.
+#[derive(Debug, Clone)]
+pub struct TapEvent {
+    pub command: String,
+    pub params: Vec<(String, String)>,
+    pub duration: Duration,
+    pub outcome: TapOutcome,
+}
+
+#[derive(Debug, Clone)]
+pub enum TapOutcome {
+    Success(Status),
+    // Non-zero status accepted because of the client's status handling
+    Warning(Warning),
+    Failure(ApiError),
+}
+
+/// Destination for mirrored commands, e.g. a channel, a file or a message queue.
+///
+/// The returned future is awaited before the command returns to the caller,
+/// so slow sinks should hand the event off (for example to a channel) rather
+/// than doing heavy work inline.
+pub trait TapSink: Send + Sync + 'static {
+    fn send(&self, event: TapEvent) -> Pin<Box<dyn Future<Output = ()> + Send + '_>>;
+}
+
+impl<F, Fut> TapSink for F
+where
+    F: Fn(TapEvent) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn send(&self, event: TapEvent) -> Pin<Box<dyn Future<Output = ()> + Send + '_>> {
+        Box::pin(self(event))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::TrueSocksClient;
+    use crate::fixtures::{ok_response, serve};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_tap_receives_redacted_events() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let events = events.clone();
+            move |event: TapEvent| {
+                events.lock().unwrap().push(event);
+                async {}
+            }
+        };
+        let zip = json!({
+            "ServerTime": 1,
+            "SearchCountryCode": "US",
+            "SearchUnits": "km",
+            "SearchRange": 25,
+            "SearchZipCode": "10001",
+            "ProxyCount": 0,
+            "ProxyList": []
+        });
+        let failure = json!({"status": {"code": 404, "message": "Not Found"}, "result": null});
+        let (url, _) = serve(vec![ok_response(zip), failure]);
+        let client = TrueSocksClient::builder("secret-tap-key")
+            .base_url(url)
+            .tap(sink)
+            .build();
+
+        client
+            .list_zip_search("US", "10001", Some("km"), Some(25))
+            .await
+            .unwrap();
+        client.ping().await.unwrap_err();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        let search = &events[0];
+        assert_eq!(search.command, "ListZipSearch");
+        assert!(search
+            .params
+            .contains(&("zipcode".to_string(), "10001".to_string())));
+        assert!(search
+            .params
+            .iter()
+            .all(|(name, value)| name != "key" && !value.contains("secret-tap-key")));
+        assert!(matches!(&search.outcome, TapOutcome::Success(status) if status.code == 0));
+
+        assert_eq!(events[1].command, "Ping");
+        assert!(matches!(events[1].outcome, TapOutcome::Failure(_)));
+        assert!(!format!("{:?}", events).contains("secret-tap-key"));
+    }
+}
@@ -0,0 +1,359 @@
+use crate::models::ConnectInfo;
+use crate::socks::SocksError;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio_socks::Error;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const PASSWORD_AUTH: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const UDP_ASSOCIATE: u8 = 3;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+// Largest payload a UDP datagram can carry
+const MAX_DATAGRAM: usize = 65_535;
+
+/// Source or destination of a datagram relayed by the proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DatagramAddr {
+    Ip(SocketAddr),
+    // Resolved by the proxy
+    Domain(String, u16),
+}
+
+impl From<SocketAddr> for DatagramAddr {
+    fn from(addr: SocketAddr) -> Self {
+        DatagramAddr::Ip(addr)
+    }
+}
+
+impl From<(&str, u16)> for DatagramAddr {
+    fn from((host, port): (&str, u16)) -> Self {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => DatagramAddr::Ip(SocketAddr::new(ip, port)),
+            Err(_) => DatagramAddr::Domain(host.to_string(), port),
+        }
+    }
+}
+
+impl fmt::Display for DatagramAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatagramAddr::Ip(addr) => write!(f, "{}", addr),
+            DatagramAddr::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl DatagramAddr {
+    fn encode(&self, out: &mut Vec<u8>) -> Result<(), Error> {
+        match self {
+            DatagramAddr::Ip(SocketAddr::V4(addr)) => {
+                out.push(ATYP_IPV4);
+                out.extend_from_slice(&addr.ip().octets());
+            }
+            DatagramAddr::Ip(SocketAddr::V6(addr)) => {
+                out.push(ATYP_IPV6);
+                out.extend_from_slice(&addr.ip().octets());
+            }
+            DatagramAddr::Domain(host, _) => {
+                let len = u8::try_from(host.len())
+                    .map_err(|_| Error::InvalidTargetAddress("domain longer than 255 bytes"))?;
+                out.push(ATYP_DOMAIN);
+                out.push(len);
+                out.extend_from_slice(host.as_bytes());
+            }
+        }
+        let port = match self {
+            DatagramAddr::Ip(addr) => addr.port(),
+            DatagramAddr::Domain(_, port) => *port,
+        };
+        out.extend_from_slice(&port.to_be_bytes());
+        Ok(())
+    }
+
+    // Address at the start of `data`, with the number of bytes it took
+    fn decode(data: &[u8]) -> Result<(DatagramAddr, usize), Error> {
+        let truncated = || Error::InvalidTargetAddress("truncated address");
+        let (&atyp, rest) = data.split_first().ok_or_else(truncated)?;
+        let host_len = match atyp {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => 1 + *rest.first().ok_or_else(truncated)? as usize,
+            _ => return Err(Error::UnknownAddressType),
+        };
+        let host = rest.get(..host_len).ok_or_else(truncated)?;
+        let port = rest.get(host_len..host_len + 2).ok_or_else(truncated)?;
+        let port = u16::from_be_bytes([port[0], port[1]]);
+        let addr = match atyp {
+            ATYP_IPV4 => {
+                let octets: [u8; 4] = host.try_into().unwrap();
+                DatagramAddr::Ip(SocketAddr::new(Ipv4Addr::from(octets).into(), port))
+            }
+            ATYP_IPV6 => {
+                let octets: [u8; 16] = host.try_into().unwrap();
+                DatagramAddr::Ip(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+            }
+            _ => DatagramAddr::Domain(String::from_utf8_lossy(&host[1..]).into_owned(), port),
+        };
+        Ok((addr, 1 + host_len + 2))
+    }
+}
+
+fn reply_error(code: u8) -> Error {
+    match code {
+        1 => Error::GeneralSocksServerFailure,
+        2 => Error::ConnectionNotAllowedByRuleset,
+        3 => Error::NetworkUnreachable,
+        4 => Error::HostUnreachable,
+        5 => Error::ConnectionRefused,
+        6 => Error::TtlExpired,
+        7 => Error::CommandNotSupported,
+        8 => Error::AddressTypeNotSupported,
+        _ => Error::UnknownError,
+    }
+}
+
+async fn authenticate(control: &mut TcpStream, connect_info: &ConnectInfo) -> Result<(), Error> {
+    let credentials = connect_info.credentials();
+    let greeting: &[u8] = match credentials {
+        Some(_) => &[VERSION, 2, NO_AUTH, PASSWORD_AUTH],
+        None => &[VERSION, 1, NO_AUTH],
+    };
+    control.write_all(greeting).await?;
+    let mut choice = [0u8; 2];
+    control.read_exact(&mut choice).await?;
+    if choice[0] != VERSION {
+        return Err(Error::InvalidResponseVersion);
+    }
+    match (choice[1], credentials) {
+        (NO_AUTH, _) => Ok(()),
+        (PASSWORD_AUTH, Some((username, password))) => {
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            control.write_all(&request).await?;
+            let mut status = [0u8; 2];
+            control.read_exact(&mut status).await?;
+            match status[1] {
+                0 => Ok(()),
+                code => Err(Error::PasswordAuthFailure(code)),
+            }
+        }
+        (NO_ACCEPTABLE_METHOD, _) => Err(Error::NoAcceptableAuthMethods),
+        _ => Err(Error::UnknownAuthMethod),
+    }
+}
+
+// Ask for a UDP relay, returning the address datagrams go to
+async fn associate(control: &mut TcpStream) -> Result<SocketAddr, Error> {
+    // The client address is not known in advance, 0.0.0.0:0 lets the proxy
+    // accept datagrams from wherever they come
+    control
+        .write_all(&[VERSION, UDP_ASSOCIATE, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    let mut header = [0u8; 3];
+    control.read_exact(&mut header).await?;
+    if header[0] != VERSION {
+        return Err(Error::InvalidResponseVersion);
+    }
+    if header[1] != 0 {
+        return Err(reply_error(header[1]));
+    }
+    let atyp = control.read_u8().await?;
+    let mut bound = vec![atyp];
+    match atyp {
+        ATYP_IPV4 => bound.resize(1 + 4 + 2, 0),
+        ATYP_IPV6 => bound.resize(1 + 16 + 2, 0),
+        ATYP_DOMAIN => {
+            let len = control.read_u8().await?;
+            bound.push(len);
+            bound.resize(2 + len as usize + 2, 0);
+        }
+        _ => return Err(Error::UnknownAddressType),
+    }
+    let start = if atyp == ATYP_DOMAIN { 2 } else { 1 };
+    control.read_exact(&mut bound[start..]).await?;
+    let relay = match DatagramAddr::decode(&bound)?.0 {
+        DatagramAddr::Ip(addr) => addr,
+        DatagramAddr::Domain(host, port) => lookup_host((host.as_str(), port))
+            .await?
+            .next()
+            .ok_or(Error::ProxyServerUnreachable)?,
+    };
+    // An unspecified address means the relay is on the proxy host itself
+    if relay.ip().is_unspecified() {
+        return Ok(SocketAddr::new(control.peer_addr()?.ip(), relay.port()));
+    }
+    Ok(relay)
+}
+
+/// A UDP association through a SOCKS5 proxy. Datagrams are wrapped in the
+/// SOCKS5 UDP header on the way out and unwrapped on the way in. The
+/// association ends when this is dropped and its control connection closes.
+/// Fragmented datagrams are not supported.
+pub struct Socks5Datagram {
+    // The proxy keeps the relay open as long as this connection lives
+    _control: TcpStream,
+    socket: UdpSocket,
+    relay: SocketAddr,
+}
+
+impl Socks5Datagram {
+    /// Address of the proxy's UDP relay.
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+
+    /// Send `buf` to `target` through the relay, returning the payload bytes
+    /// sent.
+    pub async fn send_to(
+        &self,
+        buf: &[u8],
+        target: impl Into<DatagramAddr>,
+    ) -> Result<usize, SocksError> {
+        let mut packet = vec![0, 0, 0];
+        target.into().encode(&mut packet)?;
+        let header_len = packet.len();
+        packet.extend_from_slice(buf);
+        let sent = self.socket.send(&packet).await.map_err(Error::from)?;
+        Ok(sent.saturating_sub(header_len))
+    }
+
+    /// Receive the next datagram into `buf`, returning its length and where
+    /// it came from. A payload longer than `buf` is truncated.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, DatagramAddr), SocksError> {
+        let mut packet = vec![0u8; MAX_DATAGRAM];
+        let received = self.socket.recv(&mut packet).await.map_err(Error::from)?;
+        let packet = &packet[..received];
+        if packet.len() < 3 {
+            return Err(Error::InvalidTargetAddress("truncated datagram").into());
+        }
+        if packet[2] != 0 {
+            return Err(Error::InvalidTargetAddress("fragmented datagram").into());
+        }
+        let (source, addr_len) = DatagramAddr::decode(&packet[3..])?;
+        let payload = &packet[3 + addr_len..];
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        Ok((len, source))
+    }
+}
+
+impl ConnectInfo {
+    /// Set up a SOCKS5 UDP association through this session, authenticating
+    /// with its credentials, for DNS, QUIC or other datagram traffic. Fails
+    /// with `CommandNotSupported` when the proxy does not relay UDP.
+    pub async fn udp_associate(&self) -> Result<Socks5Datagram, SocksError> {
+        let proxy = (self.connect_ip.as_str(), self.connect_port);
+        let mut control = TcpStream::connect(proxy).await.map_err(Error::from)?;
+        authenticate(&mut control, self).await?;
+        let relay = associate(&mut control).await?;
+        let local: SocketAddr = if relay.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local).await.map_err(Error::from)?;
+        socket.connect(relay).await.map_err(Error::from)?;
+        Ok(Socks5Datagram {
+            _control: control,
+            socket,
+            relay,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // SOCKS5 server accepting one UDP association and echoing its datagrams
+    async fn echo_proxy(reply: u8) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let relay_port = relay.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut control, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 4];
+            control.read_exact(&mut greeting).await.unwrap();
+            control.write_all(&[VERSION, PASSWORD_AUTH]).await.unwrap();
+            let mut auth = [0u8; 2 + 8 + 1 + 8];
+            control.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth[2..10], b"session1");
+            control.write_all(&[1, 0]).await.unwrap();
+            let mut request = [0u8; 10];
+            control.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[1], UDP_ASSOCIATE);
+            let [high, low] = relay_port.to_be_bytes();
+            control
+                .write_all(&[VERSION, reply, 0, ATYP_IPV4, 0, 0, 0, 0, high, low])
+                .await
+                .unwrap();
+            let mut packet = [0u8; 512];
+            let (len, client) = relay.recv_from(&mut packet).await.unwrap();
+            relay.send_to(&packet[..len], client).await.unwrap();
+            // Hold the association until the client hangs up
+            let _ = control.read_u8().await;
+        });
+        port
+    }
+
+    fn connect_info(port: u16) -> ConnectInfo {
+        ConnectInfo {
+            connect_ip: "127.0.0.1".to_string(),
+            connect_port: port,
+            connect_session_id: "session1".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_udp_associate_round_trip() {
+        let port = echo_proxy(0).await;
+        let datagram = connect_info(port).udp_associate().await.unwrap();
+        assert!(datagram.relay_addr().ip().is_loopback());
+
+        let sent = datagram
+            .send_to(b"ping", ("example.com", 53))
+            .await
+            .unwrap();
+        assert_eq!(sent, 4);
+        let mut buf = [0u8; 16];
+        let (len, source) = datagram.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(source, DatagramAddr::Domain("example.com".to_string(), 53));
+    }
+
+    #[tokio::test]
+    async fn test_udp_associate_not_supported() {
+        let port = echo_proxy(7).await;
+        let res = connect_info(port).udp_associate().await;
+        assert!(matches!(
+            res,
+            Err(SocksError::Socks(Error::CommandNotSupported))
+        ));
+    }
+
+    #[test]
+    fn test_datagram_addr_round_trip() {
+        for addr in [
+            DatagramAddr::from(("198.51.100.1", 53)),
+            DatagramAddr::from(("2001:db8::1", 443)),
+            DatagramAddr::from(("dns.example", 853)),
+        ] {
+            let mut encoded = Vec::new();
+            addr.encode(&mut encoded).unwrap();
+            assert_eq!(
+                DatagramAddr::decode(&encoded).unwrap(),
+                (addr, encoded.len())
+            );
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::models::{ApiError, ProxyInfo};
+// Used by the background task only, which is not built for wasm32
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::client::TrueSocksClient,
+    crate::diff::diff_proxies,
+    crate::filter::ProxyFilter,
+    futures::stream::{self, Stream, StreamExt},
+    std::collections::BTreeMap,
+    std::time::Duration,
+    tokio::time::MissedTickBehavior,
+};
+
+#[derive(Debug, Clone)]
+pub enum ProxyAvailabilityEvent {
+    // Started matching the filter, or came online
+    Appeared(ProxyInfo),
+    // Went offline or stopped matching the filter, with its last listing
+    Disappeared(ProxyInfo),
+    // Still matching but listed differently, ping and speed included
+    Changed {
+        before: Box<ProxyInfo>,
+        after: Box<ProxyInfo>,
+    },
+    // The list could not be fetched, the previous snapshot is kept
+    PollFailed(ApiError),
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type Snapshot = BTreeMap<u32, ProxyInfo>;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn diff(previous: &Snapshot, current: &Snapshot) -> Vec<ProxyAvailabilityEvent> {
+    let diff = diff_proxies(previous.values(), current.values());
+    let mut events: Vec<ProxyAvailabilityEvent> = diff
+        .removed
+        .into_iter()
+        .map(ProxyAvailabilityEvent::Disappeared)
+        .collect();
+    // Appearances and changes in ProxyID order
+    let mut updates: Vec<(u32, ProxyAvailabilityEvent)> = diff
+        .added
+        .into_iter()
+        .map(|proxy| (proxy.proxy_id, ProxyAvailabilityEvent::Appeared(proxy)))
+        .chain(diff.changed.into_iter().map(|change| {
+            (
+                change.proxy_id(),
+                ProxyAvailabilityEvent::Changed {
+                    before: Box::new(change.before),
+                    after: Box::new(change.after),
+                },
+            )
+        }))
+        .collect();
+    updates.sort_by_key(|(proxy_id, _)| *proxy_id);
+    events.extend(updates.into_iter().map(|(_, event)| event));
+    events
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TrueSocksClient {
+    /// Poll `ListOnline` every `interval` and report how the proxies matching
+    /// `filter` changed since the previous poll. The first poll reports every
+    /// matching proxy as appeared. Polling stops when the stream is dropped.
+    pub fn watch_online(
+        &self,
+        filter: ProxyFilter,
+        interval: Duration,
+    ) -> impl Stream<Item = ProxyAvailabilityEvent> + Send + 'static {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let state = (self.clone(), filter, ticker, Snapshot::new());
+        stream::unfold(state, |(client, filter, mut ticker, previous)| async move {
+            ticker.tick().await;
+            let (events, current) = match client.list_online_proxies().await {
+                Ok(list) => {
+                    let current: Snapshot = list
+                        .proxy_list
+                        .into_iter()
+                        .filter(|proxy| filter.matches(proxy))
+                        .map(|proxy| (proxy.proxy_id, proxy))
+                        .collect();
+                    (diff(&previous, &current), current)
+                }
+                Err(err) => (vec![ProxyAvailabilityEvent::PollFailed(err)], previous),
+            };
+            Some((events, (client, filter, ticker, current)))
+        })
+        .flat_map(stream::iter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ok_response, proxy_info_json, serve};
+    use serde_json::json;
+
+    fn online(proxies: Vec<serde_json::Value>) -> serde_json::Value {
+        ok_response(json!({
+            "LastUpdate": 1,
+            "ProxyCount": proxies.len(),
+            "ProxyList": proxies
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_watch_online() {
+        let mut changed = proxy_info_json(2);
+        changed["Ping"] = json!(999.0);
+        let (url, _) = serve(vec![
+            online(vec![proxy_info_json(1), proxy_info_json(2)]),
+            online(vec![changed, proxy_info_json(3)]),
+        ]);
+        let client = TrueSocksClient::builder("test")
+            .base_url(url)
+            .max_retries(0)
+            .build();
+        let events: Vec<ProxyAvailabilityEvent> = client
+            .watch_online(ProxyFilter::new(), Duration::from_millis(10))
+            .take(5)
+            .collect()
+            .await;
+
+        let summary: Vec<(&str, u32)> = events
+            .iter()
+            .map(|event| match event {
+                ProxyAvailabilityEvent::Appeared(proxy) => ("appeared", proxy.proxy_id),
+                ProxyAvailabilityEvent::Disappeared(proxy) => ("disappeared", proxy.proxy_id),
+                ProxyAvailabilityEvent::Changed { after, .. } => ("changed", after.proxy_id),
+                ProxyAvailabilityEvent::PollFailed(_) => ("failed", 0),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("appeared", 1),
+                ("appeared", 2),
+                ("disappeared", 1),
+                ("changed", 2),
+                ("appeared", 3)
+            ]
+        );
+    }
+}